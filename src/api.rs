@@ -1,21 +1,28 @@
 use std::{
+    borrow::Cow,
+    fmt,
     io::Write,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
-use crate::{cli::input, multipart};
+use crate::{
+    cli::{input, sanitize},
+    compress::Compression,
+    multipart,
+};
 use anyhow::Context;
 use base64::{prelude::BASE64_STANDARD, Engine};
-use log::warn;
+use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 
 #[cfg(test)]
 mod tests;
 
 /// Request body for the OpenAI image generation API
-#[derive(Debug, Serialize)]
+#[derive(Clone, Debug, Serialize)]
 pub struct CreateRequest {
-    /// The model to use for image generation (always gpt-image-1 for this app)
+    /// The model to use for image generation (gpt-image-1, dall-e-3, ...)
     pub model: String,
 
     /// A text description of the desired image(s)
@@ -48,21 +55,39 @@ pub struct CreateRequest {
     /// The format of the generated images (png, jpeg, webp)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub output_format: Option<String>,
+
+    /// The style of the generated images (vivid, natural) (dall-e-3 only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<String>,
+
+    /// The format to return images in (url, b64_json) (dall-e only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<String>,
 }
 
 /// Request for the OpenAI image edit API
 /// Note: This is not Serialize because it needs to be multipart-form-encoded.
+#[derive(Clone)]
 pub struct EditRequest {
     /// The image(s) to edit, represented as processed data (path or bytes).
+    /// The mask and `input_fidelity` apply to the first.
     pub images: Vec<input::ImageData>,
 
+    /// Reference images to draw from (a style, a product, a character),
+    /// sent after the images to edit (`--image-ref`)
+    pub references: Vec<input::ImageData>,
+
+    /// An image to match the style of, sent last of all, as the prompt
+    /// calls it the last input image (`--style-ref`)
+    pub style_ref: Option<input::ImageData>,
+
     /// A text description of the desired image(s)
     pub prompt: String,
 
     /// An additional image whose transparent areas indicate where to edit
     pub mask: Option<input::ImageData>,
 
-    /// The model to use for image generation (always gpt-image-1 for this app)
+    /// The model to use for image generation (gpt-image-1, dall-e-3, ...)
     pub model: String,
 
     /// The number of images to generate (1-10)
@@ -73,87 +98,222 @@ pub struct EditRequest {
 
     /// The size of the generated images (1024x1024, 1536x1024, 1024x1536, auto)
     pub size: Option<String>,
+
+    /// How closely to match the input images' details, like faces and logos
+    /// (low, high) (gpt-image-1 only)
+    pub input_fidelity: Option<String>,
+
+    /// The compression level for generated images (0-100)
+    pub output_compression: Option<u8>,
+
+    /// The format of the generated images (png, jpeg, webp)
+    pub output_format: Option<String>,
 }
 
 impl EditRequest {
-    /// Builds the multipart/form-data body for the edit request.
-    ///
-    /// # Errors
-    ///
-    /// Returns an `io::Error` if any file operations fail during building.
-    pub fn build_multipart(&self) -> multipart::Body {
+    /// Builds the multipart/form-data body for the edit request. The images
+    /// are streamed from their bytes, rather than copied into the body.
+    pub fn build_multipart(&self) -> multipart::Stream<'_> {
         let boundary = multipart::generate_boundary();
         self.build_multipart_inner(boundary)
     }
 
     // Used for testing
-    fn build_multipart_inner(&self, boundary: String) -> multipart::Body {
+    fn build_multipart_inner(&self, boundary: String) -> multipart::Stream<'_> {
         let mut builder = multipart::Builder::with_boundary(boundary);
+        for (name, field) in self.form_fields() {
+            match field {
+                FormField::Text(value) => builder.add_text(name, value),
+                FormField::File(image) => builder.add_file_bytes(
+                    name,
+                    &image.filename,
+                    image.content_type,
+                    &image.bytes,
+                ),
+            }
+        }
+        builder.build_stream()
+    }
 
-        let n_str = self.n.map(|n| n.to_string());
-        // Add text fields
-        builder.add_text("prompt", &self.prompt);
-        builder.add_text("model", &self.model);
-        if let Some(n) = n_str.as_deref() {
-            builder.add_text("n", n);
+    /// The multipart form fields, in the order they're sent.
+    pub fn form_fields(&self) -> Vec<(&'static str, FormField<'_>)> {
+        fn text(value: &str) -> FormField<'_> {
+            FormField::Text(Cow::Borrowed(value))
+        }
+        let number = |value: u8| FormField::Text(Cow::Owned(value.to_string()));
+
+        // Text fields
+        let mut fields =
+            vec![("prompt", text(&self.prompt)), ("model", text(&self.model))];
+        fields.extend(self.n.map(|n| ("n", number(n))));
+        fields.extend(self.quality.as_deref().map(|q| ("quality", text(q))));
+        fields.extend(self.size.as_deref().map(|size| ("size", text(size))));
+        fields.extend(
+            self.input_fidelity
+                .as_deref()
+                .map(|fidelity| ("input_fidelity", text(fidelity))),
+        );
+        fields.extend(
+            self.output_compression
+                .map(|level| ("output_compression", number(level))),
+        );
+        fields.extend(
+            self.output_format
+                .as_deref()
+                .map(|format| ("output_format", text(format))),
+        );
+
+        // Image files, the images to edit first, then the optional mask
+        for image in self
+            .images
+            .iter()
+            .chain(&self.references)
+            .chain(&self.style_ref)
+        {
+            fields.push(("image[]", FormField::File(image)));
         }
-        if let Some(quality) = &self.quality {
-            builder.add_text("quality", quality);
+        fields.extend(
+            self.mask
+                .as_ref()
+                .map(|mask| ("mask", FormField::File(mask))),
+        );
+        fields
+    }
+}
+
+/// A multipart form field of an edit request.
+pub enum FormField<'a> {
+    Text(Cow<'a, str>),
+    File(&'a input::ImageData),
+}
+
+/// Request to create variations of an image (dall-e-2 only)
+#[derive(Clone)]
+pub struct VariationRequest {
+    /// The image to make variations of (a square png under 4 MB)
+    pub image: input::ImageData,
+
+    /// The model to use (only dall-e-2 supports variations)
+    pub model: String,
+
+    /// The number of images to generate (1-10)
+    pub n: Option<u8>,
+
+    /// The size of the generated images (256x256, 512x512, 1024x1024)
+    pub size: Option<String>,
+
+    /// The format to return images in (url, b64_json)
+    pub response_format: Option<String>,
+}
+
+impl VariationRequest {
+    /// Builds the multipart/form-data body for the variation request.
+    pub fn build_multipart(&self) -> multipart::Stream<'_> {
+        let boundary = multipart::generate_boundary();
+        self.build_multipart_inner(boundary)
+    }
+
+    // Used for testing
+    fn build_multipart_inner(&self, boundary: String) -> multipart::Stream<'_> {
+        let mut builder = multipart::Builder::with_boundary(boundary);
+
+        builder.add_text("model", &self.model);
+        if let Some(n) = self.n {
+            builder.add_text("n", n.to_string());
         }
         if let Some(size) = &self.size {
             builder.add_text("size", size);
         }
-
-        // Add image files
-        for image in &self.images {
-            builder.add_file_bytes(
-                "image[]",
-                &image.filename,
-                image.content_type,
-                &image.bytes,
-            );
-        }
-
-        // Add optional mask file
-        if let Some(mask) = &self.mask {
-            builder.add_file_bytes(
-                "mask",
-                &mask.filename,
-                mask.content_type,
-                &mask.bytes,
-            );
+        if let Some(response_format) = &self.response_format {
+            builder.add_text("response_format", response_format);
         }
+        builder.add_file_bytes(
+            "image",
+            &self.image.filename,
+            self.image.content_type,
+            &self.image.bytes,
+        );
 
-        // Build and return the final body
-        let body = builder.build();
-
-        drop(n_str);
-        body
+        builder.build_stream()
     }
 }
 
 /// Response from the OpenAI image generation API
 #[derive(Debug, Deserialize)]
 pub struct Response {
-    /// The Unix timestamp (in seconds) of when the image was created
+    /// The Unix timestamp (in seconds) of when the image was created. Now,
+    /// if the server doesn't say.
+    #[serde(default = "unix_now")]
     pub created: u64,
 
     /// The list of generated images
     pub data: Vec<ImageData>,
 
-    /// Token usage information for the image generation
+    /// Token usage information for the image generation. Several
+    /// OpenAI-compatible servers don't report it, so it's zero if missing.
+    #[serde(default)]
     pub usage: Usage,
 }
 
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+/// The exact schema of the OpenAI API's image responses, which `--strict`
+/// holds responses to. Unlike [`Response`], nothing may be missing. Extra
+/// fields are fine, as the API adds them over time.
+#[derive(Deserialize)]
+#[allow(dead_code)] // Only deserialized, to check the schema
+pub struct StrictResponse {
+    created: u64,
+    data: Vec<serde::de::IgnoredAny>,
+    usage: StrictUsage,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct StrictUsage {
+    total_tokens: u32,
+    input_tokens: u32,
+    output_tokens: u32,
+    input_tokens_details: StrictInputTokensDetails,
+}
+
+#[derive(Deserialize)]
+#[allow(dead_code)]
+struct StrictInputTokensDetails {
+    text_tokens: u32,
+    image_tokens: u32,
+}
+
 /// Image data returned in the response
 #[derive(Debug, Deserialize)]
 pub struct ImageData {
-    /// The base64-encoded JSON of the generated image
+    /// The base64-encoded JSON of the generated image. Empty if the API
+    /// returned a `url` instead.
+    #[serde(default)]
     pub b64_json: String,
+
+    /// A temporary URL to download the generated image from (dall-e models)
+    #[serde(default)]
+    pub url: Option<String>,
+
+    /// The prompt the model actually used, if it rewrote ours (dall-e-3)
+    #[serde(default)]
+    pub revised_prompt: Option<String>,
+
+    /// The image, once downloaded from `url`, so it needn't round trip
+    /// through base64
+    #[serde(skip)]
+    pub downloaded: Option<ImageBytes>,
 }
 
-/// Token usage information
-#[derive(Debug, Deserialize)]
+/// Token usage information. Missing fields are zero.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct Usage {
     /// The total number of tokens used for the image generation
     pub total_tokens: u32,
@@ -165,20 +325,30 @@ pub struct Usage {
     pub output_tokens: u32,
 
     /// Detailed information about input tokens
-    #[allow(dead_code)]
     pub input_tokens_details: InputTokensDetails,
 }
 
+const INPUT_COST_PER_MILLION: f64 = 10.0;
+const OUTPUT_COST_PER_MILLION: f64 = 40.0;
+
 impl Usage {
+    /// Add another request's usage to this one.
+    pub fn add(&mut self, other: &Usage) {
+        self.total_tokens += other.total_tokens;
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.input_tokens_details.text_tokens +=
+            other.input_tokens_details.text_tokens;
+        self.input_tokens_details.image_tokens +=
+            other.input_tokens_details.image_tokens;
+    }
+
     /// Calculate the total cost in USD based on token usage.
     ///
     /// `gpt-image-1` costs are:
     /// * Input tokens cost $10.00 per 1M tokens
     /// * Output tokens cost $40.00 per 1M tokens
     pub fn calculate_cost(&self) -> f64 {
-        const INPUT_COST_PER_MILLION: f64 = 10.0;
-        const OUTPUT_COST_PER_MILLION: f64 = 40.0;
-
         let input_cost =
             (self.input_tokens as f64 / 1_000_000.0) * INPUT_COST_PER_MILLION;
         let output_cost =
@@ -186,11 +356,17 @@ impl Usage {
 
         input_cost + output_cost
     }
+
+    /// The part of the cost in USD spent on input image tokens.
+    pub fn image_input_cost(&self) -> f64 {
+        let image_tokens = self.input_tokens_details.image_tokens;
+        (image_tokens as f64 / 1_000_000.0) * INPUT_COST_PER_MILLION
+    }
 }
 
 /// Detailed information about input tokens
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
 pub struct InputTokensDetails {
     /// The number of text tokens in the input prompt
     pub text_tokens: u32,
@@ -199,11 +375,327 @@ pub struct InputTokensDetails {
     pub image_tokens: u32,
 }
 
+/// Error response body from the OpenAI API
+#[derive(Debug, Deserialize)]
+pub struct ErrorResponse {
+    pub error: ErrorDetail,
+}
+
+/// Details of an OpenAI API error
+#[derive(Clone, Debug, Deserialize)]
+pub struct ErrorDetail {
+    /// A human-readable error message
+    pub message: String,
+
+    /// The kind of error, e.g. "invalid_request_error"
+    #[serde(default, rename = "type")]
+    pub kind: Option<String>,
+
+    /// A machine-readable error code, e.g. "moderation_blocked"
+    #[serde(default)]
+    pub code: Option<String>,
+
+    /// The request parameter the error is about, e.g. "size"
+    #[serde(default)]
+    pub param: Option<String>,
+}
+
+impl fmt::Display for ErrorDetail {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.summary() {
+            Some(summary) => write!(f, "{summary}: {}", self.message)?,
+            None => write!(f, "{}", self.message)?,
+        }
+        match &self.param {
+            Some(param) => write!(f, " (parameter: {param})"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ErrorDetail {
+    /// What the error means, in a few words, for the errors people hit
+    /// most.
+    pub fn summary(&self) -> Option<&'static str> {
+        let summary = match self.code.as_deref() {
+            Some("billing_hard_limit_reached") => "Billing hard limit reached",
+            Some("insufficient_quota") => {
+                "Out of quota; check the plan and billing details"
+            }
+            Some("rate_limit_exceeded") => "Rate limit reached",
+            Some("moderation_blocked" | "content_policy_violation") => {
+                "Content policy violation"
+            }
+            Some("invalid_api_key") => "Invalid API key",
+            Some("model_not_found") => "Model not found",
+            _ => match self.kind.as_deref()? {
+                "invalid_request_error" => "Invalid request",
+                "server_error" => "Server error",
+                _ => return None,
+            },
+        };
+        Some(summary)
+    }
+
+    /// Whether the request was rejected by the content policy moderation.
+    pub fn is_moderation_blocked(&self) -> bool {
+        matches!(
+            self.code.as_deref(),
+            Some("moderation_blocked" | "content_policy_violation")
+        )
+    }
+
+    /// The policy categories flagged by the safety system, e.g. "sexual".
+    ///
+    /// These are only reported in the message, as
+    /// `safety_violations=[sexual, violence]`.
+    pub fn safety_violations(&self) -> Vec<&str> {
+        let Some((_, rest)) = self.message.split_once("safety_violations=[")
+        else {
+            return Vec::new();
+        };
+        let list = rest.split(']').next().unwrap_or_default();
+        list.split(',')
+            .map(str::trim)
+            .filter(|category| !category.is_empty())
+            .collect()
+    }
+}
+
+/// Request body for the OpenAI chat completions API
+#[derive(Debug, Serialize)]
+pub struct ChatRequest {
+    /// The chat model to use
+    pub model: String,
+
+    /// The conversation so far
+    pub messages: Vec<ChatMessage>,
+}
+
+/// A single message in a chat conversation
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChatMessage {
+    /// "system", "user", or "assistant"
+    pub role: String,
+
+    /// The message text, or text and images for vision models
+    pub content: ChatContent,
+}
+
+/// The content of a [`ChatMessage`]
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ChatContent {
+    Text(String),
+    Parts(Vec<ChatContentPart>),
+}
+
+/// A piece of multi-part [`ChatContent`]
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatContentPart {
+    Text { text: String },
+    ImageUrl { image_url: ChatImageUrl },
+}
+
+/// An image in a chat message, as a URL or a `data:` URL
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ChatImageUrl {
+    pub url: String,
+}
+
+impl ChatContent {
+    /// An image part, inlined as a base64 `data:` URL
+    pub fn image_part(bytes: &[u8]) -> ChatContentPart {
+        let mime = multipart::mime_from_bytes(bytes);
+        let url =
+            format!("data:{mime};base64,{}", BASE64_STANDARD.encode(bytes));
+        ChatContentPart::ImageUrl {
+            image_url: ChatImageUrl { url },
+        }
+    }
+
+    /// All the text in the content
+    pub fn text(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Parts(parts) => parts
+                .iter()
+                .filter_map(|part| match part {
+                    ChatContentPart::Text { text } => Some(text.as_str()),
+                    ChatContentPart::ImageUrl { .. } => None,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Response from the OpenAI chat completions API
+#[derive(Debug, Deserialize)]
+pub struct ChatResponse {
+    /// The generated completions
+    pub choices: Vec<ChatChoice>,
+}
+
+/// A single completion in a [`ChatResponse`]
+#[derive(Debug, Deserialize)]
+pub struct ChatChoice {
+    /// The generated message
+    pub message: ChatMessage,
+}
+
+/// Request body for the OpenAI Responses API, which we use to generate
+/// images with the image generation tool: in the background (`--detach`),
+/// streamed (`--stream`), or as a follow-up to an earlier response
+/// (`--continue`)
+#[derive(Clone, Debug, Serialize)]
+pub struct ResponsesRequest {
+    /// The mainline model, which calls the image generation tool
+    pub model: String,
+
+    /// The prompt
+    pub input: String,
+
+    /// Just the image generation tool
+    pub tools: Vec<ImageGenerationTool>,
+
+    /// Always call the image generation tool, e.g.
+    /// `{"type": "image_generation"}`
+    pub tool_choice: serde_json::Value,
+
+    /// Run the request in the background, to poll for the result later
+    pub background: bool,
+
+    /// Stream server-sent events as the response is generated (`--stream`)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stream: bool,
+
+    /// Continue the conversation from this response (`--continue`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_response_id: Option<String>,
+}
+
+/// The Responses API image generation tool, with the same options as a
+/// [`CreateRequest`]
+#[derive(Clone, Debug, Serialize)]
+pub struct ImageGenerationTool {
+    /// Always "image_generation"
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub moderation: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_compression: Option<u8>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_format: Option<String>,
+
+    /// How many partial images to stream before the final one (0-3)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub partial_images: Option<u8>,
+}
+
+/// Response from the OpenAI Responses API
+#[derive(Debug, Deserialize)]
+pub struct ResponsesResponse {
+    /// The response ID, to poll a background response with
+    pub id: String,
+
+    /// "queued", "in_progress", "completed", "failed", "cancelled", or
+    /// "incomplete"
+    pub status: String,
+
+    /// The output items, including any generated images
+    #[serde(default)]
+    pub output: Vec<ResponsesOutput>,
+
+    /// Why the response failed, if it did
+    #[serde(default)]
+    pub error: Option<ErrorDetail>,
+}
+
+impl ResponsesResponse {
+    /// Whether the response is still being generated.
+    pub fn is_pending(&self) -> bool {
+        matches!(self.status.as_str(), "queued" | "in_progress")
+    }
+
+    /// The generated images, as from the image generation API.
+    pub fn images(&self) -> Vec<ImageData> {
+        self.output
+            .iter()
+            .filter(|output| output.kind == "image_generation_call")
+            .filter_map(|output| {
+                Some(ImageData {
+                    b64_json: output.result.clone()?,
+                    url: None,
+                    revised_prompt: output.revised_prompt.clone(),
+                    downloaded: None,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A server-sent event from a streaming Responses API request. We only need
+/// a few of the many event types.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum StreamEvent {
+    /// A partial image, rendered part way through generation
+    #[serde(rename = "response.image_generation_call.partial_image")]
+    PartialImage {
+        partial_image_index: u32,
+        partial_image_b64: String,
+    },
+    #[serde(rename = "response.completed")]
+    Completed { response: ResponsesResponse },
+    #[serde(rename = "response.failed")]
+    Failed { response: ResponsesResponse },
+    #[serde(rename = "response.incomplete")]
+    Incomplete { response: ResponsesResponse },
+    /// An error that ends the stream
+    #[serde(rename = "error")]
+    Error { message: String },
+    #[serde(other)]
+    Other,
+}
+
+/// A single output item in a [`ResponsesResponse`]
+#[derive(Debug, Deserialize)]
+pub struct ResponsesOutput {
+    /// The item type, e.g. "image_generation_call" or "message"
+    #[serde(rename = "type")]
+    pub kind: String,
+
+    /// The base64-encoded image, for an image generation call
+    #[serde(default)]
+    pub result: Option<String>,
+
+    /// The prompt the model actually used, for an image generation call
+    #[serde(default)]
+    pub revised_prompt: Option<String>,
+}
+
 /// Decoded image data with raw bytes instead of base64
 #[derive(Debug)]
 pub struct DecodedImageData {
-    /// The raw image bytes decoded from base64
-    pub image_bytes: Vec<u8>,
+    /// The raw image bytes
+    pub image: ImageBytes,
+
+    /// The prompt the model actually used, if it rewrote ours
+    pub revised_prompt: Option<String>,
 }
 
 /// Decoded response with raw image bytes instead of base64
@@ -220,13 +712,51 @@ pub struct DecodedResponse {
     pub usage: Usage,
 }
 
+/// An image's raw bytes: in memory, or spooled to a temporary file with
+/// `--low-memory`.
+#[derive(Debug)]
+pub enum ImageBytes {
+    Memory(Vec<u8>),
+    /// Removed when dropped
+    Spooled(tempfile::TempPath),
+}
+
+impl ImageBytes {
+    /// The whole image, read back in if it was spooled. Only for steps that
+    /// need all of it anyway, like decoding its pixels.
+    pub fn load(&self) -> std::io::Result<Cow<'_, [u8]>> {
+        match self {
+            Self::Memory(bytes) => Ok(Cow::Borrowed(bytes)),
+            Self::Spooled(path) => std::fs::read(path).map(Cow::Owned),
+        }
+    }
+
+    /// Write the image to `out`, a chunk at a time if it was spooled.
+    pub fn write_to(&self, out: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            Self::Memory(bytes) => out.write_all(bytes),
+            Self::Spooled(path) => {
+                std::io::copy(&mut std::fs::File::open(path)?, out).map(drop)
+            }
+        }
+    }
+
+    /// The SHA-256 of the image, as hex.
+    pub fn sha256_hex(&self) -> std::io::Result<String> {
+        match self {
+            Self::Memory(bytes) => Ok(crate::history::sha256_hex(bytes)),
+            Self::Spooled(path) => {
+                crate::history::sha256_hex_reader(std::fs::File::open(path)?)
+            }
+        }
+    }
+}
+
 impl TryFrom<ImageData> for DecodedImageData {
     type Error = base64::DecodeError;
 
     fn try_from(image_data: ImageData) -> Result<Self, Self::Error> {
-        // Decode the base64 string to bytes
-        let image_bytes = BASE64_STANDARD.decode(image_data.b64_json)?;
-        Ok(DecodedImageData { image_bytes })
+        decode_image(image_data).map_err(|(err, _)| err)
     }
 }
 
@@ -248,65 +778,303 @@ impl TryFrom<Response> for DecodedResponse {
     }
 }
 
+impl DecodedResponse {
+    /// Decode each image independently, so one corrupt image doesn't lose
+    /// the rest. Returns the images that decoded, and the (0-based) index,
+    /// error, and raw base64 of each one that didn't.
+    pub fn decode_partial(
+        response: Response,
+    ) -> (Self, Vec<(usize, base64::DecodeError, String)>) {
+        let mut decoded_data = Vec::with_capacity(response.data.len());
+        let mut errors = Vec::new();
+        for (i, image_data) in response.data.into_iter().enumerate() {
+            match decode_image(image_data) {
+                Ok(decoded) => decoded_data.push(decoded),
+                Err((err, b64_json)) => errors.push((i, err, b64_json)),
+            }
+        }
+
+        let decoded = DecodedResponse {
+            created: response.created,
+            data: decoded_data,
+            usage: response.usage,
+        };
+        (decoded, errors)
+    }
+}
+
+/// Decode an image's base64, unless it was downloaded as raw bytes already.
+/// On error, returns the original base64.
+fn decode_image(
+    image_data: ImageData,
+) -> Result<DecodedImageData, (base64::DecodeError, String)> {
+    let image = match image_data.downloaded {
+        Some(image) => image,
+        None => ImageBytes::Memory(decode_in_place(image_data.b64_json)?),
+    };
+    Ok(DecodedImageData {
+        image,
+        revised_prompt: image_data.revised_prompt,
+    })
+}
+
+/// Decode base64 into the string's own buffer, a chunk at a time, so a large
+/// image never needs both its base64 and its decoded bytes in memory. On
+/// error, returns the original base64 (e.g. to rescue it).
+fn decode_in_place(
+    b64: String,
+) -> Result<Vec<u8>, (base64::DecodeError, String)> {
+    // Whole base64 quanta, so only the last chunk can have padding
+    const CHUNK: usize = 4096;
+    let mut buf = b64.into_bytes();
+    let mut scratch = [0u8; CHUNK / 4 * 3];
+    let (mut read, mut written) = (0, 0);
+    while read < buf.len() {
+        let end = (read + CHUNK).min(buf.len());
+        let err = match BASE64_STANDARD
+            .decode_slice(&buf[read..end], &mut scratch)
+        {
+            Ok(len) => {
+                // Decoding shrinks, so this never passes what's still unread
+                buf[written..written + len].copy_from_slice(&scratch[..len]);
+                written += len;
+                read = end;
+                continue;
+            }
+            Err(base64::DecodeSliceError::DecodeError(err)) => err,
+            Err(base64::DecodeSliceError::OutputSliceTooSmall) => {
+                unreachable!("A chunk always fits in the scratch buffer")
+            }
+        };
+        // Report offsets in the whole string, not the chunk
+        let err = match err {
+            base64::DecodeError::InvalidByte(i, byte) => {
+                base64::DecodeError::InvalidByte(read + i, byte)
+            }
+            err => err,
+        };
+        // The chunks decoded so far had no padding, so they re-encode to
+        // exactly the base64 they came from
+        let mut original = BASE64_STANDARD.encode(&buf[..written]);
+        original.push_str(&String::from_utf8_lossy(&buf[read..]));
+        return Err((err, original));
+    }
+    buf.truncate(written);
+    buf.shrink_to_fit();
+    Ok(buf)
+}
+
 impl DecodedImageData {
+    /// Save the image to a new file, failing if the path already exists.
+    fn save_to_new_file(&self, path: &Path) -> std::io::Result<()> {
+        with_retries(|| {
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(path)?;
+            self.image.write_to(&mut file).inspect_err(|_| {
+                // Don't leave a partial file behind to block the retry
+                let _ = std::fs::remove_file(path);
+            })
+        })
+    }
+
     /// Save the image to a file path
     fn save_to_file(&self, path: &Path) -> anyhow::Result<()> {
-        std::fs::write(path, &self.image_bytes)
+        with_retries(|| self.image.write_to(&mut std::fs::File::create(path)?))
             .with_context(|| format!("Failed to write to: {}", path.display()))
     }
 
-    /// Save the image to a file path or stdout
-    fn save_to_file_or_stdout(
+    /// Save the image to stdout, compressing it if asked
+    fn save_to_stdout(
         &self,
-        path: Option<&Path>,
+        compression: Option<Compression>,
     ) -> anyhow::Result<()> {
-        if let Some(path) = path {
-            self.save_to_file(path)
-        } else {
-            // Save to stdout
-            let mut stdout = std::io::stdout().lock();
-            stdout
-                .write_all(&self.image_bytes)
-                .with_context(|| "Failed to write to stdout")?;
-            stdout.flush()?;
-            Ok(())
+        let mut stdout = std::io::stdout().lock();
+        match compression {
+            Some(compression) => {
+                let compressed = compression.compress(&self.image.load()?)?;
+                stdout.write_all(&compressed)
+            }
+            None => self.image.write_to(&mut stdout),
         }
+        .with_context(|| "Failed to write to stdout")?;
+        stdout.flush()?;
+        Ok(())
     }
+
+    /// After saving to `path` failed with `err`, save the image to
+    /// `fallback_dir` instead, so an image that was already paid for isn't
+    /// lost.
+    fn save_to_fallback(
+        &self,
+        err: anyhow::Error,
+        path: &Path,
+        fallback_dir: &Path,
+    ) -> anyhow::Result<PathBuf> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "image.png".to_string());
+        let result = std::fs::create_dir_all(fallback_dir).and_then(|()| {
+            // Never overwrite an earlier fallback
+            let mut k = 0;
+            loop {
+                let fallback = match k {
+                    0 => fallback_dir.join(&name),
+                    k => fallback_dir.join(format!("{k}-{name}")),
+                };
+                match self.save_to_new_file(&fallback) {
+                    Ok(()) => return Ok(fallback),
+                    Err(err)
+                        if err.kind() == std::io::ErrorKind::AlreadyExists =>
+                    {
+                        k += 1
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+        });
+        match result {
+            Ok(fallback) => {
+                warn!("{err:#}; saved it to {} instead", fallback.display());
+                Ok(fallback)
+            }
+            Err(fallback_err) => Err(err.context(format!(
+                "Saving to {} instead failed too: {fallback_err}",
+                fallback_dir.display()
+            ))),
+        }
+    }
+}
+
+/// How many times to retry a failed image write, for transient errors like
+/// a hiccup on a network filesystem.
+const SAVE_RETRIES: u32 = 2;
+
+/// Run `write`, retrying on any error except the file already existing.
+fn with_retries(
+    mut write: impl FnMut() -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut attempt = 0;
+    loop {
+        match write() {
+            Err(err)
+                if err.kind() != std::io::ErrorKind::AlreadyExists
+                    && attempt < SAVE_RETRIES =>
+            {
+                attempt += 1;
+                debug!(
+                    "Write failed ({err}); retrying ({attempt}/{SAVE_RETRIES})"
+                );
+                std::thread::sleep(Duration::from_millis(100 * attempt as u64));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// The automatic name for the `i`-th (0-based) image of a run:
+/// `<prefix>.<timestamp>.<i>.<ext>`, with a `-<run>` suffix on the
+/// timestamp for later runs that would otherwise collide.
+fn automatic_path(
+    prefix: &str,
+    timestamp: &str,
+    run: usize,
+    i: usize,
+    ext: &str,
+) -> PathBuf {
+    let stamp = match run {
+        0 => timestamp.to_string(),
+        run => format!("{timestamp}-{run}"),
+    };
+    PathBuf::from(format!("{prefix}.{stamp}.{}.{ext}", i + 1))
 }
 
 impl DecodedResponse {
     /// Save image(s) to the specified output target.
     ///
-    /// Returns a list of paths to the saved files. Returns an empty list if
-    /// writing to stdout.
+    /// Returns the path each image was saved to, or why it couldn't be. Each
+    /// image is saved even if an earlier one fails, so we keep as many of
+    /// the images we paid for as possible. Returns an empty list if writing
+    /// to stdout.
+    ///
+    /// `existing` has a previously saved, identical copy of each image, if
+    /// any. Automatically named images aren't saved again, and an `--output`
+    /// file is hard-linked to its copy.
     pub fn save_images(
         &self,
         out_target: input::OutputTargetWithData<'_>,
-    ) -> anyhow::Result<Vec<PathBuf>> {
+        existing: &[Option<PathBuf>],
+        fallback_dir: &Path,
+    ) -> anyhow::Result<Vec<anyhow::Result<PathBuf>>> {
         use input::OutputTargetWithData::*;
 
         match out_target {
-            Automatic { prefix, extension } => {
-                // Write to files with a prefix and extension
-                let mut paths = Vec::with_capacity(self.data.len());
+            Automatic {
+                prefix,
+                extension,
+                filenames,
+            } => {
+                // Ensure the extension doesn't start with a dot
+                let ext = extension.trim_start_matches('.');
+                let stamp = sanitize::timestamp(self.created, filenames);
+                let path_for = |run: usize, i: usize| {
+                    automatic_path(&prefix, &stamp, run, i, ext)
+                };
+
+                // The timestamp only has second resolution, so another run with
+                // the same prompt may have used these names. Pick a run
+                // suffix that's free for all the images.
+                let n = self.data.len();
+                let mut run = (0..)
+                    .find(|&run| (0..n).all(|i| !path_for(run, i).exists()))
+                    .expect("Some run suffix is free");
+
+                // Write to files with a prefix and extension, never
+                // overwriting one created in the meantime
+                let mut paths = Vec::with_capacity(n);
                 for (i, image) in self.data.iter().enumerate() {
-                    // Ensure the extension doesn't start with a dot
-                    let ext = extension.trim_start_matches('.');
-                    let filename = format!(
-                        "{}.{}.{}.{}",
-                        prefix,
-                        self.created,
-                        i + 1,
-                        ext
-                    );
-                    let path = PathBuf::from(filename);
-                    image.save_to_file(&path)?;
-                    paths.push(path);
+                    if let Some(Some(existing)) = existing.get(i) {
+                        info!(
+                            "Image {} is identical to {}; not saving it again \
+                             (use --allow-duplicates to keep both)",
+                            i + 1,
+                            existing.display()
+                        );
+                        paths.push(Ok(existing.clone()));
+                        continue;
+                    }
+                    let result = loop {
+                        let path = path_for(run, i);
+                        match image.save_to_new_file(&path) {
+                            Ok(()) => break Ok(path),
+                            Err(err)
+                                if err.kind()
+                                    == std::io::ErrorKind::AlreadyExists =>
+                            {
+                                run += 1
+                            }
+                            Err(err) => {
+                                let err =
+                                    anyhow::Error::new(err).context(format!(
+                                        "Failed to write to: {}",
+                                        path.display()
+                                    ));
+                                break image.save_to_fallback(
+                                    err,
+                                    &path,
+                                    fallback_dir,
+                                );
+                            }
+                        }
+                    };
+                    paths.push(result);
                 }
                 Ok(paths)
             }
             // Write a single output image to a file or stdout
-            File(_) | Stdout => {
+            File(_) | Stdout(_) => {
                 let image_data = match self.data.as_slice() {
                     [image] => image,
                     [image, ..] => {
@@ -321,13 +1089,43 @@ impl DecodedResponse {
                 };
 
                 let path = out_target.file_path();
-                image_data.save_to_file_or_stdout(path)?;
-
-                let paths = match path {
-                    Some(path) => vec![PathBuf::from(path)],
-                    None => vec![],
+                let linked = match (path, existing.first()) {
+                    (Some(path), Some(Some(existing))) => {
+                        let linked = std::fs::hard_link(existing, path).is_ok();
+                        linked.then_some(existing)
+                    }
+                    _ => None,
                 };
-                Ok(paths)
+                let saved = match (path, linked) {
+                    (Some(path), Some(existing)) => {
+                        info!(
+                            "The image is identical to {}; hard-linked it \
+                             instead of saving a copy",
+                            existing.display()
+                        );
+                        Some(path.to_path_buf())
+                    }
+                    (Some(path), None) => {
+                        let saved = match image_data.save_to_file(path) {
+                            Ok(()) => path.to_path_buf(),
+                            Err(err) => image_data.save_to_fallback(
+                                err,
+                                path,
+                                fallback_dir,
+                            )?,
+                        };
+                        Some(saved)
+                    }
+                    (None, _) => {
+                        let compression = match out_target {
+                            Stdout(compression) => compression,
+                            _ => None,
+                        };
+                        image_data.save_to_stdout(compression)?;
+                        None
+                    }
+                };
+                Ok(saved.into_iter().map(Ok).collect())
             }
         }
     }
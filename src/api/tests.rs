@@ -1,6 +1,6 @@
 use super::*;
 use serde_json::json;
-use std::path::PathBuf;
+use std::{io::Read, path::PathBuf};
 
 #[test]
 fn test_parse_response() {
@@ -30,11 +30,40 @@ fn test_parse_response() {
     assert_eq!(resp.created, 1713833628);
     assert_eq!(resp.data.len(), 1);
     assert_eq!(resp.data[0].b64_json, "base64_encoded_image_data");
+    assert_eq!(resp.data[0].revised_prompt, None);
     assert_eq!(resp.usage.total_tokens, 100);
     assert_eq!(resp.usage.input_tokens, 50);
     assert_eq!(resp.usage.output_tokens, 50);
     assert_eq!(resp.usage.input_tokens_details.text_tokens, 10);
     assert_eq!(resp.usage.input_tokens_details.image_tokens, 40);
+    assert!(serde_json::from_str::<StrictResponse>(json_response).is_ok());
+}
+
+#[test]
+fn test_parse_response_lenient() {
+    // Some OpenAI-compatible servers leave out `usage` and `created`, or add
+    // their own fields
+    let json_response = r#"{
+        "data": [{ "b64_json": "base64_encoded_image_data", "seed": 42 }],
+        "model": "flux-dev"
+    }"#;
+    let resp: Response = serde_json::from_str(json_response).unwrap();
+    assert!(resp.created > 0);
+    assert_eq!(resp.data.len(), 1);
+    assert_eq!(resp.usage.total_tokens, 0);
+    assert!(serde_json::from_str::<StrictResponse>(json_response).is_err());
+
+    // Partial usage counts what's there
+    let json_response = r#"{
+        "created": 1713833628,
+        "data": [],
+        "usage": { "total_tokens": 100, "input_tokens": 50,
+                   "output_tokens": 50 }
+    }"#;
+    let resp: Response = serde_json::from_str(json_response).unwrap();
+    assert_eq!(resp.usage.output_tokens, 50);
+    assert_eq!(resp.usage.input_tokens_details.image_tokens, 0);
+    assert!(serde_json::from_str::<StrictResponse>(json_response).is_err());
 }
 
 #[test]
@@ -50,6 +79,8 @@ fn test_create_request_serialization() {
         moderation: None,
         output_compression: None,
         output_format: None,
+        style: None,
+        response_format: None,
     };
 
     // Serialize to JSON
@@ -77,6 +108,9 @@ fn test_decode_response() {
         created: 1713833628,
         data: vec![ImageData {
             b64_json: b64_data.to_string(),
+            url: None,
+            revised_prompt: Some("A revised prompt".to_string()),
+            downloaded: None,
         }],
         usage: Usage {
             total_tokens: 100,
@@ -94,11 +128,88 @@ fn test_decode_response() {
 
     // Check that the data was decoded correctly
     assert_eq!(decoded.data.len(), 1);
-    assert_eq!(decoded.data[0].image_bytes, b"test");
+    assert_eq!(&*decoded.data[0].image.load().unwrap(), b"test");
+    assert_eq!(
+        decoded.data[0].revised_prompt.as_deref(),
+        Some("A revised prompt")
+    );
     assert_eq!(decoded.created, 1713833628);
     assert_eq!(decoded.usage.total_tokens, 100);
 }
 
+#[test]
+fn test_decode_response_partial() {
+    let image = |b64_json: &str| ImageData {
+        b64_json: b64_json.to_string(),
+        url: None,
+        revised_prompt: None,
+        downloaded: None,
+    };
+    let response = Response {
+        created: 1713833628,
+        data: vec![image("dGVzdA=="), image("not base64!"), image("b2s=")],
+        usage: Usage {
+            total_tokens: 100,
+            input_tokens: 50,
+            output_tokens: 50,
+            input_tokens_details: InputTokensDetails {
+                text_tokens: 10,
+                image_tokens: 40,
+            },
+        },
+    };
+
+    // The corrupt image is reported, and the others kept
+    let (decoded, errors) = DecodedResponse::decode_partial(response);
+    assert_eq!(decoded.data.len(), 2);
+    assert_eq!(&*decoded.data[0].image.load().unwrap(), b"test");
+    assert_eq!(&*decoded.data[1].image.load().unwrap(), b"ok");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, 1);
+    assert_eq!(errors[0].2, "not base64!");
+}
+
+#[test]
+fn test_save_images_unique_names() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let prefix = temp_dir.path().join("cat").display().to_string();
+    let filenames = crate::config::Filenames::default();
+    let image = |bytes: &[u8]| DecodedImageData {
+        image: ImageBytes::Memory(bytes.to_vec()),
+        revised_prompt: None,
+    };
+    let decoded = DecodedResponse {
+        created: 1713833628,
+        data: vec![image(b"one"), image(b"two")],
+        usage: serde_json::from_value(json!({
+            "total_tokens": 2, "input_tokens": 1, "output_tokens": 1,
+            "input_tokens_details": {"text_tokens": 1, "image_tokens": 0}
+        }))
+        .unwrap(),
+    };
+    let save = || {
+        let target = input::OutputTargetWithData::Automatic {
+            prefix: prefix.clone(),
+            extension: "png",
+            filenames: &filenames,
+        };
+        decoded
+            .save_images(target, &[], temp_dir.path())
+            .unwrap()
+            .into_iter()
+            .map(|path| path.unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // Saving the same run again in the same second doesn't overwrite it
+    let first = save();
+    let second = save();
+    assert_eq!(first[0], temp_dir.path().join("cat.1713833628.1.png"));
+    assert_eq!(second[0], temp_dir.path().join("cat.1713833628-1.1.png"));
+    assert_eq!(second[1], temp_dir.path().join("cat.1713833628-1.2.png"));
+    assert_eq!(std::fs::read(&first[1]).unwrap(), b"two");
+}
+
 #[test]
 fn test_edit_request_build_multipart() {
     let input_image = input::ImageData {
@@ -116,12 +227,17 @@ fn test_edit_request_build_multipart() {
     // Create an EditRequest
     let request = EditRequest {
         images: vec![input_image.clone()],
+        references: Vec::new(),
+        style_ref: None,
         prompt: "A test edit prompt".to_string(),
         mask: Some(input_mask.clone()),
         model: "gpt-image-1".to_string(),
         n: Some(2),
         quality: Some("high".to_string()),
         size: Some("1024x1024".to_string()),
+        input_fidelity: Some("high".to_string()),
+        output_compression: Some(80),
+        output_format: Some("webp".to_string()),
     };
 
     // Build the multipart body
@@ -133,7 +249,9 @@ fn test_edit_request_build_multipart() {
     // Let's go with option (b) as it tests the production code path more closely.
 
     let boundary = "----12345";
-    let multipart_body = request.build_multipart_inner(boundary.to_owned());
+    let mut multipart_body = request.build_multipart_inner(boundary.to_owned());
+    let mut body = Vec::new();
+    multipart_body.read_to_end(&mut body).unwrap();
 
     // Extract the boundary from the content type
     let content_type = multipart_body.content_type;
@@ -144,7 +262,7 @@ fn test_edit_request_build_multipart() {
         .expect("Boundary not found in Content-Type");
 
     // Convert body bytes to string for comparison (lossy for file content)
-    let body_str = String::from_utf8_lossy(&multipart_body.body);
+    let body_str = String::from_utf8_lossy(&body);
 
     // Construct the expected body string using the extracted boundary
     let image_filename = input_image.filename.display();
@@ -168,6 +286,15 @@ fn test_edit_request_build_multipart() {
          Content-Disposition: form-data; name=\"size\"\r\n\r\n\
          1024x1024\r\n\
          --{boundary}\r\n\
+         Content-Disposition: form-data; name=\"input_fidelity\"\r\n\r\n\
+         high\r\n\
+         --{boundary}\r\n\
+         Content-Disposition: form-data; name=\"output_compression\"\r\n\r\n\
+         80\r\n\
+         --{boundary}\r\n\
+         Content-Disposition: form-data; name=\"output_format\"\r\n\r\n\
+         webp\r\n\
+         --{boundary}\r\n\
          Content-Disposition: form-data; name=\"image[]\"; filename=\"{image_filename}\"\r\n\
          Content-Type: image/jpeg\r\n\r\n\
          {image_content}\r\n\
@@ -181,3 +308,300 @@ fn test_edit_request_build_multipart() {
     // Compare the generated body with the expected body
     assert_eq!(body_str, expected_body);
 }
+
+#[test]
+fn test_edit_request_image_order() {
+    let image = |name: &str| input::ImageData {
+        bytes: name.as_bytes().to_vec(),
+        filename: PathBuf::from(name),
+        content_type: "image/png",
+    };
+    let request = EditRequest {
+        images: vec![image("edit.png")],
+        references: vec![image("product.png"), image("logo.png")],
+        style_ref: Some(image("style.png")),
+        prompt: "The product on a desk".to_string(),
+        mask: Some(image("mask.png")),
+        model: "gpt-image-1".to_string(),
+        n: None,
+        quality: None,
+        size: None,
+        input_fidelity: None,
+        output_compression: None,
+        output_format: None,
+    };
+    let mut body = Vec::new();
+    request
+        .build_multipart_inner("----12345".to_owned())
+        .read_to_end(&mut body)
+        .unwrap();
+    let body = String::from_utf8_lossy(&body);
+
+    // The prompt calls the style reference the last input image
+    let fields: Vec<_> = body
+        .match_indices("; filename=\"")
+        .map(|(i, _)| {
+            let start = body[..i].rfind("name=\"").unwrap() + 6;
+            let end = body[i..].find("\"\r\n").unwrap() + i;
+            (&body[start..i - 1], &body[i + 12..end])
+        })
+        .collect();
+    assert_eq!(
+        fields,
+        [
+            ("image[]", "edit.png"),
+            ("image[]", "product.png"),
+            ("image[]", "logo.png"),
+            ("image[]", "style.png"),
+            ("mask", "mask.png"),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_moderation_error() {
+    let json_error = r#"{
+        "error": {
+            "message": "Your request was rejected as a result of our safety system. Your request may contain content that is not allowed by our safety system. safety_violations=[sexual, violence].",
+            "type": "image_generation_user_error",
+            "param": null,
+            "code": "moderation_blocked"
+        }
+    }"#;
+    let resp: ErrorResponse = serde_json::from_str(json_error).unwrap();
+    assert!(resp.error.is_moderation_blocked());
+    assert_eq!(resp.error.safety_violations(), ["sexual", "violence"]);
+
+    let json_error = r#"{
+        "error": {
+            "message": "Invalid value: 'huge'.",
+            "type": "invalid_request_error",
+            "param": "size",
+            "code": "invalid_value"
+        }
+    }"#;
+    let resp: ErrorResponse = serde_json::from_str(json_error).unwrap();
+    assert!(!resp.error.is_moderation_blocked());
+    assert!(resp.error.safety_violations().is_empty());
+    assert_eq!(
+        resp.error.to_string(),
+        "Invalid request: Invalid value: 'huge'. (parameter: size)"
+    );
+
+    let json_error = r#"{
+        "error": {
+            "message": "Billing hard limit has been reached",
+            "type": "invalid_request_error",
+            "param": null,
+            "code": "billing_hard_limit_reached"
+        }
+    }"#;
+    let resp: ErrorResponse = serde_json::from_str(json_error).unwrap();
+    assert_eq!(
+        resp.error.to_string(),
+        "Billing hard limit reached: Billing hard limit has been reached"
+    );
+}
+
+#[test]
+fn test_chat_vision_message() {
+    let message = ChatMessage {
+        role: "user".to_string(),
+        content: ChatContent::Parts(vec![
+            ChatContentPart::Text {
+                text: "Describe this".to_string(),
+            },
+            ChatContent::image_part(b"\x89PNG\r\n\x1a\n"),
+        ]),
+    };
+    assert_eq!(
+        serde_json::to_value(&message).unwrap(),
+        json!({
+            "role": "user",
+            "content": [
+                { "type": "text", "text": "Describe this" },
+                {
+                    "type": "image_url",
+                    "image_url": { "url": "data:image/png;base64,iVBORw0KGgo=" }
+                },
+            ],
+        })
+    );
+
+    // Responses are plain text
+    let json = r#"{ "role": "assistant", "content": "A cat." }"#;
+    let message: ChatMessage = serde_json::from_str(json).unwrap();
+    assert_eq!(message.content.text(), "A cat.");
+}
+
+#[test]
+fn test_save_images_fallback() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let fallback_dir = temp_dir.path().join("fallback");
+    let decoded = DecodedResponse {
+        created: 1713833628,
+        data: vec![DecodedImageData {
+            image: ImageBytes::Memory(b"paid for".to_vec()),
+            revised_prompt: None,
+        }],
+        usage: serde_json::from_value(json!({
+            "total_tokens": 2, "input_tokens": 1, "output_tokens": 1,
+            "input_tokens_details": {"text_tokens": 1, "image_tokens": 0}
+        }))
+        .unwrap(),
+    };
+
+    // The output directory doesn't exist, so the image lands in the fallback
+    // directory, without overwriting an earlier one
+    let path = temp_dir.path().join("missing").join("cat.png");
+    let save = || {
+        let target = input::OutputTargetWithData::File(&path);
+        decoded.save_images(target, &[], &fallback_dir).unwrap()[0]
+            .as_ref()
+            .unwrap()
+            .clone()
+    };
+    assert_eq!(save(), fallback_dir.join("cat.png"));
+    assert_eq!(save(), fallback_dir.join("1-cat.png"));
+    assert_eq!(
+        std::fs::read(fallback_dir.join("cat.png")).unwrap(),
+        b"paid for"
+    );
+}
+
+#[test]
+fn test_parse_responses_response() {
+    let json_response = r#"{
+        "id": "resp_123",
+        "object": "response",
+        "status": "completed",
+        "output": [
+            {
+                "type": "image_generation_call",
+                "id": "ig_123",
+                "status": "completed",
+                "revised_prompt": "A red fox in fresh snow",
+                "result": "aGVsbG8="
+            },
+            {
+                "type": "message",
+                "id": "msg_123",
+                "content": []
+            }
+        ]
+    }"#;
+    let resp: ResponsesResponse = serde_json::from_str(json_response).unwrap();
+    assert!(!resp.is_pending());
+    let images = resp.images();
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].b64_json, "aGVsbG8=");
+    assert_eq!(
+        images[0].revised_prompt.as_deref(),
+        Some("A red fox in fresh snow")
+    );
+
+    let queued: ResponsesResponse =
+        serde_json::from_str(r#"{"id": "resp_123", "status": "queued"}"#)
+            .unwrap();
+    assert!(queued.is_pending());
+    assert!(queued.images().is_empty());
+}
+
+#[test]
+fn test_parse_stream_event() {
+    let event: StreamEvent = serde_json::from_str(
+        r#"{
+            "type": "response.image_generation_call.partial_image",
+            "output_index": 0,
+            "item_id": "ig_123",
+            "sequence_number": 3,
+            "partial_image_index": 1,
+            "partial_image_b64": "aGVsbG8="
+        }"#,
+    )
+    .unwrap();
+    assert!(matches!(
+        event,
+        StreamEvent::PartialImage {
+            partial_image_index: 1,
+            ref partial_image_b64,
+        } if partial_image_b64 == "aGVsbG8="
+    ));
+
+    let event: StreamEvent = serde_json::from_str(
+        r#"{
+            "type": "response.completed",
+            "sequence_number": 9,
+            "response": {"id": "resp_123", "status": "completed", "output": []}
+        }"#,
+    )
+    .unwrap();
+    assert!(matches!(event, StreamEvent::Completed { .. }));
+
+    // Events we don't use still parse
+    let event: StreamEvent = serde_json::from_str(
+        r#"{"type": "response.in_progress", "sequence_number": 1}"#,
+    )
+    .unwrap();
+    assert!(matches!(event, StreamEvent::Other));
+}
+
+#[test]
+fn test_variation_request_build_multipart() {
+    let request = VariationRequest {
+        image: input::ImageData {
+            bytes: b"dummy image".to_vec(),
+            filename: PathBuf::from("photo.png"),
+            content_type: "image/png",
+        },
+        model: "dall-e-2".to_string(),
+        n: Some(3),
+        size: Some("512x512".to_string()),
+        response_format: Some("b64_json".to_string()),
+    };
+    let mut multipart_body =
+        request.build_multipart_inner("----12345".to_owned());
+    let mut body = Vec::new();
+    multipart_body.read_to_end(&mut body).unwrap();
+    assert_eq!(
+        multipart_body.content_type,
+        "multipart/form-data; boundary=----12345"
+    );
+
+    let expected_body = "------12345\r\n\
+         Content-Disposition: form-data; name=\"model\"\r\n\r\n\
+         dall-e-2\r\n\
+         ------12345\r\n\
+         Content-Disposition: form-data; name=\"n\"\r\n\r\n\
+         3\r\n\
+         ------12345\r\n\
+         Content-Disposition: form-data; name=\"size\"\r\n\r\n\
+         512x512\r\n\
+         ------12345\r\n\
+         Content-Disposition: form-data; name=\"response_format\"\r\n\r\n\
+         b64_json\r\n\
+         ------12345\r\n\
+         Content-Disposition: form-data; name=\"image\"; filename=\"photo.png\"\r\n\
+         Content-Type: image/png\r\n\r\n\
+         dummy image\r\n\
+         ------12345--\r\n";
+    assert_eq!(String::from_utf8_lossy(&body), expected_body);
+}
+
+#[test]
+fn test_decode_in_place() {
+    // Several chunks, ending with padding
+    let bytes: Vec<u8> = (0..10_000u32).map(|i| (i * 7 % 251) as u8).collect();
+    let b64 = BASE64_STANDARD.encode(&bytes);
+    assert!(b64.ends_with('='));
+    assert_eq!(decode_in_place(b64.clone()).unwrap(), bytes);
+    assert_eq!(decode_in_place(String::new()).unwrap(), b"");
+
+    // A bad byte past the first chunk gives back the original base64
+    let mut corrupt = b64.into_bytes();
+    corrupt[5000] = b'!';
+    let corrupt = String::from_utf8(corrupt).unwrap();
+    let (err, original) = decode_in_place(corrupt.clone()).unwrap_err();
+    assert_eq!(err, base64::DecodeError::InvalidByte(5000, b'!'));
+    assert_eq!(original, corrupt);
+}
@@ -0,0 +1,199 @@
+//! An append-only, hash-chained audit log of API calls, for organizations
+//! that need a record of who generated what. Enabled by setting "audit_log"
+//! to a file path in the config file.
+//!
+//! Unlike the history, the audit log records every API call, including
+//! failed ones, and can't be quietly edited: each entry holds the SHA-256 of
+//! the line before it, so changing or removing an entry breaks the chain
+//! (see `imgen audit verify`). To stop users from truncating the whole file,
+//! keep it somewhere only appendable to them (e.g. `chattr +a`).
+
+use anyhow::{bail, Context};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::history::sha256_hex;
+
+/// The `prev` hash of the first entry.
+const GENESIS: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// A single API call in the audit log.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Entry {
+    /// The entry's position in the log, from 1
+    pub seq: u64,
+    /// The Unix timestamp (in seconds) of the call
+    pub time: u64,
+    /// Who ran it (`$USER`)
+    pub user: String,
+    /// The API endpoint, e.g. "images/generations"
+    pub endpoint: String,
+    /// The request parameters. Image inputs are summarized by their hash.
+    pub params: serde_json::Value,
+    /// "ok", or the error
+    pub status: String,
+    /// The cost of the call in USD, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+    /// The SHA-256 of the previous line, or all zeros for the first entry
+    pub prev: String,
+}
+
+/// The audit log file.
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    path: PathBuf,
+}
+
+impl AuditLog {
+    /// Check that the log can be appended to, so a misconfigured log fails
+    /// before anything is generated.
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        open_file(&path)?;
+        Ok(Self { path })
+    }
+
+    /// Append an entry for an API call.
+    pub fn append(
+        &self,
+        endpoint: &str,
+        params: serde_json::Value,
+        status: String,
+        cost: Option<f64>,
+    ) -> anyhow::Result<()> {
+        let mut file = open_file(&self.path)?;
+        // Other imgen processes may be appending too
+        file.lock()?;
+
+        let (seq, prev) = match last_line(&mut file)? {
+            Some(line) => {
+                let last: Entry = serde_json::from_str(&line)
+                    .context("The last audit log entry is malformed")?;
+                (last.seq + 1, sha256_hex(line.as_bytes()))
+            }
+            None => (1, GENESIS.to_string()),
+        };
+        let entry = Entry {
+            seq,
+            time: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+            user: current_user(),
+            endpoint: endpoint.to_string(),
+            params,
+            status,
+            cost,
+            prev,
+        };
+        let mut line =
+            serde_json::to_string(&entry).expect("Failed to serialize entry");
+        line.push('\n');
+        file.write_all(line.as_bytes()).with_context(|| {
+            format!("Failed to write to: {}", self.path.display())
+        })?;
+        debug!("Recorded {endpoint} call in the audit log");
+        Ok(())
+    }
+}
+
+fn open_file(path: &Path) -> anyhow::Result<fs::File> {
+    fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| {
+            format!("Failed to open audit log: {}", path.display())
+        })
+}
+
+/// The last line of the file, without its newline.
+fn last_line(file: &mut fs::File) -> anyhow::Result<Option<String>> {
+    let mut contents = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut contents)?;
+    Ok(contents.lines().last().map(str::to_string))
+}
+
+fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Check the hash chain of the log at `path`, returning the number of
+/// entries.
+pub fn verify(path: &Path) -> anyhow::Result<u64> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read: {}", path.display()))?;
+    verify_contents(&contents)
+}
+
+fn verify_contents(contents: &str) -> anyhow::Result<u64> {
+    let mut prev = GENESIS.to_string();
+    let mut count = 0;
+    for (i, line) in contents.lines().enumerate() {
+        let line_no = i + 1;
+        let entry: Entry = serde_json::from_str(line)
+            .with_context(|| format!("Malformed entry on line {line_no}"))?;
+        if entry.prev != prev {
+            bail!(
+                "The chain is broken at line {line_no} (seq {}): the entry \
+                 before it was changed or removed",
+                entry.seq
+            );
+        }
+        if entry.seq != count + 1 {
+            bail!(
+                "Expected seq {} on line {line_no}, found {}",
+                count + 1,
+                entry.seq
+            );
+        }
+        prev = sha256_hex(line.as_bytes());
+        count += 1;
+    }
+    Ok(count)
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_and_verify() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("logs").join("audit.jsonl");
+        let log = AuditLog::open(path.clone()).unwrap();
+        for i in 0..3 {
+            let params = serde_json::json!({ "prompt": format!("cat {i}") });
+            log.append("images/generations", params, "ok".into(), Some(0.04))
+                .unwrap();
+        }
+        assert_eq!(verify(&path).unwrap(), 3);
+
+        // Editing an earlier entry breaks the chain
+        let contents = fs::read_to_string(&path).unwrap();
+        let edited = contents.replacen("cat 1", "dog 1", 1);
+        let err = verify_contents(&edited).unwrap_err();
+        assert!(err.to_string().contains("line 3"), "{err}");
+
+        // So does removing one
+        let lines: Vec<_> = contents.lines().collect();
+        let removed = format!("{}\n{}\n", lines[0], lines[2]);
+        assert!(verify_contents(&removed).is_err());
+    }
+}
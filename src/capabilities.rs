@@ -0,0 +1,494 @@
+//! Per-model capabilities, so a request a model can't serve fails before
+//! it's sent (or is emulated locally), rather than failing at the API or
+//! being silently ignored.
+
+use anyhow::bail;
+
+use crate::{
+    api::{CreateRequest, EditRequest},
+    client,
+    tokens::PromptLimit,
+};
+
+/// What a single image model supports.
+#[derive(Debug)]
+pub struct ModelCapabilities {
+    /// The model name, as sent to the API
+    pub model: &'static str,
+    /// Editing `--image` inputs
+    pub edit: bool,
+    /// An edit `--mask`
+    pub mask: bool,
+    /// An edit `--input-fidelity`
+    pub input_fidelity: bool,
+    /// Edit `--image-ref` inputs, besides the image being edited
+    pub reference_images: bool,
+    /// `--background transparent`
+    pub transparent_background: bool,
+    /// The most images per request. More are sent as parallel requests.
+    pub max_n: u8,
+    /// The `--size` values it accepts, besides "auto"
+    pub sizes: &'static [&'static str],
+    /// The sizes `--size landscape` and `--size portrait` stand for, if any
+    pub landscape: Option<&'static str>,
+    pub portrait: Option<&'static str>,
+    /// The `--quality` values it accepts, besides "auto"
+    pub qualities: &'static [&'static str],
+    /// The gpt-image output options: `--background`, `--moderation`,
+    /// `--output-format`, and `--output-compression`
+    pub output_options: bool,
+    /// `--dalle-style`
+    pub style: bool,
+    /// Returns image URLs unless asked for base64 with `response_format`
+    pub response_format: bool,
+    /// The longest prompt it accepts
+    pub max_prompt: PromptLimit,
+}
+
+const GPT_IMAGE_SIZES: &[&str] = &["1024x1024", "1536x1024", "1024x1536"];
+const GPT_IMAGE_QUALITIES: &[&str] = &["low", "medium", "high"];
+
+/// Known model capabilities. Unknown models (e.g. on OpenAI-compatible
+/// servers) aren't checked.
+pub const MODELS: &[ModelCapabilities] = &[
+    ModelCapabilities {
+        model: "gpt-image-1",
+        edit: true,
+        mask: true,
+        input_fidelity: true,
+        reference_images: true,
+        transparent_background: true,
+        max_n: 10,
+        sizes: GPT_IMAGE_SIZES,
+        landscape: Some("1536x1024"),
+        portrait: Some("1024x1536"),
+        qualities: GPT_IMAGE_QUALITIES,
+        output_options: true,
+        style: false,
+        response_format: false,
+        max_prompt: PromptLimit::Chars(32_000),
+    },
+    ModelCapabilities {
+        model: "gpt-image-1-mini",
+        edit: true,
+        mask: true,
+        input_fidelity: false,
+        reference_images: true,
+        transparent_background: true,
+        max_n: 10,
+        sizes: GPT_IMAGE_SIZES,
+        landscape: Some("1536x1024"),
+        portrait: Some("1024x1536"),
+        qualities: GPT_IMAGE_QUALITIES,
+        output_options: true,
+        style: false,
+        response_format: false,
+        max_prompt: PromptLimit::Chars(32_000),
+    },
+    ModelCapabilities {
+        model: "dall-e-3",
+        edit: false,
+        mask: false,
+        input_fidelity: false,
+        reference_images: false,
+        transparent_background: false,
+        max_n: 1,
+        sizes: &["1024x1024", "1792x1024", "1024x1792"],
+        landscape: Some("1792x1024"),
+        portrait: Some("1024x1792"),
+        qualities: &["standard", "hd"],
+        output_options: false,
+        style: true,
+        response_format: true,
+        max_prompt: PromptLimit::Chars(4_000),
+    },
+    ModelCapabilities {
+        model: "dall-e-2",
+        edit: true,
+        mask: true,
+        input_fidelity: false,
+        reference_images: false,
+        transparent_background: false,
+        max_n: 10,
+        sizes: &["256x256", "512x512", "1024x1024"],
+        landscape: None,
+        portrait: None,
+        qualities: &["standard"],
+        output_options: false,
+        style: false,
+        response_format: true,
+        max_prompt: PromptLimit::Chars(1_000),
+    },
+    ModelCapabilities {
+        model: "stable-image-core",
+        ..STABILITY
+    },
+    ModelCapabilities {
+        model: "stable-image-ultra",
+        ..STABILITY
+    },
+    ModelCapabilities {
+        model: "sd3.5-large",
+        ..STABILITY
+    },
+    ModelCapabilities {
+        model: "sd3.5-large-turbo",
+        ..STABILITY
+    },
+    ModelCapabilities {
+        model: "sd3.5-medium",
+        ..STABILITY
+    },
+    ModelCapabilities {
+        model: "imagen-4.0-generate-001",
+        ..IMAGEN
+    },
+    ModelCapabilities {
+        model: "imagen-4.0-ultra-generate-001",
+        max_n: 1,
+        ..IMAGEN
+    },
+    ModelCapabilities {
+        model: "imagen-4.0-fast-generate-001",
+        qualities: &[],
+        ..IMAGEN
+    },
+    ModelCapabilities {
+        model: "imagen-3.0-generate-002",
+        qualities: &[],
+        ..IMAGEN
+    },
+];
+
+/// Stability AI's models (`--provider stability`) all work alike: one png
+/// per request, with `--size` mapped to an aspect ratio.
+const STABILITY: ModelCapabilities = ModelCapabilities {
+    model: "",
+    edit: true,
+    mask: true,
+    input_fidelity: false,
+    reference_images: false,
+    transparent_background: false,
+    max_n: 1,
+    sizes: GPT_IMAGE_SIZES,
+    landscape: Some("1536x1024"),
+    portrait: Some("1024x1536"),
+    qualities: &[],
+    output_options: false,
+    style: false,
+    response_format: false,
+    max_prompt: PromptLimit::Chars(10_000),
+};
+
+/// Google's Imagen models (`--provider gemini`) take an aspect ratio, which
+/// we list as its 1K size. `--quality high` makes 2K images.
+const IMAGEN: ModelCapabilities = ModelCapabilities {
+    model: "",
+    edit: false,
+    mask: false,
+    input_fidelity: false,
+    reference_images: false,
+    transparent_background: false,
+    max_n: 4,
+    sizes: &["1024x1024", "1408x768", "768x1408", "1280x896", "896x1280"],
+    landscape: Some("1408x768"),
+    portrait: Some("768x1408"),
+    qualities: GPT_IMAGE_QUALITIES,
+    output_options: false,
+    style: false,
+    response_format: false,
+    max_prompt: PromptLimit::Tokens(480),
+};
+
+/// Look up the capabilities of a model.
+pub fn for_model(model: &str) -> Option<&'static ModelCapabilities> {
+    MODELS
+        .iter()
+        .find(|capabilities| capabilities.model == model)
+}
+
+/// The features a request uses.
+#[derive(Debug, Default)]
+pub struct Features<'a> {
+    pub edit: bool,
+    pub mask: bool,
+    pub input_fidelity: bool,
+    pub reference_images: bool,
+    pub transparent_background: bool,
+    pub n: u8,
+    /// The `--size`, after [`ModelCapabilities::resolve_size`]
+    pub size: Option<&'a str>,
+    pub quality: Option<&'a str>,
+    pub output_format: Option<&'a str>,
+    pub style: bool,
+}
+
+/// How the API delivers the generated images.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Delivery {
+    /// As base64 in the response
+    Inline,
+    /// As temporary URLs, downloaded one at a time
+    Url,
+}
+
+/// How to emulate features the model lacks.
+#[derive(Debug, Default, PartialEq)]
+pub struct Emulation {
+    /// Send n>1 as parallel n=1 requests
+    pub split_n: bool,
+}
+
+impl ModelCapabilities {
+    /// The size a `--size` value stands for with this model: "square",
+    /// "landscape", and "portrait" become its matching size.
+    pub fn resolve_size(&self, size: &str) -> anyhow::Result<String> {
+        let shape = size.to_lowercase();
+        let resolved = match shape.as_str() {
+            "square" => Some("1024x1024"),
+            "landscape" => self.landscape,
+            "portrait" => self.portrait,
+            _ => return Ok(size.to_string()),
+        };
+        match resolved {
+            Some(resolved) => Ok(resolved.to_string()),
+            None => bail!("{} has no {shape} size", self.model),
+        }
+    }
+
+    /// The size whose aspect ratio is closest to `width`x`height`'s.
+    pub fn closest_size(&self, width: u32, height: u32) -> &'static str {
+        let aspect = |width: f64, height: f64| (width / height).ln();
+        let target = aspect(width.max(1) as f64, height.max(1) as f64);
+        let distance = |size: &&str| {
+            dimensions(size)
+                .map(|(w, h)| (aspect(w as f64, h as f64) - target).abs())
+                .unwrap_or(f64::INFINITY)
+        };
+        self.sizes
+            .iter()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .copied()
+            .unwrap_or("auto")
+    }
+
+    /// Check that the model can serve a request, returning how to emulate
+    /// what it can't do natively.
+    pub fn check(&self, features: &Features) -> anyhow::Result<Emulation> {
+        let model = self.model;
+        if features.edit && !self.edit {
+            bail!("{model} can't edit images; remove the --image inputs");
+        }
+        if features.mask && !self.mask {
+            bail!("{model} doesn't support --mask");
+        }
+        if features.input_fidelity && !self.input_fidelity {
+            bail!("{model} doesn't support --input-fidelity");
+        }
+        if features.reference_images && !self.reference_images {
+            bail!(
+                "{model} edits a single image; it doesn't support --image-ref"
+            );
+        }
+        if features.transparent_background && !self.transparent_background {
+            bail!("{model} doesn't support --background transparent");
+        }
+        if let Some(size) = features.size.filter(|size| *size != "auto") {
+            if !self.sizes.contains(&size) {
+                bail!(
+                    "{model} doesn't support --size {size}; use one of: {}",
+                    self.sizes.join(", ")
+                );
+            }
+        }
+        if let Some(quality) = features.quality.filter(|q| *q != "auto") {
+            if self.qualities.is_empty() {
+                bail!("{model} doesn't support --quality");
+            }
+            if !self.qualities.contains(&quality) {
+                bail!(
+                    "{model} doesn't support --quality {quality}; use one \
+                     of: {}",
+                    self.qualities.join(", ")
+                );
+            }
+        }
+        if features.style && !self.style {
+            bail!("{model} doesn't support --dalle-style");
+        }
+        let output_format = features.output_format.unwrap_or("png");
+        if output_format != "png" && !self.output_options {
+            bail!("{model} only makes png images; remove --output-format");
+        }
+        Ok(Emulation {
+            split_n: features.n > self.max_n,
+        })
+    }
+
+    /// Drop the edit parameters the model doesn't accept.
+    pub fn adapt_edit(&self, req: &mut EditRequest) {
+        if !self.output_options {
+            req.output_compression = None;
+            req.output_format = None;
+        }
+    }
+
+    /// How to deliver `n` images of a (resolved) `--size`: as URLs if the
+    /// model can, and either memory is tight (`--low-memory`) or the base64
+    /// response could outgrow [`client::RESPONSE_BODY_LIMIT`].
+    pub fn delivery(&self, n: u8, size: &str, low_memory: bool) -> Delivery {
+        let too_large =
+            max_response_size(n, size) > client::RESPONSE_BODY_LIMIT;
+        if self.response_format && (low_memory || too_large) {
+            Delivery::Url
+        } else {
+            Delivery::Inline
+        }
+    }
+
+    /// Drop the create parameters the model doesn't accept, and ask for
+    /// the images as base64 or URLs, if the model takes a `response_format`.
+    pub fn adapt(&self, req: &mut CreateRequest, delivery: Delivery) {
+        if !self.output_options {
+            req.background = None;
+            req.moderation = None;
+            req.output_compression = None;
+            req.output_format = None;
+        }
+        if self.response_format {
+            let format = match delivery {
+                Delivery::Inline => "b64_json",
+                Delivery::Url => "url",
+            };
+            req.response_format = Some(format.to_string());
+        }
+    }
+}
+
+/// The most a base64 response of `n` images could take: uncompressed RGBA,
+/// base64-encoded. An unknown size (like "auto") counts as the largest.
+fn max_response_size(n: u8, size: &str) -> u64 {
+    let (width, height) = dimensions(size).unwrap_or((1792, 1792));
+    u64::from(n.max(1)) * width * height * 4 * 4 / 3
+}
+
+/// The width and height of a "WxH" size.
+fn dimensions(size: &str) -> Option<(u64, u64)> {
+    let (width, height) = size.split_once('x')?;
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let gpt_image = for_model("gpt-image-1").unwrap();
+        let everything = Features {
+            edit: true,
+            mask: true,
+            input_fidelity: true,
+            reference_images: true,
+            transparent_background: true,
+            n: 4,
+            ..Features::default()
+        };
+        assert_eq!(gpt_image.check(&everything).unwrap(), Emulation::default());
+        let mini = for_model("gpt-image-1-mini").unwrap();
+        let err = mini.check(&everything).unwrap_err();
+        assert!(err.to_string().contains("--input-fidelity"), "{err}");
+
+        let dalle3 = for_model("dall-e-3").unwrap();
+        let err = dalle3.check(&everything).unwrap_err();
+        assert!(err.to_string().contains("can't edit"), "{err}");
+        let transparent = Features {
+            transparent_background: true,
+            n: 1,
+            ..Features::default()
+        };
+        assert!(dalle3.check(&transparent).is_err());
+
+        // dall-e-3 only makes one image per request, so we send several
+        let several = Features {
+            n: 3,
+            ..Features::default()
+        };
+        assert!(dalle3.check(&several).unwrap().split_n);
+
+        // Sizes and qualities are checked per model
+        let hd = Features {
+            n: 1,
+            size: Some("1792x1024"),
+            quality: Some("hd"),
+            style: true,
+            ..Features::default()
+        };
+        assert!(dalle3.check(&hd).is_ok());
+        let err = gpt_image.check(&hd).unwrap_err();
+        assert!(err.to_string().contains("--size 1792x1024"), "{err}");
+        assert_eq!(dalle3.resolve_size("landscape").unwrap(), "1792x1024");
+        assert_eq!(gpt_image.resolve_size("Portrait").unwrap(), "1024x1536");
+        assert_eq!(gpt_image.resolve_size("auto").unwrap(), "auto");
+        assert_eq!(gpt_image.closest_size(4000, 3000), "1536x1024");
+        assert_eq!(gpt_image.closest_size(900, 1600), "1024x1536");
+        assert_eq!(gpt_image.closest_size(512, 500), "1024x1024");
+        let dalle2 = for_model("dall-e-2").unwrap();
+        assert!(dalle2.resolve_size("landscape").is_err());
+        let references = Features {
+            edit: true,
+            reference_images: true,
+            n: 1,
+            ..Features::default()
+        };
+        let err = dalle2.check(&references).unwrap_err();
+        assert!(err.to_string().contains("--image-ref"), "{err}");
+
+        // Stability models make one image at a time, with no --quality
+        let core = for_model("stable-image-core").unwrap();
+        assert!(core.check(&several).unwrap().split_n);
+        let err = core.check(&hd).unwrap_err();
+        assert!(err.to_string().contains("--size"), "{err}");
+
+        assert!(for_model("flux-pro").is_none());
+    }
+
+    #[test]
+    fn test_adapt() {
+        let mut req = CreateRequest {
+            model: "dall-e-3".to_string(),
+            prompt: "A cat".to_string(),
+            n: None,
+            size: None,
+            quality: None,
+            background: None,
+            moderation: Some("low".to_string()),
+            output_compression: Some(100),
+            output_format: Some("png".to_string()),
+            style: Some("natural".to_string()),
+            response_format: None,
+        };
+        let dalle3 = for_model("dall-e-3").unwrap();
+        assert_eq!(dalle3.delivery(1, "1792x1024", false), Delivery::Inline);
+        assert_eq!(dalle3.delivery(1, "1792x1024", true), Delivery::Url);
+        let dalle2 = for_model("dall-e-2").unwrap();
+        assert_eq!(dalle2.delivery(10, "1024x1024", false), Delivery::Inline);
+        // Far over the response body limit
+        assert_eq!(dalle2.delivery(10, "4096x4096", false), Delivery::Url);
+        let gpt_image = for_model("gpt-image-1").unwrap();
+        assert_eq!(gpt_image.delivery(1, "1024x1024", true), Delivery::Inline);
+
+        dalle3.adapt(&mut req, Delivery::Inline);
+        assert_eq!(
+            serde_json::to_value(&req).unwrap(),
+            serde_json::json!({
+                "model": "dall-e-3",
+                "prompt": "A cat",
+                "style": "natural",
+                "response_format": "b64_json",
+            })
+        );
+    }
+}
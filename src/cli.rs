@@ -1,33 +1,86 @@
-use std::path::PathBuf;
+use std::{
+    io::{IsTerminal, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    api::{CreateRequest, DecodedResponse, EditRequest, Response},
+    api::{
+        ChatContent, ChatMessage, ChatRequest, CreateRequest, DecodedImageData,
+        DecodedResponse, EditRequest, ImageBytes, Response, Usage,
+    },
+    audit::AuditLog,
+    capabilities,
     cli::spinner::Spinner,
-    client::Client,
-    config::Config,
+    client::{self, Client, ClientError},
+    config::{self, Brand, Config, Locked},
+    control::ControlSocket,
+    discord,
+    events::{Event, Events},
+    gemini::{self, Gemini},
+    imageops::{self, CompositeBack, Overlay, Palette, PostProcess},
+    ipfs::Pinata,
+    pdf::{self, SheetImage},
+    record::{Environment, ImageRecord, RunRecord},
+    redact, rescue,
+    sdwebui::{self, SdWebUi},
+    sftp,
+    slack::Slack,
+    stability::{self, Stability},
 };
 use anyhow::Context;
 use clap::Parser;
 use clap_verbosity_flag::{InfoLevel, Verbosity};
+use image::{DynamicImage, ImageFormat};
 use indicatif::MultiProgress;
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
 
+mod ab;
+mod audit;
+mod checks;
+mod compare;
+mod convert;
+mod csv;
+mod daily;
+mod detach;
+mod dry_run;
+mod history;
 pub mod input;
-mod sanitize;
+mod insert;
+mod jobs;
+mod listen;
+mod pipeline;
+mod plan;
+mod preview;
+mod price;
+mod provenance;
+mod publish;
+mod responses;
+mod reveal;
+pub mod sanitize;
+mod service;
+mod sign;
 mod spinner;
+mod variation;
+mod verify;
+mod wallpaper;
 
 // Default values for CLI options
 const DEFAULT_BACKGROUND: &str = "auto";
+const DEFAULT_MODEL: &str = "gpt-image-1";
 const DEFAULT_MODERATION: &str = "low";
 const DEFAULT_NUM_IMAGES: u8 = 1;
 const DEFAULT_OUTPUT_COMPRESSION: u8 = 100;
 const DEFAULT_OUTPUT_FORMAT: &str = "png";
 const DEFAULT_QUALITY: &str = "auto";
 const DEFAULT_SIZE: &str = "1024x1024";
+const DEFAULT_STYLE_STRENGTH: f32 = 0.6;
 
 /// imgen
 ///
-/// imgen generates images using OpenAI's `gpt-image-1` image generation model.
+/// imgen generates images using OpenAI's `gpt-image-1` image generation model
+/// (or another, with `--model`).
 ///
 /// The tool operates in two modes: 'create' mode by default, or 'edit' mode
 /// when one or more `--image` inputs are provided. Some options are only
@@ -42,58 +95,329 @@ const DEFAULT_SIZE: &str = "1024x1024";
 /// # Generate a single image from a prompt
 /// imgen "A cute cat saying 'hello' on the Moon"
 ///
+/// # Build a prompt from parts (run with -v to see the assembled prompt)
+/// imgen --subject "A red fox in the snow" --style watercolor --mood serene
+///
 /// # Generate images using other images as a reference
 /// imgen -i cat.png -i hat.png "A photo of the cat weaing the hat"
 ///
 /// # Edit an image using a mask
 /// imgen -i pool.png -m mask.png "A sunlit pool containing a flamingo"
 ///
+/// # Print the request (and its estimated cost) without sending it
+/// imgen --dry-run --intent draft --size landscape "A red fox in the snow"
+///
 /// # Build image generation pipelines using standard unix pipes
 /// cat dog.webp | imgen -i - -o - prompt.md | gzip -c | hexyl
+///
+/// # Generate a blog post's hero image and link it in place of a marker
+/// imgen "A lighthouse at dusk" --insert-into post.md --marker '<!-- imgen: hero -->'
+///
+/// # A/B test two prompts with identical settings
+/// imgen ab "A red fox, watercolor" "A red fox, woodcut print" -n 2
+///
+/// # Try a prompt on several models side by side
+/// imgen compare --models gpt-image-1,gpt-image-1-mini "A lighthouse at dusk"
+///
+/// # Generate with Stability AI (reads `STABILITY_API_KEY`)
+/// imgen --provider stability "A red fox in the snow" --size landscape
+///
+/// # Generate with Google's Imagen (reads `GEMINI_API_KEY`)
+/// imgen --provider gemini "A red fox in the snow" -n 4 --size landscape
+///
+/// # Generate locally with a Stable Diffusion server (free per image)
+/// imgen --provider sdwebui "A red fox in the snow" --size 768x512
+///
+/// # Keep iterating on the last image (see `imgen history list` for others)
+/// imgen --from last "Make the sky stormier"
+///
+/// # Watch partial images render, right in the terminal
+/// imgen "A lighthouse at dusk" --stream --preview
+///
+/// # Refine an image over several runs, without uploading it again
+/// imgen --continue new "A lighthouse at dusk"
+/// imgen --continue last "Make the sky stormier"
+///
+/// # Make four variations of an existing image (no prompt)
+/// imgen variation -i photo.png -n 4
+///
+/// # Run a batch of jobs (one JSON object per line) four at a time
+/// imgen jobs - -j 4 < jobs.jsonl
+///
+/// # Run a spreadsheet of jobs (one per row), writing jobs.results.csv
+/// imgen csv jobs.csv -j 4
+///
+/// # Run new rows from a shared Google Sheet (published to the web as CSV)
+/// imgen csv 'https://docs.google.com/spreadsheets/d/e/<id>/pub?output=csv' --watch
+///
+/// # Run one JSON job from stdin and print one JSON result (for editor plugins)
+/// echo '{"prompt": "A cute cat", "response_format": "b64_json"}' | imgen --stdin-json
+///
+/// # Keep imgen running and send it jobs through a named pipe
+/// imgen listen --fifo /tmp/imgen.fifo &
+/// echo '{"prompt": "A cute cat"}' > /tmp/imgen.fifo
+///
+/// # Run a generate -> edit -> upscale -> export workflow from a file
+/// imgen pipeline card.yaml
+///
+/// # Start a slow, high quality render in the background, and save it later
+/// imgen --detach "A detailed map of a fantasy city" --quality high
+/// imgen attach <ID>
+///
+/// # Compare the cost of each quality level before generating
+/// imgen price "A watercolor map of Middle Earth" --size landscape
+///
+/// # Budget a bulk job: total cost and how long it will take
+/// imgen plan --count 500 --quality medium --size square -j 4
+///
+/// # Show the C2PA content credentials embedded in a generated image
+/// imgen provenance image.png
+///
+/// # Check that a folder's images are all unchanged imgen outputs
+/// imgen verify assets/
+///
+/// # Convert a generated image, keeping its metadata and content credentials
+/// imgen convert image.png --to jpeg --compression 80
+///
+/// # See how token usage (and cost) trends across past runs
+/// imgen history stats
 /// ```
 ///
 /// The OpenAI API key is sourced in this order:
+/// • from a file with `--openai-api-key-file`
 /// • from the command line with `--openai-api-key`
 /// • from the environment variable `OPENAI_API_KEY`
 /// • from `OPENAI_API_KEY` in a `.env` file
 /// • from the config file `~/.config/imgen/config.json` (--setup to create)
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about)]
+#[command(args_conflicts_with_subcommands = true)]
+#[command(subcommand_negates_reqs = true)]
 #[clap(verbatim_doc_comment)]
 pub struct Cli {
     /// OpenAI API key (can also be set via `OPENAI_API_KEY` environment variable)
     #[arg(short = 'k', long, env = "OPENAI_API_KEY", hide_env = true)]
     pub openai_api_key: Option<String>,
 
-    /// Store the `--openai-api-key` in the config file and exit.
+    /// Read the OpenAI API key from this file, which keeps it out of shell
+    /// history and `ps`. Takes precedence over `--openai-api-key`.
+    #[arg(long, value_name = "PATH")]
+    pub openai_api_key_file: Option<PathBuf>,
+
+    /// Store the OpenAI API key in the config file and exit. Prompts for the
+    /// key (without echoing it) unless it's given with `--openai-api-key`,
+    /// `--openai-api-key-file`, `OPENAI_API_KEY`, or piped to stdin.
     #[arg(long)]
     pub setup: bool,
 
+    /// Use this config file instead of the default one
+    /// (`~/.config/imgen/config.json`). Settings locked by the system config
+    /// still apply.
+    #[arg(long, value_name = "PATH", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Fail on image responses that don't exactly match the OpenAI API's
+    /// schema. By default, a missing `usage` or `created` (as from some
+    /// OpenAI-compatible servers) is tolerated, and costs show as $0.
+    #[arg(long, global = true)]
+    pub strict: bool,
+
+    /// Send at most this many API requests per second, across all parallel
+    /// requests (`-j`, `jobs --concurrency`, ...). Keeps scripted runs on a
+    /// shared organization key from starving everyone else.
+    #[arg(long, value_name = "N", global = true, value_parser = parse_positive)]
+    pub max_rps: Option<f64>,
+
+    /// Wait at least this many seconds between API requests, across all
+    /// parallel requests. Combines with `--max-rps` (the slower wins).
+    #[arg(
+        long,
+        value_name = "SECS",
+        global = true,
+        value_parser = parse_positive
+    )]
+    pub min_interval: Option<f64>,
+
+    /// Retry an image request that fails with a rate limit (429), a server
+    /// error (5xx), or a dropped connection up to this many times, waiting
+    /// longer each time (or as long as the server asks). 0 to fail right
+    /// away.
+    #[arg(long, value_name = "N", global = true, default_value_t = client::DEFAULT_MAX_RETRIES)]
+    pub max_retries: u32,
+
+    /// Keep as little image data in memory as possible, for small machines
+    /// and large batches: models that can deliver images as URLs do, and
+    /// downloads are spooled to a temporary file and saved from there.
+    #[arg(long, global = true)]
+    pub low_memory: bool,
+
+    /// Read a single JSON job from stdin and print a single JSON result to
+    /// stdout, and nothing else. Takes the same job format as `imgen jobs`;
+    /// use `"response_format": "b64_json"` to get the image(s) inline.
+    #[arg(long, conflicts_with = "setup")]
+    pub stdin_json: bool,
+
+    /// Generate in the background with the Responses API: print a job ID
+    /// and return right away. `imgen attach <ID>` saves the image later.
+    /// Only for generating from a prompt, without post-processing.
+    #[arg(
+        long,
+        conflicts_with_all = ["setup", "stdin_json", "image", "mask", "style_ref", "brand", "provider", "stream", "continue_from"]
+    )]
+    pub detach: bool,
+
+    /// The image generation service to use. Stability AI reads its API key
+    /// from `STABILITY_API_KEY`, defaults to `--model stable-image-core`, and
+    /// maps `--size` to an aspect ratio. Chat features (like `--translate-from`
+    /// and `--alt-text`) still use OpenAI.
+    ///
+    /// Gemini (Google's Imagen models) reads its API key from
+    /// `GEMINI_API_KEY`, defaults to `--model imagen-4.0-generate-001`, and
+    /// can't edit images.
+    ///
+    /// `sdwebui` is a local Stable Diffusion server (Automatic1111's SD WebUI,
+    /// run with `--api`), which renders with its loaded checkpoint unless
+    /// `--model` names another.
+    #[arg(long, value_enum, default_value_t = ImageProvider::Openai)]
+    pub provider: ImageProvider,
+
+    /// The SD WebUI server's URL, for `--provider sdwebui`.
+    /// [default: http://127.0.0.1:7860]
+    #[arg(long, value_name = "URL")]
+    pub api_base: Option<String>,
+
     // Embed the unified image generation arguments directly
     #[command(flatten)]
     pub args: GenerateArgs,
 
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     // Parse --verbose and --quiet flags. Default to INFO log level.
     #[command(flatten)]
     pub verbose: Verbosity<InfoLevel>,
 }
 
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Generate two prompts with identical settings, and save a montage with
+    /// prompt A's images above prompt B's
+    Ab(ab::AbArgs),
+
+    /// Generate the same prompt with several models in parallel, and save a
+    /// side-by-side montage and cost table
+    Compare(compare::CompareArgs),
+
+    /// Generate variations of an existing image, with no prompt (dall-e-2)
+    Variation(variation::VariationArgs),
+
+    /// Convert an image to another format, keeping its metadata and C2PA
+    /// content credentials
+    Convert(convert::ConvertArgs),
+
+    /// Run a grid of generation jobs from a CSV file, one per row, and write
+    /// a results CSV with each row's status
+    Csv(csv::CsvArgs),
+
+    /// Generate an image from the next prompt in a file, for scheduled runs:
+    /// silent on success, and within the monthly budget
+    Daily(daily::DailyArgs),
+
+    /// Run a queue of generation jobs from newline-delimited JSON, printing
+    /// one JSON result line per job
+    Jobs(jobs::JobsArgs),
+
+    /// Stay resident and run jobs written to a named pipe, for editor plugins
+    /// and scripts that call imgen often
+    Listen(listen::ListenArgs),
+
+    /// Run a multi-step workflow (generate, edit, upscale, composite,
+    /// export) from a YAML pipeline file, skipping up-to-date steps
+    Pipeline(pipeline::PipelineArgs),
+
+    /// Wait for a job started with `--detach` and save its image, or list
+    /// the detached jobs
+    Attach(detach::AttachArgs),
+
+    /// Inspect the history of past runs
+    History(history::HistoryArgs),
+
+    /// Check the audit log (see "audit_log" in the config file)
+    Audit(audit::AuditArgs),
+
+    /// Estimate the cost of generating image(s) at each quality level,
+    /// without generating anything
+    Price(price::PriceArgs),
+
+    /// Project the cost and wall-clock time of a bulk job (from past run
+    /// durations and rate limits), and check it against the monthly budget
+    Plan(plan::PlanArgs),
+
+    /// Display the C2PA content credentials embedded in an image
+    Provenance(provenance::ProvenanceArgs),
+
+    /// Check a directory's images against the history: report any that are
+    /// unknown, modified, or missing, or whose prompt or signature doesn't
+    /// match
+    Verify(verify::VerifyArgs),
+
+    /// Share saved images: commit them to GitHub and comment on an issue or
+    /// pull request with them
+    Publish(publish::PublishArgs),
+
+    /// Schedule an imgen command (like `imgen daily`) to run every day, with
+    /// a systemd timer or launchd agent
+    Service(service::ServiceArgs),
+}
+
 // Unified arguments struct combining CreateArgs and EditArgs
 #[derive(Parser, Debug)]
 pub struct GenerateArgs {
-    /// A text description of the desired image(s) (Required unless --setup)
+    /// A text description of the desired image(s) (Required unless --setup
+    /// or --subject)
     ///
     /// Can be a literal string, a path to a text file (if the path exists),
     /// or '-' to read from stdin. Use '@<path>' to force interpretation as a
     /// file path.
-    #[arg(verbatim_doc_comment, required_unless_present("setup"))]
+    #[arg(verbatim_doc_comment, required_unless_present_any(["setup", "stdin_json", "subject"]))]
     pub prompt: Option<input::PromptArg>,
 
+    /// The image model to use, e.g. gpt-image-1, gpt-image-1-mini, or
+    /// dall-e-3. Sizes and qualities are checked against what it supports.
+    #[arg(long, default_value = DEFAULT_MODEL)]
+    pub model: String,
+
+    /// Build the prompt from parts instead: what the image shows
+    #[arg(long, conflicts_with = "prompt")]
+    #[arg(help_heading = "Prompt Builder")]
+    pub subject: Option<String>,
+
+    /// The artistic style, e.g. "watercolor" or "isometric 3D render"
+    #[arg(long)]
+    #[arg(help_heading = "Prompt Builder")]
+    pub style: Option<String>,
+
+    /// The lighting, e.g. "golden hour" or "soft studio light"
+    #[arg(long)]
+    #[arg(help_heading = "Prompt Builder")]
+    pub lighting: Option<String>,
+
+    /// The camera and framing, e.g. "35mm, shallow depth of field"
+    #[arg(long)]
+    #[arg(help_heading = "Prompt Builder")]
+    pub camera: Option<String>,
+
+    /// The mood, e.g. "serene" or "ominous"
+    #[arg(long)]
+    #[arg(help_heading = "Prompt Builder")]
+    pub mood: Option<String>,
+
     /// Input image(s) to edit. Providing at least one input image triggers the
     /// edit operation.
     ///
     /// Can be file paths or '-' to read from stdin. Use '@<path>' to force
-    /// interpretation as a file path.
+    /// interpretation as a file path. Gzip and zstd compressed stdin is
+    /// decompressed automatically ('-.gz' and '-.zst' also work).
     ///
     /// Supported input image formats:
     /// • png, jpeg, webp
@@ -101,6 +425,20 @@ pub struct GenerateArgs {
     #[arg(help_heading = "Input Options (edit)")]
     pub image: Vec<input::ImageArg>,
 
+    /// A reference image to draw from (a style, a product, a character),
+    /// rather than to edit. Sent after the `--image` inputs, so the first
+    /// `--image` stays the one the mask and `--input-fidelity` apply to.
+    /// Needs at least one `--image`. gpt-image models only.
+    #[arg(long, value_name = "IMAGE")]
+    #[arg(help_heading = "Input Options (edit)")]
+    pub image_ref: Vec<input::ImageArg>,
+
+    /// Edit an image from the history: `last` for the most recent one, or
+    /// its ID from `imgen history list`. Adds to any `--image` inputs.
+    #[arg(long, value_name = "ID")]
+    #[arg(help_heading = "Input Options (edit)")]
+    pub from: Vec<String>,
+
     /// An image whose transparent areas indicate where to edit (edit only).
     ///
     /// Can be a file path or '-' to read from stdin. Use '@<path>' to force
@@ -112,18 +450,58 @@ pub struct GenerateArgs {
     #[arg(help_heading = "Input Options (edit)")]
     pub mask: Option<input::ImageArg>,
 
+    /// The format of an `--image -` or `--mask -` read from stdin (png,
+    /// jpeg, webp), instead of detecting it from the data (edit only).
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    #[arg(help_heading = "Input Options (edit)")]
+    pub stdin_format: Option<input::StdinFormat>,
+
+    /// How closely to preserve details of the `--image` inputs, like faces
+    /// and logos (gpt-image-1 only). `high` uses more input tokens.
+    #[arg(long, value_enum, value_name = "FIDELITY")]
+    #[arg(help_heading = "Input Options (edit)")]
+    pub input_fidelity: Option<InputFidelity>,
+
+    /// Match the look (medium, palette, texture) of this reference image,
+    /// without copying its content.
+    ///
+    /// gpt-image-1 has no native style reference, so the image is sent as an
+    /// extra edit input (after any `--image-ref`), and the prompt gets a
+    /// description of its style written by a vision model (gpt-4.1-mini).
+    /// This always uses the edit API.
+    #[arg(long, value_name = "IMAGE", verbatim_doc_comment)]
+    #[arg(help_heading = "Input Options (edit)")]
+    pub style_ref: Option<PathBuf>,
+
+    /// How closely to follow `--style-ref`, from 0 (loosely) to 1 (closely)
+    #[arg(long, default_value_t = DEFAULT_STYLE_STRENGTH)]
+    #[arg(value_name = "STRENGTH", value_parser = parse_fraction)]
+    #[arg(requires = "style_ref")]
+    #[arg(help_heading = "Input Options (edit)")]
+    pub style_strength: f32,
+
     /// Save the generated output image to this path (only supported with `-n 1`).
     ///
     /// If not specified, automatically saves to files based on the prompt.
     /// Ex: prompt='A cute cat saying "hello" on the Moon' will save to
     /// "a_cute_cat_saying_hello.<timestamp>.<i>.png" in the current directory.
     ///
-    /// Can be a file path or '-' to write to stdout. Use '@<path>' to force
+    /// Can be a file path or '-' to write to stdout. Use '-.gz' or '-.zst' to
+    /// write gzip or zstd compressed to stdout. Use '@<path>' to force
     /// interpretation as a file path.
     ///
-    /// Supported output image formats:
-    /// • png, jpeg, webp  (no --image inputs)
-    /// • png              (with --image inputs)
+    /// Use 'sftp://[user@]host[:port]/path' (or 'scp://...') to also upload
+    /// the image(s) over SSH, with the `sftp` or `scp` command (so your SSH
+    /// config, agent, and keys apply). End the path with '/' to upload into a
+    /// directory, and use '/~/' for paths in the home directory. The host
+    /// must be in ~/.ssh/known_hosts.
+    ///
+    /// Use 'ipfs://' to also pin the image(s) to IPFS with Pinata and print
+    /// their CIDs. Needs an API key (JWT) in the `PINATA_JWT` environment
+    /// variable or "pinata_jwt" in the config file.
+    ///
+    /// Supported output image formats: png, jpeg, webp, with or without
+    /// --image inputs. dall-e models only make png.
     #[arg(short, long, verbatim_doc_comment)]
     #[arg(help_heading = "Output Options")]
     pub output: Option<input::OutputArg>,
@@ -135,6 +513,283 @@ pub struct GenerateArgs {
     #[arg(help_heading = "Output Options")]
     pub open: bool,
 
+    /// Show the saved image(s) in the system file manager (Finder, Explorer,
+    /// etc.) after saving, instead of opening them.
+    #[arg(long, conflicts_with = "open")]
+    #[arg(help_heading = "Output Options")]
+    pub open_folder: bool,
+
+    /// Set the saved image as the desktop wallpaper (macOS, Windows, and
+    /// GNOME, KDE, Cinnamon, XFCE, sway, or feh on Linux). With `-n` > 1, the
+    /// first image is used.
+    #[arg(long)]
+    #[arg(help_heading = "Output Options")]
+    pub set_wallpaper: bool,
+
+    /// Show a rough preview of the generated image(s) in the terminal, using
+    /// colored block characters (works over SSH and in any terminal).
+    #[arg(long)]
+    #[arg(help_heading = "Output Options")]
+    pub preview: bool,
+
+    /// Print a JSON summary of the run (saved paths, revised prompts, token
+    /// usage, and cost) to stdout.
+    ///
+    /// Conflicts with `--output -` (stdout).
+    #[arg(long)]
+    #[arg(help_heading = "Output Options")]
+    pub json: bool,
+
+    /// Translate the prompt to English with a chat model (gpt-4.1-mini) before
+    /// generating, since gpt-image-1 follows English prompts better. Give the
+    /// prompt's language, or `auto` to detect it.
+    ///
+    /// The original prompt is kept in `--json`, `--sidecar`, and the history.
+    #[arg(long, value_name = "LANGUAGE", verbatim_doc_comment)]
+    pub translate_from: Option<String>,
+
+    /// If the prompt is rejected by content moderation, rewrite it once with
+    /// a chat model (gpt-4.1-mini) to comply with the policy, then retry.
+    #[arg(long)]
+    pub auto_soften: bool,
+
+    /// Check that the generated image(s) contain this text (e.g. a sign or
+    /// logo), using the `tesseract` OCR command. Case, spacing, and
+    /// punctuation are ignored.
+    ///
+    /// Failing images are regenerated up to `--max-attempts` times.
+    #[arg(long, value_name = "TEXT", verbatim_doc_comment)]
+    pub expect_text: Option<String>,
+
+    /// Regenerate fully transparent or near-uniform images, which the API
+    /// occasionally returns (and still bills for).
+    ///
+    /// Failing images are regenerated up to `--max-attempts` times.
+    #[arg(long, verbatim_doc_comment)]
+    pub reject_blank: bool,
+
+    /// Regenerate images whose luminance entropy is below this many bits
+    /// (0-8). Flat fills score near 0, and detailed photos 7 or more.
+    ///
+    /// Failing images are regenerated up to `--max-attempts` times.
+    #[arg(long, value_name = "BITS", verbatim_doc_comment)]
+    pub min_entropy: Option<f64>,
+
+    /// Check that the generated image(s) tile seamlessly (e.g. for game
+    /// textures), by comparing the wrap-around edges.
+    ///
+    /// Failing images are regenerated up to `--max-attempts` times. See also
+    /// `--fix-seams`.
+    #[arg(long, verbatim_doc_comment)]
+    pub tileable: bool,
+
+    /// Blend away visible wrap-around seams locally, by mixing each image
+    /// with a copy offset by half its size. Images that already tile are
+    /// left as-is.
+    #[arg(long)]
+    #[arg(help_heading = "Output Options")]
+    pub fix_seams: bool,
+
+    /// Remap the output to exactly these colors (comma-separated hex), for
+    /// pixel art and brand colors. Best with png or webp output, since jpeg
+    /// compression shifts colors again.
+    #[arg(long, value_name = "COLORS")]
+    #[arg(help_heading = "Output Options")]
+    pub palette: Option<Palette>,
+
+    /// Stamp this logo image in the bottom-right corner of each output.
+    #[arg(long, value_name = "IMAGE")]
+    #[arg(help_heading = "Output Options")]
+    pub watermark: Option<PathBuf>,
+
+    /// Composite this image (e.g. a QR code or UI chrome) onto each output.
+    /// Placed in the bottom-right corner unless `--overlay-pos` is given.
+    #[arg(long, value_name = "IMAGE")]
+    #[arg(help_heading = "Output Options")]
+    pub overlay: Option<PathBuf>,
+
+    /// Where to place the `--overlay`'s top-left corner, in output pixels.
+    #[arg(long, value_name = "X,Y", requires = "overlay")]
+    #[arg(value_parser = parse_position)]
+    #[arg(help_heading = "Output Options")]
+    pub overlay_pos: Option<(u32, u32)>,
+
+    /// Scale the `--overlay` to this fraction of the output width (e.g. 0.2).
+    /// Defaults to the overlay's own size.
+    #[arg(long, value_name = "FRACTION", requires = "overlay")]
+    #[arg(help_heading = "Output Options")]
+    pub overlay_scale: Option<f32>,
+
+    /// Apply a brand kit from the config file: its logo watermark, palette,
+    /// default size, and prompt suffix. Explicit options take precedence.
+    ///
+    /// Brands are defined under "brands" in the config file, like:
+    /// "brands": { "acme": { "logo": "/path/to/logo.png",
+    ///   "palette": ["#112233", "#aabbcc"], "sizes": ["landscape"],
+    ///   "prompt_suffix": "in Acme's flat illustration style" } }
+    #[arg(long, value_name = "NAME", verbatim_doc_comment)]
+    #[arg(help_heading = "Output Options")]
+    pub brand: Option<String>,
+
+    /// Like `--palette`, but with up to 16 colors taken from this image.
+    #[arg(long, value_name = "IMAGE", conflicts_with = "palette")]
+    #[arg(help_heading = "Output Options")]
+    pub palette_from: Option<PathBuf>,
+
+    /// The most times to generate when the images fail a check like
+    /// `--expect-text` or `--reject-blank`, before keeping the last result
+    /// anyway. Every attempt is billed.
+    #[arg(long, default_value_t = 1, value_name = "N")]
+    pub max_attempts: u8,
+
+    /// Send `-n N` as N parallel single-image requests, merging the results.
+    ///
+    /// Some models cap n, and parallel single requests often finish faster.
+    /// Images are still numbered in order, and usage and cost are combined.
+    #[arg(long, verbatim_doc_comment)]
+    pub split_n: bool,
+
+    /// Write a `<image>.json` metadata sidecar (prompt, model, per-image
+    /// cost, and the imgen version, provider, git commit, and hostname) next
+    /// to each saved image.
+    #[arg(long)]
+    #[arg(help_heading = "Output Options")]
+    pub sidecar: bool,
+
+    /// Generate with the Responses API, streaming partial images as the
+    /// final one renders. Each is saved over the last in the fallback
+    /// directory (so an image viewer shows the progress), and previewed with
+    /// `--preview`. gpt-image-1 only, one image at a time.
+    #[arg(long, conflicts_with = "provider")]
+    #[arg(help_heading = "Output Options (create)")]
+    pub stream: bool,
+
+    /// Refine the image from an earlier Responses API run ("make the sky
+    /// darker"), without uploading anything again: `new` to start, `last`
+    /// for the latest response (from `--continue` or `--stream`), or a
+    /// response ID. gpt-image-1 only, one image at a time.
+    #[arg(long = "continue", value_name = "ID", conflicts_with = "provider")]
+    #[arg(help_heading = "Output Options (create)")]
+    pub continue_from: Option<String>,
+
+    /// Write the exact prompt sent (after `--subject`, `--style`, and
+    /// `--translate-from`) to a `<image>.prompt.txt` file next to each saved
+    /// image.
+    #[arg(long)]
+    #[arg(help_heading = "Output Options")]
+    pub save_prompt: bool,
+
+    /// Emit newline-delimited JSON lifecycle events on stdout (validated,
+    /// uploading, generating, decoded, saved, done, error), for tools
+    /// wrapping imgen.
+    #[arg(long, conflicts_with = "json")]
+    #[arg(help_heading = "Output Options")]
+    pub events: bool,
+
+    /// Expose progress events and a `cancel` command on a unix socket at this
+    /// path, so a wrapping GUI can cancel an in-flight generation.
+    ///
+    /// Connected clients receive the same JSON lines as `--events`. Writing
+    /// `cancel` aborts the run, which still exits cleanly.
+    #[arg(long, value_name = "PATH", verbatim_doc_comment)]
+    #[arg(help_heading = "Output Options")]
+    pub control_socket: Option<PathBuf>,
+
+    /// Print the request instead of sending it: the JSON body, or an edit's
+    /// form fields with file sizes, and its estimated cost. Nothing touches
+    /// the network, so steps that need it (`--translate-from`,
+    /// `--style-ref`'s description, `--output sftp://`, `--slack-channel`)
+    /// are skipped, and no API key is needed.
+    #[arg(long, conflicts_with_all = ["stdin_json", "detach", "setup"])]
+    #[arg(help_heading = "Output Options")]
+    pub dry_run: bool,
+
+    /// Also lay out the generated images with their prompt and parameters in
+    /// a PDF contact sheet, for reviewing outside the terminal.
+    #[arg(long, value_name = "PATH")]
+    #[arg(help_heading = "Output Options")]
+    pub pdf: Option<PathBuf>,
+
+    /// Also trace the generated image(s) into an SVG, for simple logos and
+    /// icons. With `-n` > 1, the images are numbered: `out.1.svg`, ...
+    #[arg(long, value_name = "PATH")]
+    #[arg(help_heading = "Output Options")]
+    pub vectorize: Option<PathBuf>,
+
+    /// Set the permissions of the saved image(s), as an octal mode like 0644
+    /// (unix only). Defaults to "file_mode" in the config file, if set, or
+    /// else the umask.
+    #[arg(long, value_name = "MODE", value_parser = parse_mode)]
+    #[arg(help_heading = "Output Options")]
+    pub mode: Option<u32>,
+
+    /// Save images even if the history shows an identical image was already
+    /// saved. By default, duplicates aren't saved again (or `--output` is
+    /// hard-linked to the earlier copy).
+    #[arg(long)]
+    #[arg(help_heading = "Output Options")]
+    pub allow_duplicates: bool,
+
+    /// If an image can't be saved to its output path (even after retrying),
+    /// save it in this directory instead. Defaults to "fallback_dir" in the
+    /// config file, or else `imgen` in the system temp directory.
+    #[arg(long, value_name = "DIR")]
+    #[arg(help_heading = "Output Options")]
+    pub fallback_dir: Option<PathBuf>,
+
+    /// Sign the saved image(s) with this key, writing a detached signature
+    /// next to each. A minisign secret key signs with `minisign` (to
+    /// `<image>.minisig`). An OpenSSH private key, or a public key whose
+    /// private key is in the SSH agent, signs with `ssh-keygen -Y sign` in
+    /// the "imgen" namespace (to `<image>.sig`). The signatures are recorded
+    /// in `--json` and the history.
+    #[arg(long, value_name = "KEY")]
+    #[arg(help_heading = "Output Options")]
+    pub sign: Option<PathBuf>,
+
+    /// Also post the saved image(s) to this Slack channel (`#name` or a
+    /// channel ID), with the prompt as the message. Needs a bot token in the
+    /// `SLACK_TOKEN` environment variable or "slack_token" in the config file.
+    #[arg(long, value_name = "CHANNEL")]
+    #[arg(help_heading = "Output Options")]
+    pub slack_channel: Option<String>,
+
+    /// Also post the saved image(s) to a Discord channel through this webhook
+    /// URL, with the prompt and settings in an embed.
+    #[arg(long, value_name = "URL", value_parser = discord::parse_webhook)]
+    #[arg(help_heading = "Output Options")]
+    pub discord_webhook: Option<String>,
+
+    /// Don't record this run in the history file
+    /// (`~/.local/share/imgen/history.jsonl`).
+    #[arg(long)]
+    #[arg(help_heading = "Output Options")]
+    pub no_history: bool,
+
+    /// Describe each image with a vision model (gpt-4.1-mini) for use as
+    /// alt text. Included in `--json`, `--sidecar`, and `--insert-into`.
+    #[arg(long)]
+    #[arg(help_heading = "Output Options")]
+    pub alt_text: bool,
+
+    /// Print a one sentence description of each image (the `--alt-text`) to
+    /// the terminal, to check the results without opening an image viewer.
+    #[arg(long)]
+    #[arg(help_heading = "Output Options")]
+    pub describe_output: bool,
+
+    /// Save the image(s) next to this Markdown or HTML document, and replace
+    /// the `--marker` in it with image links (or `<img>` tags for HTML).
+    #[arg(long, value_name = "DOC", requires = "marker")]
+    #[arg(help_heading = "Output Options")]
+    pub insert_into: Option<PathBuf>,
+
+    /// The text in the `--insert-into` document to replace, e.g.
+    /// '<!-- imgen: hero -->'
+    #[arg(long, requires = "insert_into")]
+    #[arg(help_heading = "Output Options")]
+    pub marker: Option<String>,
+
     /// The number of images to generate (1-10)
     #[arg(short, long, default_value_t = DEFAULT_NUM_IMAGES)]
     #[arg(help_heading = "Output Options", verbatim_doc_comment)]
@@ -151,6 +806,20 @@ pub struct GenerateArgs {
     #[arg(help_heading = "Output Options")]
     pub quality: String,
 
+    /// The rendering style: hyper-real and dramatic (vivid), or more
+    /// natural-looking (natural) (dall-e-3 only)
+    #[arg(long, value_enum, value_name = "STYLE")]
+    #[arg(help_heading = "Output Options")]
+    pub dalle_style: Option<DalleStyle>,
+
+    /// Pick quality and output settings for what the image is for.
+    ///
+    /// Only fills in options that are left at their defaults, so e.g.
+    /// `--intent draft --quality medium` uses medium quality.
+    #[arg(long, value_enum, verbatim_doc_comment)]
+    #[arg(help_heading = "Output Options")]
+    pub intent: Option<Intent>,
+
     /// Set the desired background opacity of the generated image (create only)
     /// One of: transparent, opaque, auto
     #[arg(long, default_value = DEFAULT_BACKGROUND)]
@@ -162,59 +831,564 @@ pub struct GenerateArgs {
     #[arg(help_heading = "Output Options (create)")]
     pub moderation: String,
 
-    /// The output image compression level (jpeg and webp only) (0-100) (create only)
+    /// The output image compression level (jpeg and webp only) (0-100)
     #[arg(long, default_value_t = DEFAULT_OUTPUT_COMPRESSION)]
-    #[arg(help_heading = "Output Options (create)")]
+    #[arg(help_heading = "Output Options")]
     pub output_compression: u8,
 
-    /// The output image format (png, jpeg, webp) (create only)
+    /// The output image format (png, jpeg, webp)
     #[arg(long, default_value = DEFAULT_OUTPUT_FORMAT)]
-    #[arg(help_heading = "Output Options (create)")]
+    #[arg(help_heading = "Output Options")]
     pub output_format: String,
+
+    /// Blur faces in the `--image` inputs before uploading them (the
+    /// default), and/or in the generated images (`--blur-faces=outputs`),
+    /// for privacy.
+    ///
+    /// Faces are detected locally with the `facedetect` command.
+    #[arg(long, value_enum, value_name = "WHICH", verbatim_doc_comment)]
+    #[arg(num_args = 0..=1, require_equals = true)]
+    #[arg(default_missing_value = "inputs")]
+    #[arg(help_heading = "Input Options (edit)")]
+    pub blur_faces: Option<BlurFaces>,
+
+    /// Scale the edited image back up to the original `--image` resolution
+    /// (edit only).
+    ///
+    /// The API downscales large input images. This resizes each output to the
+    /// dimensions of the first `--image` input. With `--mask`, only the masked
+    /// area is pasted into the original full-resolution image. Unless `--size`
+    /// is set, the output size closest to the input's aspect ratio is used,
+    /// and any remaining difference is cropped evenly, never stretched.
+    #[arg(long, verbatim_doc_comment)]
+    #[arg(help_heading = "Output Options (edit)")]
+    pub composite_back: bool,
+
+    /// Carry C2PA content credentials over to locally re-encoded images
+    /// (default).
+    ///
+    /// Generated images carry C2PA provenance metadata, which local
+    /// post-processing (e.g. `--composite-back`) would otherwise drop. Note
+    /// that the carried-over manifest no longer matches the modified pixels.
+    /// Overrides an earlier `--strip-c2pa` (e.g. from a shell alias).
+    #[arg(long, verbatim_doc_comment, overrides_with = "strip_c2pa")]
+    #[arg(help_heading = "Output Options")]
+    pub keep_c2pa: bool,
+
+    /// Remove C2PA content credentials from the saved image(s). Overrides an
+    /// earlier `--keep-c2pa`.
+    #[arg(long, overrides_with = "keep_c2pa")]
+    #[arg(help_heading = "Output Options")]
+    pub strip_c2pa: bool,
+}
+
+/// Which images `--blur-faces` applies to.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum BlurFaces {
+    /// The `--image` inputs, before they're uploaded
+    Inputs,
+    /// The generated images, before they're saved
+    Outputs,
+    /// Both inputs and outputs
+    All,
+}
+
+/// The `--provider` image generation services.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ImageProvider {
+    Openai,
+    Stability,
+    Gemini,
+    Sdwebui,
+}
+
+/// The `--dalle-style` rendering styles.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum DalleStyle {
+    Vivid,
+    Natural,
+}
+
+impl DalleStyle {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Vivid => "vivid",
+            Self::Natural => "natural",
+        }
+    }
+}
+
+/// The `--input-fidelity` levels.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum InputFidelity {
+    Low,
+    High,
+}
+
+impl InputFidelity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::High => "high",
+        }
+    }
+}
+
+/// What the generated image is for, used to pick quality and output settings.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Intent {
+    /// Quick iteration: low quality, compressed jpeg (cheapest, fastest)
+    Draft,
+    /// Finished asset: medium quality png
+    Final,
+    /// Print or hero image: high quality png (most expensive)
+    Print,
+}
+
+/// The global flags that set up the API client.
+#[derive(Clone, Copy)]
+struct ClientOptions {
+    strict: bool,
+    /// The least time between API requests
+    throttle: Option<Duration>,
+    max_retries: u32,
+    low_memory: bool,
+}
+
+impl ClientOptions {
+    fn apply(self, client: Client) -> Client {
+        client
+            .with_strict(self.strict)
+            .with_throttle(self.throttle)
+            .with_max_retries(self.max_retries)
+            .with_low_memory(self.low_memory)
+    }
 }
 
 impl Cli {
-    pub fn run(self, progress: &MultiProgress) -> anyhow::Result<()> {
-        // Load the configuration file
-        let config = Config::load();
+    /// The least time between API requests, from `--max-rps` and
+    /// `--min-interval`.
+    fn throttle(&self) -> Option<Duration> {
+        let from_rps = self.max_rps.map(|rps| rps.recip());
+        let secs = match (from_rps, self.min_interval) {
+            (Some(a), Some(b)) => a.max(b),
+            (a, b) => a.or(b)?,
+        };
+        Some(Duration::from_secs_f64(secs))
+    }
 
-        // Get API key from CLI > environment variable > config file
-        let api_key = self.openai_api_key.or(config.openai_api_key).context(
-            "API key is required. Provide it with --openai-api-key or set the \
-             `OPENAI_API_KEY` environment variable.",
-        )?;
+    fn client_options(&self) -> ClientOptions {
+        ClientOptions {
+            strict: self.strict,
+            throttle: self.throttle(),
+            max_retries: self.max_retries,
+            low_memory: self.low_memory,
+        }
+    }
+
+    pub fn run(mut self, progress: &MultiProgress) -> anyhow::Result<()> {
+        // Fail on a bad `--config` now, rather than silently using defaults.
+        // `--setup` creates it.
+        if let Some(path) = &self.config {
+            if !self.setup {
+                if !path.exists() {
+                    anyhow::bail!("Config file not found: {}", path.display());
+                }
+                Config::load_from_path(path).with_context(|| {
+                    format!("Failed to load config from: {}", path.display())
+                })?;
+            }
+            config::set_path(path.clone());
+        }
+
+        let client_options = self.client_options();
+        let openai_api_key = match &self.openai_api_key_file {
+            Some(path) => Some(read_api_key_file(path)?),
+            None => self.openai_api_key,
+        };
+        if let Some(api_key) = &openai_api_key {
+            redact::add_secret(api_key);
+        }
+
+        // Run any subcommands
+        if let Some(command) = self.command {
+            return command.run(openai_api_key, client_options);
+        }
 
         // If --setup is provided, store the API key in the config file
         if self.setup {
+            let api_key = match openai_api_key {
+                Some(api_key) => api_key,
+                None => prompt_api_key()?,
+            };
             let config = Config {
-                openai_api_key: Some(api_key.clone()),
+                openai_api_key: Some(api_key),
+                ..Config::load_user()
             };
             config.save()?;
             return Ok(());
         }
 
+        // Load the configuration file
+        let config = Config::load();
+        apply_locked_provider(
+            &config.locked,
+            &mut self.provider,
+            &mut self.api_base,
+        )?;
+        let api_key = match self.provider {
+            ImageProvider::Openai if !self.args.dry_run => {
+                resolve_api_key(openai_api_key, &config)?
+            }
+            // Only needed for the chat features (and not at all to dry run)
+            ImageProvider::Openai
+            | ImageProvider::Stability
+            | ImageProvider::Gemini
+            | ImageProvider::Sdwebui => openai_api_key
+                .or_else(|| config.openai_api_key.clone())
+                .unwrap_or_default(),
+        };
+
+        if config.show_monthly_spend {
+            log_monthly_spend(config.monthly_budget);
+        }
+
         // Setup the OpenAI API client
-        let client = Client::new(api_key);
+        let mut client = client_options
+            .apply(Client::new(api_key).with_audit_log(audit_log(&config)?));
+        if self.api_base.is_some() && self.provider != ImageProvider::Sdwebui {
+            anyhow::bail!("--api-base only applies to --provider sdwebui");
+        }
+        let dry_run = self.args.dry_run;
+        let model = &mut self.args.model;
+        match self.provider {
+            ImageProvider::Openai => (),
+            ImageProvider::Stability => {
+                let stability = if dry_run {
+                    Stability::for_dry_run()
+                } else {
+                    Stability::new()?
+                };
+                client = client.with_provider(Arc::new(stability));
+                let models: Vec<_> =
+                    stability::MODELS.iter().map(|(name, _)| *name).collect();
+                provider_model(
+                    model,
+                    "stability",
+                    stability::DEFAULT_MODEL,
+                    &models,
+                )?;
+            }
+            ImageProvider::Gemini => {
+                let gemini = if dry_run {
+                    Gemini::for_dry_run()
+                } else {
+                    Gemini::new()?
+                };
+                client = client.with_provider(Arc::new(gemini));
+                provider_model(
+                    model,
+                    "gemini",
+                    gemini::DEFAULT_MODEL,
+                    gemini::MODELS,
+                )?;
+            }
+            ImageProvider::Sdwebui => {
+                let sdwebui = SdWebUi::new(self.api_base.take());
+                client = client.with_provider(Arc::new(sdwebui));
+                if model == DEFAULT_MODEL {
+                    *model = sdwebui::DEFAULT_MODEL.to_string();
+                }
+                if self.args.output_format != "png" {
+                    anyhow::bail!(
+                        "--provider sdwebui only makes png images; remove \
+                         --output-format"
+                    );
+                }
+            }
+        }
+
+        if self.detach {
+            return detach::detach(&client, self.args);
+        }
+
+        if self.stdin_json {
+            let mut json = String::new();
+            std::io::stdin()
+                .read_to_string(&mut json)
+                .context("Failed to read the job from stdin")?;
+            let result = jobs::run_job(&client, 1, &json);
+            jobs::print_result(&result);
+            return match result.error {
+                None => Ok(()),
+                Some(error) => Err(anyhow::anyhow!(error)),
+            };
+        }
 
         // Set up the spinner
-        let sp = Spinner::new(progress);
-        sp.set_message("Generating image(s)...");
+        let sp = (!self.args.dry_run).then(|| Spinner::new(progress));
+        if let Some(sp) = &sp {
+            sp.set_message("Generating image(s)...");
+        }
 
-        let result = self.args.run(&client);
-        match result {
+        let control = self
+            .args
+            .control_socket
+            .as_deref()
+            .map(ControlSocket::bind)
+            .transpose()?;
+        let subscribers = control.as_ref().map(ControlSocket::subscribers);
+        let events = Events::new(self.args.events, subscribers);
+        let result =
+            self.args
+                .run(&client, &events, control.as_ref(), sp.as_ref());
+        match &result {
             Ok(_) => info!("✓ Done"),
-            Err(_) => error!("✗ Done"),
+            Err(err) => {
+                events.emit(Event::Error {
+                    message: format!("{err:#}"),
+                });
+                error!("✗ Done")
+            }
         };
 
-        result
+        result.map(|_record| ())
+    }
+}
+
+/// Get API key from CLI > environment variable > config file
+fn resolve_api_key(
+    arg: Option<String>,
+    config: &Config,
+) -> anyhow::Result<String> {
+    let api_key = arg.or_else(|| config.openai_api_key.clone()).context(
+        "API key is required. Provide it with --openai-api-key or set the \
+         `OPENAI_API_KEY` environment variable.",
+    )?;
+    redact::add_secret(&api_key);
+    Ok(api_key)
+}
+
+/// Read an API key from a file, ignoring surrounding whitespace.
+fn read_api_key_file(path: &Path) -> anyhow::Result<String> {
+    let key = std::fs::read_to_string(path).with_context(|| {
+        format!("Failed to read API key file: {}", path.display())
+    })?;
+    let key = key.trim();
+    if key.is_empty() {
+        anyhow::bail!("API key file is empty: {}", path.display());
+    }
+    Ok(key.to_string())
+}
+
+/// Use the `--provider`'s default model in place of ours, and check that it
+/// serves any other `--model`.
+fn provider_model(
+    model: &mut String,
+    provider: &str,
+    default: &str,
+    models: &[&str],
+) -> anyhow::Result<()> {
+    if model == DEFAULT_MODEL {
+        *model = default.to_string();
+    } else if !models.contains(&model.as_str()) {
+        anyhow::bail!(
+            "--provider {provider} doesn't have --model {model}; use one of: \
+             {}",
+            models.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Ask for the API key without echoing it, or read it from stdin if that's
+/// piped.
+fn prompt_api_key() -> anyhow::Result<String> {
+    let key = if std::io::stdin().is_terminal() {
+        let term = console::Term::stderr();
+        term.write_str("OpenAI API key: ")?;
+        term.read_secure_line()?
+    } else {
+        let mut line = String::new();
+        std::io::stdin()
+            .read_line(&mut line)
+            .context("Failed to read the API key from stdin")?;
+        line
+    };
+    let key = key.trim();
+    if key.is_empty() {
+        anyhow::bail!("No API key entered");
+    }
+    Ok(key.to_string())
+}
+
+/// Open the audit log, if the config enables it.
+fn audit_log(config: &Config) -> anyhow::Result<Option<AuditLog>> {
+    config.audit_log.clone().map(AuditLog::open).transpose()
+}
+
+/// Create an API client for a subcommand, from the config file. Fails if
+/// the system config's monthly budget is spent.
+fn new_client(
+    openai_api_key: Option<String>,
+    options: ClientOptions,
+) -> anyhow::Result<Client> {
+    let config = Config::load();
+    // Subcommands only send to OpenAI
+    if let Some(provider) = &config.locked.provider {
+        if !provider.eq_ignore_ascii_case("openai") {
+            anyhow::bail!(
+                "The system config locks the provider to {provider:?}, and \
+                 subcommands only use OpenAI"
+            );
+        }
+    }
+    check_locked_budget(&config.locked)?;
+    let api_key = resolve_api_key(openai_api_key, &config)?;
+    Ok(options.apply(Client::new(api_key).with_audit_log(audit_log(&config)?)))
+}
+
+impl Command {
+    fn run(
+        self,
+        openai_api_key: Option<String>,
+        client_options: ClientOptions,
+    ) -> anyhow::Result<()> {
+        let new_client = || new_client(openai_api_key, client_options);
+        match self {
+            Self::Ab(args) => args.run(&new_client()?),
+            Self::Attach(args) => args.run(&new_client()?),
+            Self::Compare(args) => args.run(&new_client()?),
+            Self::Variation(args) => args.run(&new_client()?),
+            Self::Csv(args) => args.run(&new_client()?),
+            Self::Daily(args) => args.run(&new_client()?),
+            Self::Jobs(args) => args.run(&new_client()?),
+            Self::Listen(args) => args.run(&new_client()?),
+            Self::Pipeline(args) => {
+                // Planning doesn't need an API key
+                let client = (!args.plan).then(new_client).transpose()?;
+                args.run(client.as_ref())
+            }
+            Self::Audit(args) => args.run(),
+            Self::Convert(args) => args.run(),
+            Self::History(args) => args.run(),
+            Self::Price(args) => args.run(),
+            Self::Plan(args) => args.run(client_options.throttle),
+            Self::Provenance(args) => args.run(),
+            Self::Verify(args) => args.run(),
+            Self::Publish(args) => args.run(),
+            Self::Service(args) => args.run(),
+        }
     }
 }
 
 impl GenerateArgs {
-    /// Run the appropriate image generation or editing command based on args
-    fn run(self, client: &Client) -> anyhow::Result<()> {
+    /// Fill in any options left unset from the named `--brand` kit. Returns
+    /// the brand, for its prompt suffix.
+    fn apply_brand(&mut self, name: &str) -> anyhow::Result<Brand> {
+        let brand = Config::load().brands.remove(name).with_context(|| {
+            format!(
+                "Unknown brand {name:?}; add it under \"brands\" in the \
+                 config file"
+            )
+        })?;
+
+        if let Some(first) = brand.sizes.first() {
+            if self.size == DEFAULT_SIZE {
+                self.size = first.clone();
+            } else if !brand.sizes.contains(&self.size) {
+                warn!(
+                    "--size {} isn't one of brand {name:?}'s sizes: {}",
+                    self.size,
+                    brand.sizes.join(", ")
+                );
+            }
+        }
+        if self.palette.is_none()
+            && self.palette_from.is_none()
+            && !brand.palette.is_empty()
+        {
+            let palette =
+                brand.palette.join(",").parse().with_context(|| {
+                    format!("Invalid palette for brand {name:?}")
+                })?;
+            self.palette = Some(palette);
+        }
+        if self.watermark.is_none() {
+            self.watermark = brand.logo.clone();
+        }
+
+        debug!(
+            "--brand {name}: size={}, palette={:?}, watermark={:?}",
+            self.size, self.palette, self.watermark
+        );
+        Ok(brand)
+    }
+
+    /// Fill in any options left at their defaults from the `--intent` bundle.
+    fn apply_intent(&mut self, intent: Intent) {
+        let (quality, output_format, output_compression) = match intent {
+            Intent::Draft => ("low", "jpeg", 80),
+            Intent::Final => ("medium", "png", 100),
+            Intent::Print => ("high", "png", 100),
+        };
+
+        if self.quality == DEFAULT_QUALITY {
+            self.quality = quality.to_string();
+        }
+        if self.output_format == DEFAULT_OUTPUT_FORMAT {
+            self.output_format = output_format.to_string();
+        }
+        if self.output_compression == DEFAULT_OUTPUT_COMPRESSION {
+            self.output_compression = output_compression;
+        }
+
+        debug!(
+            "--intent {intent:?}: quality={}, output_format={}, \
+             output_compression={}",
+            self.quality, self.output_format, self.output_compression,
+        );
+    }
+
+    /// Run the appropriate image generation or editing command based on args.
+    ///
+    /// Returns the record of the completed run.
+    fn run(
+        mut self,
+        client: &Client,
+        events: &Events,
+        control: Option<&ControlSocket>,
+        spinner: Option<&Spinner>,
+    ) -> anyhow::Result<RunRecord> {
+        for id in std::mem::take(&mut self.from) {
+            let path = crate::history::find_image(&id)?;
+            info!("Editing {id}: {}", path.display());
+            self.image.push(input::ImageArg::File(path));
+        }
+        if let Some(intent) = self.intent {
+            self.apply_intent(intent);
+        }
+        let brand = match self.brand.take() {
+            Some(name) => Some(self.apply_brand(&name)?),
+            None => None,
+        };
+
         // Validate and read input prompt, images, and output target
-        let prompt_source = self.prompt.context("Missing prompt")?;
+        let prompt_source = match self.subject.take() {
+            Some(subject) => input::PromptArg::Literal(subject),
+            None => self.prompt.context("Missing prompt")?,
+        };
+        let mut send_opts = SendOptions {
+            auto_soften: self.auto_soften,
+            split_n: self.split_n,
+        };
+        let stdout_flag = if self.json {
+            Some("--json")
+        } else if self.events {
+            Some("--events")
+        } else {
+            None
+        };
         let inputs = input::InputArgs::new(
             prompt_source,
             self.image,
@@ -222,17 +1396,285 @@ impl GenerateArgs {
             self.output,
             self.n,
             self.open,
+            stdout_flag,
         )?;
-        let prompt = inputs.prompt.read_prompt()?;
-        let uses_edit_api = !inputs.images.is_empty();
-        let out_target = inputs.out_target.with_data(
-            uses_edit_api,
+        let mut prompt = build_prompt(
+            inputs.prompt.read_prompt()?,
+            &[
+                ("Style", self.style.as_deref()),
+                ("Lighting", self.lighting.as_deref()),
+                ("Camera", self.camera.as_deref()),
+                ("Mood", self.mood.as_deref()),
+            ],
+        );
+        let reads_stdin_image =
+            inputs.images.iter().any(input::ImageArg::is_stdin)
+                || inputs.mask.as_ref().is_some_and(input::ImageArg::is_stdin);
+        if self.stdin_format.is_some() && !reads_stdin_image {
+            anyhow::bail!(
+                "--stdin-format only applies to an --image or --mask read \
+                 from stdin ('-')"
+            );
+        }
+        let has_image_inputs = !inputs.images.is_empty();
+        if !self.image_ref.is_empty() {
+            if !has_image_inputs {
+                anyhow::bail!("--image-ref needs an --image to edit");
+            }
+            if self.image_ref.iter().any(input::ImageArg::is_stdin) {
+                anyhow::bail!("--image-ref can't read from stdin ('-')");
+            }
+        }
+        let uses_edit_api = has_image_inputs || self.style_ref.is_some();
+        for (flag, used) in [
+            ("--stream", self.stream),
+            ("--continue", self.continue_from.is_some()),
+        ] {
+            if !used {
+                continue;
+            }
+            if uses_edit_api {
+                anyhow::bail!("{flag} only supports creating images");
+            }
+            responses::check(flag, &self.model, self.n)?;
+        }
+        let previous_response_id = match &self.continue_from {
+            Some(id) => responses::resolve_continue(id)?,
+            None => None,
+        };
+        let mut style_ref = self
+            .style_ref
+            .map(|path| input::ImageArg::File(path).read_image(None))
+            .transpose()?;
+        if cfg!(not(feature = "vectorize")) && self.vectorize.is_some() {
+            anyhow::bail!(VECTORIZE_DISABLED);
+        }
+        if self.blur_faces.is_some() {
+            crate::faces::check_available()?;
+        }
+        let config = Config::load();
+        apply_locked(&config.locked, &mut self.moderation, uses_edit_api)?;
+
+        // Fail now on features the model lacks, or emulate them locally
+        let model = self.model.as_str();
+        let capabilities = capabilities::for_model(model);
+        if let Some(capabilities) = capabilities {
+            self.size = capabilities.resolve_size(&self.size)?;
+            let emulation = capabilities.check(&capabilities::Features {
+                edit: uses_edit_api,
+                mask: uses_edit_api && inputs.mask.is_some(),
+                input_fidelity: uses_edit_api && self.input_fidelity.is_some(),
+                reference_images: !self.image_ref.is_empty(),
+                transparent_background: !uses_edit_api
+                    && self.background == "transparent",
+                n: self.n,
+                size: Some(&self.size),
+                quality: Some(&self.quality),
+                output_format: Some(&self.output_format),
+                style: self.dalle_style.is_some(),
+            })?;
+            if emulation.split_n && !send_opts.split_n {
+                info!(
+                    "{model} makes at most {} image(s) per request; sending \
+                     parallel requests",
+                    capabilities.max_n
+                );
+                send_opts.split_n = true;
+            }
+        }
+        if let Some(spinner) = spinner {
+            match crate::history::typical_duration(
+                model,
+                &self.quality,
+                &self.size,
+            ) {
+                Ok(Some(typical)) => spinner.set_message(format!(
+                    "Generating image(s)... usually ~{}s for {}/{}",
+                    typical.as_secs(),
+                    self.quality,
+                    self.size
+                )),
+                Ok(None) => (),
+                Err(err) => debug!("Failed to estimate the duration: {err:#}"),
+            }
+        }
+        let checks = checks::Checks {
+            expect_text: self.expect_text,
+            reject_blank: self.reject_blank,
+            min_entropy: self.min_entropy,
+            tileable: self.tileable,
+            max_attempts: self.max_attempts.max(1),
+        };
+        checks.validate()?;
+        let insertion = match (self.insert_into, self.marker) {
+            (Some(doc), Some(marker)) => {
+                if matches!(inputs.out_target, input::OutputTarget::Stdout(_)) {
+                    anyhow::bail!(
+                        "Cannot use --insert-into when writing output to \
+                         stdout (`--output -`)"
+                    );
+                }
+                Some(insert::Insertion::new(doc, marker)?)
+            }
+            _ => None,
+        };
+        events.emit(Event::Validated {
+            operation: if uses_edit_api { "edit" } else { "create" },
+            n: self.n,
+        });
+        if self.dry_run {
+            for (flag, skipped) in [
+                ("--translate-from", self.translate_from.is_some()),
+                ("--style-ref", style_ref.is_some()),
+                ("--slack-channel", self.slack_channel.is_some()),
+                (
+                    "--output sftp://",
+                    matches!(inputs.out_target, input::OutputTarget::Remote(_)),
+                ),
+            ] {
+                if skipped {
+                    warn!(
+                        "--dry-run: skipping {flag}, which needs the network"
+                    );
+                }
+            }
+        }
+        let original_prompt = match &self.translate_from {
+            Some(_) if self.dry_run => None,
+            Some(language) => {
+                let translated = translate_prompt(client, &prompt, language)?;
+                if translated == prompt.trim() {
+                    debug!("--translate-from: the prompt is already English");
+                    None
+                } else {
+                    info!("Translated prompt: {translated}");
+                    Some(std::mem::replace(&mut prompt, translated))
+                }
+            }
+            None => None,
+        };
+        if let Some(suffix) = brand.and_then(|brand| brand.prompt_suffix) {
+            prompt = format!("{} {suffix}", prompt.trim_end());
+        }
+        if let Some(style_ref) = style_ref.as_ref().filter(|_| !self.dry_run) {
+            let style = describe_style(client, &style_ref.bytes)?;
+            info!("--style-ref: {style}");
+            prompt = style_ref_prompt(&prompt, &style, self.style_strength);
+        }
+        crate::tokens::check_prompt(&prompt, capabilities);
+        sanitize::validate(&config.filenames)?;
+        let mut out_target = inputs.out_target.with_data(
             &prompt,
             &self.output_format,
+            &config.filenames,
         );
+        // Save automatically named images next to the document
+        if let (
+            Some(insertion),
+            input::OutputTargetWithData::Automatic { prefix, .. },
+        ) = (&insertion, &mut out_target)
+        {
+            *prefix = insertion.dir().join(&*prefix).display().to_string();
+        }
+        // Make sure there's room to save the images before paying for them
+        if let Some(dir) = out_target.dir() {
+            let needed = crate::disk::estimate_output_bytes(
+                size_canonical(self.size.clone()).as_deref(),
+                &self.quality,
+                &self.output_format,
+                self.output_compression,
+                self.n,
+            );
+            crate::disk::check_space(dir, needed)?;
+        }
+
+        let pins = matches!(inputs.out_target, input::OutputTarget::Ipfs);
+        let pinata = (pins && !self.dry_run)
+            .then(|| Pinata::new(&config))
+            .transpose()?;
+
+        let remote = match &inputs.out_target {
+            input::OutputTarget::Remote(url) if !self.dry_run => {
+                Some(sftp::Remote::parse(url)?.connect()?)
+            }
+            _ => None,
+        };
+
+        let signer = self
+            .sign
+            .as_deref()
+            .map(sign::Signer::from_key)
+            .transpose()?;
 
-        // Determine if we're using the edit API or the create API based on the
-        // presence of `--image` options
+        let fallback_dir = self
+            .fallback_dir
+            .clone()
+            .or_else(|| config.fallback_dir.clone())
+            .unwrap_or_else(|| std::env::temp_dir().join("imgen"));
+
+        // Find the Slack channel now, so a typo doesn't waste a generation
+        let slack = self
+            .slack_channel
+            .as_deref()
+            .filter(|_| !self.dry_run)
+            .map(|channel| Slack::connect(&config, channel))
+            .transpose()?;
+
+        let palette = match self.palette_from {
+            Some(path) => {
+                let image = image::open(&path).with_context(|| {
+                    format!("Failed to read palette image: {}", path.display())
+                })?;
+                Some(Palette::extract(&image, PALETTE_FROM_COLORS))
+            }
+            None => self.palette,
+        };
+        let mode = match self.mode {
+            Some(mode) => Some(mode),
+            None => config
+                .file_mode
+                .map(|mode| {
+                    parse_mode(&mode).with_context(|| {
+                        format!("Invalid \"file_mode\" in config: {mode:?}")
+                    })
+                })
+                .transpose()?,
+        };
+        let jpeg = matches!(self.output_format.as_str(), "jpeg" | "jpg");
+        if palette.is_some() && jpeg {
+            warn!("--palette: jpeg compression won't keep the exact colors");
+        }
+        let mut overlays = Vec::new();
+        if let Some(path) = &self.watermark {
+            let logo = image::open(path).with_context(|| {
+                format!("Failed to read watermark: {}", path.display())
+            })?;
+            overlays.push(Overlay::watermark(logo));
+        }
+        if let Some(path) = &self.overlay {
+            let image = image::open(path).with_context(|| {
+                format!("Failed to read overlay: {}", path.display())
+            })?;
+            overlays.push(Overlay {
+                image,
+                position: self.overlay_pos,
+                scale: self.overlay_scale,
+            });
+        }
+        let mut post_process = PostProcess {
+            blur_faces: matches!(
+                self.blur_faces,
+                Some(BlurFaces::Outputs | BlurFaces::All)
+            ),
+            fix_seams: self.fix_seams,
+            palette,
+            overlays,
+            // Whichever came last is set
+            strip_c2pa: self.strip_c2pa && !self.keep_c2pa,
+            compression: Some(self.output_compression),
+            ..PostProcess::default()
+        };
+        let send_started;
         let result = if uses_edit_api {
             // Warn about create-API-only arguments if they are not default
             if self.background != DEFAULT_BACKGROUND {
@@ -241,47 +1683,141 @@ impl GenerateArgs {
             if self.moderation != DEFAULT_MODERATION {
                 warn!("Ignoring --moderation option; it is only applicable when generating images without --image inputs.");
             }
-            if self.output_compression != DEFAULT_OUTPUT_COMPRESSION {
-                warn!("Ignoring --output-compression option; it is only applicable when generating images without --image inputs.");
-            }
-            if self.output_format != DEFAULT_OUTPUT_FORMAT {
-                warn!("Ignoring --output-format option; it is only applicable when generating images without --image inputs.");
-            }
 
             // Read the image data
-            let images: Vec<input::ImageData> = inputs
+            let mut images: Vec<input::ImageData> = inputs
                 .images
                 .into_iter()
-                .map(|img| img.read_image())
+                .map(|img| img.read_image(self.stdin_format))
                 .collect::<Result<Vec<_>, _>>()?;
+            let mut references = std::mem::take(&mut self.image_ref)
+                .into_iter()
+                .map(|img| img.read_image(None))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            // Blur faces before anything leaves the machine
+            if matches!(
+                self.blur_faces,
+                Some(BlurFaces::Inputs | BlurFaces::All)
+            ) {
+                for image in images
+                    .iter_mut()
+                    .chain(&mut references)
+                    .chain(&mut style_ref)
+                {
+                    let (blurred, faces) = crate::faces::blur_encoded(
+                        &image.bytes,
+                    )
+                    .with_context(|| {
+                        format!(
+                            "Failed to blur faces in: {}",
+                            image.filename.display()
+                        )
+                    })?;
+                    info!(
+                        "--blur-faces: blurred {faces} face(s) in {}",
+                        image.filename.display()
+                    );
+                    image.bytes = blurred;
+                }
+            }
 
             // Read the mask data if provided
-            let mask = inputs.mask.map(|img| img.read_image()).transpose()?;
+            let mask = inputs
+                .mask
+                .map(|img| img.read_image(self.stdin_format))
+                .transpose()?;
+
+            // Keep the full-resolution inputs around to composite back onto
+            if self.composite_back && !has_image_inputs {
+                warn!("Ignoring --composite-back option; there are no --image inputs to composite onto.");
+            } else if self.composite_back {
+                // Ask for the size shaped most like the original, so little
+                // is cropped when compositing back
+                let default_size =
+                    matches!(self.size.as_str(), DEFAULT_SIZE | "auto");
+                if let Some(capabilities) =
+                    capabilities.filter(|_| default_size)
+                {
+                    let (width, height) =
+                        imageops::dimensions(&images[0].bytes)?;
+                    let size = capabilities.closest_size(width, height);
+                    if size != self.size {
+                        info!(
+                            "--composite-back: using --size {size}, the \
+                             closest to the original's {width}x{height}"
+                        );
+                        self.size = size.to_string();
+                    }
+                }
+                post_process.composite_back = Some(CompositeBack {
+                    original: images[0].bytes.clone(),
+                    mask: mask.as_ref().map(|mask| mask.bytes.clone()),
+                });
+            }
 
             // Create the EditRequest
-            let req = EditRequest {
+            let mut req = EditRequest {
                 images,
-                prompt,
+                references,
+                style_ref,
+                prompt: prompt.clone(),
                 mask,
-                model: "gpt-image-1".to_string(),
+                model: model.to_string(),
                 n: n_canonical(self.n),
                 size: size_canonical(self.size.clone()),
                 quality: quality_canonical(self.quality.clone()),
+                input_fidelity: self
+                    .input_fidelity
+                    .map(|fidelity| fidelity.as_str().to_string()),
+                output_compression: Some(self.output_compression),
+                output_format: Some(self.output_format.clone()),
             };
+            if let Some(capabilities) = capabilities {
+                capabilities.adapt_edit(&mut req);
+            }
+            if self.dry_run {
+                return dry_run::edit(client, &req, send_opts.split_n);
+            }
 
             // Call the edit API
-            client.edit_images(req)
+            events.emit(Event::Uploading {
+                images: req.images.len()
+                    + req.references.len()
+                    + usize::from(req.style_ref.is_some())
+                    + usize::from(req.mask.is_some()),
+                bytes: req
+                    .images
+                    .iter()
+                    .chain(&req.references)
+                    .chain(&req.style_ref)
+                    .chain(&req.mask)
+                    .map(|image| image.bytes.len())
+                    .sum(),
+            });
+            events.emit(Event::Generating { model });
+            send_started = Instant::now();
+            send_checked(client, req, send_opts, control, &checks)
         } else {
             // Warn about edit-API-only arguments if they are present
             if inputs.mask.is_some() {
                 warn!("Ignoring --mask option; it is only applicable when generating images using --image inputs.");
             }
+            if self.composite_back {
+                warn!("Ignoring --composite-back option; it is only applicable when generating images using --image inputs.");
+            }
+            if self.input_fidelity.is_some() {
+                warn!("Ignoring --input-fidelity option; it is only applicable when generating images using --image inputs.");
+            }
+            if self.blur_faces == Some(BlurFaces::Inputs) {
+                warn!("Ignoring --blur-faces option; there are no --image inputs to blur. Use `--blur-faces=outputs` to blur the generated images.");
+            }
             // No warning needed for --image itself, as its absence triggers this path.
 
             // Create the CreateRequest
-            let req = CreateRequest {
-                model: "gpt-image-1".to_string(),
-                prompt,
+            let mut req = CreateRequest {
+                model: model.to_string(),
+                prompt: prompt.clone(),
                 n: n_canonical(self.n),
                 size: size_canonical(self.size.clone()),
                 quality: quality_canonical(self.quality.clone()),
@@ -289,26 +1825,521 @@ impl GenerateArgs {
                 moderation: moderation_canonical(self.moderation.clone()),
                 output_compression: Some(self.output_compression), // Always send for create
                 output_format: Some(self.output_format.clone()), // Always send for create
+                style: self.dalle_style.map(|style| style.as_str().to_string()),
+                response_format: None,
             };
+            if let Some(capabilities) = capabilities {
+                // Only OpenAI returns URLs
+                let delivery = match client.provider_name() {
+                    "openai" => capabilities.delivery(
+                        self.n,
+                        &self.size,
+                        client.low_memory(),
+                    ),
+                    _ => capabilities::Delivery::Inline,
+                };
+                if delivery == capabilities::Delivery::Url {
+                    info!("Asking {model} for image URLs, to download one at a time");
+                }
+                capabilities.adapt(&mut req, delivery);
+            }
+            if self.dry_run {
+                return dry_run::create(client, &req, send_opts.split_n);
+            }
 
             // Call the create API
-            client.create_images(req)
+            events.emit(Event::Generating { model });
+            send_started = Instant::now();
+            if self.stream || self.continue_from.is_some() {
+                let stream = self.stream.then(|| responses::Partials {
+                    path: fallback_dir
+                        .join(format!("partial.{}", self.output_format)),
+                    preview: spinner
+                        .filter(|_| self.preview)
+                        .map(Spinner::progress),
+                });
+                let req = responses::ResponsesImageRequest {
+                    req,
+                    previous_response_id,
+                    stream,
+                };
+                send_checked(client, req, send_opts, control, &checks)
+            } else {
+                send_checked(client, req, send_opts, control, &checks)
+            }
         };
 
         // Handle the response (logging, decoding, saving/writing, opening)
-        let response = result?;
-        handle_response(response, out_target, self.open)
+        let (response, prompt) = result?;
+        let duration = send_started.elapsed();
+        let ctx = ResponseContext {
+            prompt: &prompt,
+            original_prompt: original_prompt.as_deref(),
+            model,
+            post_process,
+            open: self.open,
+            open_folder: self.open_folder,
+            set_wallpaper: self.set_wallpaper,
+            preview: self.preview,
+            json: self.json,
+            sidecar: self.sidecar,
+            save_prompt: self.save_prompt,
+            history: !self.no_history,
+            allow_duplicates: self.allow_duplicates,
+            pdf: self.pdf.as_deref(),
+            vectorize: self.vectorize.as_deref(),
+            insertion: insertion.as_ref(),
+            alt_text: self.alt_text || self.describe_output,
+            describe_output: self.describe_output,
+            spinner,
+            mode,
+            fallback_dir: &fallback_dir,
+            slack: slack.as_ref(),
+            pinata: pinata.as_ref(),
+            remote: remote.as_ref(),
+            signer: signer.as_ref(),
+            discord_webhook: self.discord_webhook.as_deref(),
+            client,
+            size: &self.size,
+            quality: &self.quality,
+            duration,
+            events,
+        };
+        handle_response(response, out_target, &ctx)
     }
 }
 
+/// An image request that we can resend with a rewritten prompt, or split
+/// into single-image requests.
+trait ImageRequest: Clone + Send + 'static {
+    fn prompt_mut(&mut self) -> &mut String;
+    fn n_mut(&mut self) -> &mut Option<u8>;
+    fn send(&self, client: &Client) -> Result<Response, ClientError>;
+}
+
+impl ImageRequest for CreateRequest {
+    fn prompt_mut(&mut self) -> &mut String {
+        &mut self.prompt
+    }
+    fn n_mut(&mut self) -> &mut Option<u8> {
+        &mut self.n
+    }
+    fn send(&self, client: &Client) -> Result<Response, ClientError> {
+        client.create_images(self)
+    }
+}
+
+impl ImageRequest for EditRequest {
+    fn prompt_mut(&mut self) -> &mut String {
+        &mut self.prompt
+    }
+    fn n_mut(&mut self) -> &mut Option<u8> {
+        &mut self.n
+    }
+    fn send(&self, client: &Client) -> Result<Response, ClientError> {
+        client.edit_images(self)
+    }
+}
+
+/// Options for how to send an image request.
+#[derive(Clone, Copy)]
+struct SendOptions {
+    /// Retry once with a rewritten prompt on a moderation rejection
+    auto_soften: bool,
+    /// Send n>1 as parallel n=1 requests
+    split_n: bool,
+}
+
+/// Send an image request, returning the response and the prompt actually
+/// sent. With a control socket, the request runs in the background so a
+/// client can cancel it.
+fn send_cancellable(
+    client: &Client,
+    mut req: impl ImageRequest,
+    opts: SendOptions,
+    control: Option<&ControlSocket>,
+) -> anyhow::Result<(Response, String)> {
+    let Some(control) = control else {
+        let resp = send_maybe_split(client, &mut req, opts)?;
+        return Ok((resp, std::mem::take(req.prompt_mut())));
+    };
+
+    // ureq can't abort an in-flight request, so on cancel we just stop
+    // waiting and let the process exit
+    let client = client.clone();
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let result = send_maybe_split(&client, &mut req, opts)
+            .map(|resp| (resp, std::mem::take(req.prompt_mut())));
+        let _ = tx.send(result);
+    });
+    control.wait(rx)
+}
+
+/// Send an image request, regenerating while the images fail the `checks`
+/// (up to `checks.max_attempts` times in all). Every attempt is billed, so
+/// the returned usage covers all of them.
+fn send_checked(
+    client: &Client,
+    mut req: impl ImageRequest,
+    opts: SendOptions,
+    control: Option<&ControlSocket>,
+    checks: &checks::Checks,
+) -> anyhow::Result<(Response, String)> {
+    let mut prior_usage: Option<Usage> = None;
+    let mut attempt = 1;
+    loop {
+        let (resp, prompt) =
+            send_cancellable(client, req.clone(), opts, control)?;
+        let mut resp = client.fetch_url_images(resp)?;
+        if let Some(prior_usage) = &prior_usage {
+            resp.usage.add(prior_usage);
+        }
+
+        let Some(failure) = checks.failure(&resp)? else {
+            return Ok((resp, prompt));
+        };
+        let max = checks.max_attempts;
+        if attempt >= max {
+            if max > 1 {
+                warn!("Giving up after {attempt} attempts: {failure}");
+            } else {
+                warn!("Check failed: {failure}");
+            }
+            return Ok((resp, prompt));
+        }
+        warn!("Attempt {attempt}/{max}: {failure}; regenerating");
+        // Keep any softened prompt
+        *req.prompt_mut() = prompt;
+        prior_usage = Some(resp.usage);
+        attempt += 1;
+    }
+}
+
+/// Send an image request, as parallel single-image requests with
+/// `--split-n`. Failed requests are dropped (with a warning) as long as some
+/// succeed, so we keep the images we've already paid for.
+fn send_maybe_split(
+    client: &Client,
+    req: &mut impl ImageRequest,
+    opts: SendOptions,
+) -> anyhow::Result<Response> {
+    let n = req.n_mut().unwrap_or(1);
+    if !opts.split_n || n <= 1 {
+        return send_request(client, req, opts.auto_soften);
+    }
+
+    info!("Sending {n} parallel single-image requests");
+    let results: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..n)
+            .map(|_| {
+                let mut single = req.clone();
+                *single.n_mut() = Some(1);
+                scope.spawn(move || {
+                    send_request(client, &mut single, opts.auto_soften)
+                        .map(|resp| (resp, single))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Request thread panicked"))
+            .collect()
+    });
+
+    let mut merged: Option<Response> = None;
+    let mut first_err = None;
+    for (i, result) in results.into_iter().enumerate() {
+        match result {
+            Ok((resp, mut single)) => match &mut merged {
+                Some(merged) => {
+                    merged.usage.add(&resp.usage);
+                    merged.data.extend(resp.data);
+                }
+                None => {
+                    // Keep the prompt actually sent, in case it was softened
+                    *req.prompt_mut() = std::mem::take(single.prompt_mut());
+                    merged = Some(resp);
+                }
+            },
+            Err(err) => {
+                warn!("Request {}/{n} failed: {err:#}", i + 1);
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+    match (merged, first_err) {
+        (Some(merged), _) => Ok(merged),
+        (None, Some(err)) => Err(err),
+        (None, None) => unreachable!("n > 1"),
+    }
+}
+
+/// Send an image request. If moderation rejects the prompt, explain what was
+/// likely flagged and, with `--auto-soften`, retry once with a rewritten
+/// prompt.
+fn send_request(
+    client: &Client,
+    req: &mut impl ImageRequest,
+    auto_soften: bool,
+) -> anyhow::Result<Response> {
+    let err = match req.send(client) {
+        Ok(resp) => return Ok(resp),
+        Err(err) => err,
+    };
+    let Some(api_error) = err
+        .api_error()
+        .filter(|error| error.is_moderation_blocked())
+    else {
+        return Err(err.into());
+    };
+
+    error!("The prompt was rejected by the content policy moderation.");
+    match api_error.safety_violations().as_slice() {
+        [] => warn!("The API didn't say which policy category was flagged."),
+        categories => {
+            warn!("Likely flagged categories: {}", categories.join(", "))
+        }
+    }
+    if !auto_soften {
+        info!(
+            "Hint: rephrase the prompt, or retry with --auto-soften to \
+             rewrite it automatically."
+        );
+        return Err(err.into());
+    }
+
+    let softened = soften_prompt(client, req.prompt_mut())?;
+    info!("Retrying with softened prompt: {softened}");
+    *req.prompt_mut() = softened;
+    Ok(req.send(client)?)
+}
+
+/// The chat model used to rewrite rejected prompts
+const SOFTEN_MODEL: &str = "gpt-4.1-mini";
+
+/// The most colors `--palette-from` takes from its image
+const PALETTE_FROM_COLORS: usize = 16;
+
+/// The chat model used for `--translate-from`
+const TRANSLATE_MODEL: &str = "gpt-4.1-mini";
+
+/// The vision model used to write `--alt-text`
+const ALT_TEXT_MODEL: &str = "gpt-4.1-mini";
+
+/// The vision model used to describe the `--style-ref` image
+const STYLE_REF_MODEL: &str = "gpt-4.1-mini";
+
+/// Rewrite a prompt rejected by moderation so it's more likely to pass.
+fn soften_prompt(client: &Client, prompt: &str) -> anyhow::Result<String> {
+    const INSTRUCTIONS: &str = "The following image generation prompt was \
+        rejected by a content policy filter. Rewrite it so that it complies \
+        with the policy while keeping as much of the original subject, \
+        composition, and style as possible. Reply with only the rewritten \
+        prompt.";
+
+    let content = ChatContent::Text(prompt.to_string());
+    chat_text(client, SOFTEN_MODEL, INSTRUCTIONS, content)
+        .context("Failed to rewrite the rejected prompt")
+}
+
+/// Translate a prompt written in `language` (or "auto" to detect it) to
+/// English. English prompts come back unchanged.
+fn translate_prompt(
+    client: &Client,
+    prompt: &str,
+    language: &str,
+) -> anyhow::Result<String> {
+    let source = if language.eq_ignore_ascii_case("auto") {
+        "whatever language it's in".to_string()
+    } else {
+        language.to_string()
+    };
+    let instructions = format!(
+        "Translate the following image generation prompt from {source} to \
+         English, keeping its meaning, details, and tone. If it's already in \
+         English, reply with it unchanged. Reply with only the prompt."
+    );
+
+    let content = ChatContent::Text(prompt.to_string());
+    chat_text(client, TRANSLATE_MODEL, &instructions, content)
+        .context("Failed to translate the prompt")
+}
+
+/// Describe each image for use as alt text, in parallel. Failures are logged
+/// and give `None`, since the images are already paid for.
+fn describe_images(
+    client: &Client,
+    images: &[DecodedImageData],
+) -> Vec<Option<String>> {
+    info!("Generating alt text...");
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = images
+            .iter()
+            .map(|image| {
+                scope.spawn(|| describe_image(client, &image.image.load()?))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                match handle.join().expect("Alt text thread panicked") {
+                    Ok(alt_text) => Some(alt_text),
+                    Err(err) => {
+                        warn!("Failed to generate alt text: {err:#}");
+                        None
+                    }
+                }
+            })
+            .collect()
+    })
+}
+
+/// Ask a vision model for a concise accessibility description of an image.
+fn describe_image(client: &Client, image: &[u8]) -> anyhow::Result<String> {
+    const INSTRUCTIONS: &str = "Write alt text for this image: one concise \
+        sentence describing what it shows, for screen reader users. Don't \
+        start with \"Image of\" or similar. Reply with only the alt text.";
+
+    let content = ChatContent::Parts(vec![ChatContent::image_part(image)]);
+    chat_text(client, ALT_TEXT_MODEL, INSTRUCTIONS, content)
+}
+
+/// Ask a vision model to describe an image's visual style, ignoring what it
+/// depicts.
+fn describe_style(client: &Client, image: &[u8]) -> anyhow::Result<String> {
+    const INSTRUCTIONS: &str = "Describe the visual style of this image for \
+        an image generation prompt: medium, technique, color palette, \
+        lighting, texture, and composition. Don't mention its subject or \
+        content. Reply with only a comma-separated list of style descriptors.";
+
+    let content = ChatContent::Parts(vec![ChatContent::image_part(image)]);
+    chat_text(client, STYLE_REF_MODEL, INSTRUCTIONS, content)
+        .context("Failed to describe the --style-ref image")
+}
+
+/// Send `content` to a chat `model` following the system `instructions`,
+/// and return the trimmed text of its reply.
+fn chat_text(
+    client: &Client,
+    model: &str,
+    instructions: &str,
+    content: ChatContent,
+) -> anyhow::Result<String> {
+    let req = ChatRequest {
+        model: model.to_string(),
+        messages: vec![
+            ChatMessage {
+                role: "system".to_string(),
+                content: ChatContent::Text(instructions.to_string()),
+            },
+            ChatMessage {
+                role: "user".to_string(),
+                content,
+            },
+        ],
+    };
+    let resp = client.chat(&req)?;
+    resp.choices
+        .into_iter()
+        .next()
+        .map(|choice| choice.message.content.text().trim().to_string())
+        .filter(|text| !text.is_empty())
+        .context("The chat model returned no text")
+}
+
+/// Extend the prompt to use the last input image as a style reference only.
+fn style_ref_prompt(prompt: &str, style: &str, strength: f32) -> String {
+    let how = if strength < 0.34 {
+        "Take loose inspiration from"
+    } else if strength < 0.67 {
+        "Follow"
+    } else {
+        "Closely match"
+    };
+    format!(
+        "{} {how} the visual style of the last input image ({style}). Use \
+         it only as a style reference: don't copy its subject, content, or \
+         layout.",
+        prompt.trim_end()
+    )
+}
+
+/// Everything [`handle_response`] needs besides the response itself.
+struct ResponseContext<'a> {
+    /// The prompt we sent
+    prompt: &'a str,
+    /// The prompt as written, if we translated it
+    original_prompt: Option<&'a str>,
+    /// The model we used
+    model: &'a str,
+    /// Local post-processing to apply before saving
+    post_process: PostProcess,
+    /// Open the saved images in the default system viewer
+    open: bool,
+    /// Show the saved images in the system file manager
+    open_folder: bool,
+    /// Set the first saved image as the desktop wallpaper
+    set_wallpaper: bool,
+    /// Show a preview of the images in the terminal
+    preview: bool,
+    /// Print a JSON summary of the run to stdout
+    json: bool,
+    /// Write a metadata sidecar next to each saved image
+    sidecar: bool,
+    /// Write the prompt next to each saved image
+    save_prompt: bool,
+    /// Record the run in the history file
+    history: bool,
+    /// Save images identical to ones already in the history
+    allow_duplicates: bool,
+    /// Trace the images into SVG at this path
+    vectorize: Option<&'a Path>,
+    /// Link the saved images into a document
+    insertion: Option<&'a insert::Insertion>,
+    /// Describe each image with a vision model
+    alt_text: bool,
+    /// Print the descriptions to the terminal
+    describe_output: bool,
+    /// The spinner to hide while printing them
+    spinner: Option<&'a Spinner<'a>>,
+    /// Set the saved images' permissions to this mode
+    mode: Option<u32>,
+    /// Where to save images that can't be saved to their output path
+    fallback_dir: &'a Path,
+    /// Pin the images to IPFS, for `--output ipfs://`
+    pinata: Option<&'a Pinata>,
+    /// Upload the images over SSH, for `--output sftp://...`
+    remote: Option<&'a sftp::Connection>,
+    /// Write a detached signature next to each saved image
+    signer: Option<&'a sign::Signer>,
+    /// Post the saved images to Slack
+    slack: Option<&'a Slack>,
+    /// Post the saved images to this Discord webhook
+    discord_webhook: Option<&'a str>,
+    /// For follow-up requests, like alt text
+    client: &'a Client,
+    /// Write a PDF contact sheet of the images here
+    pdf: Option<&'a Path>,
+    /// The requested image size, for the contact sheet
+    size: &'a str,
+    /// The requested image quality, for the contact sheet
+    quality: &'a str,
+    /// How long the request(s) took
+    duration: Duration,
+    /// Lifecycle events for `--events`
+    events: &'a Events,
+}
+
 /// Handles the common logic after receiving an API response.
 ///
-/// Decodes images, calculates cost, saves/writes the output, and optionally opens them.
+/// Decodes images, calculates cost, post-processes and saves/writes the output,
+/// and optionally opens them. Returns the record of the run.
 fn handle_response(
     resp: Response,
     out_target: input::OutputTargetWithData<'_>,
-    open_files: bool,
-) -> anyhow::Result<()> {
+    ctx: &ResponseContext<'_>,
+) -> anyhow::Result<RunRecord> {
     // Calculate and display cost information
     let cost = resp.usage.calculate_cost();
     info!(
@@ -317,23 +2348,621 @@ fn handle_response(
         resp.usage.input_tokens,
         resp.usage.output_tokens
     );
-    info!("Estimated cost: ${:.2}", cost); // Show more precision for cost
+    // Reference images can dominate the input cost of edits
+    let details = &resp.usage.input_tokens_details;
+    let input_split = format!(
+        "Input tokens: {} text, {} image",
+        details.text_tokens, details.image_tokens
+    );
+    if details.image_tokens > 0 {
+        let image_input_cost = resp.usage.image_input_cost();
+        info!("{input_split} (~${image_input_cost:.3} for input images)");
+    } else {
+        debug!("{input_split}");
+    }
+    // The API only reports usage for the whole run, so split it evenly
+    let n = resp.data.len();
+    let image_cost = cost / n.max(1) as f64;
+    if n > 1 {
+        info!("Estimated cost: ${cost:.2} (${image_cost:.2} per image)");
+    } else {
+        info!("Estimated cost: ${:.2}", cost); // Show more precision for cost
+    }
+
+    // Decode the images from base64. Keep going past any that fail, since
+    // the rest are already paid for.
+    let start = Instant::now();
+    let (mut decoded_resp, decode_errors) =
+        DecodedResponse::decode_partial(resp);
+    let decode_time = start.elapsed();
+    let mut failed = Vec::new();
+    for (i, err, b64_json) in decode_errors {
+        error!("Failed to decode image {}/{n}: {err}", i + 1);
+        rescue::save_and_report("image data", "b64", b64_json.as_bytes());
+        failed.push(format!("image {} (decode: {err})", i + 1));
+    }
+    if decoded_resp.data.is_empty() && !failed.is_empty() {
+        anyhow::bail!("Failed to decode base64 image data");
+    }
+
+    // Show what the model actually rendered, if it rewrote our prompt
+    for (i, image) in decoded_resp.data.iter().enumerate() {
+        if let Some(revised_prompt) = &image.revised_prompt {
+            match n {
+                1 => info!("Revised prompt: {revised_prompt}"),
+                _ => info!("Revised prompt ({}/{n}): {revised_prompt}", i + 1),
+            }
+        }
+    }
 
-    // Decode the images from base64
-    let decoded_resp = DecodedResponse::try_from(resp)
-        .context("Failed to decode base64 image data")?;
+    // Apply any local post-processing. If it fails, save the image as
+    // generated rather than lose it.
+    for image in &mut decoded_resp.data {
+        if ctx.post_process.is_empty() {
+            break;
+        }
+        let processed = image
+            .image
+            .load()
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| ctx.post_process.apply(bytes.into_owned()));
+        match processed {
+            Ok(bytes) => image.image = ImageBytes::Memory(bytes),
+            Err(err) => {
+                warn!("Post-processing failed, saving as generated: {err:#}")
+            }
+        }
+    }
 
     // Handle output based on the target
-    let out_paths = decoded_resp.save_images(out_target)?;
+    ctx.events.emit(Event::Decoded {
+        images: decoded_resp.data.len(),
+    });
+    // Don't hoard identical copies of images we've already saved
+    let hashes: Vec<String> = decoded_resp
+        .data
+        .iter()
+        .map(|image| image.image.sha256_hex())
+        .collect::<std::io::Result<_>>()
+        .context("Failed to hash the images")?;
+    let existing = if ctx.allow_duplicates {
+        Vec::new()
+    } else {
+        crate::history::find_saved(&hashes).unwrap_or_else(|err| {
+            warn!("Failed to check history for duplicates: {err:#}");
+            Vec::new()
+        })
+    };
+
+    // Each image is saved (and reported) as soon as possible
+    let start = Instant::now();
+    let saved =
+        decoded_resp.save_images(out_target, &existing, ctx.fallback_dir)?;
+    // The last phases, after the request's own (logged by the client)
+    debug!("decode {decode_time:.2?}, save {:.2?}", start.elapsed());
+    let mut image_paths = Vec::new();
+    for (index, result) in saved.into_iter().enumerate() {
+        match result {
+            Ok(path) => {
+                let event_path = Some(path.as_path());
+                ctx.events.emit(Event::Saved {
+                    index,
+                    path: event_path,
+                });
+                image_paths.push(Some(path));
+            }
+            Err(err) => {
+                error!("Failed to save image {}: {err:#}", index + 1);
+                failed.push(format!("image {} (save: {err:#})", index + 1));
+                image_paths.push(None);
+            }
+        }
+    }
+    let out_paths: Vec<PathBuf> =
+        image_paths.iter().flatten().cloned().collect();
+    if let Some(mode) = ctx.mode {
+        for path in &out_paths {
+            set_mode(path, mode)?;
+        }
+    }
+
+    // Pin to IPFS. The images are saved locally, so keep going on failure.
+    let mut cids = Vec::new();
+    if let Some(pinata) = ctx.pinata {
+        for (i, (image, path)) in
+            decoded_resp.data.iter().zip(&image_paths).enumerate()
+        {
+            let filename = path
+                .as_deref()
+                .and_then(Path::file_name)
+                .map(Path::new)
+                .unwrap_or(Path::new("image.png"));
+            let pinned = image
+                .image
+                .load()
+                .map_err(anyhow::Error::from)
+                .and_then(|bytes| pinata.pin(filename, &bytes));
+            match pinned {
+                Ok(cid) => {
+                    info!("Pinned image {} to IPFS: ipfs://{cid}", i + 1);
+                    cids.push(Some(cid));
+                }
+                Err(err) => {
+                    error!("Failed to pin image {}: {err:#}", i + 1);
+                    failed.push(format!("image {} (pin: {err:#})", i + 1));
+                    cids.push(None);
+                }
+            }
+        }
+    }
+
+    // Upload over SSH. The images are saved locally, so keep going on
+    // failure.
+    if let Some(remote) = ctx.remote {
+        for (i, (image, path)) in
+            decoded_resp.data.iter().zip(&image_paths).enumerate()
+        {
+            let filename = path
+                .as_deref()
+                .and_then(Path::file_name)
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default();
+            let uploaded =
+                image.image.load().map_err(anyhow::Error::from).and_then(
+                    |bytes| remote.upload(&filename, &bytes, ctx.mode),
+                );
+            match uploaded {
+                Ok(dest) => info!(
+                    "Uploaded image {} to {}",
+                    i + 1,
+                    remote.remote().display(&dest)
+                ),
+                Err(err) => {
+                    error!("Failed to upload image {}: {err:#}", i + 1);
+                    failed.push(format!("image {} (upload: {err:#})", i + 1));
+                }
+            }
+        }
+    }
+
+    // Sign the saved images. They're saved already, so keep going on
+    // failure.
+    let mut signatures = Vec::new();
+    if let Some(signer) = ctx.signer {
+        if out_paths.is_empty() {
+            warn!("Ignoring --sign option; no image files were saved.");
+        }
+        for (i, path) in image_paths.iter().enumerate() {
+            let Some(path) = path else {
+                signatures.push(None);
+                continue;
+            };
+            match signer.sign(path) {
+                Ok(sig) => {
+                    info!("Signed image {}: {}", i + 1, sig.display());
+                    signatures.push(Some(sig));
+                }
+                Err(err) => {
+                    error!("Failed to sign image {}: {err:#}", i + 1);
+                    failed.push(format!("image {} (sign: {err:#})", i + 1));
+                    signatures.push(None);
+                }
+            }
+        }
+    }
+
+    let alt_texts = if ctx.alt_text {
+        describe_images(ctx.client, &decoded_resp.data)
+    } else {
+        Vec::new()
+    };
+    let mut descriptions = Vec::new();
+    for (i, alt_text) in alt_texts.iter().enumerate() {
+        let Some(alt_text) = alt_text else { continue };
+        if !ctx.describe_output {
+            info!("Alt text ({}): {alt_text}", i + 1);
+            continue;
+        }
+        match image_paths.get(i).cloned().flatten() {
+            Some(path) => descriptions.push(format!(
+                "Image {} ({}): {alt_text}",
+                i + 1,
+                path.display()
+            )),
+            None => descriptions.push(format!("Image {}: {alt_text}", i + 1)),
+        }
+    }
+    // Always shown (even with -q), and kept off stdout for pipes
+    let print_descriptions = || {
+        for description in &descriptions {
+            eprintln!("{description}");
+        }
+    };
+    match ctx.spinner {
+        Some(spinner) => spinner.suspend(print_descriptions),
+        None => print_descriptions(),
+    }
+
+    let images = decoded_resp
+        .data
+        .iter()
+        .enumerate()
+        .map(|(i, image)| ImageRecord {
+            path: image_paths.get(i).cloned().flatten(),
+            revised_prompt: image.revised_prompt.clone(),
+            alt_text: alt_texts.get(i).cloned().flatten(),
+            sha256: hashes.get(i).cloned(),
+            ipfs_cid: cids.get(i).cloned().flatten(),
+            signature: signatures.get(i).cloned().flatten(),
+            cost: image_cost,
+        })
+        .collect();
+    let record = RunRecord {
+        created: decoded_resp.created,
+        model: ctx.model.to_string(),
+        prompt: ctx.prompt.to_string(),
+        original_prompt: ctx.original_prompt.map(str::to_string),
+        images,
+        usage: decoded_resp.usage,
+        cost,
+        size: Some(ctx.size.to_string()),
+        quality: Some(ctx.quality.to_string()),
+        duration: Some(ctx.duration.as_secs_f64()),
+        environment: Some(Environment::capture(ctx.client.provider_name())),
+    };
+
+    // Print a machine-readable summary of the run
+    if ctx.json {
+        let json = serde_json::to_string_pretty(&record)
+            .expect("Failed to serialize run record");
+        println!("{json}");
+    }
+
+    // The CIDs are in the JSON summary, if printed
+    if !ctx.json {
+        for cid in cids.iter().flatten() {
+            println!("{cid}");
+        }
+    }
+
+    // Write metadata sidecars next to the saved images
+    if ctx.sidecar {
+        if out_paths.is_empty() {
+            warn!("Ignoring --sidecar option; no image files were saved.");
+        }
+        for (i, path) in image_paths.iter().enumerate() {
+            if let Some(path) = path {
+                write_sidecar(path, &record, i)?;
+            }
+        }
+    }
+
+    // Write the prompt next to the saved images
+    if ctx.save_prompt {
+        if out_paths.is_empty() {
+            warn!("Ignoring --save-prompt option; no image files were saved.");
+        }
+        for path in image_paths.iter().flatten() {
+            write_prompt_file(path, ctx.prompt)?;
+        }
+    }
+
+    if let Some(pdf_path) = ctx.pdf {
+        write_contact_sheet(pdf_path, &decoded_resp.data, &record, ctx)?;
+        info!("Contact sheet saved to: {}", pdf_path.display());
+    }
+
+    if let Some(svg_path) = ctx.vectorize {
+        write_svgs(svg_path, &decoded_resp.data)?;
+    }
+
+    if let Some(insertion) = ctx.insertion {
+        let images: Vec<(&Path, &str)> = record
+            .images
+            .iter()
+            .filter_map(|image| {
+                let alt = image.alt_text.as_deref().unwrap_or(ctx.prompt);
+                Some((image.path.as_deref()?, alt))
+            })
+            .collect();
+        insertion.apply(&images)?;
+        info!("Inserted image(s) into: {}", insertion.doc().display());
+    }
+
+    // Record the run in the history. The images are already saved, so don't
+    // fail the run over it.
+    if ctx.history {
+        if let Err(err) = crate::history::append(&record) {
+            warn!("Failed to record run in history: {err:#}");
+        }
+    }
+
+    if ctx.preview {
+        for image in &decoded_resp.data {
+            let decoded = image
+                .image
+                .load()
+                .map_err(image::ImageError::IoError)
+                .and_then(|bytes| image::load_from_memory(&bytes));
+            match decoded {
+                Ok(decoded) => preview::print(&decoded),
+                Err(err) => warn!("Failed to decode image for preview: {err}"),
+            }
+        }
+    }
 
     // Open the generated images if requested
-    if open_files {
+    if ctx.open {
         open_images(&out_paths)?;
     }
+    if ctx.open_folder {
+        if out_paths.is_empty() {
+            warn!("Ignoring --open-folder option; no image files were saved.");
+        }
+        reveal::reveal(&out_paths)?;
+    }
+    if ctx.set_wallpaper {
+        match out_paths.first() {
+            Some(path) => {
+                wallpaper::set(path)?;
+                info!("Set the desktop wallpaper to: {}", path.display());
+            }
+            None => warn!(
+                "Ignoring --set-wallpaper option; no image files were saved."
+            ),
+        }
+    }
+
+    if let Some(slack) = ctx.slack {
+        if out_paths.is_empty() {
+            warn!(
+                "Ignoring --slack-channel option; no image files were saved."
+            );
+        } else {
+            let paths: Vec<&Path> =
+                out_paths.iter().map(PathBuf::as_path).collect();
+            slack.post(&paths, ctx.prompt)?;
+        }
+    }
+    if let Some(webhook) = ctx.discord_webhook {
+        if out_paths.is_empty() {
+            warn!(
+                "Ignoring --discord-webhook option; no image files were saved."
+            );
+        } else {
+            discord::post(webhook, &record, ctx.size, ctx.quality)?;
+        }
+    }
+
+    // Everything that could be saved is, so now report what's missing
+    if !failed.is_empty() {
+        let saved = out_paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>();
+        anyhow::bail!(
+            "Only saved {} of {n} image(s) [{}]; failed: {}",
+            saved.len(),
+            saved.join(", "),
+            failed.join("; ")
+        );
+    }
+
+    ctx.events.emit(Event::Done { cost });
+    Ok(record)
+}
+
+/// Enforce the settings locked by the system config.
+fn apply_locked(
+    locked: &Locked,
+    moderation_flag: &mut String,
+    uses_edit_api: bool,
+) -> anyhow::Result<()> {
+    if let Some(moderation) = &locked.moderation {
+        if *moderation_flag != DEFAULT_MODERATION
+            && !moderation_flag.eq_ignore_ascii_case(moderation)
+        {
+            anyhow::bail!(
+                "--moderation is locked to {moderation:?} by the system \
+                 config"
+            );
+        }
+        // Edits don't take a moderation level
+        if !uses_edit_api {
+            *moderation_flag = moderation.clone();
+        }
+    }
+    check_locked_budget(locked)
+}
+
+/// Fail if this month's spending has reached the system config's budget.
+fn check_locked_budget(locked: &Locked) -> anyhow::Result<()> {
+    if let Some(budget) = locked.monthly_budget {
+        let spent = crate::history::monthly_spend()
+            .context("Failed to read the history for the budget check")?;
+        if spent >= budget {
+            anyhow::bail!(
+                "Spent ${spent:.2} this month, reaching the ${budget:.2} \
+                 budget set by the system config"
+            );
+        }
+    }
+    Ok(())
+}
 
+/// Enforce the provider, and its server, locked by the system config.
+fn apply_locked_provider(
+    locked: &Locked,
+    provider_flag: &mut ImageProvider,
+    api_base_flag: &mut Option<String>,
+) -> anyhow::Result<()> {
+    if let Some(name) = &locked.provider {
+        let provider = <ImageProvider as clap::ValueEnum>::from_str(name, true)
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "The system config locks an unknown provider: {name:?}"
+                )
+            })?;
+        if *provider_flag != ImageProvider::Openai && *provider_flag != provider
+        {
+            anyhow::bail!(
+                "--provider is locked to {name:?} by the system config"
+            );
+        }
+        *provider_flag = provider;
+    }
+    if let Some(api_base) = &locked.api_base {
+        if api_base_flag.as_ref().is_some_and(|flag| flag != api_base) {
+            anyhow::bail!(
+                "--api-base is locked to {api_base:?} by the system config"
+            );
+        }
+        // Other providers don't take a server URL
+        if *provider_flag == ImageProvider::Sdwebui {
+            *api_base_flag = Some(api_base.clone());
+        }
+    }
     Ok(())
 }
 
+/// Log a one-line summary of this month's spending from the history.
+fn log_monthly_spend(budget: Option<f64>) {
+    let spent = match crate::history::monthly_spend() {
+        Ok(spent) => spent,
+        Err(err) => {
+            warn!("Failed to read history: {err:#}");
+            return;
+        }
+    };
+    match budget {
+        Some(budget) if spent >= budget => {
+            warn!("Spent ${spent:.2} of ${budget:.2} budget this month")
+        }
+        Some(budget) => {
+            info!("Spent ${spent:.2} of ${budget:.2} budget this month")
+        }
+        None => info!("Spent ${spent:.2} this month"),
+    }
+}
+
+/// Set a saved file's permissions, regardless of the umask.
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let permissions = std::fs::Permissions::from_mode(mode);
+    std::fs::set_permissions(path, permissions).with_context(|| {
+        format!("Failed to set permissions of: {}", path.display())
+    })
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> anyhow::Result<()> {
+    warn!("Ignoring --mode option; it is only supported on unix platforms.");
+    Ok(())
+}
+
+/// Write the `<image>.json` metadata sidecar for the `i`-th saved image.
+fn write_sidecar(
+    image_path: &Path,
+    record: &RunRecord,
+    i: usize,
+) -> anyhow::Result<()> {
+    let mut path = image_path.as_os_str().to_owned();
+    path.push(".json");
+    let path = PathBuf::from(path);
+
+    let json = serde_json::to_string_pretty(&record.sidecar(i))
+        .expect("Failed to serialize sidecar");
+    std::fs::write(&path, json)
+        .with_context(|| format!("Failed to write to: {}", path.display()))
+}
+
+/// Write the `<image>.prompt.txt` file for a saved image.
+fn write_prompt_file(image_path: &Path, prompt: &str) -> anyhow::Result<()> {
+    let mut path = image_path.as_os_str().to_owned();
+    path.push(".prompt.txt");
+    let path = PathBuf::from(path);
+
+    std::fs::write(&path, format!("{prompt}\n"))
+        .with_context(|| format!("Failed to write to: {}", path.display()))
+}
+
+const VECTORIZE_DISABLED: &str =
+    "--vectorize needs imgen built with the `vectorize` feature";
+
+/// Trace each image into an SVG at `path`, numbered if there are several.
+#[cfg(feature = "vectorize")]
+fn write_svgs(path: &Path, images: &[DecodedImageData]) -> anyhow::Result<()> {
+    for (i, image) in images.iter().enumerate() {
+        let path = if images.len() == 1 {
+            path.to_path_buf()
+        } else {
+            path.with_extension(format!("{}.svg", i + 1))
+        };
+        let decoded = image::load_from_memory(&image.image.load()?)
+            .context("Failed to decode image for --vectorize")?;
+        let svg = crate::vectorize::to_svg(&decoded);
+        std::fs::write(&path, svg).with_context(|| {
+            format!("Failed to write to: {}", path.display())
+        })?;
+        info!("SVG saved to: {}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "vectorize"))]
+fn write_svgs(
+    _path: &Path,
+    _images: &[DecodedImageData],
+) -> anyhow::Result<()> {
+    anyhow::bail!(VECTORIZE_DISABLED)
+}
+
+/// Write a PDF contact sheet of the generated images.
+fn write_contact_sheet(
+    path: &Path,
+    images: &[DecodedImageData],
+    record: &RunRecord,
+    ctx: &ResponseContext<'_>,
+) -> anyhow::Result<()> {
+    let n = images.len();
+    let mut sheet_images = Vec::with_capacity(n);
+    for (i, (image, image_record)) in
+        images.iter().zip(&record.images).enumerate()
+    {
+        let decoded = image::load_from_memory(&image.image.load()?)
+            .context("Failed to decode generated image")?;
+        let flattened = DynamicImage::ImageRgb8(imageops::flatten(&decoded));
+        let jpeg = imageops::encode_with_compression(
+            &flattened,
+            ImageFormat::Jpeg,
+            90,
+        )?;
+
+        let mut caption = match &image_record.path {
+            Some(path) => path.display().to_string(),
+            None => format!("Image {}/{n}", i + 1),
+        };
+        if let Some(revised_prompt) = &image_record.revised_prompt {
+            caption.push_str(&format!(" \u{2014} {revised_prompt}"));
+        }
+        sheet_images.push(SheetImage {
+            jpeg,
+            width: decoded.width(),
+            height: decoded.height(),
+            caption,
+        });
+    }
+
+    let subtitle = format!(
+        "{}, size {}, quality {}, {n} image(s), ~${:.2}",
+        record.model, ctx.size, ctx.quality, record.cost
+    );
+    let pdf = pdf::contact_sheet(&record.prompt, &subtitle, &sheet_images);
+    std::fs::write(path, pdf)
+        .with_context(|| format!("Failed to write to: {}", path.display()))
+}
+
 /// Open the generated images in the default system viewer.
 fn open_images(paths: &[PathBuf]) -> anyhow::Result<()> {
     for path in paths {
@@ -344,6 +2973,69 @@ fn open_images(paths: &[PathBuf]) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Append the `--style`, `--lighting`, etc. prompt builder parts to the
+/// prompt, as labeled sentences.
+fn build_prompt(prompt: String, parts: &[(&str, Option<&str>)]) -> String {
+    let parts: Vec<String> = parts
+        .iter()
+        .filter_map(|(label, part)| Some((label, part.map(str::trim)?)))
+        .filter(|(_, part)| !part.is_empty())
+        .map(|(label, part)| {
+            let part = part.trim_end_matches('.');
+            format!("{label}: {part}.")
+        })
+        .collect();
+    if parts.is_empty() {
+        return prompt;
+    }
+
+    let mut out = prompt.trim_end().to_string();
+    if !out.ends_with(['.', '!', '?']) {
+        out.push('.');
+    }
+    for part in parts {
+        out.push(' ');
+        out.push_str(&part);
+    }
+    debug!("Assembled prompt: {out}");
+    out
+}
+
+/// Parse a number from 0 to 1, for `--style-strength`.
+fn parse_fraction(s: &str) -> anyhow::Result<f32> {
+    let value: f32 = s.trim().parse()?;
+    if !(0.0..=1.0).contains(&value) {
+        anyhow::bail!("Expected a number from 0 to 1");
+    }
+    Ok(value)
+}
+
+/// Parse a number above 0, for `--max-rps` and `--min-interval`.
+fn parse_positive(s: &str) -> anyhow::Result<f64> {
+    let value: f64 = s.trim().parse()?;
+    if !(value > 0.0 && value.is_finite()) {
+        anyhow::bail!("Expected a number above 0");
+    }
+    Ok(value)
+}
+
+/// Parse an octal file mode like `0644`, for `--mode`.
+fn parse_mode(s: &str) -> anyhow::Result<u32> {
+    let digits = s.trim().trim_start_matches("0o");
+    let mode = u32::from_str_radix(digits, 8)
+        .context("Expected an octal mode like 0644")?;
+    if mode > 0o7777 {
+        anyhow::bail!("Expected an octal mode like 0644");
+    }
+    Ok(mode)
+}
+
+/// Parse an `X,Y` pixel position, for `--overlay-pos`.
+fn parse_position(s: &str) -> anyhow::Result<(u32, u32)> {
+    let (x, y) = s.split_once(',').context("Expected X,Y")?;
+    Ok((x.trim().parse()?, y.trim().parse()?))
+}
+
 // --- Avoid passing CLI arguments that match the API default values ---
 
 fn n_canonical(n: u8) -> Option<u8> {
@@ -384,3 +3076,110 @@ fn moderation_canonical(moderation: String) -> Option<String> {
         _ => Some(moderation),
     }
 }
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_locked_provider() {
+        let locked = Locked {
+            provider: Some("Gemini".to_string()),
+            ..Locked::default()
+        };
+        // The default provider is replaced by the locked one
+        let mut provider = ImageProvider::Openai;
+        apply_locked_provider(&locked, &mut provider, &mut None).unwrap();
+        assert_eq!(provider, ImageProvider::Gemini);
+        apply_locked_provider(&locked, &mut provider, &mut None).unwrap();
+        assert_eq!(provider, ImageProvider::Gemini);
+
+        // Any other is refused
+        let mut provider = ImageProvider::Stability;
+        let err = apply_locked_provider(&locked, &mut provider, &mut None)
+            .unwrap_err();
+        assert!(err.to_string().contains("locked"), "{err}");
+
+        let mut provider = ImageProvider::Stability;
+        apply_locked_provider(&Locked::default(), &mut provider, &mut None)
+            .unwrap();
+        assert_eq!(provider, ImageProvider::Stability);
+
+        let unknown = Locked {
+            provider: Some("midjourney".to_string()),
+            ..Locked::default()
+        };
+        let mut provider = ImageProvider::Openai;
+        assert!(
+            apply_locked_provider(&unknown, &mut provider, &mut None).is_err()
+        );
+    }
+
+    #[test]
+    fn test_apply_locked_api_base() {
+        let locked = Locked {
+            api_base: Some("http://gpu.lan:7860".to_string()),
+            ..Locked::default()
+        };
+        let mut provider = ImageProvider::Sdwebui;
+        let mut api_base = None;
+        apply_locked_provider(&locked, &mut provider, &mut api_base).unwrap();
+        assert_eq!(api_base.as_deref(), Some("http://gpu.lan:7860"));
+
+        let mut api_base = Some("http://evil.example:7860".to_string());
+        let err = apply_locked_provider(&locked, &mut provider, &mut api_base)
+            .unwrap_err();
+        assert!(err.to_string().contains("--api-base is locked"), "{err}");
+
+        // Only SD WebUI takes a server URL
+        let mut provider = ImageProvider::Openai;
+        let mut api_base = None;
+        apply_locked_provider(&locked, &mut provider, &mut api_base).unwrap();
+        assert_eq!(api_base, None);
+    }
+
+    #[test]
+    fn test_dry_run_without_keys() {
+        // An empty config file, so no key comes from the user's config. The
+        // `--config` path lasts for the rest of the test process, so it's
+        // laid out like the default one.
+        let temp_dir = tempfile::tempdir().unwrap();
+        let config = temp_dir.path().join("imgen").join("config.json");
+        std::fs::create_dir(config.parent().unwrap()).unwrap();
+        std::fs::write(&config, "{}").unwrap();
+        for provider in ["openai", "stability", "gemini"] {
+            let mut cli = Cli::try_parse_from([
+                "imgen",
+                "--config",
+                config.to_str().unwrap(),
+                "--provider",
+                provider,
+                "--dry-run",
+                "--output",
+                "ipfs://",
+                "A cute cat",
+            ])
+            .unwrap();
+            // Nor from `OPENAI_API_KEY`
+            cli.openai_api_key = None;
+            cli.run(&MultiProgress::new())
+                .unwrap_or_else(|err| panic!("--provider {provider}: {err:#}"));
+        }
+    }
+
+    #[test]
+    fn test_c2pa_flags_last_wins() {
+        let parse = |flags: &[&str]| {
+            let args = Cli::try_parse_from(
+                ["imgen"].iter().chain(flags).chain(&["A cute cat"]),
+            )
+            .unwrap()
+            .args;
+            (args.keep_c2pa, args.strip_c2pa)
+        };
+        assert_eq!(parse(&["--strip-c2pa", "--keep-c2pa"]), (true, false));
+        assert_eq!(parse(&["--keep-c2pa", "--strip-c2pa"]), (false, true));
+    }
+}
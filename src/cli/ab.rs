@@ -0,0 +1,118 @@
+//! `imgen ab`: generate two prompts with identical settings to compare them.
+
+use anyhow::Context;
+use log::{info, warn};
+use std::path::PathBuf;
+
+use crate::{
+    api::DecodedResponse,
+    cli::{
+        compare, input::PromptArg, quality_canonical, sanitize, size_canonical,
+        DEFAULT_NUM_IMAGES, DEFAULT_QUALITY, DEFAULT_SIZE,
+    },
+    client::Client,
+    config::Config,
+    pricing,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct AbArgs {
+    /// Prompt A (literal, file path, '-' for stdin, or '@<path>')
+    pub prompt_a: PromptArg,
+
+    /// Prompt B (literal, file path, '-' for stdin, or '@<path>')
+    pub prompt_b: PromptArg,
+
+    /// The number of images to generate per prompt (1-10)
+    #[arg(short, long, default_value_t = DEFAULT_NUM_IMAGES)]
+    pub n: u8,
+
+    /// The model to use for both prompts
+    #[arg(long, default_value = "gpt-image-1")]
+    pub model: String,
+
+    /// The size of the generated images.
+    /// One of: auto, 1024x1024, 1536x1024, 1024x1536, square, landscape, portrait
+    #[arg(long, default_value = DEFAULT_SIZE)]
+    pub size: String,
+
+    /// The quality of the generated images (auto, high, medium, low)
+    #[arg(long, default_value = DEFAULT_QUALITY)]
+    pub quality: String,
+}
+
+impl AbArgs {
+    pub fn run(self, client: &Client) -> anyhow::Result<()> {
+        let prompts =
+            [self.prompt_a.read_prompt()?, self.prompt_b.read_prompt()?];
+        let size = size_canonical(self.size);
+        let quality = quality_canonical(self.quality);
+        let config = Config::load();
+        let moderation = compare::locked_moderation(&config);
+
+        info!("Generating {} image(s) for each prompt...", self.n);
+        let reqs = prompts
+            .iter()
+            .map(|prompt| {
+                compare::create_request(
+                    &self.model,
+                    prompt,
+                    self.n,
+                    size.clone(),
+                    quality.clone(),
+                    moderation,
+                )
+            })
+            .collect();
+        let outcomes = compare::send_all(client, reqs);
+
+        // Save as `<prefix A>.<created>.a.<i>.png` and `... .b.<i>.png`
+        let filenames = config.filenames;
+        let prefix = sanitize::prompt_prefix(&prompts[0], &filenames);
+        let created = sanitize::timestamp(compare::now(), &filenames);
+        let mut rows = Vec::new();
+        let mut total_cost = 0.0;
+        for (label, outcome) in ["a", "b"].into_iter().zip(outcomes) {
+            let resp = outcome.result.with_context(|| {
+                format!("Prompt {} failed", label.to_uppercase())
+            })?;
+            let cost = compare::format_cost(&outcome.model, &resp);
+            if let Some(pricing) = pricing::for_model(&outcome.model) {
+                total_cost += pricing.usage_cost(&resp.usage);
+            }
+            let resp = DecodedResponse::try_from(resp)
+                .context("Failed to decode base64 image data")?;
+            if resp.data.is_empty() {
+                warn!(
+                    "Prompt {}: API returned no images",
+                    label.to_uppercase()
+                );
+            }
+
+            let mut images = Vec::with_capacity(resp.data.len());
+            for (i, data) in resp.data.iter().enumerate() {
+                let base = format!("{prefix}.{created}.{label}.{}", i + 1);
+                let (path, image) = compare::save_image(&base, data)?;
+                info!("{}: saved {}", label.to_uppercase(), path.display());
+                images.push(image);
+            }
+            rows.push((label.to_uppercase(), cost, images));
+        }
+
+        // Prompt A's images on the top row, prompt B's below
+        let montage_rows: Vec<_> = rows
+            .iter()
+            .map(|(_, _, images)| images.as_slice())
+            .collect();
+        let path = PathBuf::from(format!("{prefix}.{created}.ab.png"));
+        compare::save_montage(&path, &montage_rows)?;
+
+        for ((label, cost, _), prompt) in rows.iter().zip(&prompts) {
+            println!("{label} ({cost}): {prompt}");
+        }
+        if pricing::for_model(&self.model).is_some() {
+            info!("Estimated total cost: ${total_cost:.2}");
+        }
+        Ok(())
+    }
+}
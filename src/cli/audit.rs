@@ -0,0 +1,40 @@
+//! `imgen audit`: check the audit log's hash chain.
+
+use anyhow::Context;
+use std::path::PathBuf;
+
+use crate::{audit, config::Config};
+
+#[derive(clap::Args, Debug)]
+pub struct AuditArgs {
+    #[command(subcommand)]
+    pub command: AuditCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum AuditCommand {
+    /// Check that no entry was changed or removed since it was written
+    Verify {
+        /// The audit log to check. Defaults to "audit_log" in the config
+        /// file.
+        path: Option<PathBuf>,
+    },
+}
+
+impl AuditArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        match self.command {
+            AuditCommand::Verify { path } => {
+                let path = match path {
+                    Some(path) => path,
+                    None => Config::load().audit_log.context(
+                        "No audit log; set \"audit_log\" in the config file",
+                    )?,
+                };
+                let count = audit::verify(&path)?;
+                println!("OK: {count} entries in {}", path.display());
+                Ok(())
+            }
+        }
+    }
+}
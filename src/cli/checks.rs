@@ -0,0 +1,221 @@
+//! Checks on generated images that trigger a regeneration when they fail.
+
+use anyhow::{bail, Context};
+use base64::prelude::*;
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use image::DynamicImage;
+
+use crate::{api::Response, imageops};
+
+/// The checks to run on each generated image.
+#[derive(Debug, Default)]
+pub struct Checks {
+    /// Text the image must contain, checked with OCR
+    pub expect_text: Option<String>,
+    /// Reject fully transparent or near-uniform images
+    pub reject_blank: bool,
+    /// Reject images whose luminance entropy (0-8 bits) is below this
+    pub min_entropy: Option<f64>,
+    /// Reject images with visible seams when tiled
+    pub tileable: bool,
+    /// The most times to generate before giving up and keeping the result
+    pub max_attempts: u8,
+}
+
+impl Checks {
+    /// Make sure the tools the checks need are available, before we pay for
+    /// any images.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.expect_text.is_some() {
+            let status = Command::new("tesseract")
+                .arg("--version")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status();
+            if !status.is_ok_and(|status| status.success()) {
+                bail!(
+                    "--expect-text needs the `tesseract` OCR command; install \
+                     it with e.g. `apt install tesseract-ocr` or \
+                     `brew install tesseract`"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Why the response's images fail the checks, if any do.
+    pub fn failure(&self, resp: &Response) -> anyhow::Result<Option<String>> {
+        if !self.decodes() && self.expect_text.is_none() {
+            return Ok(None);
+        }
+
+        for (i, image) in resp.data.iter().enumerate() {
+            let bytes = match &image.downloaded {
+                Some(downloaded) => downloaded.load()?,
+                None => BASE64_STANDARD
+                    .decode(&image.b64_json)
+                    .context("Failed to decode base64 image data")?
+                    .into(),
+            };
+            if let Some(failure) = self.image_failure(&bytes)? {
+                return Ok(Some(format!("image {} {failure}", i + 1)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether any pixel-level checks are enabled
+    fn decodes(&self) -> bool {
+        self.reject_blank || self.min_entropy.is_some() || self.tileable
+    }
+
+    fn image_failure(&self, bytes: &[u8]) -> anyhow::Result<Option<String>> {
+        if self.decodes() {
+            let image = image::load_from_memory(bytes)
+                .context("Failed to decode the generated image")?;
+            if self.reject_blank && is_transparent(&image) {
+                return Ok(Some("is fully transparent".to_string()));
+            }
+            let entropy = luminance_entropy(&image);
+            if self.reject_blank && entropy < BLANK_ENTROPY {
+                return Ok(Some(format!(
+                    "is blank (entropy {entropy:.2} bits)"
+                )));
+            }
+            if let Some(min_entropy) = self.min_entropy {
+                if entropy < min_entropy {
+                    return Ok(Some(format!(
+                        "has entropy {entropy:.2} bits, below \
+                         --min-entropy {min_entropy}"
+                    )));
+                }
+            }
+            if self.tileable {
+                let score = imageops::seam_score(&image);
+                if score > imageops::SEAM_THRESHOLD {
+                    return Ok(Some(format!(
+                        "doesn't tile seamlessly (seam score {score:.2})"
+                    )));
+                }
+            }
+        }
+
+        if let Some(expected) = &self.expect_text {
+            let text = ocr(bytes)?;
+            if !contains_text(&text, expected) {
+                let text = text.split_whitespace().collect::<Vec<_>>();
+                return Ok(Some(format!(
+                    "is missing the text {expected:?} (read: {:?})",
+                    text.join(" ")
+                )));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Below this luminance entropy (in bits), an image is effectively a flat
+/// fill. Even simple flat illustrations score well above 2.
+const BLANK_ENTROPY: f64 = 1.0;
+
+/// Whether every pixel is (almost) fully transparent.
+fn is_transparent(image: &DynamicImage) -> bool {
+    image.color().has_alpha() && image.to_rgba8().pixels().all(|px| px[3] < 8)
+}
+
+/// The Shannon entropy of the image's luminance histogram, in bits (0-8).
+/// Transparent pixels are skipped, since their color is meaningless.
+fn luminance_entropy(image: &DynamicImage) -> f64 {
+    let mut histogram = [0u64; 256];
+    for px in image.to_rgba8().pixels() {
+        if px[3] < 8 {
+            continue;
+        }
+        let [r, g, b, _] = px.0.map(u32::from);
+        histogram[((r * 299 + g * 587 + b * 114) / 1000) as usize] += 1;
+    }
+    let total = histogram.iter().sum::<u64>() as f64;
+    histogram
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Read the text in an image with `tesseract`.
+fn ocr(image: &[u8]) -> anyhow::Result<String> {
+    let mut child = Command::new("tesseract")
+        .args(["stdin", "stdout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to run tesseract")?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(image)
+        .context("Failed to send image to tesseract")?;
+    let output = child.wait_with_output().context("tesseract failed")?;
+    if !output.status.success() {
+        bail!("tesseract failed: {}", output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Whether OCR'd `text` contains `expected`, ignoring case, whitespace, and
+/// punctuation (which OCR often gets wrong on stylized text).
+fn contains_text(text: &str, expected: &str) -> bool {
+    let normalize = |s: &str| -> String {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_uppercase)
+            .collect()
+    };
+    normalize(text).contains(&normalize(expected))
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blank_checks() {
+        use image::{Rgba, RgbaImage};
+
+        let clear = DynamicImage::ImageRgba8(RgbaImage::new(8, 8));
+        assert!(is_transparent(&clear));
+        assert_eq!(luminance_entropy(&clear), 0.0);
+
+        let gray = RgbaImage::from_pixel(8, 8, Rgba([128, 128, 128, 255]));
+        let gray = DynamicImage::ImageRgba8(gray);
+        assert!(!is_transparent(&gray));
+        assert_eq!(luminance_entropy(&gray), 0.0);
+
+        // Four equally common levels is exactly 2 bits
+        let steps = RgbaImage::from_fn(8, 8, |x, _| {
+            let v = (x / 2 * 60) as u8;
+            Rgba([v, v, v, 255])
+        });
+        let steps = DynamicImage::ImageRgba8(steps);
+        assert!((luminance_entropy(&steps) - 2.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_contains_text() {
+        assert!(contains_text("Grand\nOpening!\n", "GRAND OPENING"));
+        assert!(contains_text("SALE: grand opening today", "Grand Opening"));
+        assert!(!contains_text("GRAND 0PENING", "GRAND OPENING"));
+        assert!(!contains_text("", "GRAND OPENING"));
+    }
+}
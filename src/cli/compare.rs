@@ -0,0 +1,269 @@
+//! `imgen compare`: run the same prompt through several models side by side.
+
+use anyhow::Context;
+use image::DynamicImage;
+use log::{info, warn};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    api::{CreateRequest, DecodedImageData, DecodedResponse, Response},
+    capabilities,
+    cli::{
+        input::PromptArg, quality_canonical, sanitize, size_canonical,
+        DEFAULT_MODERATION, DEFAULT_QUALITY, DEFAULT_SIZE,
+    },
+    client::Client,
+    config::Config,
+    imageops, pricing,
+};
+
+/// Height of each image in a comparison montage
+const MONTAGE_HEIGHT: u32 = 768;
+
+#[derive(clap::Args, Debug)]
+pub struct CompareArgs {
+    /// A text description of the desired image
+    ///
+    /// Can be a literal string, a path to a text file (if the path exists),
+    /// or '-' to read from stdin. Use '@<path>' to force interpretation as a
+    /// file path.
+    #[arg(verbatim_doc_comment)]
+    pub prompt: PromptArg,
+
+    /// Comma-separated models to compare, e.g. gpt-image-1,gpt-image-1-mini
+    #[arg(long, required = true, value_delimiter = ',')]
+    pub models: Vec<String>,
+
+    /// The size of the generated images.
+    /// One of: auto, 1024x1024, 1536x1024, 1024x1536, square, landscape, portrait
+    #[arg(long, default_value = DEFAULT_SIZE)]
+    pub size: String,
+
+    /// The quality of the generated images (auto, high, medium, low)
+    #[arg(long, default_value = DEFAULT_QUALITY)]
+    pub quality: String,
+}
+
+impl CompareArgs {
+    pub fn run(self, client: &Client) -> anyhow::Result<()> {
+        let prompt = self.prompt.read_prompt()?;
+        let size = size_canonical(self.size);
+        let quality = quality_canonical(self.quality);
+        let config = Config::load();
+        let moderation = locked_moderation(&config);
+
+        info!("Comparing {} models...", self.models.len());
+        let reqs = self
+            .models
+            .iter()
+            .map(|model| {
+                create_request(
+                    model,
+                    &prompt,
+                    1,
+                    size.clone(),
+                    quality.clone(),
+                    moderation,
+                )
+            })
+            .collect();
+        let outcomes = send_all(client, reqs);
+
+        // Save each model's image, labeled by model
+        let filenames = config.filenames;
+        let prefix = sanitize::prompt_prefix(&prompt, &filenames);
+        let created = sanitize::timestamp(now(), &filenames);
+        let mut images = Vec::new();
+        let mut rows = Vec::new();
+        for outcome in outcomes {
+            let elapsed = format!("{:.1}s", outcome.elapsed.as_secs_f64());
+            let resp = match outcome.result {
+                Ok(resp) => resp,
+                Err(err) => {
+                    warn!("{}: {err:#}", outcome.model);
+                    let (tokens, cost) = ("-".to_string(), "failed".into());
+                    rows.push([outcome.model, elapsed, tokens, cost]);
+                    continue;
+                }
+            };
+
+            let cost = format_cost(&outcome.model, &resp);
+            let tokens = resp.usage.total_tokens.to_string();
+            let resp = DecodedResponse::try_from(resp)
+                .context("Failed to decode base64 image data")?;
+            let label = outcome.model.replace(['/', '\\'], "-");
+            for data in resp.data.iter().take(1) {
+                let base = format!("{prefix}.{created}.{label}");
+                let (path, image) = save_image(&base, data)?;
+                info!("{}: saved {}", outcome.model, path.display());
+                images.push(image);
+            }
+            rows.push([outcome.model, elapsed, tokens, cost]);
+        }
+
+        // Side-by-side montage, in the same order as the table
+        if images.len() > 1 {
+            let path = PathBuf::from(format!("{prefix}.{created}.compare.png"));
+            save_montage(&path, &[&images])?;
+        }
+
+        println!(
+            "{:<20}{:>10}{:>10}{:>10}",
+            "model", "time", "tokens", "cost"
+        );
+        for [model, elapsed, tokens, cost] in &rows {
+            println!("{model:<20}{elapsed:>10}{tokens:>10}{cost:>10}");
+        }
+
+        if images.is_empty() {
+            anyhow::bail!("All models failed");
+        }
+        Ok(())
+    }
+}
+
+/// The result of one of several parallel requests.
+pub struct Outcome {
+    pub model: String,
+    pub elapsed: Duration,
+    pub result: anyhow::Result<Response>,
+}
+
+/// A create request with the settings shared by the comparison commands.
+pub fn create_request(
+    model: &str,
+    prompt: &str,
+    n: u8,
+    size: Option<String>,
+    quality: Option<String>,
+    moderation: &str,
+) -> CreateRequest {
+    let mut req = CreateRequest {
+        model: model.to_string(),
+        prompt: prompt.to_string(),
+        n: (n != 1).then_some(n),
+        size,
+        quality,
+        background: None,
+        moderation: Some(moderation.to_string()),
+        output_compression: None,
+        output_format: None,
+        style: None,
+        response_format: None,
+    };
+    if let Some(capabilities) = capabilities::for_model(model) {
+        let size = req.size.as_deref().unwrap_or("auto");
+        let delivery = capabilities.delivery(n, size, false);
+        capabilities.adapt(&mut req, delivery);
+    }
+    req
+}
+
+/// The moderation level locked by the system config, or else the default.
+pub fn locked_moderation(config: &Config) -> &str {
+    config
+        .locked
+        .moderation
+        .as_deref()
+        .unwrap_or(DEFAULT_MODERATION)
+}
+
+/// Send the requests in parallel, returning their outcomes in order.
+pub fn send_all(client: &Client, reqs: Vec<CreateRequest>) -> Vec<Outcome> {
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = reqs
+            .into_iter()
+            .map(|req| {
+                scope.spawn(move || {
+                    let start = Instant::now();
+                    let result = client
+                        .create_images(&req)
+                        .and_then(|resp| client.fetch_url_images(resp))
+                        .map_err(anyhow::Error::from);
+                    Outcome {
+                        model: req.model,
+                        elapsed: start.elapsed(),
+                        result,
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("Request thread panicked"))
+            .collect()
+    })
+}
+
+/// The estimated cost of a response, or "n/a" for models without pricing.
+pub fn format_cost(model: &str, resp: &Response) -> String {
+    pricing::for_model(model)
+        .map(|pricing| format!("${:.3}", pricing.usage_cost(&resp.usage)))
+        .unwrap_or_else(|| "n/a".to_string())
+}
+
+/// The current Unix timestamp, shared by all outputs of a comparison.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Save an image to `<base>.<ext>`, returning the path and decoded image.
+pub fn save_image(
+    base: &str,
+    data: &DecodedImageData,
+) -> anyhow::Result<(PathBuf, DynamicImage)> {
+    let bytes = data.image.load()?;
+    let image = image::load_from_memory(&bytes)
+        .context("Failed to decode generated image")?;
+    let format = image::guess_format(&bytes)?;
+    let extension = format.extensions_str().first().unwrap_or(&"png");
+    let path = PathBuf::from(format!("{base}.{extension}"));
+    std::fs::write(&path, &bytes)
+        .with_context(|| format!("Failed to write to: {}", path.display()))?;
+    Ok((path, image))
+}
+
+/// Save a montage of rows of images as a png.
+pub fn save_montage(
+    path: &Path,
+    rows: &[&[DynamicImage]],
+) -> anyhow::Result<()> {
+    let montage = imageops::montage(rows, MONTAGE_HEIGHT, 16);
+    let bytes = imageops::encode(
+        &DynamicImage::ImageRgb8(montage),
+        image::ImageFormat::Png,
+    )?;
+    std::fs::write(path, bytes)
+        .with_context(|| format!("Failed to write to: {}", path.display()))?;
+    info!("Montage saved to: {}", path.display());
+    Ok(())
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Locked;
+
+    #[test]
+    fn test_locked_moderation() {
+        let mut config = Config::default();
+        assert_eq!(locked_moderation(&config), DEFAULT_MODERATION);
+
+        config.locked = Locked {
+            moderation: Some("auto".to_string()),
+            ..Locked::default()
+        };
+        let moderation = locked_moderation(&config);
+        let req =
+            create_request("gpt-image-1", "a cat", 1, None, None, moderation);
+        assert_eq!(req.moderation.as_deref(), Some("auto"));
+    }
+}
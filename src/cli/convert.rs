@@ -0,0 +1,119 @@
+//! `imgen convert`: transcode an image while keeping its metadata.
+
+use anyhow::{bail, Context};
+use image::ImageFormat;
+use log::{info, warn};
+use std::path::PathBuf;
+
+use crate::{imageops, metadata::Metadata};
+
+#[derive(clap::Args, Debug)]
+pub struct ConvertArgs {
+    /// The image file to convert (png, jpeg, webp)
+    pub input: PathBuf,
+
+    /// The output image format
+    #[arg(long, value_enum)]
+    pub to: Format,
+
+    /// The output image compression level (jpeg only) (0-100)
+    #[arg(long, default_value_t = 100)]
+    #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub compression: u8,
+
+    /// Save the converted image to this path.
+    ///
+    /// Defaults to the input path with the new format's extension.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+
+    /// Remove C2PA content credentials from the converted image.
+    #[arg(long)]
+    pub strip_c2pa: bool,
+}
+
+/// Output formats for `imgen convert`.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum Format {
+    Png,
+    #[value(alias = "jpg")]
+    Jpeg,
+    Webp,
+}
+
+impl Format {
+    fn image_format(self) -> ImageFormat {
+        match self {
+            Self::Png => ImageFormat::Png,
+            Self::Jpeg => ImageFormat::Jpeg,
+            Self::Webp => ImageFormat::WebP,
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::Jpeg => "jpg",
+            Self::Webp => "webp",
+        }
+    }
+}
+
+impl ConvertArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let input = &self.input;
+        let output = match self.output {
+            Some(output) => output,
+            None => {
+                let output = input.with_extension(self.to.extension());
+                if &output == input {
+                    bail!(
+                        "Refusing to overwrite the input image; use --output \
+                         to choose a different path"
+                    );
+                }
+                output
+            }
+        };
+
+        let bytes = std::fs::read(input).with_context(|| {
+            format!("Failed to read image from file: {}", input.display())
+        })?;
+        let mut metadata =
+            Metadata::read(&bytes).context("Failed to read image metadata")?;
+        let image = image::load_from_memory(&bytes).with_context(|| {
+            format!("Failed to decode image: {}", input.display())
+        })?;
+
+        if matches!(self.to, Format::Webp) && self.compression != 100 {
+            warn!("Ignoring --compression; webp images are encoded losslessly");
+        }
+        let converted = imageops::encode_with_compression(
+            &image,
+            self.to.image_format(),
+            self.compression,
+        )?;
+
+        // Carry the metadata over to the new container
+        if !matches!(self.to, Format::Png) && !metadata.png_text.is_empty() {
+            warn!(
+                "Dropping {} png text chunk(s), which {} can't hold",
+                metadata.png_text.len(),
+                self.to.extension(),
+            );
+            metadata.png_text.clear();
+        }
+        if self.strip_c2pa {
+            metadata.c2pa = None;
+        }
+        let converted = metadata
+            .write(&converted)
+            .context("Failed to write image metadata")?;
+
+        std::fs::write(&output, converted).with_context(|| {
+            format!("Failed to write to: {}", output.display())
+        })?;
+        info!("Converted {} -> {}", input.display(), output.display());
+        Ok(())
+    }
+}
@@ -0,0 +1,567 @@
+//! `imgen csv`: run a grid of generation jobs from a spreadsheet.
+
+use anyhow::Context;
+use clap::Args;
+use log::{debug, info, warn};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use crate::{
+    cli::jobs::{self, JobResult},
+    client::Client,
+};
+/// The columns we understand, named like the `imgen jobs` JSON fields
+const COLUMNS: &[&str] = &[
+    "id",
+    "prompt",
+    "images",
+    "mask",
+    "output",
+    "n",
+    "size",
+    "quality",
+    "background",
+    "moderation",
+    "output_format",
+    "output_compression",
+];
+
+/// The columns holding numbers rather than text
+const NUMBER_COLUMNS: &[&str] = &["n", "output_compression"];
+
+/// The columns appended to each row in the results CSV
+const RESULT_COLUMNS: &[&str] = &["status", "saved", "cost", "error"];
+
+#[derive(Args, Debug)]
+pub struct CsvArgs {
+    /// A CSV file with a header row, and one job per row, or an https:// URL
+    /// to download it from (e.g. a Google Sheet published as CSV). Only the
+    /// "prompt" column is required.
+    ///
+    /// Columns are matched by header (case-insensitive) to the `imgen jobs`
+    /// fields: id, prompt, images, mask, output, n, size, quality,
+    /// background, moderation, output_format, output_compression.
+    /// Separate multiple images with ';'. Empty cells use the defaults, and
+    /// other columns (e.g. notes) are copied to the results as-is.
+    #[arg(value_name = "FILE|URL", verbatim_doc_comment)]
+    pub source: Source,
+
+    /// Where to write the results: the input rows, plus status, saved image
+    /// paths, cost, and error columns.
+    ///
+    /// [default: <FILE>.results.csv, or results.csv for a URL]
+    #[arg(short, long, value_name = "PATH", verbatim_doc_comment)]
+    pub results: Option<PathBuf>,
+
+    /// The number of jobs to run at once
+    #[arg(short = 'j', long, default_value_t = 1)]
+    pub concurrency: usize,
+
+    /// Keep checking the source for new or edited rows, and run just those.
+    ///
+    /// Rows already marked ok in the results file (e.g. from before a
+    /// restart) aren't run again. Runs until killed.
+    #[arg(long, verbatim_doc_comment)]
+    pub watch: bool,
+
+    /// How often to check the source with `--watch`, in seconds
+    #[arg(long, default_value_t = 60, requires = "watch")]
+    #[arg(value_name = "SECS")]
+    pub interval: u64,
+}
+
+/// Where to read a CSV job sheet from.
+#[derive(Clone, Debug)]
+pub enum Source {
+    File(PathBuf),
+    Url(String),
+}
+
+/// A parsed CSV job sheet.
+struct Sheet {
+    headers: csv::StringRecord,
+    /// The known column each header maps to, if any
+    columns: Vec<Option<&'static str>>,
+    /// The rows, padded to the number of headers
+    rows: Vec<csv::StringRecord>,
+}
+
+/// A row's contents, to recognize rows we've already run.
+type RowKey = Vec<String>;
+
+impl CsvArgs {
+    pub fn run(self, client: &Client) -> anyhow::Result<()> {
+        let results_path =
+            self.results.unwrap_or_else(|| self.source.results_path());
+
+        // The number of times we've run each distinct row
+        let mut done = HashMap::<RowKey, usize>::new();
+        let mut results = Vec::new();
+        if self.watch {
+            for row in load_results(&results_path)? {
+                let (input, status) = split_result_row(&row);
+                if status == "ok" {
+                    *done.entry(input).or_default() += 1;
+                    results.push(row);
+                }
+            }
+            if !results.is_empty() {
+                info!(
+                    "Skipping {} row(s) already done in {}",
+                    results.len(),
+                    results_path.display()
+                );
+            }
+        }
+
+        let mut failed = 0;
+        let mut first = true;
+        loop {
+            match self.source.read(client) {
+                Ok(sheet) => {
+                    let rows = sheet.take_new_rows(&mut done);
+                    if !rows.is_empty() {
+                        info!(
+                            "Running {} job(s) from {}",
+                            rows.len(),
+                            self.source
+                        );
+                        let new = sheet.run(&rows, client, self.concurrency);
+                        failed += new.iter().filter(|r| !r.ok).count();
+                        results.extend(
+                            rows.iter()
+                                .zip(&new)
+                                .map(|(row, result)| result_row(row, result)),
+                        );
+                        save_results(&results_path, &sheet.headers, &results)?;
+                        info!("Saved results to {}", results_path.display());
+                    }
+                }
+                // Keep watching through transient errors
+                Err(err) if self.watch && !first => {
+                    warn!("Failed to check {}: {err:#}", self.source)
+                }
+                Err(err) => return Err(err),
+            }
+            if !self.watch {
+                break;
+            }
+            first = false;
+            std::thread::sleep(Duration::from_secs(self.interval));
+        }
+
+        if failed > 0 {
+            warn!("{failed} job(s) failed");
+        }
+        Ok(())
+    }
+}
+
+impl Source {
+    fn read(&self, client: &Client) -> anyhow::Result<Sheet> {
+        match self {
+            Self::File(path) => {
+                let file = std::fs::File::open(path).with_context(|| {
+                    format!("Failed to open CSV file: {}", path.display())
+                })?;
+                Sheet::read(file)
+            }
+            Self::Url(url) => {
+                let bytes = client.download(url)?;
+                Sheet::read(bytes.as_slice())
+            }
+        }
+        .with_context(|| format!("Failed to read CSV: {self}"))
+    }
+
+    /// The default results path: `jobs.csv` -> `jobs.results.csv`.
+    fn results_path(&self) -> PathBuf {
+        match self {
+            Self::File(path) => path.with_extension("results.csv"),
+            Self::Url(_) => PathBuf::from("results.csv"),
+        }
+    }
+}
+
+impl FromStr for Source {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("https://") {
+            Ok(Self::Url(s.to_string()))
+        } else if s.starts_with("http://") {
+            anyhow::bail!("Only https:// URLs are supported")
+        } else {
+            Ok(Self::File(PathBuf::from(s)))
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File(path) => write!(f, "{}", path.display()),
+            Self::Url(url) => f.write_str(url),
+        }
+    }
+}
+
+impl Sheet {
+    fn read(reader: impl Read) -> anyhow::Result<Self> {
+        let mut reader =
+            csv::ReaderBuilder::new().flexible(true).from_reader(reader);
+        let headers = reader.headers()?.clone();
+        let columns: Vec<_> = headers.iter().map(column_for).collect();
+        if !columns.contains(&Some("prompt")) {
+            anyhow::bail!("Missing a \"prompt\" column");
+        }
+        for (header, column) in headers.iter().zip(&columns) {
+            if column.is_none() {
+                debug!("Copying unknown column \"{header}\" to the results");
+            }
+        }
+        let rows = reader
+            .records()
+            .map(|row| {
+                let mut row = row?;
+                // Pad short rows so the result columns line up
+                while row.len() < headers.len() {
+                    row.push_field("");
+                }
+                Ok(row)
+            })
+            .collect::<Result<_, csv::Error>>()?;
+        Ok(Self {
+            headers,
+            columns,
+            rows,
+        })
+    }
+
+    /// The rows not yet in `done`, which are then marked done. Repeated rows
+    /// are each run once.
+    fn take_new_rows(
+        &self,
+        done: &mut HashMap<RowKey, usize>,
+    ) -> Vec<&csv::StringRecord> {
+        let mut seen = HashMap::<RowKey, usize>::new();
+        let rows: Vec<_> = self
+            .rows
+            .iter()
+            .filter(|row| {
+                let key = row_key(row);
+                let done = done.get(&key).copied().unwrap_or(0);
+                let seen = seen.entry(key).or_default();
+                *seen += 1;
+                *seen > done
+            })
+            .collect();
+        for row in &rows {
+            *done.entry(row_key(row)).or_default() += 1;
+        }
+        rows
+    }
+
+    /// Run `rows` on `concurrency` workers, returning the results in row
+    /// order.
+    fn run(
+        &self,
+        rows: &[&csv::StringRecord],
+        client: &Client,
+        concurrency: usize,
+    ) -> Vec<JobResult> {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::with_capacity(rows.len()));
+        std::thread::scope(|scope| {
+            for _ in 0..concurrency.max(1) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(row) = rows.get(i) else { break };
+                    let line = row.position().map_or(0, |pos| {
+                        usize::try_from(pos.line()).unwrap_or_default()
+                    });
+                    let json = self.job_json(row).to_string();
+                    let result = jobs::run_job(client, line, &json);
+                    results.lock().unwrap().push((i, result));
+                });
+            }
+        });
+
+        let mut results = results.into_inner().unwrap();
+        results.sort_by_key(|(i, _)| *i);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Convert a row to an `imgen jobs` JSON object, skipping empty cells.
+    fn job_json(&self, row: &csv::StringRecord) -> serde_json::Value {
+        let mut job = serde_json::Map::new();
+        for (column, cell) in self.columns.iter().zip(row) {
+            let (Some(column), cell) = (column, cell.trim()) else {
+                continue;
+            };
+            if cell.is_empty() {
+                continue;
+            }
+            let value = match *column {
+                "images" => cell
+                    .split(';')
+                    .map(str::trim)
+                    .filter(|path| !path.is_empty())
+                    .map(serde_json::Value::from)
+                    .collect(),
+                // Leave invalid numbers as text, for the job to report
+                column if NUMBER_COLUMNS.contains(&column) => cell
+                    .parse::<u64>()
+                    .map_or_else(|_| cell.into(), serde_json::Value::from),
+                _ => cell.into(),
+            };
+            job.insert(column.to_string(), value);
+        }
+        job.into()
+    }
+}
+
+fn row_key(row: &csv::StringRecord) -> RowKey {
+    row.iter().map(str::to_string).collect()
+}
+
+/// An input row with its result columns appended.
+fn result_row(
+    row: &csv::StringRecord,
+    result: &JobResult,
+) -> csv::StringRecord {
+    let mut row = row.clone();
+    let (saved, cost) = match &result.record {
+        Some(record) => (
+            record
+                .images
+                .iter()
+                .filter_map(|image| image.path.as_deref())
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(";"),
+            format!("{:.4}", record.cost),
+        ),
+        None => (String::new(), String::new()),
+    };
+    row.push_field(if result.ok { "ok" } else { "failed" });
+    row.push_field(&saved);
+    row.push_field(&cost);
+    row.push_field(result.error.as_deref().unwrap_or_default());
+    row
+}
+
+/// Split a results row into its input row's key and its status.
+fn split_result_row(row: &csv::StringRecord) -> (RowKey, &str) {
+    let inputs = row.len().saturating_sub(RESULT_COLUMNS.len());
+    let status = row.get(inputs).unwrap_or_default();
+    (
+        row.iter().take(inputs).map(str::to_string).collect(),
+        status,
+    )
+}
+
+/// Write the results CSV: the input headers plus the result columns, then
+/// each result row.
+fn write_results(
+    writer: impl Write,
+    headers: &csv::StringRecord,
+    rows: &[csv::StringRecord],
+) -> anyhow::Result<()> {
+    let mut writer =
+        csv::WriterBuilder::new().flexible(true).from_writer(writer);
+    let mut headers = headers.clone();
+    headers.extend(RESULT_COLUMNS);
+    writer.write_record(&headers)?;
+    for row in rows {
+        writer.write_record(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn save_results(
+    path: &Path,
+    headers: &csv::StringRecord,
+    rows: &[csv::StringRecord],
+) -> anyhow::Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create: {}", path.display()))?;
+    write_results(file, headers, rows)
+        .with_context(|| format!("Failed to write results: {}", path.display()))
+}
+
+/// Load the rows of an earlier results CSV. A missing file has none.
+fn load_results(path: &Path) -> anyhow::Result<Vec<csv::StringRecord>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Vec::new())
+        }
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to open: {}", path.display()))
+        }
+    };
+    csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(file)
+        .records()
+        .collect::<Result<_, _>>()
+        .with_context(|| format!("Failed to read: {}", path.display()))
+}
+
+/// Match a header to a known column, ignoring case and treating spaces and
+/// dashes like underscores (e.g. "Output Format").
+fn column_for(header: &str) -> Option<&'static str> {
+    let name = header.trim().to_lowercase().replace([' ', '-'], "_");
+    let name = match name.as_str() {
+        "image" => "images",
+        name => name,
+    };
+    COLUMNS.iter().copied().find(|column| *column == name)
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::RunRecord;
+
+    const SHEET: &str = "\
+Prompt,Output Format,n,Image,Notes
+A cat,webp,2,cat.png; hat.png,for the blog
+A dog,,x,,
+";
+
+    #[test]
+    fn test_column_for() {
+        assert_eq!(column_for(" Prompt "), Some("prompt"));
+        assert_eq!(column_for("output-format"), Some("output_format"));
+        assert_eq!(column_for("Image"), Some("images"));
+        assert_eq!(column_for("notes"), None);
+    }
+
+    #[test]
+    fn test_job_json() {
+        let sheet = Sheet::read(SHEET.as_bytes()).unwrap();
+        assert_eq!(
+            sheet.job_json(&sheet.rows[0]),
+            serde_json::json!({
+                "prompt": "A cat",
+                "output_format": "webp",
+                "n": 2,
+                "images": ["cat.png", "hat.png"],
+            })
+        );
+        assert_eq!(
+            sheet.job_json(&sheet.rows[1]),
+            serde_json::json!({ "prompt": "A dog", "n": "x" })
+        );
+
+        let err = Sheet::read("name,size\nA cat,square\n".as_bytes());
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_write_results() {
+        let sheet = Sheet::read(SHEET.as_bytes()).unwrap();
+        let record: RunRecord = serde_json::from_value(serde_json::json!({
+            "created": 0,
+            "model": "gpt-image-1",
+            "prompt": "A cat",
+            "images": [
+                {"path": "a.webp", "cost": 0.1},
+                {"path": "b.webp", "cost": 0.1},
+            ],
+            "usage": {
+                "total_tokens": 2,
+                "input_tokens": 1,
+                "output_tokens": 1,
+                "input_tokens_details": {"text_tokens": 1, "image_tokens": 0},
+            },
+            "cost": 0.2,
+        }))
+        .unwrap();
+        let results = [
+            JobResult {
+                line: 2,
+                id: None,
+                ok: true,
+                record: Some(record),
+                b64_json: Vec::new(),
+                error: None,
+            },
+            JobResult {
+                line: 3,
+                id: None,
+                ok: false,
+                record: None,
+                b64_json: Vec::new(),
+                error: Some("Invalid job".to_string()),
+            },
+        ];
+
+        let rows: Vec<_> = sheet
+            .rows
+            .iter()
+            .zip(&results)
+            .map(|(row, result)| result_row(row, result))
+            .collect();
+        let mut out = Vec::new();
+        write_results(&mut out, &sheet.headers, &rows).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\
+Prompt,Output Format,n,Image,Notes,status,saved,cost,error
+A cat,webp,2,cat.png; hat.png,for the blog,ok,a.webp;b.webp,0.2000,
+A dog,,x,,,failed,,,Invalid job
+"
+        );
+        assert_eq!(split_result_row(&rows[0]), (row_key(&sheet.rows[0]), "ok"));
+    }
+
+    #[test]
+    fn test_take_new_rows() {
+        let sheet =
+            Sheet::read("prompt\nA cat\nA dog\nA cat\n".as_bytes()).unwrap();
+        let mut done = HashMap::new();
+        assert_eq!(sheet.take_new_rows(&mut done).len(), 3);
+        assert!(sheet.take_new_rows(&mut done).is_empty());
+
+        // New and repeated rows run; edited rows count as new
+        let sheet =
+            Sheet::read("prompt\nA cat\nA dog!\nA cat\nA cat\n".as_bytes())
+                .unwrap();
+        let new: Vec<_> = sheet
+            .take_new_rows(&mut done)
+            .into_iter()
+            .map(|row| &row[0])
+            .collect();
+        assert_eq!(new, ["A dog!", "A cat"]);
+    }
+
+    #[test]
+    fn test_source() {
+        assert!(matches!(
+            "https://example.com/a.csv".parse(),
+            Ok(Source::Url(_))
+        ));
+        assert!("http://example.com/a.csv".parse::<Source>().is_err());
+        let source: Source = "dir/jobs.csv".parse().unwrap();
+        assert_eq!(
+            source.results_path(),
+            PathBuf::from("dir/jobs.results.csv")
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! `imgen daily`: generate an image from the next prompt in a list, for
+//! scheduled runs (e.g. from cron).
+//!
+//! Which prompt is next is kept in the data directory, per prompt file. It
+//! only advances on success, so a failed run retries the same prompt.
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use log::{debug, LevelFilter};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::{input::PromptArg, Cli},
+    client::Client,
+    config::{self, Config},
+    events::Events,
+    history,
+    pricing::{self, Quality},
+};
+
+const STATE_FILE_NAME: &str = "daily.json";
+
+#[derive(clap::Args, Debug)]
+pub struct DailyArgs {
+    /// A text file with one prompt per line, used in turn. Blank lines and
+    /// lines starting with '#' are skipped.
+    #[arg(long, value_name = "PATH")]
+    pub prompt_file: PathBuf,
+
+    /// What the images are for, which sets their size and quality
+    #[arg(long, value_enum, default_value = "wallpaper")]
+    pub preset: Preset,
+
+    /// The directory to save images in. Defaults to `daily` in the data
+    /// directory (`~/.local/share/imgen/daily`).
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Skip the run if it would take this month's spending over this many
+    /// USD. Defaults to "monthly_budget" in the config file, if set.
+    #[arg(long, value_name = "USD")]
+    pub budget: Option<f64>,
+}
+
+/// Settings bundles for `imgen daily`.
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Preset {
+    /// Landscape, high quality, and set as the desktop wallpaper
+    Wallpaper,
+    /// Square, medium quality, e.g. for a profile picture
+    Avatar,
+    /// Portrait, high quality
+    Poster,
+}
+
+impl Preset {
+    /// The size and quality of the preset's images.
+    fn settings(self) -> (&'static str, Quality) {
+        match self {
+            Self::Wallpaper => ("1536x1024", Quality::High),
+            Self::Avatar => ("1024x1024", Quality::Medium),
+            Self::Poster => ("1024x1536", Quality::High),
+        }
+    }
+}
+
+/// The index of the next prompt to use, for each prompt file.
+#[derive(Default, Serialize, Deserialize)]
+struct State {
+    next: BTreeMap<PathBuf, usize>,
+}
+
+impl DailyArgs {
+    pub fn run(self, client: &Client) -> anyhow::Result<()> {
+        // Stay quiet on success, so cron only sends mail when something's
+        // wrong. `-v` still shows the usual progress.
+        if log::max_level() == LevelFilter::Info {
+            log::set_max_level(LevelFilter::Warn);
+        }
+
+        let text =
+            std::fs::read_to_string(&self.prompt_file).with_context(|| {
+                format!(
+                    "Failed to read prompt file: {}",
+                    self.prompt_file.display()
+                )
+            })?;
+        let prompts = parse_prompts(&text);
+        if prompts.is_empty() {
+            bail!("No prompts in: {}", self.prompt_file.display());
+        }
+
+        let state_path = state_path()?;
+        let mut state = load_state(&state_path)?;
+        let key = std::path::absolute(&self.prompt_file)?;
+        let index = state.next.get(&key).copied().unwrap_or(0) % prompts.len();
+        let prompt = prompts[index];
+        debug!("Prompt {} of {}: {prompt}", index + 1, prompts.len());
+
+        let (size, quality) = self.preset.settings();
+        let config = Config::load();
+        let budget = match (self.budget, config.locked.monthly_budget) {
+            (Some(budget), Some(locked)) if budget > locked => bail!(
+                "--budget can't be over the ${locked:.2} budget locked by the \
+                 system config"
+            ),
+            (budget, _) => budget.or(config.monthly_budget),
+        };
+        if let Some(budget) = budget {
+            check_budget(budget, prompt, size, quality)?;
+        }
+
+        let dir = match self.output_dir {
+            Some(dir) => dir,
+            None => config::data_dir()
+                .context("Couldn't find the data directory; set --output-dir")?
+                .join("daily"),
+        };
+        std::fs::create_dir_all(&dir).with_context(|| {
+            format!("Failed to create output directory: {}", dir.display())
+        })?;
+        // Automatically named images are saved in the current directory
+        std::env::set_current_dir(&dir).with_context(|| {
+            format!("Failed to enter output directory: {}", dir.display())
+        })?;
+
+        // Start from the CLI defaults
+        let mut args = Cli::try_parse_from(["imgen", ""])
+            .expect("Default arguments should parse")
+            .args;
+        args.prompt = Some(PromptArg::Literal(prompt.to_string()));
+        args.size = size.to_string();
+        args.quality = quality.as_str().to_string();
+        args.set_wallpaper = self.preset == Preset::Wallpaper;
+        // Include the cause, since only the top-level error is logged
+        if let Err(err) =
+            args.run(client, &Events::new(false, None), None, None)
+        {
+            bail!(
+                "Failed to generate prompt {} of {} from {}: {err:#}",
+                index + 1,
+                prompts.len(),
+                self.prompt_file.display()
+            );
+        }
+
+        state.next.insert(key, index + 1);
+        save_state(&state_path, &state)
+    }
+}
+
+/// The prompts in a prompt file: its non-blank lines, minus `#` comments.
+fn parse_prompts(text: &str) -> Vec<&str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+/// Fail if generating the image could take this month's spending over
+/// `budget`.
+fn check_budget(
+    budget: f64,
+    prompt: &str,
+    size: &str,
+    quality: Quality,
+) -> anyhow::Result<()> {
+    let spent = history::monthly_spend()
+        .context("Failed to read the history for the budget check")?;
+    let estimate = pricing::for_model("gpt-image-1")
+        .and_then(|pricing| {
+            let prompt_tokens = pricing::estimate_text_tokens(prompt);
+            pricing.estimate_cost(quality, size, 1, prompt_tokens)
+        })
+        .unwrap_or(0.0);
+    if spent + estimate > budget {
+        bail!(
+            "Skipping: spent ${spent:.2} this month, and another image \
+             (~${estimate:.2}) would go over the ${budget:.2} budget"
+        );
+    }
+    Ok(())
+}
+
+fn state_path() -> anyhow::Result<PathBuf> {
+    let dir = config::data_dir().context("Couldn't find the data directory")?;
+    Ok(dir.join(STATE_FILE_NAME))
+}
+
+fn load_state(path: &Path) -> anyhow::Result<State> {
+    match std::fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).with_context(|| {
+            format!("Invalid daily state file: {}", path.display())
+        }),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            Ok(State::default())
+        }
+        Err(err) => Err(anyhow::Error::new(err).context(format!(
+            "Failed to read daily state file: {}",
+            path.display()
+        ))),
+    }
+}
+
+fn save_state(path: &Path, state: &State) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let json = serde_json::to_string_pretty(state)
+        .expect("Failed to serialize daily state");
+    std::fs::write(path, json).with_context(|| {
+        format!("Failed to write daily state file: {}", path.display())
+    })
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prompts() {
+        let text = "# Themes\nautumn forest\n\n  misty harbor at dawn \n#off\n";
+        assert_eq!(
+            parse_prompts(text),
+            ["autumn forest", "misty harbor at dawn"]
+        );
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("nested").join(STATE_FILE_NAME);
+        assert!(load_state(&path).unwrap().next.is_empty());
+
+        let mut state = State::default();
+        state.next.insert(PathBuf::from("/themes.txt"), 3);
+        save_state(&path, &state).unwrap();
+        let loaded = load_state(&path).unwrap();
+        assert_eq!(loaded.next[Path::new("/themes.txt")], 3);
+    }
+}
@@ -0,0 +1,319 @@
+//! `--detach` and `imgen attach`: generate an image in the background with
+//! the Responses API, and pick up the result later. Slow, high quality
+//! renders then survive a dropped connection or a closed laptop lid.
+//!
+//! Detached jobs are kept in the data directory
+//! (`~/.local/share/imgen/detached`) until they're attached.
+
+use anyhow::{bail, Context};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    api::{
+        DecodedResponse, ImageGenerationTool, Response, ResponsesRequest,
+        ResponsesResponse, Usage,
+    },
+    cli::{
+        background_canonical, build_prompt, input, moderation_canonical,
+        quality_canonical, sanitize, size_canonical, GenerateArgs,
+    },
+    client::{Client, ClientError},
+    config::{self, Config},
+    history,
+    pricing::{self, Quality},
+    record::{Environment, ImageRecord, RunRecord},
+};
+
+const DIR_NAME: &str = "detached";
+
+/// The mainline model for background requests, which calls the image
+/// generation tool.
+pub const RESPONSES_MODEL: &str = "gpt-4.1-mini";
+
+/// The image model the image generation tool uses.
+pub const IMAGE_MODEL: &str = "gpt-image-1";
+
+/// How often to check on a background response.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(clap::Args, Debug)]
+pub struct AttachArgs {
+    /// The job ID printed by `imgen --detach`. Lists the detached jobs if
+    /// not given.
+    pub id: Option<String>,
+}
+
+/// A detached job, saved until it's attached.
+#[derive(Debug, Deserialize, Serialize)]
+struct Job {
+    /// The Responses API response ID
+    id: String,
+    /// The Unix timestamp (in seconds) of when the job was started
+    created: u64,
+    prompt: String,
+    size: String,
+    quality: String,
+    output_format: String,
+    /// The `--output` file, if any
+    output: Option<PathBuf>,
+    /// The directory to save automatically named images in
+    dir: PathBuf,
+}
+
+/// Start generating in the background, print the job ID, and return.
+pub fn detach(client: &Client, mut args: GenerateArgs) -> anyhow::Result<()> {
+    if let Some(intent) = args.intent {
+        args.apply_intent(intent);
+    }
+    let prompt_source = match args.subject.take() {
+        Some(subject) => input::PromptArg::Literal(subject),
+        None => args.prompt.take().context("Missing prompt")?,
+    };
+    let prompt = build_prompt(
+        prompt_source.read_prompt()?,
+        &[
+            ("Style", args.style.as_deref()),
+            ("Lighting", args.lighting.as_deref()),
+            ("Camera", args.camera.as_deref()),
+            ("Mood", args.mood.as_deref()),
+        ],
+    );
+    if args.model != IMAGE_MODEL {
+        bail!("--detach only supports {IMAGE_MODEL}");
+    }
+    if args.n != 1 {
+        bail!("--detach generates one image at a time");
+    }
+    let dir = std::env::current_dir()?;
+    let output = match args.output.take() {
+        None => None,
+        Some(input::OutputArg::File(path)) => Some(dir.join(path)),
+        Some(_) => bail!("--detach can only --output to a file"),
+    };
+    let config = Config::load();
+    super::apply_locked(&config.locked, &mut args.moderation, false)?;
+
+    let request = ResponsesRequest {
+        model: RESPONSES_MODEL.to_string(),
+        input: prompt.clone(),
+        tools: vec![ImageGenerationTool {
+            kind: "image_generation".to_string(),
+            size: size_canonical(args.size.clone()),
+            quality: quality_canonical(args.quality.clone()),
+            background: background_canonical(args.background),
+            moderation: moderation_canonical(args.moderation),
+            output_compression: Some(args.output_compression),
+            output_format: Some(args.output_format.clone()),
+            partial_images: None,
+        }],
+        tool_choice: serde_json::json!({ "type": "image_generation" }),
+        background: true,
+        stream: false,
+        previous_response_id: None,
+    };
+    let response = client.create_response(&request)?;
+
+    let job = Job {
+        id: response.id,
+        created: now(),
+        prompt,
+        size: args.size,
+        quality: args.quality,
+        output_format: args.output_format,
+        output,
+        dir,
+    };
+    let path = job_path(&job.id)?;
+    let json = serde_json::to_vec_pretty(&job)?;
+    std::fs::write(&path, json)
+        .with_context(|| format!("Failed to write: {}", path.display()))?;
+    info!("Detached; run `imgen attach {}` for the result", job.id);
+    println!("{}", job.id);
+    Ok(())
+}
+
+impl AttachArgs {
+    pub fn run(self, client: &Client) -> anyhow::Result<()> {
+        let Some(id) = self.id else {
+            return list();
+        };
+        let path = job_path(&id)?;
+        let json = std::fs::read(&path)
+            .with_context(|| format!("No detached job with ID: {id}"))?;
+        let job: Job = serde_json::from_slice(&json)
+            .with_context(|| format!("Invalid job file: {}", path.display()))?;
+
+        let response = poll(client, &job.id)?;
+        let result = match response.status.as_str() {
+            "completed" => save(&job, &response),
+            status => {
+                let reason = response
+                    .error
+                    .map(|error| error.message)
+                    .unwrap_or_else(|| status.to_string());
+                Err(anyhow::anyhow!("The job didn't complete: {reason}"))
+            }
+        };
+        // Done with the job either way, unless saving failed
+        let save_failed = result.is_err() && response.status == "completed";
+        if !save_failed {
+            std::fs::remove_file(&path).with_context(|| {
+                format!("Failed to remove: {}", path.display())
+            })?;
+        }
+        result
+    }
+}
+
+/// Wait for a background response to finish. Connection errors are retried,
+/// since the point is to survive a flaky connection.
+fn poll(client: &Client, id: &str) -> anyhow::Result<ResponsesResponse> {
+    loop {
+        match client.get_response(id) {
+            Ok(response) if response.is_pending() => {
+                info!("{id}: {}...", response.status)
+            }
+            Ok(response) => return Ok(response),
+            Err(ClientError::ApiError {
+                status, message, ..
+            }) => {
+                bail!("Failed to check on {id} ({status}): {message}")
+            }
+            Err(err) => warn!("Failed to check on {id}: {err}; retrying"),
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Save a completed job's images, and record it in the history.
+fn save(job: &Job, response: &ResponsesResponse) -> anyhow::Result<()> {
+    let data = response.images();
+    if data.is_empty() {
+        bail!("The job completed without an image");
+    }
+    let response = DecodedResponse::try_from(Response {
+        created: job.created,
+        data,
+        usage: Usage::default(),
+    })
+    .context("Failed to decode base64 image data")?;
+
+    let config = Config::load();
+    let out_target = match &job.output {
+        Some(path) => input::OutputTargetWithData::File(path),
+        None => input::OutputTargetWithData::Automatic {
+            prefix: job
+                .dir
+                .join(sanitize::prompt_prefix(&job.prompt, &config.filenames))
+                .display()
+                .to_string(),
+            extension: &job.output_format,
+            filenames: &config.filenames,
+        },
+    };
+    let fallback_dir = config
+        .fallback_dir
+        .clone()
+        .unwrap_or_else(|| std::env::temp_dir().join("imgen"));
+    let existing = vec![None; response.data.len()];
+    let saved = response.save_images(out_target, &existing, &fallback_dir)?;
+
+    // The image tool's usage isn't reported, so estimate the cost
+    let cost = pricing::for_model(IMAGE_MODEL)
+        .and_then(|pricing| {
+            let size = size_canonical(job.size.clone())
+                .unwrap_or("1024x1024".to_string());
+            let quality =
+                Quality::from_name(&job.quality).unwrap_or(Quality::High);
+            let prompt_tokens = pricing::estimate_text_tokens(&job.prompt);
+            pricing.estimate_cost(quality, &size, 1, prompt_tokens)
+        })
+        .unwrap_or(0.0);
+    let mut images = Vec::new();
+    for (data, result) in response.data.iter().zip(saved) {
+        let path = result?;
+        info!("Saved {}", path.display());
+        images.push(ImageRecord {
+            path: Some(path),
+            revised_prompt: data.revised_prompt.clone(),
+            alt_text: None,
+            sha256: Some(data.image.sha256_hex()?),
+            ipfs_cid: None,
+            signature: None,
+            cost,
+        });
+    }
+    let record = RunRecord {
+        created: job.created,
+        model: IMAGE_MODEL.to_string(),
+        prompt: job.prompt.clone(),
+        original_prompt: None,
+        images,
+        usage: Usage::default(),
+        cost,
+        size: Some(job.size.clone()),
+        quality: Some(job.quality.clone()),
+        duration: None,
+        environment: Some(Environment::capture("openai")),
+    };
+    if let Err(err) = history::append(&record) {
+        warn!("Failed to record run in history: {err:#}");
+    }
+    Ok(())
+}
+
+/// Print the detached jobs, oldest first.
+fn list() -> anyhow::Result<()> {
+    let dir = jobs_dir()?;
+    let mut jobs: Vec<Job> = std::fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read: {}", dir.display()))?
+        .filter_map(|entry| {
+            let json = std::fs::read(entry.ok()?.path()).ok()?;
+            serde_json::from_slice(&json).ok()
+        })
+        .collect();
+    if jobs.is_empty() {
+        info!("No detached jobs");
+        return Ok(());
+    }
+    jobs.sort_by_key(|job| job.created);
+    for job in jobs {
+        let started = jiff::Timestamp::from_second(job.created as i64)
+            .map(|time| {
+                let time = time.to_zoned(jiff::tz::TimeZone::system());
+                time.strftime("%Y-%m-%d %H:%M").to_string()
+            })
+            .unwrap_or_default();
+        println!("{}  {started}  {}", job.id, job.prompt);
+    }
+    Ok(())
+}
+
+fn jobs_dir() -> anyhow::Result<PathBuf> {
+    let dir = config::data_dir()
+        .context("Could not determine the data directory")?
+        .join(DIR_NAME);
+    std::fs::create_dir_all(&dir)
+        .with_context(|| format!("Failed to create: {}", dir.display()))?;
+    Ok(dir)
+}
+
+fn job_path(id: &str) -> anyhow::Result<PathBuf> {
+    // The ID comes from the command line, so keep it in the jobs directory
+    if id.is_empty() || !id.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        bail!("Invalid job ID: {id}");
+    }
+    Ok(jobs_dir()?.join(Path::new(id).with_extension("json")))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,183 @@
+//! `--dry-run`: print the request that would be sent, and roughly what it
+//! would cost, without sending it. Shows the options as the API would see
+//! them, after canonicalization and the model's capabilities.
+
+use crate::{
+    api::{self, CreateRequest, EditRequest, FormField, Usage},
+    client::Client,
+    pricing::{self, Quality},
+    record::RunRecord,
+};
+
+/// Print a create request as the JSON body we'd send.
+pub fn create(
+    client: &Client,
+    req: &CreateRequest,
+    split_n: bool,
+) -> anyhow::Result<RunRecord> {
+    print_endpoint(client, "images/generations");
+    println!("{}", serde_json::to_string_pretty(req)?);
+    let estimate = Estimate::new(
+        &req.model,
+        req.quality.as_deref(),
+        req.size.as_deref(),
+        req.n,
+        &req.prompt,
+    );
+    estimate.print(req.n, split_n, "");
+    Ok(estimate.record(req.model.clone(), req.prompt.clone()))
+}
+
+/// Print an edit request's multipart form fields, with the files' sizes
+/// rather than their contents.
+pub fn edit(
+    client: &Client,
+    req: &EditRequest,
+    split_n: bool,
+) -> anyhow::Result<RunRecord> {
+    print_endpoint(client, "images/edits");
+    for (name, field) in req.form_fields() {
+        match field {
+            FormField::Text(value) => println!("{name}: {value:?}"),
+            FormField::File(image) => println!(
+                "{name}: {} ({}, {} bytes)",
+                image.filename.display(),
+                image.content_type,
+                image.bytes.len()
+            ),
+        }
+    }
+    let estimate = Estimate::new(
+        &req.model,
+        req.quality.as_deref(),
+        req.size.as_deref(),
+        req.n,
+        &req.prompt,
+    );
+    estimate.print(req.n, split_n, ", plus the input images' tokens");
+    Ok(estimate.record(req.model.clone(), req.prompt.clone()))
+}
+
+fn print_endpoint(client: &Client, endpoint: &str) {
+    match client.provider_name() {
+        "openai" => println!("Dry run (not sent): POST {endpoint}\n"),
+        provider => println!(
+            "Dry run (not sent): {endpoint}, which {provider} maps to its \
+             own API\n"
+        ),
+    }
+}
+
+/// The estimated cost of a request, from its prompt and output tokens.
+#[derive(Debug, PartialEq)]
+struct Estimate {
+    /// `None` if the model has no pricing
+    cost: Option<f64>,
+    /// What we assumed for "auto" options
+    notes: Vec<&'static str>,
+    size: Option<String>,
+    quality: Option<String>,
+}
+
+impl Estimate {
+    fn new(
+        model: &str,
+        quality: Option<&str>,
+        size: Option<&str>,
+        n: Option<u8>,
+        prompt: &str,
+    ) -> Self {
+        let mut notes = Vec::new();
+        // The API picks "auto" options; assume the most expensive quality,
+        // and the cheapest (square) size
+        let assumed_quality = match quality.and_then(Quality::from_name) {
+            Some(quality) => quality,
+            None => {
+                notes.push("assuming high quality");
+                Quality::High
+            }
+        };
+        let assumed_size = match size.filter(|size| *size != "auto") {
+            Some(size) => size,
+            None => {
+                notes.push("assuming 1024x1024");
+                "1024x1024"
+            }
+        };
+        let cost = pricing::for_model(model).and_then(|pricing| {
+            pricing.estimate_cost(
+                assumed_quality,
+                assumed_size,
+                n.unwrap_or(1),
+                pricing::estimate_text_tokens(prompt),
+            )
+        });
+        Self {
+            cost,
+            notes,
+            size: size.map(str::to_string),
+            quality: quality.map(str::to_string),
+        }
+    }
+
+    fn print(&self, n: Option<u8>, split_n: bool, extra: &str) {
+        println!();
+        let n = n.unwrap_or(1);
+        if split_n && n > 1 {
+            println!("Sent as {n} parallel single-image requests");
+        }
+        let notes = match self.notes.as_slice() {
+            [] => String::new(),
+            notes => format!(" ({})", notes.join(", ")),
+        };
+        match self.cost {
+            Some(cost) => println!("Estimated cost: ~${cost:.3}{extra}{notes}"),
+            None => {
+                println!("Estimated cost: unknown (no pricing for the model)")
+            }
+        }
+    }
+
+    /// A record of the run, without images.
+    fn record(self, model: String, prompt: String) -> RunRecord {
+        RunRecord {
+            created: api::unix_now(),
+            model,
+            prompt,
+            original_prompt: None,
+            images: Vec::new(),
+            usage: Usage::default(),
+            cost: self.cost.unwrap_or(0.0),
+            size: self.size,
+            quality: self.quality,
+            duration: None,
+            environment: None,
+        }
+    }
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate() {
+        let prompt = "A cute cat";
+        let low = Estimate::new(
+            "gpt-image-1",
+            Some("low"),
+            Some("1024x1024"),
+            Some(2),
+            prompt,
+        );
+        let auto = Estimate::new("gpt-image-1", None, None, Some(2), prompt);
+        assert!(low.notes.is_empty());
+        assert_eq!(auto.notes, ["assuming high quality", "assuming 1024x1024"]);
+        assert!(low.cost.unwrap() < auto.cost.unwrap());
+
+        let unknown = Estimate::new("flux-pro", None, None, None, prompt);
+        assert_eq!(unknown.cost, None);
+    }
+}
@@ -0,0 +1,150 @@
+//! `imgen history`: inspect past runs recorded in the history file.
+
+use crate::{history, record::RunRecord};
+
+#[derive(clap::Args, Debug)]
+pub struct HistoryArgs {
+    #[command(subcommand)]
+    pub command: HistoryCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum HistoryCommand {
+    /// List recent images, newest first, with the IDs `--from` takes
+    List {
+        /// How many images to list
+        #[arg(short, long, default_value_t = 20)]
+        n: usize,
+    },
+    /// Show how text, image input, and output tokens trend across runs, for
+    /// creations vs. edits
+    Stats,
+}
+
+impl HistoryArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        match self.command {
+            HistoryCommand::List { n } => list(n),
+            HistoryCommand::Stats => stats(),
+        }
+    }
+}
+
+/// Token totals over a group of runs.
+#[derive(Default)]
+struct TokenStats {
+    runs: u32,
+    text_tokens: u64,
+    image_tokens: u64,
+    output_tokens: u64,
+    cost: f64,
+}
+
+impl TokenStats {
+    fn add(&mut self, record: &RunRecord) {
+        let details = &record.usage.input_tokens_details;
+        self.runs += 1;
+        self.text_tokens += u64::from(details.text_tokens);
+        self.image_tokens += u64::from(details.image_tokens);
+        self.output_tokens += u64::from(record.usage.output_tokens);
+        self.cost += record.cost;
+    }
+
+    /// Print a row of per-run averages.
+    fn print_row(&self, label: &str) {
+        let avg = |total: u64| total / u64::from(self.runs.max(1));
+        println!(
+            "{label:<10}{:>6}{:>10}{:>10}{:>10}{:>12}",
+            self.runs,
+            avg(self.text_tokens),
+            avg(self.image_tokens),
+            avg(self.output_tokens),
+            format!("${:.3}", self.cost / f64::from(self.runs.max(1))),
+        );
+    }
+}
+
+fn print_header(label: &str) {
+    println!(
+        "{label:<10}{:>6}{:>10}{:>10}{:>10}{:>12}",
+        "runs", "text", "image", "output", "cost"
+    );
+}
+
+fn stats() -> anyhow::Result<()> {
+    let records = history::load()?;
+    if records.is_empty() {
+        println!("No runs recorded in the history yet.");
+        return Ok(());
+    }
+
+    // Only edits send input images, so that's how we tell them apart
+    let (mut creates, mut edits) =
+        (TokenStats::default(), TokenStats::default());
+    let mut months: Vec<(String, TokenStats)> = Vec::new();
+    for record in &records {
+        if record.usage.input_tokens_details.image_tokens > 0 {
+            edits.add(record);
+        } else {
+            creates.add(record);
+        }
+
+        let month = history::month_label(record.created);
+        match months.last_mut() {
+            Some((last, stats)) if *last == month => stats.add(record),
+            _ => {
+                let mut stats = TokenStats::default();
+                stats.add(record);
+                months.push((month, stats));
+            }
+        }
+    }
+
+    println!("Average tokens and cost per run:\n");
+    print_header("kind");
+    creates.print_row("create");
+    edits.print_row("edit");
+
+    println!();
+    print_header("month");
+    for (month, stats) in &months {
+        stats.print_row(month);
+    }
+    Ok(())
+}
+
+fn list(n: usize) -> anyhow::Result<()> {
+    let records = history::load()?;
+    let images = records
+        .iter()
+        .rev()
+        .flat_map(|record| {
+            record.images.iter().rev().map(move |image| (record, image))
+        })
+        .take(n);
+    let mut empty = true;
+    for (record, image) in images {
+        empty = false;
+        let id = image
+            .sha256
+            .as_deref()
+            .map(|hash| &hash[..history::ID_LEN.min(hash.len())])
+            .unwrap_or("-");
+        let path = image
+            .path
+            .as_deref()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "(not saved)".to_string());
+        let created = jiff::Timestamp::from_second(record.created as i64)
+            .map(|time| {
+                let time = time.to_zoned(jiff::tz::TimeZone::system());
+                time.strftime("%Y-%m-%d %H:%M").to_string()
+            })
+            .unwrap_or_default();
+        println!("{id:<8}  {created}  {path}  {}", record.prompt);
+    }
+    if empty {
+        println!("No runs recorded in the history yet.");
+    }
+    Ok(())
+}
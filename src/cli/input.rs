@@ -1,11 +1,14 @@
 //! Prompt and image input handling
 
 use anyhow::{anyhow, Context};
-use std::io::Read;
+use log::debug;
+use std::io::{IsTerminal, Read};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use crate::cli::sanitize;
+use crate::compress::{self, Compression};
+use crate::config::Filenames;
 use crate::multipart;
 
 /// Parsed inputs from the command line. Ensures at most one input uses stdin.
@@ -25,19 +28,34 @@ pub enum PromptArg {
     Stdin,
 }
 
-/// Image inputs can be a file path or stdin ('-').
+/// Image inputs can be a file path or stdin ('-'). Gzip or zstd compressed
+/// stdin is decompressed automatically.
 #[derive(Clone, Debug)]
 pub enum ImageArg {
     File(PathBuf),
     Stdin,
 }
 
+/// The format of an image piped to stdin, for `--stdin-format`.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum StdinFormat {
+    Png,
+    #[value(alias = "jpg")]
+    Jpeg,
+    Webp,
+}
+
 /// Represents the parsed value of the `--output` argument *before* validation
 /// against other arguments like `-n`.
 #[derive(Clone, Debug)]
 pub enum OutputArg {
     File(PathBuf),
-    Stdout,
+    /// `-`, or `-.gz` / `-.zst` to compress the stream
+    Stdout(Option<Compression>),
+    /// `ipfs://`: pin to IPFS (and save automatically)
+    Ipfs,
+    /// An `sftp://` or `scp://` URL to upload to (and save automatically)
+    Remote(String),
 }
 
 /// Represents the validated output destination for the generated image(s).
@@ -46,19 +64,28 @@ pub enum OutputTarget {
     Automatic,
     /// Save to a specific file path. Only valid for n=1.
     File(PathBuf),
-    /// Write to standard output. Only valid for n=1.
-    Stdout,
+    /// Write to standard output, optionally compressed. Only valid for n=1.
+    Stdout(Option<Compression>),
+    /// Save automatically, and pin the image(s) to IPFS.
+    Ipfs,
+    /// Save automatically, and upload the image(s) over SSH. Only valid for
+    /// n=1, unless the URL is a directory.
+    Remote(String),
 }
 
 /// [`OutputTarget`] with additional data needed to write the output files.
 pub enum OutputTargetWithData<'a> {
-    Automatic { prefix: String, extension: &'a str },
+    Automatic {
+        prefix: String,
+        extension: &'a str,
+        filenames: &'a Filenames,
+    },
     File(&'a Path),
-    Stdout,
+    Stdout(Option<Compression>),
 }
 
 /// The read image data, including the raw bytes and metadata.
-#[cfg_attr(test, derive(Clone))]
+#[derive(Clone)]
 pub struct ImageData {
     pub bytes: Vec<u8>,
     pub filename: PathBuf,
@@ -72,6 +99,8 @@ impl InputArgs {
     ///
     /// * More than one input source uses stdin (`-`).
     /// * `--output` is specified (not automatic) but `n` is not 1.
+    /// * `--open`, or a flag that also writes to stdout (`stdout_flag`, e.g.
+    ///   `--json`), is used with `--output -` (stdout).
     pub fn new(
         prompt: PromptArg,
         images: Vec<ImageArg>,
@@ -79,6 +108,7 @@ impl InputArgs {
         output_arg: Option<OutputArg>,
         n: u8,
         open: bool,
+        stdout_flag: Option<&str>,
     ) -> anyhow::Result<Self> {
         // Only use stdin once across all inputs
         let prompt_stdin_count = matches!(prompt, PromptArg::Stdin) as usize;
@@ -108,23 +138,49 @@ impl InputArgs {
                 }
                 OutputTarget::File(path)
             }
-            Some(OutputArg::Stdout) => {
+            Some(OutputArg::Stdout(compression)) => {
                 if n != 1 {
                     return Err(anyhow!(
                         "Cannot use --output - (stdout) when generating more than one image (n={n})"
                     ));
                 }
-                OutputTarget::Stdout
+                // Binary data garbles a terminal, and Windows consoles only
+                // accept UTF-8 text anyway. Pipes and files get the raw
+                // bytes, without any newline translation.
+                if std::io::stdout().is_terminal() {
+                    return Err(anyhow!(
+                        "Refusing to write binary image data to the terminal; redirect `--output -` to a file or pipe"
+                    ));
+                }
+                OutputTarget::Stdout(compression)
+            }
+            Some(OutputArg::Ipfs) => OutputTarget::Ipfs,
+            Some(OutputArg::Remote(url)) => {
+                if n != 1 && !url.ends_with('/') {
+                    return Err(anyhow!(
+                        "Cannot upload more than one image (n={n}) to a single remote file; end the URL with '/' to upload into a directory"
+                    ));
+                }
+                OutputTarget::Remote(url)
             }
         };
 
         // Cannot use `--open` with `--output -` (stdout)
-        if open && matches!(out_target, OutputTarget::Stdout) {
+        if open && matches!(out_target, OutputTarget::Stdout(_)) {
             return Err(anyhow!(
                 "Cannot use --open flag when writing output to stdout (`--output -`)"
             ));
         }
 
+        // Cannot mix other stdout output with `--output -` (stdout)
+        if let Some(flag) = stdout_flag {
+            if matches!(out_target, OutputTarget::Stdout(_)) {
+                return Err(anyhow!(
+                    "Cannot use {flag} flag when writing output to stdout (`--output -`)"
+                ));
+            }
+        }
+
         Ok(Self {
             prompt,
             images,
@@ -169,8 +225,27 @@ impl FromStr for PromptArg {
     }
 }
 
+impl StdinFormat {
+    fn mime(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpeg => "image/jpeg",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
 impl ImageArg {
-    pub fn read_image(self) -> anyhow::Result<ImageData> {
+    pub fn is_stdin(&self) -> bool {
+        matches!(self, Self::Stdin)
+    }
+
+    /// Read the image. An image from stdin is `stdin_format`, if given, or
+    /// else whatever its magic bytes say.
+    pub fn read_image(
+        self,
+        stdin_format: Option<StdinFormat>,
+    ) -> anyhow::Result<ImageData> {
         match self {
             ImageArg::File(path) => {
                 let bytes = std::fs::read(&path).with_context(|| {
@@ -192,9 +267,25 @@ impl ImageArg {
                     .lock()
                     .read_to_end(&mut bytes)
                     .context("Failed to read image from stdin")?;
+                let bytes = compress::maybe_decompress(bytes)
+                    .context("Failed to read image from stdin")?;
 
-                // Infer the content type from the bytes we read off stdin.
-                let content_type = multipart::mime_from_bytes(&bytes);
+                // Infer the content type from the bytes we read off stdin,
+                // unless the user told us
+                let detected = multipart::mime_from_bytes(&bytes);
+                let content_type = match stdin_format {
+                    Some(format) => {
+                        if format.mime() != detected {
+                            debug!(
+                                "--stdin-format: using {}, though the data \
+                                 looks like {detected}",
+                                format.mime()
+                            );
+                        }
+                        format.mime()
+                    }
+                    None => detected,
+                };
 
                 // Use fake filename for stdin: "stdin.{png,jpg,webp}"
                 let mut filename = PathBuf::from("stdin");
@@ -213,6 +304,11 @@ impl ImageArg {
 impl FromStr for ImageArg {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // `-.gz` and `-.zst` are stdin too, for symmetry with `--output`.
+        // The compression is detected from the data either way.
+        if Compression::from_stream_arg(s).is_some() {
+            return Ok(Self::Stdin);
+        }
         match LiteralOrFileOrStdin::from_str(s)? {
             LiteralOrFileOrStdin::Literal(_) => Err(anyhow::anyhow!(
                 "Expected a file path or '-' for stdin for --image input"
@@ -240,13 +336,13 @@ impl FromStr for LiteralOrFileOrStdin {
         // Check if the string starts with '@' to indicate that the user
         // explicitly wants only a file path
         let (require_file, path) = if let Some(s) = s.strip_prefix('@') {
-            (true, Path::new(s))
+            (true, normalize_verbatim(s))
         } else {
-            (false, Path::new(s))
+            (false, normalize_verbatim(s))
         };
 
         if path.exists() {
-            Ok(LiteralOrFileOrStdin::File(PathBuf::from(path)))
+            Ok(LiteralOrFileOrStdin::File(path))
         } else if !require_file {
             Ok(LiteralOrFileOrStdin::Literal(String::from(s)))
         } else {
@@ -255,10 +351,28 @@ impl FromStr for LiteralOrFileOrStdin {
     }
 }
 
+/// Windows `\\?\` paths (for paths longer than 260 characters) skip the usual
+/// normalization, so `/` isn't a separator in them. Accept it anyway, as
+/// most Windows programs do.
+fn normalize_verbatim(path: &str) -> PathBuf {
+    match path.strip_prefix(r"\\?\") {
+        Some(rest) if rest.contains('/') => {
+            PathBuf::from(format!(r"\\?\{}", rest.replace('/', "\\")))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
 impl From<String> for OutputArg {
     fn from(s: String) -> Self {
         if s == "-" {
-            Self::Stdout
+            Self::Stdout(None)
+        } else if let Some(compression) = Compression::from_stream_arg(&s) {
+            Self::Stdout(Some(compression))
+        } else if s == "ipfs://" {
+            Self::Ipfs
+        } else if s.starts_with("sftp://") || s.starts_with("scp://") {
+            Self::Remote(s)
         } else if let Some(s) = s.strip_prefix('@') {
             Self::File(PathBuf::from(s))
         } else {
@@ -272,23 +386,23 @@ impl OutputTarget {
     /// the output.
     pub fn with_data<'a>(
         &'a self,
-        uses_edit_api: bool,
         prompt: &str,
         output_format: &'a str,
+        filenames: &'a Filenames,
     ) -> OutputTargetWithData<'a> {
         match self {
-            Self::Automatic => {
-                let prefix = sanitize::prompt_prefix(prompt);
-                let extension = if uses_edit_api {
-                    // "edit" API only supports PNG output
-                    "png"
-                } else {
-                    output_format
-                };
-                OutputTargetWithData::Automatic { prefix, extension }
+            Self::Automatic | Self::Ipfs | Self::Remote(_) => {
+                let prefix = sanitize::prompt_prefix(prompt, filenames);
+                OutputTargetWithData::Automatic {
+                    prefix,
+                    extension: output_format,
+                    filenames,
+                }
             }
             Self::File(path) => OutputTargetWithData::File(path),
-            Self::Stdout => OutputTargetWithData::Stdout,
+            Self::Stdout(compression) => {
+                OutputTargetWithData::Stdout(*compression)
+            }
         }
     }
 }
@@ -297,7 +411,40 @@ impl<'a> OutputTargetWithData<'a> {
     pub fn file_path(&self) -> Option<&'a Path> {
         match self {
             Self::File(path) => Some(path),
-            Self::Automatic { .. } | Self::Stdout => None,
+            Self::Automatic { .. } | Self::Stdout(_) => None,
         }
     }
+
+    /// The directory the image(s) will be saved in. `None` for stdout.
+    pub fn dir(&self) -> Option<&Path> {
+        let path = match self {
+            Self::Automatic { prefix, .. } => Path::new(prefix.as_str()),
+            Self::File(path) => path,
+            Self::Stdout(_) => return None,
+        };
+        match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => Some(dir),
+            _ => Some(Path::new(".")),
+        }
+    }
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_verbatim() {
+        assert_eq!(
+            normalize_verbatim(r"\\?\C:/Users/me/cat.png"),
+            PathBuf::from(r"\\?\C:\Users\me\cat.png")
+        );
+        assert_eq!(
+            normalize_verbatim(r"\\?\C:\cat.png"),
+            PathBuf::from(r"\\?\C:\cat.png")
+        );
+        assert_eq!(normalize_verbatim("a/b.png"), PathBuf::from("a/b.png"));
+    }
 }
@@ -0,0 +1,163 @@
+//! `--insert-into`: place generated images into a Markdown or HTML document.
+
+use anyhow::{bail, Context};
+use std::path::{Component, Path, PathBuf};
+
+/// Where to insert the generated images.
+pub struct Insertion {
+    doc: PathBuf,
+    marker: String,
+}
+
+impl Insertion {
+    /// Check that `doc` contains `marker` up front, so we don't pay for
+    /// images we can't place.
+    pub fn new(doc: PathBuf, marker: String) -> anyhow::Result<Self> {
+        let contents = read(&doc)?;
+        if !contents.contains(&marker) {
+            bail!("Marker {marker:?} not found in {}", doc.display());
+        }
+        Ok(Self { doc, marker })
+    }
+
+    /// The document's directory, where automatically named images are saved.
+    pub fn dir(&self) -> &Path {
+        match self.doc.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        }
+    }
+
+    /// Replace the marker with links to the saved images, given as
+    /// `(path, alt text)` pairs.
+    pub fn apply(&self, images: &[(&Path, &str)]) -> anyhow::Result<()> {
+        let html = self
+            .doc
+            .extension()
+            .is_some_and(|ext| ext == "html" || ext == "htm");
+        let links = images
+            .iter()
+            .map(|&(path, alt)| {
+                let src = relative_path(path, self.dir())?;
+                Ok(if html {
+                    html_img(&src, alt)
+                } else {
+                    markdown_img(&src, alt)
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .join("\n");
+
+        // Re-read in case the document was edited while we were generating
+        let contents = read(&self.doc)?;
+        if !contents.contains(&self.marker) {
+            bail!(
+                "Marker {:?} no longer in {}",
+                self.marker,
+                self.doc.display()
+            );
+        }
+        let contents = contents.replacen(&self.marker, &links, 1);
+        std::fs::write(&self.doc, contents).with_context(|| {
+            format!("Failed to write document: {}", self.doc.display())
+        })
+    }
+
+    pub fn doc(&self) -> &Path {
+        &self.doc
+    }
+}
+
+fn read(doc: &Path) -> anyhow::Result<String> {
+    std::fs::read_to_string(doc)
+        .with_context(|| format!("Failed to read document: {}", doc.display()))
+}
+
+/// The path to `path` from `dir`, with '/' separators, for use in a link.
+fn relative_path(path: &Path, dir: &Path) -> anyhow::Result<String> {
+    let path = std::path::absolute(path)?;
+    let dir = std::path::absolute(dir)?;
+    let path: Vec<Component> = path.components().collect();
+    let dir: Vec<Component> = dir.components().collect();
+
+    // Different roots (e.g. Windows drives) can't be made relative
+    if path.first() != dir.first() {
+        return Ok(path.iter().collect::<PathBuf>().display().to_string());
+    }
+    let common = path.iter().zip(&dir).take_while(|(a, b)| a == b).count();
+    let parts: Vec<String> =
+        std::iter::repeat_n("..".to_string(), dir.len() - common)
+            .chain(
+                path[common..]
+                    .iter()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+            )
+            .collect();
+    Ok(parts.join("/"))
+}
+
+fn markdown_img(src: &str, alt: &str) -> String {
+    let alt = single_line(alt).replace('[', "\\[").replace(']', "\\]");
+    if src.contains([' ', '(', ')']) {
+        format!("![{alt}](<{src}>)")
+    } else {
+        format!("![{alt}]({src})")
+    }
+}
+
+fn html_img(src: &str, alt: &str) -> String {
+    let escape = |s: &str| {
+        s.replace('&', "&amp;")
+            .replace('"', "&quot;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+    };
+    let alt = single_line(alt);
+    format!("<img src=\"{}\" alt=\"{}\">", escape(src), escape(&alt))
+}
+
+fn single_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_path() {
+        let rel = |path, dir| relative_path(Path::new(path), Path::new(dir));
+        assert_eq!(rel("/a/b/c.png", "/a/b").unwrap(), "c.png");
+        assert_eq!(rel("/a/img/c.png", "/a/b").unwrap(), "../img/c.png");
+        assert_eq!(rel("c.png", ".").unwrap(), "c.png");
+    }
+
+    #[test]
+    fn test_insert() {
+        let dir = tempfile::tempdir().unwrap();
+        let md = dir.path().join("post.md");
+        std::fs::write(&md, "# Hi\n<!-- imgen: hero -->\n").unwrap();
+        let html = dir.path().join("index.html");
+        std::fs::write(&html, "<!-- imgen: hero -->").unwrap();
+
+        let marker = "<!-- imgen: hero -->".to_string();
+        let err = Insertion::new(md.clone(), "<!-- nope -->".to_string());
+        assert!(err.is_err());
+
+        let image = dir.path().join("a cat.png");
+        let insertion = Insertion::new(md.clone(), marker.clone()).unwrap();
+        insertion.apply(&[(&image, "A [cute]\ncat")]).unwrap();
+        let contents = std::fs::read_to_string(&md).unwrap();
+        assert_eq!(contents, "# Hi\n![A \\[cute\\] cat](<a cat.png>)\n");
+
+        let insertion = Insertion::new(html.clone(), marker).unwrap();
+        insertion.apply(&[(&image, "A \"cute\" cat")]).unwrap();
+        let contents = std::fs::read_to_string(&html).unwrap();
+        assert_eq!(
+            contents,
+            r#"<img src="a cat.png" alt="A &quot;cute&quot; cat">"#
+        );
+    }
+}
@@ -0,0 +1,260 @@
+//! `imgen jobs`: run a queue of generation jobs from newline-delimited JSON.
+
+use anyhow::Context;
+use base64::prelude::*;
+use clap::Parser;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    path::PathBuf,
+    sync::{mpsc, Mutex},
+};
+
+use crate::{
+    cli::{input, Cli, GenerateArgs},
+    client::Client,
+    events::Events,
+    record::RunRecord,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct JobsArgs {
+    /// A file of newline-delimited JSON jobs, or '-' to read from stdin
+    ///
+    /// Each line is an object like (see also `imgen --stdin-json`):
+    /// {"prompt": "A cat", "images": ["cat.png"], "mask": "mask.png",
+    ///  "output": "out.png", "n": 1, "size": "square", "quality": "high",
+    ///  "background": "transparent", "moderation": "low",
+    ///  "output_format": "webp", "output_compression": 80, "id": 7,
+    ///  "response_format": "b64_json"}
+    ///
+    /// Only "prompt" is required. One JSON result line is printed to stdout
+    /// per job, as each finishes.
+    #[arg(verbatim_doc_comment)]
+    pub file: PathBuf,
+
+    /// The number of jobs to run at once
+    #[arg(short = 'j', long, default_value_t = 1)]
+    pub concurrency: usize,
+}
+
+/// A single job. Unset parameters use the same defaults as the CLI.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Job {
+    /// Echoed back in the result, to match results to jobs
+    #[serde(default)]
+    id: Option<serde_json::Value>,
+    prompt: String,
+    #[serde(default)]
+    images: Vec<PathBuf>,
+    mask: Option<PathBuf>,
+    output: Option<PathBuf>,
+    n: Option<u8>,
+    size: Option<String>,
+    quality: Option<String>,
+    background: Option<String>,
+    moderation: Option<String>,
+    output_format: Option<String>,
+    output_compression: Option<u8>,
+    /// "path" (the default) to report where each image was saved, or
+    /// "b64_json" to return the images inline instead of keeping them
+    #[serde(default)]
+    response_format: ResponseFormat,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ResponseFormat {
+    #[default]
+    Path,
+    B64Json,
+}
+
+/// The result line printed for each job.
+#[derive(Serialize)]
+pub struct JobResult {
+    /// The job's line number in the input (1-based)
+    pub line: usize,
+    pub id: Option<serde_json::Value>,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<RunRecord>,
+    /// The base64-encoded images, in the same order as `record.images`,
+    /// with `"response_format": "b64_json"`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub b64_json: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl JobsArgs {
+    pub fn run(self, client: &Client) -> anyhow::Result<()> {
+        let reader: Box<dyn Read> = if self.file.as_os_str() == "-" {
+            Box::new(std::io::stdin())
+        } else {
+            let file = std::fs::File::open(&self.file).with_context(|| {
+                format!("Failed to open jobs file: {}", self.file.display())
+            })?;
+            Box::new(file)
+        };
+
+        let failed = run_queue(client, reader, self.concurrency)?;
+        if failed > 0 {
+            warn!("{failed} job(s) failed");
+        }
+        Ok(())
+    }
+}
+
+/// Run each job line read from `reader` on `concurrency` workers, printing a
+/// result line per job. Returns the number of failed jobs.
+pub fn run_queue(
+    client: &Client,
+    reader: impl Read,
+    concurrency: usize,
+) -> anyhow::Result<usize> {
+    // Workers pull jobs from a shared queue as the input is read
+    let (tx, rx) = mpsc::channel::<(usize, String)>();
+    let rx = Mutex::new(rx);
+    let mut failed = 0;
+    std::thread::scope(|scope| -> anyhow::Result<()> {
+        let workers: Vec<_> = (0..concurrency.max(1))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut failed = 0;
+                    loop {
+                        let next = rx.lock().unwrap().recv();
+                        let Ok((line, json)) = next else { break };
+                        let result = run_job(client, line, &json);
+                        failed += usize::from(!result.ok);
+                        print_result(&result);
+                    }
+                    failed
+                })
+            })
+            .collect();
+
+        for (i, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line.context("Failed to read jobs")?;
+            if !line.trim().is_empty() {
+                tx.send((i + 1, line)).expect("Job workers exited");
+            }
+        }
+        drop(tx);
+
+        for worker in workers {
+            failed += worker.join().expect("Job worker panicked");
+        }
+        Ok(())
+    })?;
+    Ok(failed)
+}
+
+/// Parse and run a single job.
+pub fn run_job(client: &Client, line: usize, json: &str) -> JobResult {
+    let failed = |id, error| JobResult {
+        line,
+        id,
+        ok: false,
+        record: None,
+        b64_json: Vec::new(),
+        error: Some(error),
+    };
+    let mut job = match serde_json::from_str::<Job>(json) {
+        Ok(job) => job,
+        Err(err) => return failed(None, format!("Invalid job: {err}")),
+    };
+    let id = job.id.clone();
+    let response_format = std::mem::take(&mut job.response_format);
+
+    info!("Job {line}: starting");
+    let result = job
+        .into_args()
+        .run(client, &Events::new(false, None), None, None)
+        .and_then(|record| {
+            let b64_json = match response_format {
+                ResponseFormat::Path => Vec::new(),
+                ResponseFormat::B64Json => take_images(&record)?,
+            };
+            Ok((record, b64_json))
+        });
+    match result {
+        Ok((record, b64_json)) => JobResult {
+            line,
+            id,
+            ok: true,
+            record: Some(record),
+            b64_json,
+            error: None,
+        },
+        Err(err) => {
+            warn!("Job {line} failed: {err:#}");
+            failed(id, format!("{err:#}"))
+        }
+    }
+}
+
+/// Read back and base64-encode each saved image, removing it from disk.
+fn take_images(record: &RunRecord) -> anyhow::Result<Vec<String>> {
+    record
+        .images
+        .iter()
+        .filter_map(|image| image.path.as_deref())
+        .map(|path| {
+            let bytes = std::fs::read(path).with_context(|| {
+                format!("Failed to read image: {}", path.display())
+            })?;
+            let _ = std::fs::remove_file(path);
+            Ok(BASE64_STANDARD.encode(bytes))
+        })
+        .collect()
+}
+
+pub fn print_result(result: &JobResult) {
+    let json =
+        serde_json::to_string(result).expect("Failed to serialize job result");
+    let mut stdout = std::io::stdout().lock();
+    let _ = writeln!(stdout, "{json}");
+    let _ = stdout.flush();
+}
+
+impl Job {
+    /// Convert to the equivalent CLI arguments.
+    fn into_args(self) -> GenerateArgs {
+        // Start from the CLI defaults
+        let mut args = Cli::try_parse_from(["imgen", ""])
+            .expect("Default arguments should parse")
+            .args;
+
+        // Stdin is the job queue, so inputs are always literal or files
+        args.prompt = Some(input::PromptArg::Literal(self.prompt));
+        args.image =
+            self.images.into_iter().map(input::ImageArg::File).collect();
+        args.mask = self.mask.map(input::ImageArg::File);
+        args.output = self.output.map(input::OutputArg::File);
+        if let Some(n) = self.n {
+            args.n = n;
+        }
+        if let Some(size) = self.size {
+            args.size = size;
+        }
+        if let Some(quality) = self.quality {
+            args.quality = quality;
+        }
+        if let Some(background) = self.background {
+            args.background = background;
+        }
+        if let Some(moderation) = self.moderation {
+            args.moderation = moderation;
+        }
+        if let Some(output_format) = self.output_format {
+            args.output_format = output_format;
+        }
+        if let Some(output_compression) = self.output_compression {
+            args.output_compression = output_compression;
+        }
+        args
+    }
+}
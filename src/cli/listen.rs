@@ -0,0 +1,104 @@
+//! `imgen listen`: a resident job server fed through a named pipe (FIFO).
+
+use clap::Args;
+use log::info;
+use std::path::PathBuf;
+
+use crate::{cli::jobs, client::Client};
+
+#[derive(Args, Debug)]
+pub struct ListenArgs {
+    /// The named pipe to read job lines from. Created if it doesn't exist.
+    ///
+    /// Jobs use the same JSON format as `imgen jobs`, and one JSON result
+    /// line per job is printed to stdout. Any number of writers may open,
+    /// write to, and close the pipe; imgen keeps listening until killed,
+    /// reusing its connection to the API across jobs.
+    #[arg(long)]
+    pub fifo: PathBuf,
+
+    /// The number of jobs to run at once
+    #[arg(short = 'j', long, default_value_t = 1)]
+    pub concurrency: usize,
+}
+
+impl ListenArgs {
+    #[cfg(unix)]
+    pub fn run(self, client: &Client) -> anyhow::Result<()> {
+        let reader = fifo::Reader::create(self.fifo)?;
+        info!("Listening for jobs on {}", reader.path.display());
+        jobs::run_queue(client, reader, self.concurrency)?;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn run(self, _client: &Client) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "`imgen listen --fifo` is only supported on unix platforms"
+        )
+    }
+}
+
+#[cfg(unix)]
+mod fifo {
+    use anyhow::{bail, Context};
+    use std::{
+        fs::File,
+        io::{self, Read},
+        os::unix::fs::FileTypeExt,
+        path::PathBuf,
+        process::Command,
+    };
+
+    /// Reads from a FIFO forever, reopening it whenever the last writer
+    /// closes it.
+    pub struct Reader {
+        pub path: PathBuf,
+        file: Option<File>,
+    }
+
+    impl Reader {
+        /// Create the FIFO at `path` if needed.
+        pub fn create(path: PathBuf) -> anyhow::Result<Self> {
+            match std::fs::metadata(&path) {
+                Ok(meta) if meta.file_type().is_fifo() => (),
+                Ok(_) => bail!("Not a named pipe: {}", path.display()),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                    let status = Command::new("mkfifo")
+                        .arg(&path)
+                        .status()
+                        .context("Failed to run mkfifo")?;
+                    if !status.success() {
+                        bail!(
+                            "Failed to create named pipe: {}",
+                            path.display()
+                        );
+                    }
+                }
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!("Failed to stat {}", path.display())
+                    })
+                }
+            }
+            Ok(Self { path, file: None })
+        }
+    }
+
+    impl Read for Reader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                // Opening blocks until a writer connects
+                let file = match &mut self.file {
+                    Some(file) => file,
+                    None => self.file.insert(File::open(&self.path)?),
+                };
+                match file.read(buf)? {
+                    // All writers closed, wait for the next one
+                    0 if !buf.is_empty() => self.file = None,
+                    n => return Ok(n),
+                }
+            }
+        }
+    }
+}
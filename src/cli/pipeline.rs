@@ -0,0 +1,1193 @@
+//! `imgen pipeline`: run a multi-step image workflow described in a YAML
+//! file, e.g. generate -> edit with a mask -> upscale -> composite -> export
+//! sizes.
+//!
+//! Steps may reference earlier steps' outputs with `{step: <id>}`, and run
+//! in dependency order. Each step's outputs are saved in the output
+//! directory, and a step is skipped on later runs while its definition and
+//! inputs are unchanged, so re-running a pipeline after changing one step
+//! only pays for the steps downstream of it.
+//!
+//! `vars` are substituted into text fields (prompts, paths) as `${name}`.
+//! A step with `foreach: <list var>` fans out into one run per value, in
+//! parallel; a later step with the same `foreach` gets the matching value's
+//! images from it, and any other step gets all of them. Generate and edit
+//! steps can `check` their images, regenerating up to `max_attempts` times.
+//!
+//! `--plan` shows the steps, which are up to date, and the estimated cost,
+//! without running anything.
+
+use anyhow::{anyhow, bail, Context};
+use clap::Parser;
+use image::{imageops::FilterType, DynamicImage, ImageFormat};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::{input, Cli, GenerateArgs},
+    client::Client,
+    events::Events,
+    history::sha256_hex,
+    imageops::{self, Overlay},
+    pricing::{self, Quality},
+};
+
+/// The model generate and edit steps use, as the CLI does.
+const MODEL: &str = "gpt-image-1";
+
+/// Where the hashes of completed steps are kept, in the output directory.
+const STATE_FILE: &str = ".imgen-pipeline.json";
+
+#[derive(clap::Args, Debug)]
+pub struct PipelineArgs {
+    /// The pipeline file
+    ///
+    /// For example:
+    ///   vars:
+    ///     animal: [fox, owl]
+    ///   steps:
+    ///     - id: portrait
+    ///       foreach: animal
+    ///       generate:
+    ///         prompt: "A ${animal} holding a sign that says hello"
+    ///         check: {expect_text: hello}
+    ///         max_attempts: 3
+    ///     - id: fox
+    ///       generate: {prompt: "A red fox in the snow", size: landscape}
+    ///     - id: hat
+    ///       edit:
+    ///         images: [{step: fox}]
+    ///         mask: hat-mask.png
+    ///         prompt: "Give the fox a wool hat"
+    ///     - id: big
+    ///       upscale: {image: {step: hat}, scale: 2}
+    ///     - id: card
+    ///       composite: {base: card.png, image: {step: big}, scale: 0.5}
+    ///     - id: icons
+    ///       export: {image: {step: card}, sizes: [512, 256x128]}
+    ///
+    /// Relative paths are relative to the pipeline file.
+    #[arg(verbatim_doc_comment)]
+    pub file: PathBuf,
+
+    /// Where to save each step's outputs. Defaults to a directory next to
+    /// the pipeline file, named after it.
+    #[arg(long, value_name = "DIR")]
+    pub out_dir: Option<PathBuf>,
+
+    /// Re-run every step, even those whose outputs are up to date
+    #[arg(long)]
+    pub force: bool,
+
+    /// Print the steps in the order they'd run, which are up to date, and
+    /// the estimated cost, without running anything
+    #[arg(long)]
+    pub plan: bool,
+
+    /// The most runs of a `foreach` step at once
+    #[arg(short = 'j', long, default_value_t = 4)]
+    pub concurrency: usize,
+}
+
+/// A pipeline file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Pipeline {
+    /// Values to substitute for `${name}`. Lists are for `foreach`.
+    #[serde(default)]
+    vars: BTreeMap<String, Var>,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Var {
+    One(String),
+    List(Vec<String>),
+}
+
+/// A single step, which saves one or more images.
+#[derive(Debug, Deserialize)]
+struct Step {
+    /// Names the step, for later steps to reference
+    id: String,
+    /// Run once per value of this list variable
+    foreach: Option<String>,
+    #[serde(flatten)]
+    action: Action,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Action {
+    /// Generate an image from a prompt
+    Generate(Generate),
+    /// Edit images with a prompt, and an optional mask
+    Edit(Edit),
+    /// Scale an image up (or down)
+    Upscale(Upscale),
+    /// Composite an image onto a base image
+    Composite(Composite),
+    /// Save an image at several sizes
+    Export(Export),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Generate {
+    prompt: String,
+    size: Option<String>,
+    quality: Option<String>,
+    background: Option<String>,
+    moderation: Option<String>,
+    output_format: Option<String>,
+    output_compression: Option<u8>,
+    #[serde(default)]
+    check: Check,
+    max_attempts: Option<u8>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Edit {
+    prompt: String,
+    images: Vec<Input>,
+    mask: Option<Input>,
+    size: Option<String>,
+    quality: Option<String>,
+    #[serde(default)]
+    check: Check,
+    max_attempts: Option<u8>,
+}
+
+/// Checks on generated images, which regenerate them when they fail (see
+/// `--expect-text` and friends).
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Check {
+    expect_text: Option<String>,
+    #[serde(default)]
+    reject_blank: bool,
+    min_entropy: Option<f64>,
+    #[serde(default)]
+    tileable: bool,
+}
+
+/// Attempts per checked step, unless `max_attempts` says otherwise.
+const DEFAULT_CHECKED_ATTEMPTS: u8 = 3;
+
+impl Check {
+    fn apply(&self, max_attempts: Option<u8>, args: &mut GenerateArgs) {
+        args.expect_text.clone_from(&self.expect_text);
+        args.reject_blank = self.reject_blank;
+        args.min_entropy = self.min_entropy;
+        args.tileable = self.tileable;
+        args.max_attempts = self.max_attempts(max_attempts);
+    }
+
+    /// The most times to generate, given the step's `max_attempts`.
+    fn max_attempts(&self, max_attempts: Option<u8>) -> u8 {
+        let checked = self.expect_text.is_some()
+            || self.reject_blank
+            || self.min_entropy.is_some()
+            || self.tileable;
+        match max_attempts {
+            Some(max_attempts) => max_attempts,
+            None if checked => DEFAULT_CHECKED_ATTEMPTS,
+            None => 1,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Upscale {
+    image: Input,
+    /// The scale factor
+    #[serde(default = "default_scale")]
+    scale: f32,
+}
+
+fn default_scale() -> f32 {
+    2.0
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Composite {
+    base: Input,
+    image: Input,
+    /// The top-left corner of the image on the base. Defaults to the
+    /// bottom-right corner.
+    position: Option<(u32, u32)>,
+    /// The image's width, as a fraction of the base's width
+    scale: Option<f32>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Export {
+    image: Input,
+    /// Each size, as `WxH` (cropped to fill) or a single width (keeping the
+    /// aspect ratio)
+    sizes: Vec<ExportSize>,
+    /// png, jpeg, or webp. Defaults to the image's own format.
+    format: Option<String>,
+}
+
+/// A step's input image: a file, or another step's output.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum Input {
+    Step { step: String },
+    File(PathBuf),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum ExportSize {
+    Width(u32),
+    Size(String),
+}
+
+impl ExportSize {
+    /// The width and height, or just the width.
+    fn parse(&self) -> anyhow::Result<(u32, Option<u32>)> {
+        match self {
+            Self::Width(width) => Ok((*width, None)),
+            Self::Size(size) => {
+                let parse = || {
+                    let (width, height) = size.split_once('x')?;
+                    Some((width.parse().ok()?, height.parse().ok()?))
+                };
+                let (width, height) = parse().with_context(|| {
+                    format!("Invalid export size {size:?}; expected WxH")
+                })?;
+                Ok((width, Some(height)))
+            }
+        }
+    }
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Generate(_) => "generate",
+            Self::Edit(_) => "edit",
+            Self::Upscale(_) => "upscale",
+            Self::Composite(_) => "composite",
+            Self::Export(_) => "export",
+        }
+    }
+
+    fn inputs(&self) -> Vec<&Input> {
+        match self {
+            Self::Generate(_) => Vec::new(),
+            Self::Edit(edit) => edit.images.iter().chain(&edit.mask).collect(),
+            Self::Upscale(upscale) => vec![&upscale.image],
+            Self::Composite(composite) => {
+                vec![&composite.base, &composite.image]
+            }
+            Self::Export(export) => vec![&export.image],
+        }
+    }
+}
+
+/// The estimated cost of the API requests a plan would make.
+#[derive(Default)]
+struct Estimate {
+    requests: u32,
+    /// The cost if every image passes its checks first time
+    cost: f64,
+    /// The cost if every checked image takes all its attempts
+    max_cost: f64,
+    /// Requests with an unknown cost (e.g. a custom size)
+    unknown: u32,
+    /// Whether the cost leaves out edits' input images
+    edits: bool,
+}
+
+impl Estimate {
+    /// Add a run of `action`, returning its cost for display.
+    fn add(&mut self, action: &Action) -> String {
+        let (prompt, size, quality, check, max_attempts) = match action {
+            Action::Generate(generate) => (
+                &generate.prompt,
+                &generate.size,
+                &generate.quality,
+                &generate.check,
+                generate.max_attempts,
+            ),
+            Action::Edit(edit) => {
+                self.edits = true;
+                (
+                    &edit.prompt,
+                    &edit.size,
+                    &edit.quality,
+                    &edit.check,
+                    edit.max_attempts,
+                )
+            }
+            _ => return "-".to_string(),
+        };
+        self.requests += 1;
+
+        // Assume the most expensive quality, and a square "auto" size
+        let quality = quality.as_deref().and_then(Quality::from_name);
+        let size = super::size_canonical(
+            size.clone().unwrap_or(super::DEFAULT_SIZE.to_string()),
+        )
+        .unwrap_or("1024x1024".to_string());
+        let cost = pricing::for_model(MODEL).and_then(|pricing| {
+            let prompt_tokens = pricing::estimate_text_tokens(prompt);
+            let quality = quality.unwrap_or(Quality::High);
+            pricing.estimate_cost(quality, &size, 1, prompt_tokens)
+        });
+        let Some(cost) = cost else {
+            self.unknown += 1;
+            return "?".to_string();
+        };
+        self.cost += cost;
+        self.max_cost += cost * check.max_attempts(max_attempts).max(1) as f64;
+        format!("~${cost:.3}")
+    }
+}
+
+impl std::fmt::Display for Estimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Estimated cost: ~${:.3} for {} API request(s)",
+            self.cost, self.requests
+        )?;
+        if self.max_cost > self.cost {
+            write!(f, ", up to ${:.3} if checks fail", self.max_cost)?;
+        }
+        if self.unknown > 0 {
+            write!(f, ", plus {} of unknown cost", self.unknown)?;
+        }
+        if self.edits {
+            write!(f, " (not counting edits' input images)")?;
+        }
+        Ok(())
+    }
+}
+
+/// The hash of a completed step and what it saved.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct StepState {
+    hash: String,
+    outputs: Vec<PathBuf>,
+}
+
+/// A completed step's images, by `foreach` value.
+struct Outputs {
+    foreach: Option<String>,
+    items: Vec<(Option<String>, Vec<PathBuf>)>,
+}
+
+/// One run of a step: the step itself, or one value of its `foreach`.
+struct Item {
+    /// Names the run's outputs, e.g. "portrait-fox"
+    name: String,
+    /// The run's key in the state file, e.g. "portrait[fox]"
+    key: String,
+    /// The step's action, with variables substituted
+    action: Action,
+    /// The `foreach` variable and value
+    foreach: Option<(String, String)>,
+}
+
+/// Runs steps, tracking their outputs.
+struct Runner<'a> {
+    /// `None` with `--plan`, which doesn't call the API
+    client: Option<&'a Client>,
+    base_dir: PathBuf,
+    out_dir: PathBuf,
+    vars: BTreeMap<String, Var>,
+    outputs: HashMap<String, Outputs>,
+    state: BTreeMap<String, StepState>,
+    force: bool,
+    concurrency: usize,
+}
+
+impl PipelineArgs {
+    /// Run the pipeline. `client` is only needed without `--plan`.
+    pub fn run(self, client: Option<&Client>) -> anyhow::Result<()> {
+        let pipeline = load(&self.file)?;
+        let base_dir =
+            self.file.parent().unwrap_or(Path::new("")).to_path_buf();
+        let out_dir = self.out_dir.clone().unwrap_or_else(|| {
+            let stem = self.file.file_stem().unwrap_or("pipeline".as_ref());
+            base_dir.join(stem)
+        });
+        let steps = order(&pipeline.steps)?;
+        if !self.plan {
+            std::fs::create_dir_all(&out_dir).with_context(|| {
+                format!("Failed to create: {}", out_dir.display())
+            })?;
+        }
+        let mut runner = Runner {
+            client,
+            base_dir,
+            state: read_state(&out_dir),
+            out_dir,
+            vars: pipeline.vars,
+            outputs: HashMap::new(),
+            force: self.force,
+            concurrency: self.concurrency.max(1),
+        };
+        if self.plan {
+            return runner.plan(&steps);
+        }
+        for step in steps {
+            runner.run(step)?;
+        }
+        Ok(())
+    }
+}
+
+fn load(path: &Path) -> anyhow::Result<Pipeline> {
+    let yaml = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read: {}", path.display()))?;
+    serde_yaml_ng::from_str(&yaml)
+        .map_err(|err| anyhow!("Invalid pipeline {}: {err}", path.display()))
+}
+
+/// Sort the steps so each comes after the steps it references.
+fn order(steps: &[Step]) -> anyhow::Result<Vec<&Step>> {
+    let mut by_id = HashMap::new();
+    for step in steps {
+        if by_id.insert(step.id.as_str(), step).is_some() {
+            bail!("Duplicate step id: {}", step.id);
+        }
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mark {
+        Visiting,
+        Done,
+    }
+    fn visit<'a>(
+        step: &'a Step,
+        by_id: &HashMap<&str, &'a Step>,
+        marks: &mut HashMap<&'a str, Mark>,
+        sorted: &mut Vec<&'a Step>,
+    ) -> anyhow::Result<()> {
+        match marks.get(step.id.as_str()) {
+            Some(Mark::Done) => return Ok(()),
+            Some(Mark::Visiting) => {
+                bail!("Steps form a cycle through: {}", step.id)
+            }
+            None => {}
+        }
+        marks.insert(&step.id, Mark::Visiting);
+        for input in step.action.inputs() {
+            if let Input::Step { step: id } = input {
+                let dep = by_id.get(id.as_str()).with_context(|| {
+                    format!("Step {} references unknown step: {id}", step.id)
+                })?;
+                visit(dep, by_id, marks, sorted)?;
+            }
+        }
+        marks.insert(&step.id, Mark::Done);
+        sorted.push(step);
+        Ok(())
+    }
+
+    let mut marks = HashMap::new();
+    let mut sorted = Vec::new();
+    for step in steps {
+        visit(step, &by_id, &mut marks, &mut sorted)?;
+    }
+    Ok(sorted)
+}
+
+fn read_state(out_dir: &Path) -> BTreeMap<String, StepState> {
+    std::fs::read(out_dir.join(STATE_FILE))
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .unwrap_or_default()
+}
+
+impl Runner<'_> {
+    fn run(&mut self, step: &Step) -> anyhow::Result<()> {
+        let items = self.items(step)?;
+
+        // Skip the runs that are up to date
+        let mut results = Vec::new();
+        let mut stale = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            let hash = self.hash(item)?;
+            match self.up_to_date(item, &hash) {
+                Some(outputs) => {
+                    info!("{}: up to date", item.name);
+                    results.push(Some(outputs));
+                }
+                None => {
+                    results.push(None);
+                    stale.push((i, hash));
+                }
+            }
+        }
+
+        // Run the rest, a batch at a time
+        let mut first_err = None;
+        for batch in stale.chunks(self.concurrency) {
+            let runner = &*self;
+            let ran: Vec<_> = std::thread::scope(|scope| {
+                let handles: Vec<_> = batch
+                    .iter()
+                    .map(|(i, _)| {
+                        let item = &items[*i];
+                        info!("{}: running", item.name);
+                        scope.spawn(move || runner.run_action(item))
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .map(|handle| handle.join().expect("Step panicked"))
+                    .collect()
+            });
+            for ((i, hash), result) in batch.iter().zip(ran) {
+                let item = &items[*i];
+                match result {
+                    Ok(outputs) => {
+                        for path in &outputs {
+                            info!("{}: saved {}", item.name, path.display());
+                        }
+                        let state = StepState {
+                            hash: hash.clone(),
+                            outputs: outputs.clone(),
+                        };
+                        self.state.insert(item.key.clone(), state);
+                        results[*i] = Some(outputs);
+                    }
+                    Err(err) => {
+                        let err = anyhow!("{} failed: {err:#}", item.name);
+                        first_err.get_or_insert(err);
+                    }
+                }
+            }
+            // Keep what's done, even if another run failed
+            self.save_state()?;
+            if let Some(err) = first_err.take() {
+                return Err(err);
+            }
+        }
+
+        let items = items
+            .into_iter()
+            .zip(results)
+            .map(|(item, outputs)| {
+                let value = item.foreach.map(|(_, value)| value);
+                (value, outputs.unwrap_or_default())
+            })
+            .collect();
+        let outputs = Outputs {
+            foreach: step.foreach.clone(),
+            items,
+        };
+        self.outputs.insert(step.id.clone(), outputs);
+        Ok(())
+    }
+
+    /// Print the plan for the (sorted) steps.
+    fn plan(&mut self, steps: &[&Step]) -> anyhow::Result<()> {
+        println!(
+            "{:<24}{:<11}{:<28}{:>9}  inputs",
+            "step", "action", "status", "cost"
+        );
+        let mut stale_steps = HashSet::new();
+        let mut estimate = Estimate::default();
+        for step in steps {
+            let deps: Vec<_> = step
+                .action
+                .inputs()
+                .into_iter()
+                .filter_map(|input| match input {
+                    Input::Step { step } => Some(step.as_str()),
+                    Input::File(_) => None,
+                })
+                .collect();
+            let stale_dep = deps.iter().find(|dep| stale_steps.contains(*dep));
+
+            let items = self.items(step)?;
+            let mut fresh = Vec::new();
+            for item in &items {
+                // A step after a stale one gets new inputs, so it runs too
+                let outputs = match stale_dep {
+                    Some(_) => None,
+                    None => self.up_to_date(item, &self.hash(item)?),
+                };
+                let (status, cost) = match (outputs, stale_dep) {
+                    (Some(outputs), _) => {
+                        let value =
+                            item.foreach.clone().map(|(_, value)| value);
+                        fresh.push((value, outputs));
+                        ("up to date, skip".to_string(), "-".to_string())
+                    }
+                    (None, stale_dep) => {
+                        let status = match stale_dep {
+                            Some(dep) => format!("run (after {dep})"),
+                            None => "run".to_string(),
+                        };
+                        (status, estimate.add(&item.action))
+                    }
+                };
+                println!(
+                    "{:<24}{:<11}{status:<28}{cost:>9}  {}",
+                    item.name,
+                    item.action.name(),
+                    deps.join(", ")
+                );
+            }
+
+            if fresh.len() == items.len() {
+                let outputs = Outputs {
+                    foreach: step.foreach.clone(),
+                    items: fresh,
+                };
+                self.outputs.insert(step.id.clone(), outputs);
+            } else {
+                stale_steps.insert(step.id.as_str());
+            }
+        }
+        println!();
+        println!("{estimate}");
+        Ok(())
+    }
+
+    /// The outputs of the last run of `item`, if it's up to date.
+    fn up_to_date(&self, item: &Item, hash: &str) -> Option<Vec<PathBuf>> {
+        let state = self.state.get(&item.key)?;
+        let exists = state.outputs.iter().all(|path| path.exists());
+        (!self.force && state.hash == hash && exists)
+            .then(|| state.outputs.clone())
+    }
+
+    /// The runs of a step, with variables substituted.
+    fn items(&self, step: &Step) -> anyhow::Result<Vec<Item>> {
+        let mut vars: HashMap<&str, &str> = self
+            .vars
+            .iter()
+            .filter_map(|(name, var)| match var {
+                Var::One(value) => Some((name.as_str(), value.as_str())),
+                Var::List(_) => None,
+            })
+            .collect();
+        let Some(var) = &step.foreach else {
+            let action = substitute(&step.action, &vars)
+                .map_err(|err| anyhow!("In step {}: {err:#}", step.id))?;
+            return Ok(vec![Item {
+                name: step.id.clone(),
+                key: step.id.clone(),
+                action,
+                foreach: None,
+            }]);
+        };
+
+        let values = match self.vars.get(var) {
+            Some(Var::List(values)) => values,
+            Some(Var::One(_)) => bail!("foreach: {var} isn't a list"),
+            None => bail!("foreach: unknown variable {var}"),
+        };
+        values
+            .iter()
+            .map(|value| {
+                vars.insert(var, value);
+                let action = substitute(&step.action, &vars)
+                    .map_err(|err| anyhow!("In step {}: {err:#}", step.id))?;
+                Ok(Item {
+                    name: format!("{}-{}", step.id, slug(value)),
+                    key: format!("{}[{value}]", step.id),
+                    action,
+                    foreach: Some((var.clone(), value.clone())),
+                })
+            })
+            .collect()
+    }
+
+    /// Hash a run's definition and the contents of its inputs, so it
+    /// re-runs when either changes.
+    fn hash(&self, item: &Item) -> anyhow::Result<String> {
+        let mut data = serde_json::to_vec(&item.action)?;
+        for input in item.action.inputs() {
+            for path in self.resolve(input, item)? {
+                let bytes = std::fs::read(&path).with_context(|| {
+                    format!("Failed to read: {}", path.display())
+                })?;
+                data.extend(sha256_hex(&bytes).into_bytes());
+            }
+        }
+        Ok(sha256_hex(&data))
+    }
+
+    fn save_state(&self) -> anyhow::Result<()> {
+        let path = self.out_dir.join(STATE_FILE);
+        let json = serde_json::to_vec_pretty(&self.state)?;
+        std::fs::write(&path, json)
+            .with_context(|| format!("Failed to write: {}", path.display()))
+    }
+
+    /// The image files an input refers to. A step run with the same
+    /// `foreach` as `item` gives only the matching value's images.
+    fn resolve(
+        &self,
+        input: &Input,
+        item: &Item,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let step = match input {
+            Input::File(path) => return Ok(vec![self.base_dir.join(path)]),
+            Input::Step { step } => step,
+        };
+        let outputs = self
+            .outputs
+            .get(step)
+            .with_context(|| format!("Step {step} hasn't run"))?;
+        let matching =
+            |value: &Option<String>| match (&outputs.foreach, &item.foreach) {
+                (Some(var), Some((item_var, item_value)))
+                    if var == item_var =>
+                {
+                    value.as_ref() == Some(item_value)
+                }
+                _ => true,
+            };
+        Ok(outputs
+            .items
+            .iter()
+            .filter(|(value, _)| matching(value))
+            .flat_map(|(_, paths)| paths.iter().cloned())
+            .collect())
+    }
+
+    /// The single image file an input refers to.
+    fn resolve_one(
+        &self,
+        input: &Input,
+        item: &Item,
+    ) -> anyhow::Result<PathBuf> {
+        let mut paths = self.resolve(input, item)?;
+        if paths.len() != 1 {
+            bail!("Expected one image from {input:?}, got {}", paths.len());
+        }
+        Ok(paths.remove(0))
+    }
+
+    fn run_action(&self, item: &Item) -> anyhow::Result<Vec<PathBuf>> {
+        let id = &item.name;
+        match &item.action {
+            Action::Generate(generate) => {
+                let format = generate.output_format.as_deref();
+                let output = self.out_dir.join(format!(
+                    "{id}.{}",
+                    format.unwrap_or(super::DEFAULT_OUTPUT_FORMAT)
+                ));
+                let mut args = default_args(&generate.prompt, &output);
+                set(&mut args.size, &generate.size);
+                set(&mut args.quality, &generate.quality);
+                set(&mut args.background, &generate.background);
+                set(&mut args.moderation, &generate.moderation);
+                set(&mut args.output_format, &generate.output_format);
+                if let Some(compression) = generate.output_compression {
+                    args.output_compression = compression;
+                }
+                generate.check.apply(generate.max_attempts, &mut args);
+                self.generate(args, output)
+            }
+            Action::Edit(edit) => {
+                let output = self.out_dir.join(format!("{id}.png"));
+                let mut args = default_args(&edit.prompt, &output);
+                for image in &edit.images {
+                    args.image.extend(
+                        self.resolve(image, item)?
+                            .into_iter()
+                            .map(input::ImageArg::File),
+                    );
+                }
+                args.mask = edit
+                    .mask
+                    .as_ref()
+                    .map(|mask| self.resolve_one(mask, item))
+                    .transpose()?
+                    .map(input::ImageArg::File);
+                set(&mut args.size, &edit.size);
+                set(&mut args.quality, &edit.quality);
+                edit.check.apply(edit.max_attempts, &mut args);
+                self.generate(args, output)
+            }
+            Action::Upscale(upscale) => {
+                if !upscale.scale.is_finite() || upscale.scale <= 0.0 {
+                    bail!("scale must be positive");
+                }
+                let path = self.resolve_one(&upscale.image, item)?;
+                let image = open(&path)?;
+                let width = scaled(image.width(), upscale.scale);
+                let height = scaled(image.height(), upscale.scale);
+                let image =
+                    image.resize_exact(width, height, FilterType::Lanczos3);
+                let output = self.output(id, &path, None)?;
+                save(&image, &output)?;
+                Ok(vec![output])
+            }
+            Action::Composite(composite) => {
+                let base_path = self.resolve_one(&composite.base, item)?;
+                let overlay = Overlay {
+                    image: open(&self.resolve_one(&composite.image, item)?)?,
+                    position: composite.position,
+                    scale: composite.scale,
+                };
+                let image = overlay.apply(&open(&base_path)?);
+                let output = self.output(id, &base_path, None)?;
+                save(&image, &output)?;
+                Ok(vec![output])
+            }
+            Action::Export(export) => {
+                let path = self.resolve_one(&export.image, item)?;
+                let image = open(&path)?;
+                let mut outputs = Vec::new();
+                for size in &export.sizes {
+                    let resized = match size.parse()? {
+                        (width, Some(height)) => image.resize_to_fill(
+                            width,
+                            height,
+                            FilterType::Lanczos3,
+                        ),
+                        (width, None) => {
+                            image.resize(width, u32::MAX, FilterType::Lanczos3)
+                        }
+                    };
+                    let name = format!(
+                        "{id}-{}x{}",
+                        resized.width(),
+                        resized.height()
+                    );
+                    let output =
+                        self.output(&name, &path, export.format.as_deref())?;
+                    save(&resized, &output)?;
+                    outputs.push(output);
+                }
+                Ok(outputs)
+            }
+        }
+    }
+
+    /// Run a generate or edit step through the usual CLI path.
+    fn generate(
+        &self,
+        args: GenerateArgs,
+        output: PathBuf,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let client = self.client.expect("Steps don't run with --plan");
+        args.run(client, &Events::new(false, None), None, None)?;
+        Ok(vec![output])
+    }
+
+    /// The output path for a step, in the input's format unless `format`
+    /// is given.
+    fn output(
+        &self,
+        name: &str,
+        input: &Path,
+        format: Option<&str>,
+    ) -> anyhow::Result<PathBuf> {
+        let ext = match format {
+            Some(format) => format,
+            None => input
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("png"),
+        };
+        Ok(self.out_dir.join(format!("{name}.{ext}")))
+    }
+}
+
+/// The CLI's default arguments, for a prompt saved to `output`.
+fn default_args(prompt: &str, output: &Path) -> GenerateArgs {
+    let mut args = Cli::try_parse_from(["imgen", ""])
+        .expect("Default arguments should parse")
+        .args;
+    args.prompt = Some(input::PromptArg::Literal(prompt.to_string()));
+    args.output = Some(input::OutputArg::File(output.to_path_buf()));
+    args
+}
+
+/// Substitute `${name}` variables throughout an action.
+fn substitute(
+    action: &Action,
+    vars: &HashMap<&str, &str>,
+) -> anyhow::Result<Action> {
+    fn walk(
+        value: &mut Value,
+        vars: &HashMap<&str, &str>,
+    ) -> anyhow::Result<()> {
+        match value {
+            Value::String(text) => *text = expand(text, vars)?,
+            Value::Array(values) => {
+                for value in values {
+                    walk(value, vars)?;
+                }
+            }
+            Value::Object(fields) => {
+                for value in fields.values_mut() {
+                    walk(value, vars)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+    let mut value = serde_json::to_value(action)?;
+    walk(&mut value, vars)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Substitute `${name}` variables in `text`.
+fn expand(text: &str, vars: &HashMap<&str, &str>) -> anyhow::Result<String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .with_context(|| format!("Unclosed ${{ in: {text}"))?;
+        let name = &rest[start + 2..start + end];
+        let value = vars.get(name).with_context(|| {
+            format!("Unknown variable ${{{name}}} (lists need foreach)")
+        })?;
+        out.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// A `foreach` value, made safe for a file name.
+fn slug(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+fn set(arg: &mut String, value: &Option<String>) {
+    if let Some(value) = value {
+        arg.clone_from(value);
+    }
+}
+
+fn scaled(len: u32, scale: f32) -> u32 {
+    ((len as f32 * scale).round() as u32).max(1)
+}
+
+fn open(path: &Path) -> anyhow::Result<DynamicImage> {
+    image::open(path)
+        .with_context(|| format!("Failed to read image: {}", path.display()))
+}
+
+fn save(image: &DynamicImage, path: &Path) -> anyhow::Result<()> {
+    let format = ImageFormat::from_path(path)
+        .with_context(|| format!("Unknown image format: {}", path.display()))?;
+    let bytes = imageops::encode(image, format)?;
+    std::fs::write(path, bytes)
+        .with_context(|| format!("Failed to write: {}", path.display()))
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Pipeline {
+        serde_yaml_ng::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_order() {
+        let pipeline = parse(
+            "
+steps:
+  - id: small
+    export: {image: {step: big}, sizes: [64, 32x16]}
+  - id: big
+    upscale: {image: {step: base}}
+  - id: base
+    generate: {prompt: A red fox}
+",
+        );
+        let ids: Vec<_> = order(&pipeline.steps)
+            .unwrap()
+            .iter()
+            .map(|step| step.id.as_str())
+            .collect();
+        assert_eq!(ids, ["base", "big", "small"]);
+
+        let cycle = parse(
+            "
+steps:
+  - id: a
+    upscale: {image: {step: b}}
+  - id: b
+    upscale: {image: {step: a}}
+",
+        );
+        assert!(order(&cycle.steps).is_err());
+
+        let unknown = parse(
+            "
+steps:
+  - id: a
+    upscale: {image: {step: nope}}
+",
+        );
+        let err = order(&unknown.steps).unwrap_err();
+        assert!(err.to_string().contains("unknown step: nope"), "{err}");
+    }
+
+    #[test]
+    fn test_run_local_steps() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let base = DynamicImage::new_rgb8(40, 20);
+        save(&base, &temp_dir.path().join("base.png")).unwrap();
+        let pipeline = temp_dir.path().join("card.yaml");
+        std::fs::write(
+            &pipeline,
+            "
+steps:
+  - id: big
+    upscale: {image: base.png, scale: 2}
+  - id: card
+    composite: {base: {step: big}, image: base.png, position: [0, 0]}
+  - id: icons
+    export: {image: {step: card}, sizes: [20, 10x10], format: jpeg}
+",
+        )
+        .unwrap();
+        let args = PipelineArgs {
+            file: pipeline,
+            out_dir: None,
+            force: false,
+            plan: false,
+            concurrency: 1,
+        };
+        let client = Client::new("sk-test".to_string());
+        args.run(Some(&client)).unwrap();
+
+        let out_dir = temp_dir.path().join("card");
+        let big = image::open(out_dir.join("big.png")).unwrap();
+        assert_eq!((big.width(), big.height()), (80, 40));
+        assert!(out_dir.join("card.png").exists());
+        assert!(out_dir.join("icons-20x10.jpeg").exists());
+        assert!(out_dir.join("icons-10x10.jpeg").exists());
+
+        // Unchanged steps are skipped on the next run
+        let state = read_state(&out_dir);
+        assert_eq!(state.len(), 3);
+        let before = std::fs::metadata(out_dir.join("big.png"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        let args = PipelineArgs {
+            file: temp_dir.path().join("card.yaml"),
+            out_dir: None,
+            force: false,
+            plan: false,
+            concurrency: 1,
+        };
+        args.run(Some(&client)).unwrap();
+        let after = std::fs::metadata(out_dir.join("big.png"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn test_estimate() {
+        let pipeline = parse(
+            "
+steps:
+  - id: a
+    generate: {prompt: A fox, quality: low, check: {reject_blank: true}}
+  - id: b
+    edit: {prompt: Add a hat, images: [{step: a}], size: 512x512}
+  - id: c
+    upscale: {image: {step: b}}
+",
+        );
+        let mut estimate = Estimate::default();
+        let costs: Vec<_> = pipeline
+            .steps
+            .iter()
+            .map(|step| estimate.add(&step.action))
+            .collect();
+        assert_eq!(costs, ["~$0.011", "?", "-"]);
+        assert_eq!(estimate.requests, 2);
+        assert_eq!(estimate.unknown, 1);
+        assert!((estimate.max_cost - 3.0 * estimate.cost).abs() < 1e-9);
+        assert!(estimate.to_string().contains("not counting edits"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let vars = HashMap::from([("animal", "fox"), ("style", "ink")]);
+        assert_eq!(
+            expand("A ${animal}, ${style} drawing", &vars).unwrap(),
+            "A fox, ink drawing"
+        );
+        assert_eq!(expand("no vars", &vars).unwrap(), "no vars");
+        assert!(expand("A ${bird}", &vars).is_err());
+        assert!(expand("A ${animal", &vars).is_err());
+    }
+
+    #[test]
+    fn test_foreach() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        for (name, width) in [("a.png", 10), ("b.png", 20)] {
+            let image = DynamicImage::new_rgb8(width, 10);
+            save(&image, &temp_dir.path().join(name)).unwrap();
+        }
+        let pipeline = temp_dir.path().join("fan.yaml");
+        std::fs::write(
+            &pipeline,
+            "
+vars:
+  name: [a, b]
+  dir: .
+steps:
+  - id: big
+    foreach: name
+    upscale: {image: '${dir}/${name}.png', scale: 2}
+  - id: bigger
+    foreach: name
+    upscale: {image: {step: big}, scale: 3}
+  - id: all
+    export: {image: {step: bigger}, sizes: [5]}
+",
+        )
+        .unwrap();
+        let args = PipelineArgs {
+            file: pipeline,
+            out_dir: None,
+            force: false,
+            plan: false,
+            concurrency: 2,
+        };
+        let client = Client::new("sk-test".to_string());
+        let err = args.run(Some(&client)).unwrap_err();
+        // `all` gets both `bigger` images, but export takes one
+        assert!(format!("{err:#}").contains("got 2"), "{err:#}");
+
+        let out_dir = temp_dir.path().join("fan");
+        let bigger = image::open(out_dir.join("bigger-b.png")).unwrap();
+        assert_eq!(bigger.width(), 120);
+        assert_eq!(read_state(&out_dir).len(), 4);
+    }
+}
@@ -0,0 +1,284 @@
+//! `imgen plan`: project the cost and wall-clock time of a bulk job before
+//! committing to it.
+
+use anyhow::{bail, Context};
+use std::time::Duration;
+
+use crate::{
+    capabilities,
+    cli::{
+        size_canonical, DEFAULT_MODEL, DEFAULT_NUM_IMAGES, DEFAULT_QUALITY,
+        DEFAULT_SIZE,
+    },
+    config::Config,
+    history,
+    pricing::{self, Quality},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct PlanArgs {
+    /// How many images the job generates in total
+    #[arg(long)]
+    pub count: u32,
+
+    /// The image model the job uses
+    #[arg(long, default_value = DEFAULT_MODEL)]
+    pub model: String,
+
+    /// The quality of the generated images (auto, low, medium, high)
+    #[arg(long, default_value = DEFAULT_QUALITY)]
+    pub quality: String,
+
+    /// The size of the generated images.
+    /// One of: auto, 1024x1024, 1536x1024, 1024x1536, square, landscape, portrait
+    #[arg(long, default_value = DEFAULT_SIZE)]
+    pub size: String,
+
+    /// The number of images per request (1-10)
+    #[arg(short, long, default_value_t = DEFAULT_NUM_IMAGES)]
+    pub n: u8,
+
+    /// How many requests run at once (as with `jobs --concurrency`)
+    #[arg(short = 'j', long, default_value_t = 1)]
+    pub concurrency: usize,
+
+    /// The organization's rate limit for the model, in images per minute
+    #[arg(long, value_name = "N")]
+    pub images_per_minute: Option<u32>,
+
+    /// The length of a typical prompt, in tokens (about 4 characters each)
+    #[arg(long, value_name = "N", default_value_t = 50)]
+    pub prompt_tokens: u32,
+}
+
+/// What sets the pace of a job.
+#[derive(Debug, PartialEq)]
+enum Pace {
+    /// Each request's own duration, `-j` at a time
+    Concurrency,
+    /// `--max-rps` or `--min-interval`
+    Throttle,
+    /// `--images-per-minute`
+    RateLimit,
+}
+
+impl Pace {
+    fn describe(&self, args: &PlanArgs) -> String {
+        match self {
+            Self::Concurrency => {
+                format!("{} request(s) at a time (-j)", args.concurrency)
+            }
+            Self::Throttle => "--max-rps / --min-interval".to_string(),
+            Self::RateLimit => format!(
+                "the {} images/minute rate limit",
+                args.images_per_minute.unwrap_or_default()
+            ),
+        }
+    }
+}
+
+impl PlanArgs {
+    /// `throttle` is the least time between requests, from the global
+    /// `--max-rps` and `--min-interval`.
+    pub fn run(self, throttle: Option<Duration>) -> anyhow::Result<()> {
+        if self.count == 0 {
+            bail!("--count must be at least 1");
+        }
+        let capabilities = capabilities::for_model(&self.model);
+        let requested_size = match capabilities {
+            Some(capabilities) => capabilities.resolve_size(&self.size)?,
+            None => self.size.clone(),
+        };
+        // The API picks the size for "auto"; assume the cheapest square
+        let (size, size_note) = match size_canonical(requested_size.clone()) {
+            Some(size) => (size, ""),
+            None => ("1024x1024".to_string(), " (auto, assuming square)"),
+        };
+        let (quality, quality_note) = match Quality::from_name(&self.quality) {
+            Some(quality) => (quality, ""),
+            None => (Quality::High, " (auto, assuming high)"),
+        };
+        // More images than the model makes at once are sent as more requests
+        let per_request = match capabilities {
+            Some(capabilities) => self.n.clamp(1, capabilities.max_n),
+            None => self.n.max(1),
+        };
+        let full_requests = self.count / u32::from(per_request);
+        let remainder = (self.count % u32::from(per_request)) as u8;
+        let requests = full_requests + u32::from(remainder > 0);
+
+        println!(
+            "Plan: {} image(s) with {} at {}{quality_note} quality, \
+             {size}{size_note}",
+            self.count,
+            self.model,
+            quality.as_str(),
+        );
+        println!(
+            "      {requests} request(s) of up to {per_request} image(s), {} \
+             at a time\n",
+            self.concurrency.max(1),
+        );
+
+        // Cost
+        let cost = pricing::for_model(&self.model).and_then(|pricing| {
+            let request_cost = |n| {
+                pricing.estimate_cost(quality, &size, n, self.prompt_tokens)
+            };
+            let remainder_cost = match remainder {
+                0 => 0.0,
+                n => request_cost(n)?,
+            };
+            Some(
+                f64::from(full_requests) * request_cost(per_request)?
+                    + remainder_cost,
+            )
+        });
+        let cost = match cost {
+            Some(cost) => {
+                println!(
+                    "Cost:  ~${cost:.2} (${:.3} per image)",
+                    cost / f64::from(self.count)
+                );
+                cost
+            }
+            None => {
+                println!("Cost:  unknown ({} has no pricing)", self.model);
+                0.0
+            }
+        };
+
+        // Wall-clock time
+        let typical = history::typical_duration(
+            &self.model,
+            &self.quality,
+            &requested_size,
+        )
+        .context("Failed to read the history")?;
+        let (request_duration, duration_note) = match typical {
+            Some(typical) => (typical, "the median of recent runs"),
+            None => (assumed_duration(quality), "no history yet; a guess"),
+        };
+        let (total, pace) = project(
+            requests,
+            request_duration,
+            self.concurrency,
+            throttle,
+            self.images_per_minute
+                .map(|ipm| f64::from(ipm) / f64::from(per_request)),
+        );
+        println!(
+            "Time:  ~{} (~{}s per request, {duration_note})",
+            format_duration(total),
+            request_duration.as_secs(),
+        );
+        println!("       paced by {}", pace.describe(&self));
+
+        // Budget
+        let config = Config::load();
+        if let Some(budget) = config.monthly_budget {
+            let spent = history::monthly_spend()
+                .context("Failed to read the history for the budget check")?;
+            println!(
+                "\nBudget: ${spent:.2} of ${budget:.2} spent this month; \
+                 ${:.2} left after this job",
+                budget - spent - cost
+            );
+            if spent + cost > budget {
+                bail!(
+                    "This job (~${cost:.2}) would go over the monthly \
+                     budget by ${:.2}",
+                    spent + cost - budget
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How long a request takes when there's no history to go on.
+fn assumed_duration(quality: Quality) -> Duration {
+    Duration::from_secs(match quality {
+        Quality::Low => 15,
+        Quality::Medium => 30,
+        Quality::High => 60,
+    })
+}
+
+/// The wall-clock time of `requests` requests, each taking
+/// `request_duration`, and what limits their pace: running `concurrency` at
+/// a time, at least `throttle` apart, or at most `rate_limit` requests per
+/// minute. The slowest of these wins.
+fn project(
+    requests: u32,
+    request_duration: Duration,
+    concurrency: usize,
+    throttle: Option<Duration>,
+    rate_limit: Option<f64>,
+) -> (Duration, Pace) {
+    // Requests spaced out at a rate (per second) start one after another,
+    // and the last one then takes its own time
+    let spaced = |rate: f64| {
+        let starts = f64::from(requests.saturating_sub(1)) / rate;
+        Duration::from_secs_f64(starts) + request_duration
+    };
+    let waves = requests.div_ceil(concurrency.max(1) as u32);
+    let mut paces = vec![(request_duration * waves, Pace::Concurrency)];
+    if let Some(throttle) = throttle {
+        paces.push((spaced(1.0 / throttle.as_secs_f64()), Pace::Throttle));
+    }
+    if let Some(rate_limit) = rate_limit {
+        paces.push((spaced(rate_limit / 60.0), Pace::RateLimit));
+    }
+    paces
+        .into_iter()
+        .max_by_key(|(total, _)| *total)
+        .expect("There's always a concurrency limit")
+}
+
+/// A duration like "1h 23m", "4m 10s", or "40s".
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else if minutes > 0 {
+        format!("{minutes}m {secs}s")
+    } else {
+        format!("{secs}s")
+    }
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project() {
+        let minute = Duration::from_secs(60);
+        let secs = |(total, pace): (Duration, Pace)| (total.as_secs(), pace);
+
+        // One at a time: back to back
+        let projected = project(10, minute, 1, None, None);
+        assert_eq!(secs(projected), (600, Pace::Concurrency));
+
+        // Ten at a time: all at once
+        let projected = project(10, minute, 10, None, None);
+        assert_eq!(secs(projected), (60, Pace::Concurrency));
+
+        // A throttle slower than the concurrency allows
+        let throttle = Some(Duration::from_secs(30));
+        let projected = project(10, minute, 10, throttle, None);
+        assert_eq!(secs(projected), (330, Pace::Throttle));
+
+        // 5 requests per minute
+        let projected = project(11, minute, 10, None, Some(5.0));
+        assert_eq!(secs(projected), (180, Pace::RateLimit));
+
+        assert_eq!(format_duration(Duration::from_secs(40)), "40s");
+        assert_eq!(format_duration(Duration::from_secs(250)), "4m 10s");
+        assert_eq!(format_duration(Duration::from_secs(4980)), "1h 23m");
+    }
+}
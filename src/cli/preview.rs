@@ -0,0 +1,110 @@
+//! `--preview`: a rough look at the result right in the terminal.
+//!
+//! Renders with colored half-block characters, which work over SSH and in
+//! any terminal without a graphics protocol.
+
+use image::{imageops::FilterType, DynamicImage};
+use std::{fmt::Write as _, io::Write};
+
+use crate::imageops;
+
+/// The widest preview, in columns, even on wide terminals.
+const MAX_COLUMNS: u32 = 80;
+
+/// Print a preview of `image` to stderr, sized to the terminal.
+pub fn print(image: &DynamicImage) {
+    let columns = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse::<u32>().ok())
+        .unwrap_or(MAX_COLUMNS)
+        .clamp(8, MAX_COLUMNS);
+    let truecolor = std::env::var("COLORTERM")
+        .is_ok_and(|term| term == "truecolor" || term == "24bit");
+
+    let mut stderr = std::io::stderr().lock();
+    let _ = stderr.write_all(render(image, columns, truecolor).as_bytes());
+    let _ = stderr.flush();
+}
+
+/// Render `image` as `columns` wide rows of upper half blocks, each showing
+/// two pixels: the top as the foreground color, the bottom as the
+/// background.
+fn render(image: &DynamicImage, columns: u32, truecolor: bool) -> String {
+    // Terminal cells are about twice as tall as wide, so each half block is
+    // roughly square
+    let width = columns.min(image.width()).max(1);
+    let height = (image.height() as u64 * width as u64
+        / image.width().max(1) as u64)
+        .max(2) as u32
+        & !1;
+    let small = image.resize_exact(width, height, FilterType::Triangle);
+    let pixels = imageops::flatten(&small);
+
+    let mut out = String::new();
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let top = pixels.get_pixel(x, y).0;
+            let bottom = pixels.get_pixel(x, y + 1).0;
+            if truecolor {
+                let [r1, g1, b1] = top;
+                let [r2, g2, b2] = bottom;
+                let _ = write!(
+                    out,
+                    "\x1b[38;2;{r1};{g1};{b1};48;2;{r2};{g2};{b2}m\u{2580}"
+                );
+            } else {
+                let (fg, bg) = (ansi256(top), ansi256(bottom));
+                let _ = write!(out, "\x1b[38;5;{fg};48;5;{bg}m\u{2580}");
+            }
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+/// The closest color in the xterm 256-color 6x6x6 cube.
+fn ansi256([r, g, b]: [u8; 3]) -> u8 {
+    let level = |c: u8| ((c as u16 * 5 + 127) / 255) as u8;
+    16 + 36 * level(r) + 6 * level(g) + level(b)
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_render() {
+        // Red on top, blue below
+        let image = RgbaImage::from_fn(4, 4, |_, y| {
+            if y < 2 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        });
+        let image = DynamicImage::ImageRgba8(image);
+
+        let out = render(&image, 80, true);
+        let red = "\x1b[38;2;255;0;0;48;2;255;0;0m\u{2580}";
+        assert_eq!(out.lines().count(), 2);
+        assert_eq!(out.lines().next().unwrap(), red.repeat(4) + "\x1b[0m");
+
+        let out = render(&image, 80, false);
+        assert!(out.ends_with("\x1b[38;5;21;48;5;21m\u{2580}\x1b[0m\n"));
+
+        // Scaled down to fit
+        let out = render(&image, 2, false);
+        assert_eq!(out.lines().count(), 1);
+        assert_eq!(out.matches('\u{2580}').count(), 2);
+    }
+
+    #[test]
+    fn test_ansi256() {
+        assert_eq!(ansi256([0, 0, 0]), 16);
+        assert_eq!(ansi256([255, 255, 255]), 231);
+        assert_eq!(ansi256([255, 0, 0]), 196);
+    }
+}
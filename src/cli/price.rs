@@ -0,0 +1,63 @@
+//! `imgen price`: estimate what a generation would cost at each quality level.
+
+use crate::{
+    cli::{input::PromptArg, size_canonical, DEFAULT_NUM_IMAGES, DEFAULT_SIZE},
+    pricing::{self, Quality},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct PriceArgs {
+    /// A text description of the desired image(s)
+    ///
+    /// Can be a literal string, a path to a text file (if the path exists),
+    /// or '-' to read from stdin. Use '@<path>' to force interpretation as a
+    /// file path.
+    #[arg(verbatim_doc_comment)]
+    pub prompt: PromptArg,
+
+    /// The number of images to generate (1-10)
+    #[arg(short, long, default_value_t = DEFAULT_NUM_IMAGES)]
+    pub n: u8,
+
+    /// The size of the generated images.
+    /// One of: auto, 1024x1024, 1536x1024, 1024x1536, square, landscape, portrait
+    #[arg(long, default_value = DEFAULT_SIZE)]
+    pub size: String,
+}
+
+impl PriceArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let prompt = self.prompt.read_prompt()?;
+        let prompt_tokens = pricing::estimate_text_tokens(&prompt);
+
+        // The API picks the size for "auto"; assume the cheapest square
+        let (size, size_note) = match size_canonical(self.size) {
+            Some(size) => (size, ""),
+            None => ("1024x1024".to_string(), " (auto, assuming square)"),
+        };
+
+        println!(
+            "Estimated cost for {} image(s) at {size}{size_note}, \
+             ~{prompt_tokens} prompt tokens:\n",
+            self.n,
+        );
+        print!("{:<16}", "model");
+        for quality in Quality::ALL {
+            print!("{:>10}", quality.as_str());
+        }
+        println!();
+
+        for model in pricing::MODELS {
+            print!("{:<16}", model.model);
+            for quality in Quality::ALL {
+                let cost = model
+                    .estimate_cost(quality, &size, self.n, prompt_tokens)
+                    .map(|cost| format!("${cost:.3}"))
+                    .unwrap_or_else(|| "n/a".to_string());
+                print!("{cost:>10}");
+            }
+            println!();
+        }
+        Ok(())
+    }
+}
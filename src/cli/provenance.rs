@@ -0,0 +1,30 @@
+//! `imgen provenance`: display the C2PA content credentials in an image.
+
+use anyhow::Context;
+use std::path::PathBuf;
+
+use crate::metadata::{self, c2pa::JumbfBox};
+
+#[derive(clap::Args, Debug)]
+pub struct ProvenanceArgs {
+    /// The image file to inspect (png, jpeg, webp)
+    pub file: PathBuf,
+}
+
+impl ProvenanceArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let path = &self.file;
+        let bytes = std::fs::read(path).with_context(|| {
+            format!("Failed to read image from file: {}", path.display())
+        })?;
+
+        let manifest = metadata::read_c2pa(&bytes)?.with_context(|| {
+            format!("No C2PA content credentials found in: {}", path.display())
+        })?;
+        let manifest = JumbfBox::parse(&manifest)
+            .context("Failed to parse C2PA manifest store")?;
+
+        print!("{}", manifest.describe());
+        Ok(())
+    }
+}
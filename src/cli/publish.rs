@@ -0,0 +1,394 @@
+//! `imgen publish`: share saved images outside imgen.
+//!
+//! `imgen publish gh` commits the images to a branch of a GitHub repo, then
+//! comments on an issue or pull request with them embedded, along with the
+//! prompt and settings from the history.
+
+use anyhow::{bail, Context};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use log::{debug, info, warn};
+use serde_json::json;
+use std::{path::PathBuf, process::Command};
+
+use crate::{client, history, record::RunRecord, redact};
+
+const API_URL: &str = "https://api.github.com";
+
+/// The branch images are committed to, unless `--branch` is given
+const DEFAULT_BRANCH: &str = "imgen-assets";
+
+/// The directory in the branch that images are committed to
+const IMAGE_DIR: &str = "imgen";
+
+#[derive(clap::Args, Debug)]
+pub struct PublishArgs {
+    #[command(subcommand)]
+    target: Target,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Target {
+    /// Commit images to a GitHub repo and comment on an issue or pull
+    /// request with them embedded
+    ///
+    /// Needs a token with write access to the repo's contents and issues, from
+    /// `GITHUB_TOKEN`, `GH_TOKEN`, or the GitHub CLI (`gh auth login`).
+    Gh(GhArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct GhArgs {
+    /// The image file(s) to publish
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    /// The repository, as `owner/name`
+    #[arg(long, value_parser = parse_repo)]
+    repo: String,
+
+    /// The issue or pull request number to comment on
+    #[arg(long, visible_alias = "pr", value_name = "NUMBER")]
+    issue: u64,
+
+    /// The branch to commit the images to. Created from the default branch
+    /// if it doesn't exist.
+    #[arg(long, default_value = DEFAULT_BRANCH)]
+    branch: String,
+}
+
+impl PublishArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        match self.target {
+            Target::Gh(args) => args.run(),
+        }
+    }
+}
+
+impl GhArgs {
+    fn run(self) -> anyhow::Result<()> {
+        let github = GitHub::new(github_token()?, self.repo);
+        let records = history::load().unwrap_or_else(|err| {
+            warn!("Couldn't read the history for prompts: {err:#}");
+            Vec::new()
+        });
+
+        github.ensure_branch(&self.branch)?;
+        let mut images = Vec::with_capacity(self.files.len());
+        for path in &self.files {
+            let bytes = std::fs::read(path).with_context(|| {
+                format!("Failed to read image: {}", path.display())
+            })?;
+            let sha256 = history::sha256_hex(&bytes);
+            let extension = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .unwrap_or("png");
+            // Name by content, so publishing again reuses the same file
+            let repo_path =
+                format!("{IMAGE_DIR}/{}.{extension}", &sha256[..16]);
+            github.upload(&self.branch, &repo_path, &bytes, self.issue)?;
+            let url = format!(
+                "https://github.com/{}/blob/{}/{repo_path}?raw=true",
+                github.repo, self.branch
+            );
+            let run = find_run(&records, &sha256);
+            images.push(Published { url, run });
+        }
+
+        let comment = comment_body(&images);
+        let url = github.comment(self.issue, &comment)?;
+        info!("Commented on {}#{}", github.repo, self.issue);
+        println!("{url}");
+        Ok(())
+    }
+}
+
+/// A thin GitHub REST API client for one repo.
+struct GitHub {
+    agent: ureq::Agent,
+    token: String,
+    repo: String,
+}
+
+impl GitHub {
+    fn new(token: String, repo: String) -> Self {
+        Self {
+            agent: client::agent(),
+            token,
+            repo,
+        }
+    }
+
+    /// Create `branch` from the default branch, if it doesn't exist yet.
+    fn ensure_branch(&self, branch: &str) -> anyhow::Result<()> {
+        let uri = self.uri(&format!("/branches/{branch}"));
+        let response = self.request(self.agent.get(uri)).call();
+        if response.as_ref().is_ok_and(|r| r.status() == 404) {
+            let repo: serde_json::Value = read_json(
+                self.request(self.agent.get(self.uri(""))).call(),
+                "Failed to get repo",
+            )?;
+            let default = repo["default_branch"]
+                .as_str()
+                .context("Repo has no default branch")?;
+            let head: serde_json::Value = read_json(
+                self.request(
+                    self.agent
+                        .get(self.uri(&format!("/git/ref/heads/{default}"))),
+                )
+                .call(),
+                "Failed to get default branch",
+            )?;
+            let sha = head["object"]["sha"]
+                .as_str()
+                .context("Default branch has no commit")?;
+            let _: serde_json::Value = read_json(
+                self.request(self.agent.post(self.uri("/git/refs")))
+                    .send_json(json!({
+                        "ref": format!("refs/heads/{branch}"),
+                        "sha": sha,
+                    })),
+                "Failed to create branch",
+            )?;
+            info!("Created branch {branch} from {default}");
+            return Ok(());
+        }
+        let _: serde_json::Value =
+            read_json(response, &format!("Failed to get branch {branch}"))?;
+        Ok(())
+    }
+
+    /// Commit a file to `branch`, unless it's already there.
+    fn upload(
+        &self,
+        branch: &str,
+        path: &str,
+        bytes: &[u8],
+        issue: u64,
+    ) -> anyhow::Result<()> {
+        let uri = self.uri(&format!("/contents/{path}"));
+        let existing = self
+            .request(self.agent.get(&uri))
+            .query("ref", branch)
+            .call();
+        if existing.is_ok_and(|response| response.status().is_success()) {
+            debug!("{path} is already on {branch}");
+            return Ok(());
+        }
+        let _: serde_json::Value = read_json(
+            self.request(self.agent.put(&uri)).send_json(json!({
+                "message": format!("Add image for #{issue}"),
+                "content": BASE64_STANDARD.encode(bytes),
+                "branch": branch,
+            })),
+            "Failed to upload image",
+        )?;
+        debug!("Uploaded {path} to {branch}");
+        Ok(())
+    }
+
+    /// Comment on an issue or pull request, returning the comment's URL.
+    fn comment(&self, issue: u64, body: &str) -> anyhow::Result<String> {
+        let comment: serde_json::Value = read_json(
+            self.request(
+                self.agent
+                    .post(self.uri(&format!("/issues/{issue}/comments"))),
+            )
+            .send_json(json!({ "body": body })),
+            "Failed to comment",
+        )?;
+        Ok(comment["html_url"].as_str().unwrap_or_default().to_string())
+    }
+
+    fn uri(&self, path: &str) -> String {
+        format!("{API_URL}/repos/{}{path}", self.repo)
+    }
+
+    fn request<B>(
+        &self,
+        request: ureq::RequestBuilder<B>,
+    ) -> ureq::RequestBuilder<B> {
+        request
+            .header("Authorization", format!("Bearer {}", self.token))
+            .header("Accept", "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+    }
+}
+
+/// Read a GitHub API response, with GitHub's error message on failure.
+fn read_json(
+    response: Result<ureq::http::Response<ureq::Body>, ureq::Error>,
+    context: &str,
+) -> anyhow::Result<serde_json::Value> {
+    let mut response = response.context(context.to_string())?;
+    let status = response.status();
+    let body: serde_json::Value =
+        response.body_mut().read_json().unwrap_or_default();
+    if !status.is_success() {
+        let message = body["message"].as_str().unwrap_or_default();
+        bail!("{context}: {status} {message}");
+    }
+    Ok(body)
+}
+
+/// The GitHub token from the environment, or else from the GitHub CLI.
+fn github_token() -> anyhow::Result<String> {
+    let token = find_github_token()?;
+    redact::add_secret(&token);
+    Ok(token)
+}
+
+fn find_github_token() -> anyhow::Result<String> {
+    for var in ["GITHUB_TOKEN", "GH_TOKEN"] {
+        if let Ok(token) = std::env::var(var) {
+            return Ok(token);
+        }
+    }
+    let output = Command::new("gh").args(["auth", "token"]).output();
+    match output {
+        Ok(output) if output.status.success() => {
+            Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+        _ => bail!(
+            "No GitHub token; set `GITHUB_TOKEN` or log in with `gh auth \
+             login`"
+        ),
+    }
+}
+
+/// Check that `repo` is `owner/name`. For clap's `value_parser`.
+fn parse_repo(repo: &str) -> Result<String, String> {
+    match repo.split_once('/') {
+        Some((owner, name))
+            if !owner.is_empty() && !name.is_empty() && !name.contains('/') =>
+        {
+            Ok(repo.to_string())
+        }
+        _ => Err("expected a repository like `owner/name`".to_string()),
+    }
+}
+
+/// An uploaded image, and the history run that made it.
+struct Published<'a> {
+    url: String,
+    run: Option<Run<'a>>,
+}
+
+struct Run<'a> {
+    record: &'a RunRecord,
+    revised_prompt: Option<&'a str>,
+}
+
+/// The history run that saved an image with this SHA-256, if any.
+fn find_run<'a>(records: &'a [RunRecord], sha256: &str) -> Option<Run<'a>> {
+    records.iter().rev().find_map(|record| {
+        let image = record
+            .images
+            .iter()
+            .find(|image| image.sha256.as_deref() == Some(sha256))?;
+        Some(Run {
+            record,
+            revised_prompt: image.revised_prompt.as_deref(),
+        })
+    })
+}
+
+/// The markdown comment: each image, followed by the prompt and settings
+/// that made it, if we know them.
+fn comment_body(images: &[Published]) -> String {
+    let mut body = String::new();
+    for Published { url, run } in images {
+        if !body.is_empty() {
+            body.push_str("\n\n");
+        }
+        let Some(Run {
+            record,
+            revised_prompt,
+        }) = run
+        else {
+            body.push_str(&format!("![generated image]({url})"));
+            continue;
+        };
+        let alt = record.prompt.replace(['[', ']', '\n'], " ");
+        body.push_str(&format!("![{alt}]({url})\n\n"));
+        for line in record.prompt.lines() {
+            body.push_str(&format!("> {line}\n"));
+        }
+        if let Some(revised) = revised_prompt.filter(|r| *r != record.prompt) {
+            body.push_str(&format!(
+                "\n<details><summary>Revised prompt</summary>\n\n\
+                 {revised}\n</details>\n"
+            ));
+        }
+        body.push_str(&format!(
+            "\n<sub>{} · ${:.3} · generated with imgen</sub>",
+            record.model, record.cost
+        ));
+    }
+    body
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::Usage, record::ImageRecord};
+
+    #[test]
+    fn test_parse_repo() {
+        assert_eq!(parse_repo("phlip9/imgen").as_deref(), Ok("phlip9/imgen"));
+        assert!(parse_repo("imgen").is_err());
+        assert!(parse_repo("/imgen").is_err());
+        assert!(parse_repo("a/b/c").is_err());
+    }
+
+    #[test]
+    fn test_comment_body() {
+        let usage: Usage = serde_json::from_str(
+            r#"{"total_tokens":2,"input_tokens":1,"output_tokens":1,
+                "input_tokens_details":{"text_tokens":1,"image_tokens":0}}"#,
+        )
+        .unwrap();
+        let record = RunRecord {
+            created: 0,
+            model: "gpt-image-1".to_string(),
+            prompt: "a [red] fox\nin snow".to_string(),
+            original_prompt: None,
+            images: vec![ImageRecord {
+                path: None,
+                revised_prompt: None,
+                alt_text: None,
+                sha256: Some("abc".to_string()),
+                ipfs_cid: None,
+                signature: None,
+                cost: 0.042,
+            }],
+            usage,
+            cost: 0.042,
+            size: None,
+            quality: None,
+            duration: None,
+            environment: None,
+        };
+        let records = [record];
+        let images = [
+            Published {
+                url: "https://x/1.png".to_string(),
+                run: find_run(&records, "abc"),
+            },
+            Published {
+                url: "https://x/2.png".to_string(),
+                run: find_run(&records, "def"),
+            },
+        ];
+        assert_eq!(
+            comment_body(&images),
+            "![a  red  fox in snow](https://x/1.png)\n\n\
+             > a [red] fox\n\
+             > in snow\n\
+             \n<sub>gpt-image-1 · $0.042 · generated with imgen</sub>\
+             \n\n![generated image](https://x/2.png)"
+        );
+    }
+}
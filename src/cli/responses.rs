@@ -0,0 +1,230 @@
+//! Creating images with the Responses API's image generation tool, for
+//! `--stream` and `--continue`.
+//!
+//! `--stream` streams partial images as the final one renders. Each partial
+//! is saved over the last (so an image viewer that reloads shows the
+//! progress), and previewed in the terminal with `--preview`.
+//!
+//! `--continue` refines the image from an earlier response ("make the sky
+//! darker"), which the API remembers, so nothing is uploaded again. The
+//! latest response is kept in the data directory
+//! (`~/.local/share/imgen/last_response`) for `--continue last`.
+
+use anyhow::Context;
+use base64::{prelude::BASE64_STANDARD, Engine};
+use indicatif::MultiProgress;
+use log::{debug, info, warn};
+use std::path::PathBuf;
+
+use crate::{
+    api::{
+        self, CreateRequest, ImageGenerationTool, InputTokensDetails, Response,
+        ResponsesRequest, ResponsesResponse, Usage,
+    },
+    cli::{
+        detach::{IMAGE_MODEL, RESPONSES_MODEL},
+        preview, ImageRequest,
+    },
+    client::{Client, ClientError},
+    config,
+    pricing::{self, Quality},
+};
+
+const LAST_RESPONSE_FILE_NAME: &str = "last_response";
+
+/// How many partial images to stream before the final one (at most 3)
+const PARTIAL_IMAGES: u8 = 2;
+
+/// The image output tokens each partial image adds
+const PARTIAL_IMAGE_TOKENS: u32 = 100;
+
+/// A create request, sent to the Responses API.
+#[derive(Clone)]
+pub struct ResponsesImageRequest {
+    pub req: CreateRequest,
+    /// The response to continue from, for `--continue`
+    pub previous_response_id: Option<String>,
+    /// Stream partial images, for `--stream`
+    pub stream: Option<Partials>,
+}
+
+/// Where to show the partial images.
+#[derive(Clone)]
+pub struct Partials {
+    /// Where to save each partial image, over the last one
+    pub path: PathBuf,
+    /// Preview each partial image in the terminal, hiding these progress
+    /// bars while printing
+    pub preview: Option<MultiProgress>,
+}
+
+/// Check that a request can go to the Responses API.
+pub fn check(flag: &str, model: &str, n: u8) -> anyhow::Result<()> {
+    if model != IMAGE_MODEL {
+        anyhow::bail!("{flag} only supports {IMAGE_MODEL}");
+    }
+    if n != 1 {
+        anyhow::bail!("{flag} generates one image at a time");
+    }
+    Ok(())
+}
+
+/// The response ID a `--continue` value refers to: "new" for none, "last"
+/// for the latest response, or else the ID itself.
+pub fn resolve_continue(id: &str) -> anyhow::Result<Option<String>> {
+    match id {
+        "new" => Ok(None),
+        "last" => {
+            let path = last_response_path()?;
+            let id = std::fs::read_to_string(&path).context(
+                "No response to continue yet; start with `--continue new`",
+            )?;
+            Ok(Some(id.trim().to_string()))
+        }
+        id => Ok(Some(id.to_string())),
+    }
+}
+
+fn last_response_path() -> anyhow::Result<PathBuf> {
+    let dir =
+        config::data_dir().context("Could not determine the data directory")?;
+    Ok(dir.join(LAST_RESPONSE_FILE_NAME))
+}
+
+/// Remember the latest response, for `--continue last`.
+fn save_last_response(id: &str) -> anyhow::Result<()> {
+    let path = last_response_path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create: {}", dir.display()))?;
+    }
+    std::fs::write(&path, id)
+        .with_context(|| format!("Failed to write: {}", path.display()))?;
+    debug!("Saved the response ID to: {}", path.display());
+    Ok(())
+}
+
+impl Partials {
+    fn show(&self, index: u32, b64: String) {
+        let bytes = match BASE64_STANDARD.decode(b64) {
+            Ok(bytes) => bytes,
+            Err(err) => return warn!("Failed to decode partial image: {err}"),
+        };
+        if let Some(dir) = self.path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        match std::fs::write(&self.path, &bytes) {
+            Ok(()) => info!(
+                "Partial image {}/{PARTIAL_IMAGES} saved to: {}",
+                index + 1,
+                self.path.display()
+            ),
+            Err(err) => warn!(
+                "Failed to save partial image to {}: {err}",
+                self.path.display()
+            ),
+        }
+        if let Some(progress) = &self.preview {
+            match image::load_from_memory(&bytes) {
+                Ok(image) => progress.suspend(|| preview::print(&image)),
+                Err(err) => warn!("Failed to decode partial image: {err}"),
+            }
+        }
+    }
+}
+
+impl ResponsesImageRequest {
+    /// The image tool's usage isn't reported, so estimate it like the images
+    /// API would report it. (Earlier turns of a `--continue` conversation
+    /// aren't counted.)
+    fn estimate_usage(&self) -> Usage {
+        let input_tokens = pricing::estimate_text_tokens(&self.req.prompt);
+        let quality = self
+            .req
+            .quality
+            .as_deref()
+            .and_then(Quality::from_name)
+            .unwrap_or(Quality::High);
+        let size = self.req.size.as_deref().unwrap_or("1024x1024");
+        let partial_images = match self.stream {
+            Some(_) => u32::from(PARTIAL_IMAGES),
+            None => 0,
+        };
+        let output_tokens = pricing::for_model(IMAGE_MODEL)
+            .and_then(|pricing| pricing.output_tokens(quality, size))
+            .unwrap_or(0)
+            + partial_images * PARTIAL_IMAGE_TOKENS;
+        Usage {
+            total_tokens: input_tokens + output_tokens,
+            input_tokens,
+            output_tokens,
+            input_tokens_details: InputTokensDetails {
+                text_tokens: input_tokens,
+                image_tokens: 0,
+            },
+        }
+    }
+
+    fn send_request(
+        &self,
+        client: &Client,
+    ) -> Result<ResponsesResponse, ClientError> {
+        let request = ResponsesRequest {
+            model: RESPONSES_MODEL.to_string(),
+            input: self.req.prompt.clone(),
+            tools: vec![ImageGenerationTool {
+                kind: "image_generation".to_string(),
+                size: self.req.size.clone(),
+                quality: self.req.quality.clone(),
+                background: self.req.background.clone(),
+                moderation: self.req.moderation.clone(),
+                output_compression: self.req.output_compression,
+                output_format: self.req.output_format.clone(),
+                partial_images: self.stream.as_ref().map(|_| PARTIAL_IMAGES),
+            }],
+            tool_choice: serde_json::json!({ "type": "image_generation" }),
+            background: false,
+            stream: self.stream.is_some(),
+            previous_response_id: self.previous_response_id.clone(),
+        };
+        match &self.stream {
+            Some(partials) => client.stream_response(&request, |index, b64| {
+                partials.show(index, b64)
+            }),
+            None => client.create_response(&request),
+        }
+    }
+}
+
+impl ImageRequest for ResponsesImageRequest {
+    fn prompt_mut(&mut self) -> &mut String {
+        &mut self.req.prompt
+    }
+    fn n_mut(&mut self) -> &mut Option<u8> {
+        &mut self.req.n
+    }
+    fn send(&self, client: &Client) -> Result<Response, ClientError> {
+        let response = self.send_request(client)?;
+        match save_last_response(&response.id) {
+            Ok(()) => info!(
+                "Response {}; refine the image with `--continue last`",
+                response.id
+            ),
+            Err(err) => warn!("Failed to save the response ID: {err:#}"),
+        }
+        let data = response.images();
+        if data.is_empty() {
+            return Err(ClientError::ApiError {
+                status: ureq::http::StatusCode::OK,
+                message: "The response completed without an image".to_string(),
+                retry_after: None,
+                error: None,
+            });
+        }
+        Ok(Response {
+            created: api::unix_now(),
+            data,
+            usage: self.estimate_usage(),
+        })
+    }
+}
@@ -0,0 +1,105 @@
+//! `--open-folder`: show saved images in the system file manager.
+
+use anyhow::Context;
+use log::debug;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Reveal (select) the files in the system file manager, or at least open
+/// the folder containing them.
+pub fn reveal(paths: &[PathBuf]) -> anyhow::Result<()> {
+    let Some(first) = paths.first() else {
+        return Ok(());
+    };
+    let paths = paths
+        .iter()
+        .map(std::path::absolute)
+        .collect::<Result<Vec<_>, _>>()?;
+    if select_files(&paths) {
+        return Ok(());
+    }
+
+    debug!("Couldn't select the files; opening their folder instead");
+    let dir = paths[0].parent().unwrap_or(Path::new("."));
+    open::that_detached(dir).with_context(|| {
+        format!("Failed to open folder for: {}", first.display())
+    })
+}
+
+/// Select the files in Finder. Returns whether it worked.
+#[cfg(target_os = "macos")]
+fn select_files(paths: &[PathBuf]) -> bool {
+    run(Command::new("open").arg("-R").args(paths))
+}
+
+/// Select the first file in Explorer, which can only select one. Returns
+/// whether it worked.
+#[cfg(windows)]
+fn select_files(paths: &[PathBuf]) -> bool {
+    let mut select = std::ffi::OsString::from("/select,");
+    select.push(&paths[0]);
+    // Explorer's exit code is unreliable, so only check that it started
+    Command::new("explorer").arg(select).spawn().is_ok()
+}
+
+/// Select the files with the freedesktop.org file manager D-Bus interface,
+/// which Nautilus, Dolphin, Nemo, Thunar, and others implement. Returns
+/// whether it worked.
+#[cfg(not(any(target_os = "macos", windows)))]
+fn select_files(paths: &[PathBuf]) -> bool {
+    let uris: Vec<String> = paths.iter().map(|path| file_uri(path)).collect();
+    run(Command::new("dbus-send")
+        .args([
+            "--session",
+            "--print-reply",
+            "--dest=org.freedesktop.FileManager1",
+            "--type=method_call",
+            "/org/freedesktop/FileManager1",
+            "org.freedesktop.FileManager1.ShowItems",
+        ])
+        .arg(format!("array:string:{}", uris.join(",")))
+        .arg("string:"))
+}
+
+#[cfg(not(windows))]
+fn run(command: &mut Command) -> bool {
+    use std::process::Stdio;
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// A `file://` URI for an absolute path, percent-encoding anything but
+/// unreserved characters and slashes. Commas are encoded too, since
+/// `dbus-send` splits arrays on them.
+#[cfg_attr(any(target_os = "macos", windows), allow(dead_code))]
+pub fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_encoded_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => uri.push(byte as char),
+            b'-' | b'.' | b'_' | b'~' | b'/' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    uri
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_uri() {
+        assert_eq!(
+            file_uri(Path::new("/home/me/a cat, café.png")),
+            "file:///home/me/a%20cat%2C%20caf%C3%A9.png"
+        );
+    }
+}
@@ -1,7 +1,27 @@
+use anyhow::Context;
+use log::warn;
+use std::borrow::Cow;
+
+use crate::config::Filenames;
+
+/// Device names Windows reserves, even with an extension (e.g. "con.png")
+const WINDOWS_RESERVED: &[&str] = &[
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6",
+    "com7", "com8", "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6",
+    "lpt7", "lpt8", "lpt9",
+];
+
 /// Sanitize the prompt to create a prefix for the output files
-pub fn prompt_prefix(prompt: &str) -> String {
+pub fn prompt_prefix(prompt: &str, opts: &Filenames) -> String {
     // Sanitize only a small prefix
-    let (prefix, _) = prompt.split_at_floor_char_boundary(32);
+    let (prefix, _) = prompt.split_at_floor_char_boundary(opts.max_length);
+
+    // e.g. "café" -> "cafe"
+    let prefix = if opts.transliterate {
+        Cow::Owned(deunicode::deunicode(prefix))
+    } else {
+        Cow::Borrowed(prefix)
+    };
 
     // Create a sanitized prefix from the prompt (first few words)
     let sanitized = prefix
@@ -15,18 +35,64 @@ pub fn prompt_prefix(prompt: &str) -> String {
                 .collect::<String>()
         })
         .filter(|s| !s.is_empty())
-        .take(5) // Take first 5 words
+        .take(opts.max_words)
         .collect::<Vec<_>>()
         .join("_");
 
-    // Ensure the prefix is not empty
+    // Ensure the prefix is not empty, or a name Windows can't create
     if sanitized.is_empty() {
         "imgen".to_string()
+    } else if WINDOWS_RESERVED.contains(&sanitized.as_str()) {
+        format!("{sanitized}_")
     } else {
         sanitized
     }
 }
 
+/// The timestamp part of automatic filenames: the Unix time `created`, or
+/// the local time in the configured `timestamp_format`.
+pub fn timestamp(created: u64, opts: &Filenames) -> String {
+    let Some(format) = &opts.timestamp_format else {
+        return created.to_string();
+    };
+    // The format is validated up front, but never lose an image over it
+    format_timestamp(created, format).unwrap_or_else(|err| {
+        warn!("Ignoring invalid timestamp_format: {err:#}");
+        created.to_string()
+    })
+}
+
+/// Make sure the configured `timestamp_format` works, before generating.
+pub fn validate(opts: &Filenames) -> anyhow::Result<()> {
+    if let Some(format) = &opts.timestamp_format {
+        format_timestamp(0, format).with_context(|| {
+            format!("Invalid \"timestamp_format\" in config: {format:?}")
+        })?;
+    }
+    Ok(())
+}
+
+/// Format the Unix time `created` in local time.
+fn format_timestamp(created: u64, format: &str) -> anyhow::Result<String> {
+    let seconds = i64::try_from(created)?;
+    let time = jiff::Timestamp::from_second(seconds)?
+        .to_zoned(jiff::tz::TimeZone::system());
+    let stamp = jiff::fmt::strtime::format(format, &time)?;
+
+    // Keep path separators and other awkward characters out of the name
+    let stamp: String = stamp
+        .chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || c == '-' || c == '_' => c,
+            _ => '-',
+        })
+        .collect();
+    if stamp.is_empty() {
+        anyhow::bail!("The timestamp is empty");
+    }
+    Ok(stamp)
+}
+
 trait StrExt {
     /// Safely splits the string at `mid` (or the last valid char boundary).
     /// Unlike `std::str::split_at`, this will never panic.
@@ -57,3 +123,47 @@ impl StrExt for str {
             .unwrap_or(index)
     }
 }
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prompt_prefix() {
+        let opts = Filenames::default();
+        assert_eq!(
+            prompt_prefix("A cute cat saying \"hello\" on the Moon", &opts),
+            "a_cute_cat_saying_hello"
+        );
+        assert_eq!(prompt_prefix("Café au lait", &opts), "café_au_lait");
+        assert_eq!(prompt_prefix("!!!", &opts), "imgen");
+        assert_eq!(prompt_prefix("Con", &opts), "con_");
+        assert_eq!(prompt_prefix("LPT1", &opts), "lpt1_");
+
+        let opts = Filenames {
+            transliterate: true,
+            max_length: 64,
+            max_words: 2,
+            timestamp_format: None,
+        };
+        assert_eq!(prompt_prefix("Café au lait", &opts), "cafe_au");
+        assert_eq!(prompt_prefix("日本語", &opts), "ri_ben");
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let mut opts = Filenames::default();
+        assert_eq!(timestamp(1_700_000_000, &opts), "1700000000");
+
+        // November 2023 in any time zone
+        opts.timestamp_format = Some("%Y/%m %b".to_string());
+        assert_eq!(timestamp(1_700_000_000, &opts), "2023-11-Nov");
+        assert!(validate(&opts).is_ok());
+
+        opts.timestamp_format = Some("%Y%".to_string());
+        assert!(validate(&opts).is_err());
+        assert_eq!(timestamp(1_700_000_000, &opts), "1700000000");
+    }
+}
@@ -0,0 +1,430 @@
+//! `imgen service`: schedule an imgen command with the system's user-level
+//! service manager (a systemd timer on Linux, a launchd agent on macOS).
+
+use anyhow::{bail, Context};
+use log::info;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct ServiceArgs {
+    #[command(subcommand)]
+    action: Action,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Action {
+    /// Run an imgen command every day, e.g.
+    /// `imgen service install --at 07:30 -- daily --prompt-file themes.txt`
+    Install(InstallArgs),
+
+    /// Stop and remove a scheduled command
+    Uninstall(UninstallArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct InstallArgs {
+    /// The local time to run at each day, as HH:MM
+    #[arg(long, default_value = "09:00", value_parser = parse_time)]
+    at: (u8, u8),
+
+    /// A name for the service, to tell several apart
+    #[arg(long, default_value = "daily", value_parser = parse_name)]
+    name: String,
+
+    /// The imgen arguments to run, after `--`. Relative paths are relative
+    /// to the current directory.
+    #[arg(last = true, required = true, value_name = "ARGS")]
+    args: Vec<OsString>,
+}
+
+#[derive(clap::Args, Debug)]
+struct UninstallArgs {
+    /// The name the service was installed with
+    #[arg(long, default_value = "daily", value_parser = parse_name)]
+    name: String,
+}
+
+impl ServiceArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        match self.action {
+            Action::Install(args) => args.run(),
+            Action::Uninstall(args) => uninstall(&args.name),
+        }
+    }
+}
+
+/// The command to schedule, and when.
+struct Job<'a> {
+    name: &'a str,
+    hour: u8,
+    minute: u8,
+    /// The full command line, starting with the imgen executable
+    command: Vec<String>,
+    working_dir: &'a Path,
+}
+
+impl InstallArgs {
+    fn run(self) -> anyhow::Result<()> {
+        let exe = std::env::current_exe()
+            .context("Couldn't find the imgen executable")?;
+        let working_dir = std::env::current_dir()?;
+        let command = std::iter::once(exe.into_os_string())
+            .chain(self.args)
+            .map(|arg| {
+                arg.into_string().map_err(|arg| {
+                    anyhow::anyhow!("Non-UTF-8 argument: {arg:?}")
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let (hour, minute) = self.at;
+        install(&Job {
+            name: &self.name,
+            hour,
+            minute,
+            command,
+            working_dir: &working_dir,
+        })
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn install(job: &Job) -> anyhow::Result<()> {
+    let path = launchd_plist_path(job.name)?;
+    let log = home_dir()?
+        .join("Library/Logs")
+        .join(format!("imgen-{}.log", job.name));
+    write_file(&path, &launchd_plist(job, &log))?;
+    // Reload, in case it was already installed
+    let _ = Command::new("launchctl").arg("unload").arg(&path).output();
+    run(Command::new("launchctl").arg("load").arg("-w").arg(&path))?;
+    info!(
+        "Scheduled daily at {:02}:{:02}; errors are logged to: {}",
+        job.hour,
+        job.minute,
+        log.display()
+    );
+    Ok(())
+}
+
+#[cfg(target_os = "macos")]
+fn uninstall(name: &str) -> anyhow::Result<()> {
+    let path = launchd_plist_path(name)?;
+    if !path.exists() {
+        bail!("No service named {name:?}");
+    }
+    let _ = Command::new("launchctl").arg("unload").arg(&path).output();
+    std::fs::remove_file(&path)?;
+    info!("Removed: {}", path.display());
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn install(job: &Job) -> anyhow::Result<()> {
+    let dir = systemd_user_dir()?;
+    let unit = format!("imgen-{}", job.name);
+    write_file(&dir.join(format!("{unit}.service")), &systemd_service(job))?;
+    write_file(&dir.join(format!("{unit}.timer")), &systemd_timer(job))?;
+
+    let enabled =
+        run(Command::new("systemctl").args(["--user", "daemon-reload"]))
+            .and_then(|()| {
+                run(Command::new("systemctl")
+                    .args(["--user", "enable", "--now"])
+                    .arg(format!("{unit}.timer")))
+            });
+    if let Err(err) = enabled {
+        log::warn!("{err:#}");
+        bail!(
+            "Couldn't enable the timer; run `systemctl --user enable --now \
+             {unit}.timer` once systemd is available"
+        );
+    }
+    info!(
+        "Scheduled daily at {:02}:{:02}; see logs with `journalctl --user -u \
+         {unit}`",
+        job.hour, job.minute
+    );
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn uninstall(name: &str) -> anyhow::Result<()> {
+    let dir = systemd_user_dir()?;
+    let unit = format!("imgen-{name}");
+    let timer = dir.join(format!("{unit}.timer"));
+    if !timer.exists() {
+        bail!("No service named {name:?}");
+    }
+    let _ = Command::new("systemctl")
+        .args(["--user", "disable", "--now"])
+        .arg(format!("{unit}.timer"))
+        .output();
+    for path in [timer, dir.join(format!("{unit}.service"))] {
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+            info!("Removed: {}", path.display());
+        }
+    }
+    let _ = Command::new("systemctl")
+        .args(["--user", "daemon-reload"])
+        .output();
+    Ok(())
+}
+
+#[cfg(windows)]
+fn install(job: &Job) -> anyhow::Result<()> {
+    bail!(
+        "imgen service isn't supported on Windows yet; schedule it with Task \
+         Scheduler instead, e.g.:\n  schtasks /Create /SC DAILY /ST \
+         {:02}:{:02} /TN imgen-{} /TR \"{}\"",
+        job.hour,
+        job.minute,
+        job.name,
+        job.command.join(" ")
+    )
+}
+
+#[cfg(windows)]
+fn uninstall(name: &str) -> anyhow::Result<()> {
+    bail!(
+        "imgen service isn't supported on Windows yet; remove the task with \
+         `schtasks /Delete /TN imgen-{name}`"
+    )
+}
+
+/// A systemd service unit running the command once.
+#[cfg_attr(any(target_os = "macos", windows), allow(dead_code))]
+fn systemd_service(job: &Job) -> String {
+    let command: Vec<_> =
+        job.command.iter().map(|arg| systemd_quote(arg)).collect();
+    format!(
+        "[Unit]\n\
+         Description=imgen {name}\n\
+         \n\
+         [Service]\n\
+         Type=oneshot\n\
+         WorkingDirectory={dir}\n\
+         ExecStart={command}\n",
+        name = job.name,
+        // Not quoted, but specifiers still apply
+        dir = job.working_dir.to_string_lossy().replace('%', "%%"),
+        command = command.join(" "),
+    )
+}
+
+/// A systemd timer unit starting the service daily. `Persistent` catches up
+/// on runs missed while the computer was off.
+#[cfg_attr(any(target_os = "macos", windows), allow(dead_code))]
+fn systemd_timer(job: &Job) -> String {
+    format!(
+        "[Unit]\n\
+         Description=Timer for imgen {name}\n\
+         \n\
+         [Timer]\n\
+         OnCalendar=*-*-* {hour:02}:{minute:02}:00\n\
+         Persistent=true\n\
+         \n\
+         [Install]\n\
+         WantedBy=timers.target\n",
+        name = job.name,
+        hour = job.hour,
+        minute = job.minute,
+    )
+}
+
+/// Quote an argument for a systemd `ExecStart=` line.
+#[cfg_attr(any(target_os = "macos", windows), allow(dead_code))]
+fn systemd_quote(arg: &str) -> String {
+    let escaped = arg
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%")
+        .replace('$', "$$");
+    format!("\"{escaped}\"")
+}
+
+/// A launchd agent running the command daily.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn launchd_plist(job: &Job, log: &Path) -> String {
+    let args: String = job
+        .command
+        .iter()
+        .map(|arg| format!("        <string>{}</string>\n", xml_escape(arg)))
+        .collect();
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n\
+         \x20   <key>Label</key>\n\
+         \x20   <string>{label}</string>\n\
+         \x20   <key>ProgramArguments</key>\n\
+         \x20   <array>\n\
+         {args}\
+         \x20   </array>\n\
+         \x20   <key>WorkingDirectory</key>\n\
+         \x20   <string>{dir}</string>\n\
+         \x20   <key>StartCalendarInterval</key>\n\
+         \x20   <dict>\n\
+         \x20       <key>Hour</key>\n\
+         \x20       <integer>{hour}</integer>\n\
+         \x20       <key>Minute</key>\n\
+         \x20       <integer>{minute}</integer>\n\
+         \x20   </dict>\n\
+         \x20   <key>StandardErrorPath</key>\n\
+         \x20   <string>{log}</string>\n\
+         </dict>\n\
+         </plist>\n",
+        label = launchd_label(job.name),
+        dir = xml_escape(&job.working_dir.to_string_lossy()),
+        hour = job.hour,
+        minute = job.minute,
+        log = xml_escape(&log.to_string_lossy()),
+    )
+}
+
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn launchd_label(name: &str) -> String {
+    format!("io.github.phlip9.imgen.{name}")
+}
+
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(target_os = "macos")]
+fn launchd_plist_path(name: &str) -> anyhow::Result<PathBuf> {
+    Ok(home_dir()?
+        .join("Library/LaunchAgents")
+        .join(format!("{}.plist", launchd_label(name))))
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn systemd_user_dir() -> anyhow::Result<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir()?.join(".config"),
+    };
+    Ok(config.join("systemd").join("user"))
+}
+
+#[cfg_attr(windows, allow(dead_code))]
+fn home_dir() -> anyhow::Result<PathBuf> {
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .context("$HOME isn't set")
+}
+
+#[cfg_attr(windows, allow(dead_code))]
+fn write_file(path: &Path, contents: &str) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, contents)
+        .with_context(|| format!("Failed to write to: {}", path.display()))?;
+    info!("Wrote: {}", path.display());
+    Ok(())
+}
+
+/// Run a command, failing with its stderr if it doesn't succeed.
+#[cfg_attr(windows, allow(dead_code))]
+fn run(command: &mut Command) -> anyhow::Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .output()
+        .with_context(|| format!("Failed to run {program}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{program} failed: {}", stderr.trim());
+    }
+    Ok(())
+}
+
+/// Parse a 24-hour `HH:MM` time. For clap's `value_parser`.
+fn parse_time(s: &str) -> Result<(u8, u8), String> {
+    let invalid = || format!("expected a time like 07:30, got {s:?}");
+    let (hour, minute) = s.split_once(':').ok_or_else(invalid)?;
+    let hour: u8 = hour.parse().map_err(|_| invalid())?;
+    let minute: u8 = minute.parse().map_err(|_| invalid())?;
+    if hour > 23 || minute > 59 {
+        return Err(invalid());
+    }
+    Ok((hour, minute))
+}
+
+/// Service names end up in file names, so keep them simple. For clap's
+/// `value_parser`.
+fn parse_name(s: &str) -> Result<String, String> {
+    let valid = !s.is_empty()
+        && s.bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-');
+    if !valid {
+        return Err("use only letters, digits, and '-'".to_string());
+    }
+    Ok(s.to_string())
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(working_dir: &Path) -> Job<'_> {
+        Job {
+            name: "daily",
+            hour: 7,
+            minute: 5,
+            command: vec![
+                "/usr/bin/imgen".to_string(),
+                "daily".to_string(),
+                "--prompt-file".to_string(),
+                "my \"themes\" 100%.txt".to_string(),
+            ],
+            working_dir,
+        }
+    }
+
+    #[test]
+    fn test_systemd_units() {
+        let job = job(Path::new("/home/me"));
+        assert_eq!(
+            systemd_service(&job),
+            "[Unit]\nDescription=imgen daily\n\n[Service]\nType=oneshot\n\
+             WorkingDirectory=/home/me\n\
+             ExecStart=\"/usr/bin/imgen\" \"daily\" \"--prompt-file\" \
+             \"my \\\"themes\\\" 100%%.txt\"\n"
+        );
+        assert!(systemd_timer(&job).contains("OnCalendar=*-*-* 07:05:00\n"));
+    }
+
+    #[test]
+    fn test_launchd_plist() {
+        let job = job(Path::new("/Users/me"));
+        let plist = launchd_plist(&job, Path::new("/Users/me/imgen.log"));
+        assert!(
+            plist.contains("<string>my &quot;themes&quot; 100%.txt</string>\n")
+        );
+        assert!(plist.contains("<integer>7</integer>"));
+        assert!(plist.contains("<string>io.github.phlip9.imgen.daily</string>"));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("07:30"), Ok((7, 30)));
+        assert_eq!(parse_time("23:59"), Ok((23, 59)));
+        assert!(parse_time("24:00").is_err());
+        assert!(parse_time("7").is_err());
+        assert!(parse_name("daily-2").is_ok());
+        assert!(parse_name("../x").is_err());
+    }
+}
@@ -1,5 +1,5 @@
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use std::time::Duration;
+use std::{borrow::Cow, time::Duration};
 
 /// A RAII struct that automatically finishes the spinner when dropped.
 pub struct Spinner<'a> {
@@ -21,9 +21,7 @@ impl<'a> Spinner<'a> {
         spinner.set_style(
             ProgressStyle::with_template("{spinner:.blue} {msg}")
                 .unwrap()
-                .tick_strings(&[
-                    "⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏",
-                ]),
+                .tick_strings(tick_strings()),
         );
         Self {
             global_progress,
@@ -31,9 +29,30 @@ impl<'a> Spinner<'a> {
         }
     }
 
-    pub fn set_message(&self, message: &'static str) {
+    pub fn set_message(&self, message: impl Into<Cow<'static, str>>) {
         self.spinner.set_message(message);
     }
+
+    /// The progress bars this spinner is shown with.
+    pub fn progress(&self) -> MultiProgress {
+        self.global_progress.clone()
+    }
+
+    /// Hide the spinner while `f` writes to the terminal.
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.global_progress.suspend(f)
+    }
+}
+
+/// The spinner's frames, and the final frame.
+fn tick_strings() -> &'static [&'static str] {
+    // The classic Windows console (conhost, `CONOUT$`) font has no braille,
+    // so use ASCII there. Windows Terminal sets `WT_SESSION`.
+    if cfg!(windows) && std::env::var_os("WT_SESSION").is_none() {
+        &["|", "/", "-", "\\", " "]
+    } else {
+        &["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]
+    }
 }
 
 impl Drop for Spinner<'_> {
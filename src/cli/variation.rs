@@ -0,0 +1,144 @@
+//! `imgen variation`: generate variations of an existing image, with no
+//! prompt. Only dall-e-2 supports the variations endpoint.
+
+use anyhow::{bail, Context};
+use log::{info, warn};
+use std::path::PathBuf;
+
+use crate::{
+    api::{DecodedResponse, VariationRequest},
+    capabilities,
+    cli::{input, sanitize, size_canonical},
+    client::Client,
+    config::Config,
+    history,
+    record::{Environment, ImageRecord, RunRecord},
+};
+
+/// The only model with a variations endpoint
+const MODEL: &str = "dall-e-2";
+
+#[derive(clap::Args, Debug)]
+pub struct VariationArgs {
+    /// The image to make variations of: a square png under 4 MB.
+    ///
+    /// Can be a file path or '-' to read from stdin. Use '@<path>' to force
+    /// interpretation as a file path.
+    #[arg(short, long, verbatim_doc_comment)]
+    pub image: input::ImageArg,
+
+    /// The number of variations to generate (1-10)
+    #[arg(short, long, default_value_t = 1)]
+    pub n: u8,
+
+    /// The size of the variations (256x256, 512x512, 1024x1024, square)
+    #[arg(long, default_value = "1024x1024")]
+    pub size: String,
+
+    /// Where to save the variation (with -n 1). Defaults to a name based on
+    /// the input image's, in the current directory.
+    #[arg(short, long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+}
+
+impl VariationArgs {
+    pub fn run(self, client: &Client) -> anyhow::Result<()> {
+        if !(1..=10).contains(&self.n) {
+            bail!("-n must be between 1 and 10");
+        }
+        if self.output.is_some() && self.n != 1 {
+            bail!(
+                "Cannot use --output <file> when generating more than one \
+                 image (n={})",
+                self.n
+            );
+        }
+        let capabilities = capabilities::for_model(MODEL)
+            .context("Missing model capabilities")?;
+        let size = capabilities.resolve_size(&self.size)?;
+        capabilities.check(&capabilities::Features {
+            n: self.n,
+            size: Some(&size),
+            ..capabilities::Features::default()
+        })?;
+        let image = self.image.read_image(None)?;
+        if image.content_type != "image/png" {
+            bail!("{MODEL} only makes variations of png images");
+        }
+        let source = image.filename.clone();
+
+        let req = VariationRequest {
+            image,
+            model: MODEL.to_string(),
+            n: (self.n != 1).then_some(self.n),
+            size: size_canonical(size),
+            response_format: Some("b64_json".to_string()),
+        };
+        info!(
+            "Generating {} variation(s) of {}...",
+            self.n,
+            source.display()
+        );
+        let response = client.create_variations(&req)?;
+        let response = DecodedResponse::try_from(response)
+            .context("Failed to decode base64 image data")?;
+
+        // Name the variations after the input image
+        let config = Config::load();
+        let stem = source
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "stdin".to_string());
+        let prompt = format!("Variation of {}", source.display());
+        let out_target = match &self.output {
+            Some(path) => input::OutputTargetWithData::File(path),
+            None => input::OutputTargetWithData::Automatic {
+                prefix: sanitize::prompt_prefix(
+                    &format!("{stem} variation"),
+                    &config.filenames,
+                ),
+                extension: "png",
+                filenames: &config.filenames,
+            },
+        };
+        let fallback_dir = config
+            .fallback_dir
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("imgen"));
+        let existing = vec![None; response.data.len()];
+        let saved =
+            response.save_images(out_target, &existing, &fallback_dir)?;
+
+        let mut images = Vec::new();
+        for (data, result) in response.data.iter().zip(saved) {
+            let path = result?;
+            info!("Saved {}", path.display());
+            images.push(ImageRecord {
+                path: Some(path),
+                revised_prompt: None,
+                alt_text: None,
+                sha256: Some(data.image.sha256_hex()?),
+                ipfs_cid: None,
+                signature: None,
+                cost: 0.0,
+            });
+        }
+        let record = RunRecord {
+            created: response.created,
+            model: MODEL.to_string(),
+            prompt,
+            original_prompt: None,
+            images,
+            usage: response.usage,
+            cost: 0.0,
+            size: req.size.clone(),
+            quality: None,
+            duration: None,
+            environment: Some(Environment::capture(client.provider_name())),
+        };
+        if let Err(err) = history::append(&record) {
+            warn!("Failed to record run in history: {err:#}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+//! `imgen verify`: check a directory of images against the history, for
+//! audited pipelines. Every image should be one imgen saved, unchanged, with
+//! its sidecar, prompt file, and signature (if any) intact.
+
+use anyhow::{bail, Context};
+use log::warn;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::sign,
+    history,
+    record::{ImageRecord, RunRecord},
+};
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    /// The directory to check, including its subdirectories
+    pub dir: PathBuf,
+
+    /// The minisign public key to check `.minisig` signatures with. SSH
+    /// signatures are checked without one.
+    #[arg(long, value_name = "PATH")]
+    pub minisign_key: Option<PathBuf>,
+}
+
+/// What's wrong with an image.
+#[derive(Debug, PartialEq)]
+enum Problem {
+    /// Not an image imgen saved
+    Unknown,
+    /// Saved by imgen, but changed since
+    Modified,
+    /// In the history, but no longer on disk
+    Missing,
+    /// Its sidecar or prompt file has a different prompt than the history
+    Prompt(PathBuf),
+    /// Its signature is gone or doesn't check out
+    Signature(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "unknown (not in the history)"),
+            Self::Modified => write!(f, "modified since it was saved"),
+            Self::Missing => write!(f, "missing"),
+            Self::Prompt(path) => {
+                write!(f, "prompt doesn't match: {}", path.display())
+            }
+            Self::Signature(err) => write!(f, "bad signature: {err}"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Report {
+    /// How many images were on disk
+    checked: usize,
+    problems: Vec<(PathBuf, Problem)>,
+    /// Signatures we couldn't check without `--minisign-key`
+    unchecked_signatures: usize,
+}
+
+impl VerifyArgs {
+    pub fn run(self) -> anyhow::Result<()> {
+        let dir = std::path::absolute(&self.dir)?;
+        if !dir.is_dir() {
+            bail!("Not a directory: {}", self.dir.display());
+        }
+        let records = history::load()?;
+        let report = verify(&records, &dir, self.minisign_key.as_deref())?;
+
+        for (path, problem) in &report.problems {
+            println!("{}: {problem}", path.display());
+        }
+        if report.unchecked_signatures > 0 {
+            warn!(
+                "{} minisign signature(s) not checked; pass --minisign-key",
+                report.unchecked_signatures
+            );
+        }
+        if !report.problems.is_empty() {
+            bail!(
+                "{} problem(s) found in {} image(s)",
+                report.problems.len(),
+                report.checked
+            );
+        }
+        println!("All {} image(s) verified", report.checked);
+        Ok(())
+    }
+}
+
+/// Check every image in `dir` (an absolute path) against the history.
+fn verify(
+    records: &[RunRecord],
+    dir: &Path,
+    minisign_key: Option<&Path>,
+) -> anyhow::Result<Report> {
+    // Later runs may have saved over an earlier run's path
+    let mut by_path = BTreeMap::new();
+    let mut by_hash = HashMap::new();
+    for record in records {
+        for image in &record.images {
+            if let Some(path) = &image.path {
+                by_path.insert(path.as_path(), (record, image));
+            }
+            if let Some(hash) = &image.sha256 {
+                by_hash.insert(hash.as_str(), (record, image));
+            }
+        }
+    }
+
+    let mut report = Report::default();
+    let mut found = HashSet::new();
+    let mut files = Vec::new();
+    find_images(dir, &mut files)?;
+    files.sort();
+    for path in files {
+        report.checked += 1;
+        let bytes = std::fs::read(&path)
+            .with_context(|| format!("Failed to read: {}", path.display()))?;
+        let hash = history::sha256_hex(&bytes);
+        found.insert(hash.clone());
+        let entry = match by_path.get(path.as_path()) {
+            Some((_, image))
+                if image
+                    .sha256
+                    .as_ref()
+                    .is_some_and(|saved| *saved != hash) =>
+            {
+                report.problems.push((path, Problem::Modified));
+                continue;
+            }
+            Some(entry) => Some(*entry),
+            // Moved or copied here
+            None => by_hash.get(hash.as_str()).copied(),
+        };
+        let Some((record, image)) = entry else {
+            report.problems.push((path, Problem::Unknown));
+            continue;
+        };
+        if let Some(problem) = check_prompt(&path, record) {
+            report.problems.push((path.clone(), problem));
+        }
+        match check_signature(&path, image, minisign_key) {
+            Ok(true) => (),
+            Ok(false) => report.unchecked_signatures += 1,
+            Err(err) => report
+                .problems
+                .push((path, Problem::Signature(format!("{err:#}")))),
+        }
+    }
+
+    // Images that were moved (within `dir`) aren't missing
+    for (path, (_, image)) in by_path.range(dir..) {
+        if !path.starts_with(dir) {
+            break;
+        }
+        let moved = image.sha256.as_ref().is_some_and(|h| found.contains(h));
+        if !path.exists() && !moved {
+            report.problems.push((path.to_path_buf(), Problem::Missing));
+        }
+    }
+    Ok(report)
+}
+
+/// The png, jpeg, and webp files in `dir` and its subdirectories.
+fn find_images(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read: {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_images(&path, files)?;
+        } else if path.extension().is_some_and(|ext| {
+            let ext = ext.to_string_lossy().to_ascii_lowercase();
+            matches!(ext.as_str(), "png" | "jpg" | "jpeg" | "webp")
+        }) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Check the prompt in the image's sidecar and prompt file, if it has them.
+fn check_prompt(path: &Path, record: &RunRecord) -> Option<Problem> {
+    let sidecar = sign::append_extension(path, "json");
+    if let Ok(json) = std::fs::read_to_string(&sidecar) {
+        let prompt = serde_json::from_str::<serde_json::Value>(&json)
+            .ok()
+            .and_then(|sidecar| Some(sidecar["prompt"].as_str()?.to_string()));
+        if prompt.as_deref() != Some(record.prompt.as_str()) {
+            return Some(Problem::Prompt(sidecar));
+        }
+    }
+    let prompt_file = sign::append_extension(path, "prompt.txt");
+    if let Ok(prompt) = std::fs::read_to_string(&prompt_file) {
+        if prompt.trim_end_matches('\n') != record.prompt {
+            return Some(Problem::Prompt(prompt_file));
+        }
+    }
+    None
+}
+
+/// Check the image's signature, if it was signed. `Ok(false)` if it's a
+/// minisign signature, and there's no key to check it with.
+fn check_signature(
+    path: &Path,
+    image: &ImageRecord,
+    minisign_key: Option<&Path>,
+) -> anyhow::Result<bool> {
+    let Some(saved_sig) = &image.signature else {
+        return Ok(true);
+    };
+    // Look next to the image, in case it was moved along with its signature
+    let ext = saved_sig.extension().unwrap_or_default().to_string_lossy();
+    let sig = sign::append_extension(path, &ext);
+    if !sig.exists() {
+        bail!("not found: {}", sig.display());
+    }
+    if ext == "minisig" && minisign_key.is_none() {
+        return Ok(false);
+    }
+    sign::verify(path, &sig, minisign_key)?;
+    Ok(true)
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::Usage;
+
+    fn record(prompt: &str, images: &[(&Path, &[u8])]) -> RunRecord {
+        RunRecord {
+            created: 0,
+            model: "gpt-image-1".to_string(),
+            prompt: prompt.to_string(),
+            original_prompt: None,
+            images: images
+                .iter()
+                .map(|(path, bytes)| ImageRecord {
+                    path: Some(path.to_path_buf()),
+                    revised_prompt: None,
+                    alt_text: None,
+                    sha256: Some(history::sha256_hex(bytes)),
+                    ipfs_cid: None,
+                    signature: None,
+                    cost: 0.0,
+                })
+                .collect(),
+            usage: Usage::default(),
+            cost: 0.0,
+            size: None,
+            quality: None,
+            duration: None,
+            environment: None,
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let dir = temp_dir.path();
+        let (ok, modified, moved, missing, unknown) = (
+            dir.join("ok.png"),
+            dir.join("modified.png"),
+            dir.join("sub").join("moved.png"),
+            dir.join("missing.png"),
+            dir.join("unknown.webp"),
+        );
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        std::fs::write(&ok, b"ok").unwrap();
+        std::fs::write(&modified, b"changed").unwrap();
+        std::fs::write(&moved, b"moved").unwrap();
+        std::fs::write(&unknown, b"unknown").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"not an image").unwrap();
+        std::fs::write(sign::append_extension(&ok, "prompt.txt"), "a cat\n")
+            .unwrap();
+        std::fs::write(
+            sign::append_extension(&moved, "json"),
+            r#"{"prompt": "a different dog"}"#,
+        )
+        .unwrap();
+
+        let records = [
+            record("a cat", &[(&ok, b"ok"), (&modified, b"original")]),
+            record(
+                "a dog",
+                &[(&dir.join("elsewhere.png"), b"moved"), (&missing, b"gone")],
+            ),
+        ];
+        let report = verify(&records, dir, None).unwrap();
+        assert_eq!(report.checked, 4);
+        assert_eq!(
+            report.problems,
+            [
+                (modified, Problem::Modified),
+                (
+                    moved.clone(),
+                    Problem::Prompt(sign::append_extension(&moved, "json"))
+                ),
+                (unknown, Problem::Unknown),
+                (missing, Problem::Missing),
+            ]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! `--set-wallpaper`: set a saved image as the desktop wallpaper.
+
+use anyhow::{bail, Context};
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+/// Set the image as the wallpaper on every desktop and monitor.
+pub fn set(path: &Path) -> anyhow::Result<()> {
+    let path = std::path::absolute(path)?;
+    set_wallpaper(&path).with_context(|| {
+        format!("Failed to set the wallpaper to: {}", path.display())
+    })
+}
+
+/// Set the picture of every desktop with System Events.
+#[cfg(target_os = "macos")]
+fn set_wallpaper(path: &Path) -> anyhow::Result<()> {
+    let path = path.to_str().context("Path isn't valid UTF-8")?;
+    let script = format!(
+        "tell application \"System Events\" to tell every desktop to set \
+         picture to {}",
+        applescript_string(path)
+    );
+    run(Command::new("osascript").arg("-e").arg(script))
+}
+
+/// Set the wallpaper with `SystemParametersInfo`, via PowerShell.
+#[cfg(windows)]
+fn set_wallpaper(path: &Path) -> anyhow::Result<()> {
+    let path = path.to_str().context("Path isn't valid UTF-8")?;
+    // SPI_SETDESKWALLPAPER, saved to the user profile and broadcast
+    let script = format!(
+        "Add-Type -TypeDefinition 'using System.Runtime.InteropServices; \
+         public class W {{ [DllImport(\"user32.dll\", CharSet = \
+         CharSet.Unicode)] public static extern int \
+         SystemParametersInfo(int a, int b, string c, int d); }}'; \
+         if ([W]::SystemParametersInfo(20, 0, '{}', 3) -eq 0) {{ exit 1 }}",
+        path.replace('\'', "''")
+    );
+    run(Command::new("powershell").args(["-NoProfile", "-Command", &script]))
+}
+
+/// Set the wallpaper with the desktop environment's own tool, falling back
+/// to `feh` for plain X11 window managers.
+#[cfg(not(any(target_os = "macos", windows)))]
+fn set_wallpaper(path: &Path) -> anyhow::Result<()> {
+    let desktop = std::env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .to_lowercase();
+    log::debug!("Desktop: {desktop:?}");
+    if desktop.contains("kde") {
+        return run(Command::new("plasma-apply-wallpaperimage").arg(path));
+    }
+    if desktop.contains("xfce") {
+        return set_xfce(path);
+    }
+    if std::env::var_os("SWAYSOCK").is_some() {
+        return run(Command::new("swaymsg")
+            .args(["output", "*", "bg"])
+            .arg(path)
+            .arg("fill"));
+    }
+    if ["gnome", "unity", "budgie", "pantheon"]
+        .iter()
+        .any(|name| desktop.contains(name))
+    {
+        // GNOME 42+ has a separate picture for dark mode
+        let uri = super::reveal::file_uri(path);
+        let schema = "org.gnome.desktop.background";
+        run(Command::new("gsettings").args([
+            "set",
+            schema,
+            "picture-uri",
+            &uri,
+        ]))?;
+        let _ = run(Command::new("gsettings").args([
+            "set",
+            schema,
+            "picture-uri-dark",
+            &uri,
+        ]));
+        return Ok(());
+    }
+    if desktop.contains("cinnamon") {
+        let uri = super::reveal::file_uri(path);
+        let schema = "org.cinnamon.desktop.background";
+        return run(Command::new("gsettings").args([
+            "set",
+            schema,
+            "picture-uri",
+            &uri,
+        ]));
+    }
+    run(Command::new("feh").arg("--bg-fill").arg(path))
+        .context("Unsupported desktop; set XDG_CURRENT_DESKTOP, or install feh")
+}
+
+/// Set every XFCE monitor and workspace's image.
+#[cfg(not(any(target_os = "macos", windows)))]
+fn set_xfce(path: &Path) -> anyhow::Result<()> {
+    let output = Command::new("xfconf-query")
+        .args(["--channel", "xfce4-desktop", "--list"])
+        .output()
+        .context("Failed to run xfconf-query")?;
+    let listing = String::from_utf8_lossy(&output.stdout);
+    let properties: Vec<_> = listing
+        .lines()
+        .filter(|property| property.ends_with("/last-image"))
+        .collect();
+    if properties.is_empty() {
+        bail!("No XFCE desktop backgrounds found");
+    }
+    for property in properties {
+        run(Command::new("xfconf-query")
+            .args(["--channel", "xfce4-desktop", "--property", property])
+            .arg("--set")
+            .arg(path))?;
+    }
+    Ok(())
+}
+
+/// Run a command, failing with its stderr if it doesn't succeed.
+fn run(command: &mut Command) -> anyhow::Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .with_context(|| format!("Failed to run {program}"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{program} failed: {}", stderr.trim());
+    }
+    Ok(())
+}
+
+/// Quote a string for AppleScript.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_applescript_string() {
+        assert_eq!(
+            applescript_string(r#"/Users/me/"quoted" \ cat.png"#),
+            r#""/Users/me/\"quoted\" \\ cat.png""#
+        );
+    }
+}
@@ -1,12 +1,22 @@
-use crate::api::{CreateRequest, EditRequest, Response};
-use log::info;
+use crate::api::{
+    ChatRequest, ChatResponse, CreateRequest, EditRequest, ErrorDetail,
+    ErrorResponse, ImageBytes, Response, ResponsesRequest, ResponsesResponse,
+    StreamEvent, StrictResponse, VariationRequest,
+};
+use crate::audit::AuditLog;
+use crate::history::sha256_hex;
+use crate::redact;
+use log::{debug, error, info, warn};
 use std::error::Error;
 use std::fmt;
-use std::io;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
 use std::time::Duration;
 use std::time::Instant;
 use ureq::http::{self, HeaderValue};
 use ureq::typestate::WithBody;
+use ureq::SendBody;
 
 /// OpenAI API endpoint
 static BASE_URL: &str = "https://api.openai.com/v1";
@@ -20,14 +30,30 @@ static USER_AGENT: &str =
 /// Our timeout needs to long to handle OpenAI's glacial image generation time.
 const TIMEOUT: Duration = Duration::from_secs(20 * 60); // 20 min
 
+/// How often to log that a request is still waiting on the server.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many times to resume an interrupted image download.
+const DOWNLOAD_RETRIES: u32 = 5;
+
+/// How many times to retry a failed image request by default.
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// The delay before the first retry of a failed image request. Each retry
+/// after that waits twice as long.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+
+/// The longest we'll wait before a retry, however long the server asks.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
 /// Limit responses to at most 100 MiB.
-const RESPONSE_BODY_LIMIT: u64 = 100 << 20; // 100 MiB
+pub const RESPONSE_BODY_LIMIT: u64 = 100 << 20; // 100 MiB
 
 /// Error type for OpenAI API client operations
 #[derive(Debug)]
 pub enum ClientError {
     /// Error from the HTTP client (transport level, DNS, timeouts, etc.)
-    Http(ureq::Error),
+    Http(ureq::Error, TransferStats),
     /// Error parsing the response JSON
     Parse(serde_json::Error),
     /// Error during file I/O for multipart request
@@ -35,19 +61,95 @@ pub enum ClientError {
     /// Error reported by the OpenAI API (e.g., invalid request, rate limit)
     ApiError {
         status: http::StatusCode,
+        /// What went wrong, for people: the structured error's message, or
+        /// else the raw response
         message: String,
+        /// The structured error, if the response had one
+        error: Option<Box<ErrorDetail>>,
+        /// How long the server asked us to wait before retrying
+        /// (`Retry-After`), if it said
+        retry_after: Option<Duration>,
+    },
+    /// The `--provider` can't serve this kind of request
+    Unsupported {
+        provider: &'static str,
+        feature: &'static str,
     },
 }
 
 impl fmt::Display for ClientError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            ClientError::Http(err) => write!(f, "HTTP transport error: {err}"),
+            ClientError::Http(err, stats) => {
+                write!(f, "HTTP transport error {stats}: {err}")
+            }
             ClientError::Parse(err) => write!(f, "JSON parse error: {err}"),
             ClientError::Io(err) => write!(f, "File I/O error: {err}"),
-            ClientError::ApiError { status, message } => {
+            ClientError::ApiError {
+                status, message, ..
+            } => {
                 write!(f, "HTTP error {status}: {message}")
             }
+            ClientError::Unsupported { provider, feature } => {
+                write!(f, "{provider} doesn't support {feature}")
+            }
+        }
+    }
+}
+
+impl ClientError {
+    /// An API error from an error response's body, parsed if it has the
+    /// standard `{"error": {...}}` shape.
+    pub fn from_body(
+        status: http::StatusCode,
+        body: String,
+        retry_after: Option<Duration>,
+    ) -> Self {
+        debug!("HTTP {status} response: {body}");
+        let error = serde_json::from_str::<ErrorResponse>(&body)
+            .ok()
+            .map(|response| Box::new(response.error));
+        let message = match &error {
+            Some(error) => error.to_string(),
+            None => body,
+        };
+        ClientError::ApiError {
+            status,
+            message,
+            error,
+            retry_after,
+        }
+    }
+
+    /// Whether retrying might succeed: rate limits (but not a used-up
+    /// quota), server errors, and dropped connections.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            ClientError::ApiError { status, .. }
+                if *status == http::StatusCode::TOO_MANY_REQUESTS =>
+            {
+                let code =
+                    self.api_error().and_then(|error| error.code.as_ref());
+                code.is_none_or(|code| code != "insufficient_quota")
+            }
+            ClientError::ApiError { status, .. } => status.is_server_error(),
+            ClientError::Http(ureq::Error::Io(err), _) => matches!(
+                err.kind(),
+                io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            ClientError::Http(ureq::Error::ConnectionFailed, _) => true,
+            _ => false,
+        }
+    }
+
+    /// The structured error details, if the API returned any.
+    pub fn api_error(&self) -> Option<&ErrorDetail> {
+        match self {
+            ClientError::ApiError { error, .. } => error.as_deref(),
+            _ => None,
         }
     }
 }
@@ -55,21 +157,17 @@ impl fmt::Display for ClientError {
 impl Error for ClientError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
-            ClientError::Http(e) => Some(e),
+            ClientError::Http(e, _) => Some(e),
             ClientError::Parse(e) => Some(e),
             ClientError::Io(e) => Some(e),
             // API errors don't wrap another error
-            ClientError::ApiError { .. } => None,
+            ClientError::ApiError { .. } | ClientError::Unsupported { .. } => {
+                None
+            }
         }
     }
 }
 
-impl From<ureq::Error> for ClientError {
-    fn from(err: ureq::Error) -> Self {
-        ClientError::Http(err)
-    }
-}
-
 impl From<serde_json::Error> for ClientError {
     fn from(err: serde_json::Error) -> Self {
         ClientError::Parse(err)
@@ -83,12 +181,360 @@ impl From<io::Error> for ClientError {
     }
 }
 
+/// How far a request got before a transport failure, so a long wait doesn't
+/// end in an opaque one-line error.
+#[derive(Debug)]
+pub struct TransferStats {
+    /// How long the request ran
+    pub elapsed: Duration,
+    /// The phase the request failed in
+    pub phase: Phase,
+    /// Request body bytes handed to the connection
+    pub uploaded: u64,
+    /// Total request body size
+    pub upload_total: u64,
+    /// Response body bytes received
+    pub downloaded: u64,
+}
+
+impl fmt::Display for TransferStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "while {} after {:.1?} (uploaded {} of {} bytes, downloaded {} \
+             bytes)",
+            self.phase.as_str(),
+            self.elapsed,
+            self.uploaded,
+            self.upload_total,
+            self.downloaded,
+        )
+    }
+}
+
+/// The phase of a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Resolving DNS and opening the TCP connection
+    Connect,
+    /// The TLS handshake
+    Tls,
+    /// Sending the request
+    Upload,
+    /// Waiting for the model to respond
+    Wait,
+    /// Reading the response body
+    Download,
+}
+
+impl Phase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Connect => "connecting",
+            Self::Tls => "negotiating TLS",
+            Self::Upload => "uploading the request",
+            Self::Wait => "waiting for the response",
+            Self::Download => "downloading the response",
+        }
+    }
+
+    /// The phase implied by the error itself, if any.
+    fn from_error(err: &ureq::Error) -> Option<Self> {
+        use ureq::{Error, Timeout};
+        match err {
+            Error::Timeout(Timeout::Resolve | Timeout::Connect)
+            | Error::HostNotFound
+            | Error::ConnectionFailed => Some(Self::Connect),
+            Error::Tls(_) | Error::NativeTls(_) | Error::Der(_) => {
+                Some(Self::Tls)
+            }
+            Error::Timeout(Timeout::SendRequest | Timeout::SendBody) => {
+                Some(Self::Upload)
+            }
+            Error::Timeout(Timeout::RecvResponse) => Some(Self::Wait),
+            Error::Timeout(Timeout::RecvBody) | Error::BodyExceedsLimit(_) => {
+                Some(Self::Download)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How long each phase of a completed request took, to tell a slow network
+/// from a slow server.
+///
+/// DNS, TCP connect, and TLS are one phase: ureq doesn't report when each
+/// finishes, and resolving the host ourselves first would only time a
+/// second, cached lookup.
+#[derive(Debug, PartialEq)]
+struct Timings {
+    /// DNS, TCP connect, and TLS, until the request body started uploading
+    connect: Duration,
+    upload: Duration,
+    /// Waiting for the server (time to first byte)
+    wait: Duration,
+    download: Duration,
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "connect (DNS+TCP+TLS) {:.2?}, upload {:.2?}, server wait {:.2?}, download \
+             {:.2?}",
+            self.connect, self.upload, self.wait, self.download
+        )
+    }
+}
+
+/// Tracks a request's progress, to report how far it got if it fails, and
+/// how long each phase took if it succeeds.
+pub struct Transfer {
+    start: Instant,
+    upload_total: u64,
+    uploaded: Arc<AtomicU64>,
+    /// When the request body started and finished uploading
+    upload_started: Arc<OnceLock<Instant>>,
+    upload_finished: Arc<OnceLock<Instant>>,
+    downloaded: u64,
+    /// When the response headers arrived
+    response_at: Option<Instant>,
+}
+
+impl Transfer {
+    pub fn new(upload_total: usize) -> Self {
+        Self {
+            start: Instant::now(),
+            upload_total: upload_total as u64,
+            uploaded: Arc::new(AtomicU64::new(0)),
+            upload_started: Arc::default(),
+            upload_finished: Arc::default(),
+            downloaded: 0,
+            response_at: None,
+        }
+    }
+
+    /// Count the bytes uploaded from `body`, and when.
+    pub fn reader<R: Read>(&self, body: R) -> CountingReader<R> {
+        CountingReader {
+            inner: body,
+            count: self.uploaded.clone(),
+            total: self.upload_total,
+            started: self.upload_started.clone(),
+            finished: self.upload_finished.clone(),
+        }
+    }
+
+    /// The phases of a request that finished downloading at `done`. Phases
+    /// that didn't happen (like an empty upload) take no time.
+    fn timings(&self, done: Instant) -> Timings {
+        let response_at = self.response_at.unwrap_or(done);
+        let started = self.upload_started.get().copied().unwrap_or(response_at);
+        let finished = self.upload_finished.get().copied().unwrap_or(started);
+        Timings {
+            connect: started.saturating_duration_since(self.start),
+            upload: finished.saturating_duration_since(started),
+            wait: response_at.saturating_duration_since(finished),
+            download: done.saturating_duration_since(response_at),
+        }
+    }
+
+    pub fn error(&self, err: ureq::Error) -> ClientError {
+        let uploaded = self.uploaded.load(Ordering::Relaxed);
+        // Global timeouts and I/O errors don't say when they happened, so
+        // infer the phase from our progress
+        let phase =
+            Phase::from_error(&err).unwrap_or(if self.response_at.is_some() {
+                Phase::Download
+            } else if uploaded >= self.upload_total {
+                Phase::Wait
+            } else if uploaded > 0 {
+                Phase::Upload
+            } else {
+                Phase::Connect
+            });
+        let stats = TransferStats {
+            elapsed: self.start.elapsed(),
+            phase,
+            uploaded,
+            upload_total: self.upload_total,
+            downloaded: self.downloaded,
+        };
+        ClientError::Http(err, stats)
+    }
+}
+
+/// Log that a request is still in flight every [`HEARTBEAT_INTERVAL`], until
+/// `done` disconnects. Even without the spinner (e.g. in CI), a long wait
+/// then doesn't look hung.
+fn heartbeat(transfer: &Transfer, done: mpsc::Receiver<()>) {
+    while let Err(mpsc::RecvTimeoutError::Timeout) =
+        done.recv_timeout(HEARTBEAT_INTERVAL)
+    {
+        let elapsed = format_elapsed(transfer.start.elapsed());
+        if transfer.upload_finished.get().is_some() {
+            info!("Still generating… {elapsed} elapsed");
+        } else {
+            info!("Still uploading… {elapsed} elapsed");
+        }
+    }
+}
+
+/// Format a duration in whole seconds, like "45s" or "2m30s".
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{secs}s")
+    } else {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+/// Counts the bytes read through it, and notes when reading started (once
+/// connected) and finished.
+pub struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+    total: u64,
+    started: Arc<OnceLock<Instant>>,
+    finished: Arc<OnceLock<Instant>>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.started.get_or_init(Instant::now);
+        let n = self.inner.read(buf)?;
+        let count =
+            self.count.fetch_add(n as u64, Ordering::Relaxed) + n as u64;
+        if n == 0 || count >= self.total {
+            self.finished.get_or_init(Instant::now);
+        }
+        Ok(n)
+    }
+}
+
 /// Client for the OpenAI API
+#[derive(Clone)]
 pub struct Client {
     /// HTTP agent for making requests
     agent: ureq::Agent,
     /// Authorization header value
     auth: HeaderValue,
+    /// Where to record each API call, if auditing is enabled
+    audit: Option<AuditLog>,
+    /// Hold image responses to the exact OpenAI API schema
+    strict: bool,
+    /// Send image requests here instead of to OpenAI (`--provider`)
+    provider: Option<Arc<dyn Provider>>,
+    /// Space out requests (`--max-rps`, `--min-interval`)
+    throttle: Option<Throttle>,
+    /// How many times to retry a failed image request (`--max-retries`)
+    max_retries: u32,
+    /// Spool image downloads to disk rather than memory (`--low-memory`)
+    low_memory: bool,
+}
+
+/// Spaces out requests to at most one per interval, across every thread
+/// sharing the client (like `-j` or `jobs --concurrency` workers).
+#[derive(Clone)]
+struct Throttle {
+    interval: Duration,
+    /// When the next request may start
+    next: Arc<Mutex<Instant>>,
+}
+
+impl Throttle {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            next: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Wait for our turn to send a request. Each caller reserves the next
+    /// slot, then sleeps without holding the lock.
+    fn wait(&self) {
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            let start = (*next).max(Instant::now());
+            *next = start + self.interval;
+            start
+        };
+        let delay = start.saturating_duration_since(Instant::now());
+        if !delay.is_zero() {
+            debug!("Throttled: waiting {delay:.2?}");
+            std::thread::sleep(delay);
+        }
+    }
+}
+
+/// An image generation service besides OpenAI. It takes the same requests,
+/// and maps them to its own API's parameters.
+pub trait Provider: Send + Sync {
+    /// The provider's name, for errors and the audit log
+    fn name(&self) -> &'static str;
+
+    fn create_images(
+        &self,
+        request: &CreateRequest,
+    ) -> Result<Response, ClientError>;
+
+    fn edit_images(
+        &self,
+        request: &EditRequest,
+    ) -> Result<Response, ClientError>;
+
+    fn create_variations(
+        &self,
+        request: &VariationRequest,
+    ) -> Result<Response, ClientError>;
+}
+
+/// How long to keep an idle connection for the next request. Image
+/// requests are slow, so this is well over the usual gap between a run's
+/// requests (and the same for a `pipeline` or `csv` run's steps).
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How many idle connections to keep per host: enough for `-j` parallel
+/// requests to all find a warm connection next time.
+const IDLE_CONNECTIONS_PER_HOST: usize = 8;
+
+/// The process-wide HTTP agent. See [`agent`].
+static AGENT: OnceLock<ureq::Agent> = OnceLock::new();
+
+/// The process-wide HTTP agent, with our usual settings (https only,
+/// platform root certs, and a long timeout). 4xx/5xx responses are `Ok(_)`,
+/// so we can read their error bodies.
+///
+/// Every client shares its connection pool, so repeated requests to a host
+/// reuse a connection and skip the TCP and TLS handshakes. (ureq only
+/// speaks HTTP/1.1, so pooling is what we get instead of HTTP/2.)
+pub fn agent() -> ureq::Agent {
+    AGENT.get_or_init(|| new_agent(true)).clone()
+}
+
+/// Like [`agent`], but allows plain http, for servers on the local network
+/// (like `--provider sdwebui`).
+pub fn local_agent() -> ureq::Agent {
+    new_agent(false)
+}
+
+fn new_agent(https_only: bool) -> ureq::Agent {
+    let config = ureq::config::Config::builder()
+        .https_only(https_only)
+        .tls_config(
+            ureq::tls::TlsConfig::builder()
+                .provider(ureq::tls::TlsProvider::NativeTls)
+                .root_certs(ureq::tls::RootCerts::PlatformVerifier)
+                .build(),
+        )
+        .timeout_global(Some(TIMEOUT))
+        .user_agent(USER_AGENT)
+        .http_status_as_error(false) // Don't treat 4xx/5xx as `Err(_)`
+        .max_idle_age(IDLE_TIMEOUT)
+        .max_idle_connections_per_host(IDLE_CONNECTIONS_PER_HOST)
+        .build();
+    ureq::Agent::new_with_config(config)
 }
 
 impl Client {
@@ -96,20 +542,145 @@ impl Client {
     pub fn new(api_key: String) -> Self {
         let auth = HeaderValue::try_from(format!("Bearer {}", api_key))
             .expect("Invalid API key format");
-        let config = ureq::config::Config::builder()
-            .https_only(true)
-            .tls_config(
-                ureq::tls::TlsConfig::builder()
-                    .provider(ureq::tls::TlsProvider::NativeTls)
-                    .root_certs(ureq::tls::RootCerts::PlatformVerifier)
-                    .build(),
-            )
-            .timeout_global(Some(TIMEOUT))
-            .user_agent(USER_AGENT)
-            .http_status_as_error(false) // Don't treat 4xx/5xx as `Err(_)`
-            .build();
-        let agent = ureq::Agent::new_with_config(config);
-        Self { agent, auth }
+        Self {
+            agent: agent(),
+            auth,
+            audit: None,
+            strict: false,
+            provider: None,
+            throttle: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            low_memory: false,
+        }
+    }
+
+    /// Retry image requests that fail transiently up to `max_retries` times,
+    /// with exponential backoff.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Send an image request, retrying if it fails transiently (see
+    /// [`ClientError::is_transient`]). A request whose connection drops
+    /// while the server is generating may have been billed already, but the
+    /// image is lost either way.
+    fn with_retries<T>(
+        &self,
+        endpoint: &str,
+        mut send: impl FnMut() -> Result<T, ClientError>,
+    ) -> Result<T, ClientError> {
+        let mut attempt = 0;
+        loop {
+            let err = match send() {
+                Err(err)
+                    if attempt < self.max_retries && err.is_transient() =>
+                {
+                    err
+                }
+                result => return result,
+            };
+            attempt += 1;
+            let (reason, retry_after) = match &err {
+                ClientError::ApiError {
+                    status,
+                    retry_after,
+                    ..
+                } => (status.to_string(), *retry_after),
+                err => (err.to_string(), None),
+            };
+            let delay = retry_delay(attempt, retry_after);
+            warn!(
+                "{endpoint} failed ({reason}); retrying in {delay:.1?} \
+                 ({attempt}/{})",
+                self.max_retries
+            );
+            std::thread::sleep(delay);
+        }
+    }
+
+    /// Send at most one request per `interval`, however many threads share
+    /// the client (`--max-rps`, `--min-interval`).
+    pub fn with_throttle(mut self, interval: Option<Duration>) -> Self {
+        self.throttle = interval.map(Throttle::new);
+        self
+    }
+
+    /// Wait for the throttle, if any, before sending a request.
+    fn throttle(&self) {
+        if let Some(throttle) = &self.throttle {
+            throttle.wait();
+        }
+    }
+
+    /// Reject image responses that don't match the OpenAI API's schema
+    /// exactly, rather than filling in what's missing (`--strict`).
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Keep as little image data in memory as possible (`--low-memory`):
+    /// image URLs are downloaded to temporary files, and saved from them.
+    pub fn with_low_memory(mut self, low_memory: bool) -> Self {
+        self.low_memory = low_memory;
+        self
+    }
+
+    /// Whether to keep as little image data in memory as possible.
+    pub fn low_memory(&self) -> bool {
+        self.low_memory
+    }
+
+    /// Send image requests to this provider instead of OpenAI. Other
+    /// requests (chat, responses) still go to OpenAI.
+    pub fn with_provider(mut self, provider: Arc<dyn Provider>) -> Self {
+        self.provider = Some(provider);
+        self
+    }
+
+    /// The name of the provider image requests go to.
+    pub fn provider_name(&self) -> &'static str {
+        match &self.provider {
+            Some(provider) => provider.name(),
+            None => "openai",
+        }
+    }
+
+    /// The audit log name of an image endpoint, noting the provider if it
+    /// isn't OpenAI.
+    fn image_endpoint(&self, endpoint: &str) -> String {
+        match &self.provider {
+            Some(provider) => format!("{}:{endpoint}", provider.name()),
+            None => endpoint.to_string(),
+        }
+    }
+
+    /// Record every API call in this audit log.
+    pub fn with_audit_log(mut self, audit: Option<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Record an API call in the audit log, if enabled. The call already
+    /// happened, so a failure to record it is logged rather than returned.
+    fn audit<T>(
+        &self,
+        endpoint: &str,
+        params: impl FnOnce() -> serde_json::Value,
+        result: &Result<T, ClientError>,
+        cost: impl FnOnce(&T) -> Option<f64>,
+    ) {
+        let Some(audit) = &self.audit else {
+            return;
+        };
+        let (status, cost) = match result {
+            Ok(response) => ("ok".to_string(), cost(response)),
+            Err(err) => (err.to_string(), None),
+        };
+        if let Err(err) = audit.append(endpoint, params(), status, cost) {
+            error!("Failed to record {endpoint} call in audit log: {err:#}");
+        }
     }
 
     fn post(&self, uri: &str) -> ureq::RequestBuilder<WithBody> {
@@ -118,19 +689,247 @@ impl Client {
             .header(http::header::AUTHORIZATION, self.auth.clone())
     }
 
-    /// Create an image using the OpenAI API
+    /// POST `body` and read the JSON response.
+    fn post_json<T: serde::de::DeserializeOwned>(
+        &self,
+        uri: &str,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<T, ClientError> {
+        let body =
+            self.post_body(uri, content_type, body.as_slice(), body.len())?;
+        parse_json(&body)
+    }
+
+    /// POST an image request of `len` bytes, and read the image response.
+    fn post_images(
+        &self,
+        uri: &str,
+        content_type: &str,
+        body: impl Read,
+        len: usize,
+    ) -> Result<Response, ClientError> {
+        let body = self.post_body(uri, content_type, body, len)?;
+        if self.strict {
+            parse_json::<StrictResponse>(&body)?;
+        }
+        let response: Response = parse_json(&body)?;
+        if response.usage.total_tokens == 0 {
+            warn!(
+                "The server didn't report token usage; costs will show as $0"
+            );
+        }
+        Ok(response)
+    }
+
+    /// POST `len` bytes of `body` and read the successful response body.
+    ///
+    /// In order to give the user good error messages on 4xx/5xx errors, we
+    /// need to explicitly check the status code and read the body on error.
+    fn post_body(
+        &self,
+        uri: &str,
+        content_type: &str,
+        body: impl Read,
+        len: usize,
+    ) -> Result<Vec<u8>, ClientError> {
+        self.throttle();
+        let transfer = Transfer::new(len);
+        let mut reader = transfer.reader(body);
+        let (done_tx, done_rx) = mpsc::channel::<()>();
+        let response = std::thread::scope(|scope| {
+            scope.spawn(|| heartbeat(&transfer, done_rx));
+            let response = self
+                .post(uri)
+                .header(http::header::CONTENT_TYPE, content_type)
+                .header(http::header::CONTENT_LENGTH, transfer.upload_total)
+                .send(SendBody::from_reader(&mut reader));
+            drop(done_tx);
+            response
+        })
+        .map_err(|err| transfer.error(err))?;
+        read_body(uri, response, transfer)
+    }
+
+    /// GET `uri` and read the JSON response.
+    fn get_json<T: serde::de::DeserializeOwned>(
+        &self,
+        uri: &str,
+    ) -> Result<T, ClientError> {
+        self.throttle();
+        let transfer = Transfer::new(0);
+        let response = self
+            .agent
+            .get(uri)
+            .header(http::header::AUTHORIZATION, self.auth.clone())
+            .call()
+            .map_err(|err| transfer.error(err))?;
+        parse_json(&read_body(uri, response, transfer)?)
+    }
+
+    /// Download any images the API returned as URLs rather than inline
+    /// base64 data. With `--low-memory`, they're spooled to temporary files
+    /// instead of read into memory.
+    pub fn fetch_url_images(
+        &self,
+        mut response: Response,
+    ) -> Result<Response, ClientError> {
+        for image in &mut response.data {
+            if !image.b64_json.is_empty() || image.downloaded.is_some() {
+                continue;
+            }
+            if let Some(url) = &image.url {
+                image.downloaded = Some(if self.low_memory {
+                    ImageBytes::Spooled(self.download_spooled(url)?)
+                } else {
+                    ImageBytes::Memory(self.download(url)?)
+                });
+            }
+        }
+        Ok(response)
+    }
+
+    /// Download a file into memory. See [`Client::download_to`].
+    pub fn download(&self, url: &str) -> Result<Vec<u8>, ClientError> {
+        let mut data = Cursor::new(Vec::new());
+        let len = self.download_to(url, &mut data)?;
+        let mut data = data.into_inner();
+        data.truncate(len as usize);
+        Ok(data)
+    }
+
+    /// Download a file to a temporary file, which is removed when the
+    /// returned path is dropped.
+    fn download_spooled(
+        &self,
+        url: &str,
+    ) -> Result<tempfile::TempPath, ClientError> {
+        let mut file = tempfile::Builder::new()
+            .prefix("imgen-")
+            .suffix(".download")
+            .tempfile()?;
+        let len = self.download_to(url, file.as_file_mut())?;
+        file.as_file().set_len(len)?;
+        Ok(file.into_temp_path())
+    }
+
+    /// Download a file to `out` (from its current position), resuming with
+    /// a `Range` request if the connection drops partway through. Some
+    /// networks reliably kill long transfers. Only transient failures (see
+    /// [`ClientError::is_transient`]) are retried, after a backoff; failing
+    /// to write to `out` isn't. Returns the file's length, as `out` may hold
+    /// stale bytes past it if the download started over.
+    pub fn download_to(
+        &self,
+        url: &str,
+        out: &mut (impl Write + Seek),
+    ) -> Result<u64, ClientError> {
+        let start_time = Instant::now();
+        let mut len = out.stream_position()?;
+        let mut attempt = 0;
+        loop {
+            let mut transfer = Transfer::new(0);
+            let mut request = self.agent.get(url);
+            if len > 0 {
+                let range = format!("bytes={len}-");
+                request = request.header(http::header::RANGE, range);
+            }
+
+            let err = match request.call() {
+                Ok(response) => {
+                    let status = response.status();
+                    if status == http::StatusCode::OK && len > 0 {
+                        // The server ignored our range; start over
+                        out.rewind()?;
+                        len = 0;
+                    }
+                    if matches!(
+                        status,
+                        http::StatusCode::OK
+                            | http::StatusCode::PARTIAL_CONTENT
+                    ) {
+                        transfer.response_at = Some(Instant::now());
+                        let before = len;
+                        let mut reader = response
+                            .into_body()
+                            .into_with_config()
+                            .limit(RESPONSE_BODY_LIMIT)
+                            .reader();
+                        let result = copy_body(&mut reader, out);
+                        len = out.stream_position()?;
+                        transfer.downloaded = len - before;
+                        match result? {
+                            Ok(()) => break,
+                            Err(err) => transfer.error(ureq::Error::from(err)),
+                        }
+                    } else {
+                        ClientError::ApiError {
+                            status,
+                            message: format!("Failed to download: {url}"),
+                            error: None,
+                            retry_after: retry_after(response.headers()),
+                        }
+                    }
+                }
+                Err(err) => transfer.error(err),
+            };
+
+            attempt += 1;
+            if attempt > DOWNLOAD_RETRIES || !err.is_transient() {
+                return Err(err);
+            }
+            let retry_after = match &err {
+                ClientError::ApiError { retry_after, .. } => *retry_after,
+                _ => None,
+            };
+            let delay = retry_delay(attempt, retry_after);
+            warn!(
+                "Download interrupted after {len} bytes ({err}); resuming in \
+                 {delay:.1?} ({attempt}/{DOWNLOAD_RETRIES})"
+            );
+            std::thread::sleep(delay);
+        }
+
+        let duration = start_time.elapsed();
+        info!("download: {len} bytes in {duration:.2?}");
+        Ok(len)
+    }
+
+    /// Create an image using the OpenAI API (or the `--provider`)
     pub fn create_images(
         &self,
-        request: CreateRequest,
+        request: &CreateRequest,
     ) -> Result<Response, ClientError> {
         // Start timing the request
         let start_time = Instant::now();
 
-        // Make the API request
-        let response = self
-            .post(&format!("{BASE_URL}/images/generations"))
-            .send_json(&request)?
-            .read_json()?;
+        // Make the API request, recording each attempt in the audit log
+        let endpoint = self.image_endpoint("images/generations");
+        let result = self.with_retries("images/generations", || {
+            let result = match &self.provider {
+                Some(provider) => {
+                    self.throttle();
+                    provider.create_images(request)
+                }
+                None => {
+                    let body = serde_json::to_vec(request)?;
+                    self.post_images(
+                        &format!("{BASE_URL}/images/generations"),
+                        "application/json",
+                        body.as_slice(),
+                        body.len(),
+                    )
+                }
+            };
+            self.audit(
+                &endpoint,
+                || serde_json::to_value(request).unwrap_or_default(),
+                &result,
+                |response: &Response| Some(response.usage.calculate_cost()),
+            );
+            result
+        });
+        let response = result?;
 
         // Log the request duration
         let duration = start_time.elapsed();
@@ -141,20 +940,40 @@ impl Client {
 
     pub fn edit_images(
         &self,
-        request: EditRequest,
+        request: &EditRequest,
     ) -> Result<Response, ClientError> {
         // Start timing the request
         let start_time = Instant::now();
 
-        // Build the multipart request body
-        let multipart_body = request.build_multipart();
-
-        // Make the API request
-        let response = self
-            .post(&format!("{BASE_URL}/images/edits"))
-            .header(http::header::CONTENT_TYPE, multipart_body.content_type)
-            .send(multipart_body.body)?
-            .read_json()?;
+        // Make the API request, recording each attempt in the audit log
+        let endpoint = self.image_endpoint("images/edits");
+        let result = self.with_retries("images/edits", || {
+            let result = match &self.provider {
+                Some(provider) => {
+                    self.throttle();
+                    provider.edit_images(request)
+                }
+                None => {
+                    let body = request.build_multipart();
+                    let content_type = body.content_type.clone();
+                    let len = body.len;
+                    self.post_images(
+                        &format!("{BASE_URL}/images/edits"),
+                        &content_type,
+                        body,
+                        len,
+                    )
+                }
+            };
+            self.audit(
+                &endpoint,
+                || edit_params(request),
+                &result,
+                |response: &Response| Some(response.usage.calculate_cost()),
+            );
+            result
+        });
+        let response = result?;
 
         // Log the request duration
         let duration = start_time.elapsed();
@@ -162,48 +981,630 @@ impl Client {
 
         Ok(response)
     }
-}
 
-trait ResponseExt {
-    /// Read the response body as a JSON object.
-    fn read_json<T: serde::de::DeserializeOwned>(
-        self,
-    ) -> Result<T, ClientError>;
-}
+    pub fn create_variations(
+        &self,
+        request: &VariationRequest,
+    ) -> Result<Response, ClientError> {
+        // Start timing the request
+        let start_time = Instant::now();
 
-impl ResponseExt for http::Response<ureq::Body> {
-    // In order to give the user good error messages on 4xx/5xx errors, we need
-    // to explicitly check the status code and read the body on error.
-    fn read_json<T: serde::de::DeserializeOwned>(
-        self,
-    ) -> Result<T, ClientError> {
-        let status = self.status();
-        if status.is_success() {
-            // Success case (2xx)
-            // Read the response body as JSON
-            self.into_body()
-                .with_config()
-                .limit(RESPONSE_BODY_LIMIT)
-                .read_json()
-                .map_err(ClientError::from)
-        } else {
-            // Error case
-            // Try to read the response body as a string
-            let body = self
+        // Make the API request, recording each attempt in the audit log
+        let endpoint = self.image_endpoint("images/variations");
+        let result = self.with_retries("images/variations", || {
+            let result = match &self.provider {
+                Some(provider) => {
+                    self.throttle();
+                    provider.create_variations(request)
+                }
+                None => {
+                    let body = request.build_multipart();
+                    let content_type = body.content_type.clone();
+                    let len = body.len;
+                    self.post_images(
+                        &format!("{BASE_URL}/images/variations"),
+                        &content_type,
+                        body,
+                        len,
+                    )
+                }
+            };
+            self.audit(
+                &endpoint,
+                || variation_params(request),
+                &result,
+                |response: &Response| Some(response.usage.calculate_cost()),
+            );
+            result
+        });
+        let response = result?;
+
+        // Log the request duration
+        let duration = start_time.elapsed();
+        info!("create_variations: done in {duration:.2?}");
+
+        Ok(response)
+    }
+
+    /// Start generating an image in the background with the Responses API.
+    pub fn create_response(
+        &self,
+        request: &ResponsesRequest,
+    ) -> Result<ResponsesResponse, ClientError> {
+        let result = self.post_json(
+            &format!("{BASE_URL}/responses"),
+            "application/json",
+            serde_json::to_vec(request)?,
+        );
+        self.audit(
+            "responses",
+            || serde_json::to_value(request).unwrap_or_default(),
+            &result,
+            |_| None,
+        );
+        result
+    }
+
+    /// Generate with the Responses API, streaming each partial image (and its
+    /// index) to `on_partial` as it renders.
+    pub fn stream_response(
+        &self,
+        request: &ResponsesRequest,
+        mut on_partial: impl FnMut(u32, String),
+    ) -> Result<ResponsesResponse, ClientError> {
+        let result = self.post_stream(
+            &format!("{BASE_URL}/responses"),
+            serde_json::to_vec(request)?,
+            &mut on_partial,
+        );
+        self.audit(
+            "responses",
+            || serde_json::to_value(request).unwrap_or_default(),
+            &result,
+            |_| None,
+        );
+        result
+    }
+
+    /// POST a streaming Responses API request, and read its events until the
+    /// response is done.
+    fn post_stream(
+        &self,
+        uri: &str,
+        body: Vec<u8>,
+        on_partial: &mut impl FnMut(u32, String),
+    ) -> Result<ResponsesResponse, ClientError> {
+        self.throttle();
+        let mut transfer = Transfer::new(body.len());
+        let reader = transfer.reader(Cursor::new(body));
+        let response = self
+            .post(uri)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .header(http::header::CONTENT_LENGTH, transfer.upload_total)
+            .header(http::header::ACCEPT, "text/event-stream")
+            .send(SendBody::from_owned_reader(reader))
+            .map_err(|err| transfer.error(err))?;
+        let status = response.status();
+        if !status.is_success() {
+            // Read the error message
+            let result = read_body(uri, response, transfer);
+            return Err(result.expect_err("Not a success status"));
+        }
+
+        transfer.response_at = Some(Instant::now());
+        let mut events = BufReader::new(
+            response
                 .into_body()
-                .with_config()
+                .into_with_config()
                 .limit(RESPONSE_BODY_LIMIT)
-                .read_to_vec()?;
-            let body_str = match String::from_utf8(body) {
-                Ok(s) => s,
-                Err(err) => {
-                    String::from_utf8_lossy(err.as_bytes()).into_owned()
+                .reader(),
+        );
+        loop {
+            let data = next_event_data(&mut events)
+                .map_err(|err| transfer.error(ureq::Error::from(err)))?;
+            let Some(data) = data else {
+                return Err(ClientError::Io(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "The stream ended before the response was done",
+                )));
+            };
+            let response = match parse_json(data.as_bytes())? {
+                StreamEvent::PartialImage {
+                    partial_image_index,
+                    partial_image_b64,
+                } => {
+                    on_partial(partial_image_index, partial_image_b64);
+                    continue;
+                }
+                StreamEvent::Completed { response } => {
+                    debug!("{uri}: {}", transfer.timings(Instant::now()));
+                    return Ok(response);
                 }
+                StreamEvent::Failed { response }
+                | StreamEvent::Incomplete { response } => response,
+                StreamEvent::Error { message } => {
+                    return Err(ClientError::ApiError {
+                        status,
+                        message,
+                        error: None,
+                        retry_after: None,
+                    })
+                }
+                StreamEvent::Other => continue,
+            };
+            let message = match &response.error {
+                Some(error) => error.to_string(),
+                None => format!("The response was {}", response.status),
             };
-            Err(ClientError::ApiError {
+            return Err(ClientError::ApiError {
                 status,
-                message: body_str,
-            })
+                message,
+                error: response.error.map(Box::new),
+                retry_after: None,
+            });
+        }
+    }
+
+    /// Check on a background response.
+    pub fn get_response(
+        &self,
+        id: &str,
+    ) -> Result<ResponsesResponse, ClientError> {
+        self.get_json(&format!("{BASE_URL}/responses/{id}"))
+    }
+
+    /// Complete a chat conversation using the OpenAI API
+    pub fn chat(
+        &self,
+        request: &ChatRequest,
+    ) -> Result<ChatResponse, ClientError> {
+        let result = self.post_json(
+            &format!("{BASE_URL}/chat/completions"),
+            "application/json",
+            serde_json::to_vec(request)?,
+        );
+        // Messages can hold whole images, so only count them
+        self.audit(
+            "chat/completions",
+            || {
+                serde_json::json!({
+                    "model": request.model,
+                    "messages": request.messages.len(),
+                })
+            },
+            &result,
+            |_| None,
+        );
+        result
+    }
+}
+
+/// Read a response's body, failing with the API's error message on a
+/// 4xx/5xx status.
+fn read_body(
+    uri: &str,
+    response: http::Response<ureq::Body>,
+    mut transfer: Transfer,
+) -> Result<Vec<u8>, ClientError> {
+    transfer.response_at = Some(Instant::now());
+    let status = response.status();
+    let retry_after = retry_after(response.headers());
+    let mut body = Vec::new();
+    let result = response
+        .into_body()
+        .into_with_config()
+        .limit(RESPONSE_BODY_LIMIT)
+        .reader()
+        .read_to_end(&mut body);
+    transfer.downloaded = body.len() as u64;
+    result.map_err(|err| transfer.error(ureq::Error::from(err)))?;
+    debug!("{uri}: {}", transfer.timings(Instant::now()));
+
+    if status.is_success() {
+        Ok(body)
+    } else {
+        let body = String::from_utf8_lossy(&body);
+        // In case the server echoes our request headers back
+        let body = redact::scrub(&body).into_owned();
+        Err(ClientError::from_body(status, body, retry_after))
+    }
+}
+
+/// How long a response asks us to wait before retrying: OpenAI's
+/// `retry-after-ms`, or the standard `Retry-After` in seconds. (The
+/// HTTP-date form isn't supported.)
+pub fn retry_after(headers: &http::HeaderMap) -> Option<Duration> {
+    let header = |name: &str| -> Option<f64> {
+        headers.get(name)?.to_str().ok()?.trim().parse().ok()
+    };
+    let secs = match header("retry-after-ms") {
+        Some(ms) => ms / 1000.0,
+        None => header("retry-after")?,
+    };
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Copy a response body to `out`. Failing to read the body is returned
+/// inside, to be retried; failing to write `out` is a local problem, so it's
+/// returned as is.
+fn copy_body(
+    reader: &mut impl Read,
+    out: &mut impl Write,
+) -> Result<io::Result<()>, ClientError> {
+    let mut buf = [0; 64 * 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(Ok(())),
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Ok(Err(err)),
+        };
+        out.write_all(&buf[..n])?;
+    }
+}
+
+/// The delay before the `attempt`-th retry (from 1): what the server asked
+/// for, or else exponential backoff with jitter, so parallel requests that
+/// failed together don't retry together.
+fn retry_delay(attempt: u32, retry_after: Option<Duration>) -> Duration {
+    let delay = retry_after.unwrap_or_else(|| {
+        let backoff = RETRY_BASE_DELAY * 2u32.saturating_pow(attempt - 1);
+        backoff.mul_f64(rand::random_range(0.5..=1.0))
+    });
+    delay.min(MAX_RETRY_DELAY)
+}
+
+/// Read the next server-sent event's data, or `None` at the end of the
+/// stream. Multi-line data is joined with newlines; the event names, IDs,
+/// and comments are skipped, since our events' JSON has their type.
+fn next_event_data(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut data = String::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok((!data.is_empty()).then_some(data));
+        }
+        let field = line.trim_end_matches(['\r', '\n']);
+        if field.is_empty() {
+            // A blank line ends the event
+            if !data.is_empty() {
+                return Ok(Some(data));
+            }
+        } else if let Some(value) = field.strip_prefix("data:") {
+            if !data.is_empty() {
+                data.push('\n');
+            }
+            data.push_str(value.strip_prefix(' ').unwrap_or(value));
         }
     }
 }
+
+/// Parse a successful response body.
+fn parse_json<T: serde::de::DeserializeOwned>(
+    body: &[u8],
+) -> Result<T, ClientError> {
+    serde_json::from_slice(body).map_err(|err| {
+        // Don't lose a paid-for response to a parsing bug
+        error!("Failed to parse the API response: {err}");
+        crate::rescue::save_and_report("response", "json", body);
+        ClientError::Parse(err)
+    })
+}
+
+/// The audit log parameters of an edit request, with the input images
+/// summarized by their hash.
+fn edit_params(request: &EditRequest) -> serde_json::Value {
+    let image = |image: &crate::cli::input::ImageData| {
+        serde_json::json!({
+            "filename": image.filename,
+            "sha256": sha256_hex(&image.bytes),
+        })
+    };
+    serde_json::json!({
+        "model": request.model,
+        "prompt": request.prompt,
+        "n": request.n,
+        "quality": request.quality,
+        "size": request.size,
+        "input_fidelity": request.input_fidelity,
+        "output_compression": request.output_compression,
+        "output_format": request.output_format,
+        "images": request.images.iter().map(image).collect::<Vec<_>>(),
+        "references": request.references.iter().map(image).collect::<Vec<_>>(),
+        "style_ref": request.style_ref.as_ref().map(image),
+        "mask": request.mask.as_ref().map(image),
+    })
+}
+
+/// The audit log parameters of a variation request, with the input image
+/// summarized by its hash.
+fn variation_params(request: &VariationRequest) -> serde_json::Value {
+    serde_json::json!({
+        "model": request.model,
+        "n": request.n,
+        "size": request.size,
+        "image": {
+            "filename": request.image.filename,
+            "sha256": sha256_hex(&request.image.bytes),
+        },
+    })
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retries() {
+        let api_error = |status: u16, body: &str| {
+            let status = http::StatusCode::from_u16(status).unwrap();
+            ClientError::from_body(status, body.to_string(), None)
+        };
+        assert!(api_error(429, "Rate limit reached").is_transient());
+        assert!(!api_error(
+            429,
+            r#"{"error": {"message": "Quota exceeded", "type": "insufficient_quota", "code": "insufficient_quota"}}"#
+        )
+        .is_transient());
+        assert!(api_error(503, "Service unavailable").is_transient());
+        assert!(!api_error(400, "Bad request").is_transient());
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        let stats = TransferStats {
+            elapsed: Duration::from_secs(30),
+            phase: Phase::Wait,
+            uploaded: 100,
+            upload_total: 100,
+            downloaded: 0,
+        };
+        assert!(ClientError::Http(reset.into(), stats).is_transient());
+
+        let mut headers = http::HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert("retry-after", "20".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(20)));
+        headers.insert("retry-after-ms", "1500".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(1500)));
+
+        // Backoff doubles (with jitter), up to the cap
+        let delay = retry_delay(2, None);
+        assert!(RETRY_BASE_DELAY <= delay && delay <= RETRY_BASE_DELAY * 2);
+        assert_eq!(retry_delay(20, None), MAX_RETRY_DELAY);
+        let asked = Duration::from_secs(5);
+        assert_eq!(retry_delay(1, Some(asked)), asked);
+        assert_eq!(retry_delay(1, Some(asked * 60)), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_throttle_spacing() {
+        let interval = Duration::from_millis(50);
+        let throttle = Throttle::new(interval);
+        let start = Instant::now();
+        std::thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| throttle.wait());
+            }
+        });
+        throttle.wait();
+        // The first request goes right away; the other three each wait
+        assert!(start.elapsed() >= 3 * interval);
+    }
+
+    fn phase(transfer: &Transfer, err: ureq::Error) -> Phase {
+        match transfer.error(err) {
+            ClientError::Http(_, stats) => stats.phase,
+            err => panic!("unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    fn test_transfer_error_phase() {
+        let global_timeout = || ureq::Error::Timeout(ureq::Timeout::Global);
+        let mut transfer = Transfer::new(100);
+        assert_eq!(phase(&transfer, global_timeout()), Phase::Connect);
+        assert_eq!(phase(&transfer, ureq::Error::HostNotFound), Phase::Connect);
+
+        transfer.uploaded.store(40, Ordering::Relaxed);
+        assert_eq!(phase(&transfer, global_timeout()), Phase::Upload);
+
+        transfer.uploaded.store(100, Ordering::Relaxed);
+        assert_eq!(phase(&transfer, global_timeout()), Phase::Wait);
+
+        transfer.response_at = Some(Instant::now());
+        transfer.downloaded = 1234;
+        assert_eq!(phase(&transfer, global_timeout()), Phase::Download);
+
+        let err = transfer.error(global_timeout()).to_string();
+        assert!(err.starts_with(
+            "HTTP transport error while downloading the response after"
+        ));
+        assert!(
+            err.contains("(uploaded 100 of 100 bytes, downloaded 1234 bytes)")
+        );
+    }
+
+    #[test]
+    fn test_transfer_timings() {
+        let mut transfer = Transfer::new(3);
+        let start = transfer.start;
+        let at = |millis| start + Duration::from_millis(millis);
+        let mut reader = transfer.reader(Cursor::new(vec![1, 2, 3]));
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        transfer.upload_started = Arc::new(OnceLock::from(at(100)));
+        transfer.upload_finished = Arc::new(OnceLock::from(at(300)));
+        transfer.response_at = Some(at(1300));
+        assert_eq!(
+            transfer.timings(at(1350)),
+            Timings {
+                connect: Duration::from_millis(100),
+                upload: Duration::from_millis(200),
+                wait: Duration::from_secs(1),
+                download: Duration::from_millis(50),
+            }
+        );
+        assert_eq!(transfer.uploaded.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_next_event_data() {
+        let stream = "event: response.created\r\ndata: {\"a\": 1}\r\n\r\n\
+                      : keep-alive\n\n\
+                      data: {\"b\":\ndata: 2}\n\n\
+                      data: [DONE]";
+        let mut reader = Cursor::new(stream);
+        let mut next = || next_event_data(&mut reader).unwrap();
+        assert_eq!(next().as_deref(), Some(r#"{"a": 1}"#));
+        assert_eq!(next().as_deref(), Some("{\"b\":\n2}"));
+        assert_eq!(next().as_deref(), Some("[DONE]"));
+        assert_eq!(next(), None);
+    }
+
+    #[test]
+    fn test_format_elapsed() {
+        assert_eq!(format_elapsed(Duration::from_millis(45_900)), "45s");
+        assert_eq!(format_elapsed(Duration::from_secs(60)), "1m00s");
+        assert_eq!(format_elapsed(Duration::from_secs(150)), "2m30s");
+    }
+
+    #[test]
+    fn test_audit_each_attempt() {
+        /// Busy the first time, then rejects the request
+        struct Flaky(AtomicU64);
+        impl Provider for Flaky {
+            fn name(&self) -> &'static str {
+                "flaky"
+            }
+            fn create_images(
+                &self,
+                _: &CreateRequest,
+            ) -> Result<Response, ClientError> {
+                let (status, retry_after) =
+                    match self.0.fetch_add(1, Ordering::Relaxed) {
+                        0 => (503, Some(Duration::ZERO)),
+                        _ => (400, None),
+                    };
+                let status = http::StatusCode::from_u16(status).unwrap();
+                Err(ClientError::from_body(status, String::new(), retry_after))
+            }
+            fn edit_images(
+                &self,
+                _: &EditRequest,
+            ) -> Result<Response, ClientError> {
+                unimplemented!()
+            }
+            fn create_variations(
+                &self,
+                _: &VariationRequest,
+            ) -> Result<Response, ClientError> {
+                unimplemented!()
+            }
+        }
+
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("audit.jsonl");
+        let client = Client::new("sk-test".to_string())
+            .with_provider(Arc::new(Flaky(AtomicU64::new(0))))
+            .with_audit_log(Some(AuditLog::open(path.clone()).unwrap()));
+        let request = CreateRequest {
+            model: "gpt-image-1".to_string(),
+            prompt: "a cat".to_string(),
+            n: None,
+            size: None,
+            quality: None,
+            background: None,
+            moderation: None,
+            output_compression: None,
+            output_format: None,
+            style: None,
+            response_format: None,
+        };
+        assert!(client.create_images(&request).is_err());
+        assert_eq!(crate::audit::verify(&path).unwrap(), 2);
+    }
+
+    /// Serve `body` to a single plain-HTTP GET, returning its URL.
+    fn serve_once(body: &'static [u8]) -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url =
+            format!("http://{}/image.png", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Skip the request head, up to its blank line
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                line.clear();
+            }
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(body).unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn test_fetch_url_images_low_memory() {
+        let client = Client {
+            agent: local_agent(),
+            ..Client::new("sk-test".to_string())
+        }
+        .with_low_memory(true);
+        let response = Response {
+            created: 1713833628,
+            data: vec![crate::api::ImageData {
+                b64_json: String::new(),
+                url: Some(serve_once(b"image bytes")),
+                revised_prompt: None,
+                downloaded: None,
+            }],
+            usage: Default::default(),
+        };
+        let response = client.fetch_url_images(response).unwrap();
+
+        // The image goes straight to disk, and never through base64
+        assert!(response.data[0].b64_json.is_empty());
+        let decoded = crate::api::DecodedResponse::try_from(response).unwrap();
+        let ImageBytes::Spooled(path) = &decoded.data[0].image else {
+            panic!("Expected a spooled image: {:?}", decoded.data[0].image);
+        };
+        assert_eq!(std::fs::read(path).unwrap(), b"image bytes");
+        let path = path.to_path_buf();
+        drop(decoded);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_download_write_error() {
+        /// A file on a full disk
+        struct Full;
+        impl Write for Full {
+            fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+                Err(io::Error::other("No space left on device"))
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        impl Seek for Full {
+            fn seek(&mut self, _: io::SeekFrom) -> io::Result<u64> {
+                Ok(0)
+            }
+        }
+
+        // Not retried: the server only answers once
+        let client = Client {
+            agent: local_agent(),
+            ..Client::new("sk-test".to_string())
+        };
+        let url = serve_once(b"image bytes");
+        let err = client.download_to(&url, &mut Full).unwrap_err();
+        assert!(matches!(err, ClientError::Io(_)), "{err}");
+    }
+}
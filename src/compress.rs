@@ -0,0 +1,105 @@
+//! Gzip and zstd compression for piped images (`--image -.gz`,
+//! `--output -.zst`), so they cross slow links (like SSH) faster.
+
+use anyhow::Context;
+use std::io::{Read, Write};
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// A compression format for stdin and stdout streams.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// The format for a `-.<ext>` stream argument, e.g. `-.gz`.
+    pub fn from_stream_arg(arg: &str) -> Option<Self> {
+        match arg.strip_prefix("-.")? {
+            "gz" | "gzip" => Some(Self::Gzip),
+            "zst" | "zstd" => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Detect compressed data by its magic bytes.
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Some(Self::Gzip)
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Some(Self::Zstd)
+        } else {
+            None
+        }
+    }
+
+    pub fn compress(self, bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(
+                    Vec::new(),
+                    flate2::Compression::default(),
+                );
+                encoder.write_all(bytes)?;
+                Ok(encoder.finish()?)
+            }
+            Self::Zstd => Ok(zstd::encode_all(bytes, 0)?),
+        }
+    }
+
+    fn decompress(self, bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match self {
+            Self::Gzip => {
+                flate2::read::MultiGzDecoder::new(bytes)
+                    .read_to_end(&mut out)?;
+            }
+            Self::Zstd => out = zstd::decode_all(bytes)?,
+        }
+        Ok(out)
+    }
+}
+
+/// Decompress `bytes` if they're gzip or zstd compressed.
+pub fn maybe_decompress(bytes: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    match Compression::detect(&bytes) {
+        Some(compression) => compression
+            .decompress(&bytes)
+            .with_context(|| format!("Failed to decompress {compression:?}")),
+        None => Ok(bytes),
+    }
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"\x89PNG\r\n\x1a\n not really a png".repeat(10);
+        assert_eq!(maybe_decompress(data.clone()).unwrap(), data);
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let compressed = compression.compress(&data).unwrap();
+            assert_eq!(Compression::detect(&compressed), Some(compression));
+            assert_eq!(maybe_decompress(compressed).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_from_stream_arg() {
+        assert_eq!(
+            Compression::from_stream_arg("-.gz"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_stream_arg("-.zst"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_stream_arg("-"), None);
+        assert_eq!(Compression::from_stream_arg("a.gz"), None);
+    }
+}
@@ -9,22 +9,161 @@ use serde::{Deserialize, Serialize};
 #[cfg(unix)]
 use std::os::unix::fs::OpenOptionsExt;
 use std::{
+    collections::BTreeMap,
     env,
     error::Error,
     fmt, fs,
     io::{self, Write},
     path::{Path, PathBuf},
+    sync::OnceLock,
 };
 
 const CONFIG_FILE_NAME: &str = "config.json";
 const APPLICATION: &str = "imgen";
 
+/// The config file from `--config`, used instead of the default location.
+static PATH_OVERRIDE: OnceLock<PathBuf> = OnceLock::new();
+
 /// Represents the user configuration.
 #[derive(Serialize, Deserialize, Default)]
-#[cfg_attr(test, derive(Debug, Clone, PartialEq, Eq))]
+#[cfg_attr(test, derive(Debug, Clone, PartialEq))]
 pub struct Config {
     /// The user's OpenAI API key.
     pub openai_api_key: Option<String>,
+
+    /// Print a one-line summary of this month's spending at the start of each
+    /// run.
+    #[serde(default)]
+    pub show_monthly_spend: bool,
+
+    /// The monthly spending budget in USD, shown in the spending summary.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_budget: Option<f64>,
+
+    /// Brand kits, selected by name with `--brand`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub brands: BTreeMap<String, Brand>,
+
+    /// How automatic output filenames are made from the prompt.
+    #[serde(default, skip_serializing_if = "Filenames::is_default")]
+    pub filenames: Filenames,
+
+    /// The default `--mode` for saved images, as an octal string like
+    /// `"0644"`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_mode: Option<String>,
+
+    /// A Slack bot token for `--slack-channel`. The `SLACK_TOKEN` environment
+    /// variable takes precedence.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slack_token: Option<String>,
+
+    /// A Pinata API key (JWT) for `--output ipfs://`. The `PINATA_JWT`
+    /// environment variable takes precedence.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pinata_jwt: Option<String>,
+
+    /// Record every API call in a hash-chained audit log at this path.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<PathBuf>,
+
+    /// Where to save images that can't be written to their output path.
+    /// Defaults to `imgen` in the system temp directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_dir: Option<PathBuf>,
+
+    /// Settings locked by the system config. Only read from there, and never
+    /// saved.
+    #[serde(default, skip_serializing)]
+    pub locked: Locked,
+}
+
+/// Settings an administrator pins in the system config (`/etc/imgen/config.json`,
+/// or `%ProgramData%\imgen\config.json` on Windows), e.g.
+/// `"locked": { "moderation": "auto", "monthly_budget": 50, "provider":
+/// "sdwebui", "api_base": "http://gpu.lan:7860" }`. Neither the user's
+/// config file nor command line options can override them.
+#[derive(Serialize, Deserialize, Default, PartialEq)]
+#[cfg_attr(test, derive(Debug, Clone))]
+#[serde(deny_unknown_fields)]
+pub struct Locked {
+    /// The content-moderation level for every request.
+    pub moderation: Option<String>,
+
+    /// The monthly budget in USD. Runs fail once this month's spending
+    /// reaches it.
+    pub monthly_budget: Option<f64>,
+
+    /// The audit log every API call is recorded in.
+    pub audit_log: Option<PathBuf>,
+
+    /// The `--provider` for every image request (e.g. `"openai"`).
+    pub provider: Option<String>,
+
+    /// The SD WebUI server's URL (`--api-base`) for `--provider sdwebui`.
+    pub api_base: Option<String>,
+}
+
+/// A brand kit: post-processing and prompt settings applied together with
+/// `--brand <name>`. Explicit command line options take precedence.
+#[derive(Serialize, Deserialize, Default)]
+#[cfg_attr(test, derive(Debug, Clone, PartialEq))]
+#[serde(deny_unknown_fields)]
+pub struct Brand {
+    /// A logo to watermark each image with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logo: Option<PathBuf>,
+
+    /// Hex colors (e.g. `"#112233"`) to remap each image to.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub palette: Vec<String>,
+
+    /// The allowed sizes. The first is used unless `--size` is given.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sizes: Vec<String>,
+
+    /// Text appended to every prompt, e.g. a style description.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_suffix: Option<String>,
+}
+
+/// How automatic output filenames are made from the prompt, e.g.
+/// `"filenames": { "transliterate": true, "max_words": 8 }`.
+#[derive(Serialize, Deserialize, PartialEq)]
+#[cfg_attr(test, derive(Debug, Clone))]
+#[serde(default, deny_unknown_fields)]
+pub struct Filenames {
+    /// Transliterate non-ASCII text to ASCII (e.g. "é" to "e", and "日本語"
+    /// to "ri_ben_yu"). Otherwise it's kept as-is.
+    pub transliterate: bool,
+
+    /// How many bytes of the prompt to take words from.
+    pub max_length: usize,
+
+    /// The most words to use.
+    pub max_words: usize,
+
+    /// A strftime-style format for the timestamp, in local time (e.g.
+    /// `"%Y%m%d-%H%M%S"`). Defaults to Unix seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp_format: Option<String>,
+}
+
+impl Default for Filenames {
+    fn default() -> Self {
+        Self {
+            transliterate: false,
+            max_length: 32,
+            max_words: 5,
+            timestamp_format: None,
+        }
+    }
+}
+
+impl Filenames {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
 }
 
 /// Errors that can occur during configuration loading or saving.
@@ -89,21 +228,72 @@ fn config_dir() -> Option<PathBuf> {
     Some(dir)
 }
 
-/// Gets the platform-specific path to the configuration file.
+/// Gets the platform-specific path to the data directory, which holds the
+/// generation history.
+///
+/// Returns `None` if the data directory cannot be determined.
+pub fn data_dir() -> Option<PathBuf> {
+    let mut dir =
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                env::var_os("HOME").map(|home| {
+                    let mut path = PathBuf::from(home);
+                    path.push(".local");
+                    path.push("share");
+                    path
+                })
+            })?;
+
+    dir.push(APPLICATION);
+    Some(dir)
+}
+
+/// Gets the platform-specific path to the system-wide configuration file,
+/// which holds the [`Locked`] settings.
+fn system_config_path() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let dir = PathBuf::from(env::var_os("ProgramData")?);
+    #[cfg(not(windows))]
+    let dir = PathBuf::from("/etc");
+    Some(dir.join(APPLICATION).join(CONFIG_FILE_NAME))
+}
+
+/// Use this config file instead of the default one, for the rest of the
+/// process.
+pub fn set_path(path: PathBuf) {
+    if PATH_OVERRIDE.set(path).is_err() {
+        warn!("The config file path was already set");
+    }
+}
+
+/// Gets the path to the configuration file: the `--config` file, if given,
+/// or else the platform-specific default.
 ///
 /// Returns `None` if the config path cannot be determined.
 fn config_path() -> Option<PathBuf> {
+    if let Some(path) = PATH_OVERRIDE.get() {
+        return Some(path.clone());
+    }
     let mut path = config_dir()?;
     path.push(CONFIG_FILE_NAME);
     Some(path)
 }
 
 impl Config {
-    /// Loads the configuration from the default location.
+    /// Loads the configuration from the default location, with any settings
+    /// locked by the system config applied.
     ///
     /// If the config file does not exist or cannot be read/parsed,
     /// a default `Config` is returned and a warning is logged.
     pub fn load() -> Config {
+        let mut config = Config::load_user();
+        config.apply_locked(load_locked());
+        config
+    }
+
+    /// Loads only the user's configuration, e.g. to change and save it.
+    pub fn load_user() -> Config {
         let config_path = match config_path() {
             Some(path) => path,
             None => return Config::default(),
@@ -125,6 +315,17 @@ impl Config {
         }
     }
 
+    /// Override settings with the locked ones.
+    fn apply_locked(&mut self, locked: Locked) {
+        if locked.monthly_budget.is_some() {
+            self.monthly_budget = locked.monthly_budget;
+        }
+        if locked.audit_log.is_some() {
+            self.audit_log = locked.audit_log.clone();
+        }
+        self.locked = locked;
+    }
+
     /// Tries to load the configuration from a specific path.
     pub fn load_from_path(path: &Path) -> Result<Config, ConfigError> {
         debug!("Attempting to load config from: {}", path.display());
@@ -179,6 +380,27 @@ impl Config {
     }
 }
 
+/// Loads the locked settings from the system config, if there is one.
+fn load_locked() -> Locked {
+    let Some(path) = system_config_path() else {
+        return Locked::default();
+    };
+    match Config::load_from_path(&path) {
+        Ok(config) => {
+            debug!("System config loaded from: {}", path.display());
+            config.locked
+        }
+        Err(ConfigError::NoConfig) => Locked::default(),
+        Err(err) => {
+            warn!(
+                "Failed to load system config from {}: {err}",
+                path.display()
+            );
+            Locked::default()
+        }
+    }
+}
+
 // --- Tests ---
 
 #[cfg(test)]
@@ -218,6 +440,29 @@ mod tests {
 
         let original_config = Config {
             openai_api_key: Some("test-api-key-123".to_string()),
+            show_monthly_spend: true,
+            monthly_budget: Some(20.0),
+            brands: BTreeMap::from([(
+                "acme".to_string(),
+                Brand {
+                    logo: Some(PathBuf::from("/brand/acme.png")),
+                    palette: vec!["#112233".to_string()],
+                    sizes: vec!["landscape".to_string()],
+                    prompt_suffix: Some("in flat vector style".to_string()),
+                },
+            )]),
+            filenames: Filenames {
+                transliterate: true,
+                max_length: 48,
+                max_words: 8,
+                timestamp_format: Some("%Y%m%d-%H%M%S".to_string()),
+            },
+            file_mode: Some("0640".to_string()),
+            slack_token: Some("xoxb-test".to_string()),
+            pinata_jwt: Some("eyJ-test".to_string()),
+            audit_log: Some(PathBuf::from("/var/log/imgen/audit.jsonl")),
+            fallback_dir: Some(PathBuf::from("/tmp/imgen-rescue")),
+            locked: Locked::default(),
         };
 
         // Save the config
@@ -244,4 +489,35 @@ mod tests {
         // Verify the loaded config matches the original
         assert_eq!(loaded_config, original_config);
     }
+
+    #[test]
+    fn test_apply_locked() {
+        // A user's own "locked" section means nothing
+        let mut config: Config = serde_json::from_str(
+            r#"{ "monthly_budget": 500, "locked": { "monthly_budget": 900 } }"#,
+        )
+        .unwrap();
+        let system: Config = serde_json::from_str(
+            r#"{ "locked": {
+                "moderation": "auto",
+                "monthly_budget": 50,
+                "provider": "sdwebui",
+                "api_base": "http://gpu.lan:7860"
+            } }"#,
+        )
+        .unwrap();
+        config.apply_locked(system.locked);
+        assert_eq!(config.monthly_budget, Some(50.0));
+        assert_eq!(config.locked.moderation.as_deref(), Some("auto"));
+        assert_eq!(config.locked.provider.as_deref(), Some("sdwebui"));
+        assert_eq!(
+            config.locked.api_base.as_deref(),
+            Some("http://gpu.lan:7860")
+        );
+        assert_eq!(config.audit_log, None);
+
+        // The locked settings are never written to the user's config
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("locked"), "{json}");
+    }
 }
@@ -0,0 +1,176 @@
+//! A unix socket (`--control-socket`) for wrapping GUIs: connected clients
+//! receive the same JSON lifecycle events as `--events`, and can send
+//! `cancel` to abort an in-flight generation without killing the process.
+
+use anyhow::Context;
+use log::{debug, warn};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Writers that receive each event line.
+pub type Subscribers = Arc<Mutex<Vec<Box<dyn Write + Send>>>>;
+
+/// How often to check for cancellation while waiting on a request.
+const CANCEL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A bound control socket. The socket file is removed on drop.
+pub struct ControlSocket {
+    path: PathBuf,
+    subscribers: Subscribers,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ControlSocket {
+    /// Bind the control socket at `path` and start accepting clients in the
+    /// background.
+    #[cfg(unix)]
+    pub fn bind(path: &Path) -> anyhow::Result<Self> {
+        use std::{
+            io::{BufRead, BufReader},
+            os::unix::net::{UnixListener, UnixStream},
+            thread,
+        };
+
+        // Replace a stale socket left behind by a crashed run, but don't
+        // steal one that's still in use
+        if path.exists() && UnixStream::connect(path).is_err() {
+            debug!("Removing stale control socket: {}", path.display());
+            let _ = std::fs::remove_file(path);
+        }
+        let listener = UnixListener::bind(path).with_context(|| {
+            format!("Failed to bind control socket: {}", path.display())
+        })?;
+
+        let subscribers = Subscribers::default();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        {
+            let subscribers = subscribers.clone();
+            let cancelled = cancelled.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else { continue };
+                    if let Ok(writer) = stream.try_clone() {
+                        subscribers.lock().unwrap().push(Box::new(writer));
+                    }
+                    let cancelled = cancelled.clone();
+                    thread::spawn(move || {
+                        for line in BufReader::new(stream).lines() {
+                            let Ok(line) = line else { break };
+                            match line.trim() {
+                                "cancel" => {
+                                    warn!(
+                                        "Cancel requested via control socket"
+                                    );
+                                    cancelled.store(true, Ordering::Relaxed);
+                                }
+                                "" => {}
+                                command => warn!(
+                                    "Ignoring unknown control command: \
+                                     {command}"
+                                ),
+                            }
+                        }
+                    });
+                }
+            });
+        }
+
+        debug!("Control socket listening at: {}", path.display());
+        Ok(Self {
+            path: path.to_path_buf(),
+            subscribers,
+            cancelled,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn bind(_path: &Path) -> anyhow::Result<Self> {
+        anyhow::bail!("--control-socket is only supported on unix platforms")
+    }
+
+    /// The writers that should receive each event line.
+    pub fn subscribers(&self) -> Subscribers {
+        self.subscribers.clone()
+    }
+
+    /// Wait for a result from a background request, or bail if a client
+    /// cancels it first.
+    pub fn wait<T>(
+        &self,
+        rx: mpsc::Receiver<anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        loop {
+            if self.cancelled.load(Ordering::Relaxed) {
+                anyhow::bail!("Cancelled via control socket");
+            }
+            match rx.recv_timeout(CANCEL_POLL_INTERVAL) {
+                Ok(result) => return result,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => {
+                    anyhow::bail!("Request thread exited unexpectedly")
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// --- Tests ---
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::events::{Event, Events};
+    use std::{
+        io::{BufRead, BufReader},
+        os::unix::net::UnixStream,
+    };
+
+    #[test]
+    fn test_progress_and_cancel() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("imgen.sock");
+        let control = ControlSocket::bind(&path).unwrap();
+
+        let mut client = UnixStream::connect(&path).unwrap();
+        // Wait for the listener to register the client
+        while control.subscribers.lock().unwrap().is_empty() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let events = Events::new(false, Some(control.subscribers()));
+        events.emit(Event::Generating {
+            model: "gpt-image-1",
+        });
+        let mut line = String::new();
+        BufReader::new(client.try_clone().unwrap())
+            .read_line(&mut line)
+            .unwrap();
+        assert_eq!(
+            line,
+            "{\"event\":\"generating\",\"model\":\"gpt-image-1\"}\n"
+        );
+
+        // The request never finishes, but the client cancels it
+        let (_tx, rx) = mpsc::channel::<anyhow::Result<()>>();
+        client.write_all(b"cancel\n").unwrap();
+        let err = control.wait(rx).unwrap_err();
+        assert_eq!(err.to_string(), "Cancelled via control socket");
+
+        drop(control);
+        assert!(!path.exists());
+    }
+}
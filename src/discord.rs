@@ -0,0 +1,210 @@
+//! Posting generated images to a Discord channel webhook, for
+//! `--discord-webhook`.
+//!
+//! Each message carries the images as attachments, with an embed per image.
+//! The first embed also has the prompt and generation settings. Webhook
+//! uploads are size-limited, so large batches are split over several
+//! messages.
+
+use anyhow::{bail, Context};
+use log::{debug, info};
+use serde_json::json;
+use std::path::Path;
+
+use crate::{client, multipart, record::RunRecord};
+
+/// The upload limit for a message without server boosts
+const MAX_UPLOAD_BYTES: usize = 10 * 1024 * 1024;
+
+/// The longest embed title Discord accepts
+const MAX_TITLE_CHARS: usize = 256;
+
+/// The hosts Discord serves webhooks from
+const WEBHOOK_HOSTS: &[&str] = &[
+    "discord.com",
+    "ptb.discord.com",
+    "canary.discord.com",
+    "discordapp.com",
+];
+
+/// Check that `url` looks like a Discord webhook URL, so a bad paste fails
+/// before we generate anything. For clap's `value_parser`.
+pub fn parse_webhook(url: &str) -> Result<String, String> {
+    let invalid = || {
+        "expected a Discord webhook URL like \
+         https://discord.com/api/webhooks/<id>/<token>"
+            .to_string()
+    };
+    let rest = url.strip_prefix("https://").ok_or_else(invalid)?;
+    let (host, path) = rest.split_once('/').ok_or_else(invalid)?;
+    let path = path.strip_prefix("api/").ok_or_else(invalid)?;
+    // Allow an API version, like `api/v10/webhooks/...`
+    let path = match path.split_once('/') {
+        Some((version, rest)) if version.starts_with('v') => rest,
+        _ => path,
+    };
+    let webhook = path.strip_prefix("webhooks/").ok_or_else(invalid)?;
+    let mut parts = webhook.split('/');
+    let (Some(id), Some(token)) = (parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    if !WEBHOOK_HOSTS.contains(&host)
+        || id.is_empty()
+        || !id.bytes().all(|byte| byte.is_ascii_digit())
+        || token.is_empty()
+    {
+        return Err(invalid());
+    }
+    Ok(url.to_string())
+}
+
+/// Post the run's saved images to the webhook.
+pub fn post(
+    webhook: &str,
+    record: &RunRecord,
+    size: &str,
+    quality: &str,
+) -> anyhow::Result<()> {
+    let mut files = Vec::new();
+    for (i, image) in record.images.iter().enumerate() {
+        let Some(path) = &image.path else { continue };
+        let bytes = std::fs::read(path).with_context(|| {
+            format!("Failed to read image: {}", path.display())
+        })?;
+        if bytes.len() > MAX_UPLOAD_BYTES {
+            bail!(
+                "{} is too large to post to Discord (over {} MiB)",
+                path.display(),
+                MAX_UPLOAD_BYTES / (1024 * 1024)
+            );
+        }
+        // Attachment names end up in URLs, so keep them simple
+        let extension = path.extension().and_then(|ext| ext.to_str());
+        let filename = format!("image{}.{}", i + 1, extension.unwrap_or("png"));
+        files.push((filename, image.alt_text.as_deref(), bytes));
+    }
+
+    // Split into messages that fit under the upload limit
+    let mut batches: Vec<&[_]> = Vec::new();
+    let (mut start, mut batch_bytes) = (0, 0);
+    for (i, (_, _, bytes)) in files.iter().enumerate() {
+        if i > start && batch_bytes + bytes.len() > MAX_UPLOAD_BYTES {
+            batches.push(&files[start..i]);
+            (start, batch_bytes) = (i, 0);
+        }
+        batch_bytes += bytes.len();
+    }
+    batches.push(&files[start..]);
+
+    let agent = client::agent();
+    let url = format!("{webhook}?wait=true");
+    for (i, batch) in batches.iter().enumerate() {
+        let embeds: Vec<_> = batch
+            .iter()
+            .map(|(filename, alt_text, _)| {
+                let mut embed = json!({
+                    "image": { "url": format!("attachment://{filename}") },
+                });
+                if let Some(alt_text) = alt_text {
+                    embed["description"] = json!(alt_text);
+                }
+                embed
+            })
+            .collect();
+        let mut payload = json!({ "embeds": embeds });
+        if i == 0 {
+            describe(&mut payload["embeds"][0], record, size, quality);
+        }
+        let payload = payload.to_string();
+
+        let mut builder = multipart::Builder::new();
+        builder.add_text("payload_json", &payload);
+        let names: Vec<_> =
+            (0..batch.len()).map(|j| format!("files[{j}]")).collect();
+        for ((filename, _, bytes), name) in batch.iter().zip(&names) {
+            builder.add_file_bytes(
+                name,
+                Path::new(filename),
+                multipart::mime_from_bytes(bytes),
+                bytes,
+            );
+        }
+        let body = builder.build();
+
+        let mut response = agent
+            .post(&url)
+            .header("Content-Type", &body.content_type)
+            .send(&body.body)
+            .context("Discord webhook request failed")?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.body_mut().read_to_string().unwrap_or_default();
+            debug!("Discord webhook response: {body}");
+            let message = serde_json::from_str::<serde_json::Value>(&body)
+                .ok()
+                .and_then(|body| body["message"].as_str().map(str::to_string))
+                .unwrap_or_else(|| status.to_string());
+            bail!("Discord webhook failed: {message}");
+        }
+    }
+    info!("Posted {} image(s) to Discord", files.len());
+    Ok(())
+}
+
+/// Add the prompt and generation settings to an embed.
+fn describe(
+    embed: &mut serde_json::Value,
+    record: &RunRecord,
+    size: &str,
+    quality: &str,
+) {
+    let title = if record.prompt.chars().count() > MAX_TITLE_CHARS {
+        let prefix: String =
+            record.prompt.chars().take(MAX_TITLE_CHARS - 1).collect();
+        format!("{prefix}…")
+    } else {
+        record.prompt.clone()
+    };
+    embed["title"] = json!(title);
+    embed["fields"] = json!([
+        { "name": "Model", "value": record.model, "inline": true },
+        { "name": "Size", "value": size, "inline": true },
+        { "name": "Quality", "value": quality, "inline": true },
+        {
+            "name": "Cost",
+            "value": format!("${:.3}", record.cost),
+            "inline": true,
+        },
+    ]);
+    if let Ok(created) = jiff::Timestamp::from_second(record.created as i64) {
+        embed["timestamp"] = json!(created.to_string());
+    }
+    embed["footer"] = json!({ "text": "imgen" });
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_webhook() {
+        for url in [
+            "https://discord.com/api/webhooks/123456/abc-DEF_ghi",
+            "https://discordapp.com/api/webhooks/123456/token",
+            "https://canary.discord.com/api/v10/webhooks/123456/token",
+        ] {
+            assert_eq!(parse_webhook(url).as_deref(), Ok(url));
+        }
+        for url in [
+            "http://discord.com/api/webhooks/123456/token",
+            "https://discord.com.evil.example/api/webhooks/123456/token",
+            "https://discord.com/api/webhooks/123456",
+            "https://discord.com/api/webhooks/abc/token",
+            "https://discord.com/channels/123456/789",
+        ] {
+            assert!(parse_webhook(url).is_err(), "{url}");
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Free disk space checks, so we don't pay for images we can't save.
+//!
+//! Free space comes from the POSIX `df` command, since std has no portable
+//! way to ask. If `df` isn't available the check is skipped.
+
+use anyhow::{bail, Context};
+use log::{debug, warn};
+use std::{path::Path, process::Command};
+
+/// The size assumed for `--size auto`: the largest size the API picks
+const AUTO_PIXELS: u64 = 1536 * 1024;
+
+/// Room for everything else we write (sidecars, history, contact sheets)
+const MARGIN_BYTES: u64 = 1024 * 1024;
+
+/// A generous estimate of how many bytes `n` generated images take on disk.
+///
+/// `size` is the API's "WxH" (or `None` for auto). Generated images are
+/// detailed and compress poorly, so png is close to raw RGB, and lower
+/// quality means less fine detail to encode.
+pub fn estimate_output_bytes(
+    size: Option<&str>,
+    quality: &str,
+    output_format: &str,
+    output_compression: u8,
+    n: u8,
+) -> u64 {
+    let pixels = size
+        .and_then(|size| size.split_once('x'))
+        .and_then(|(w, h)| {
+            Some(w.parse::<u64>().ok()? * h.parse::<u64>().ok()?)
+        })
+        .unwrap_or(AUTO_PIXELS);
+    // In tenths of a byte
+    let per_pixel = match output_format {
+        "jpeg" | "jpg" | "webp" => 2 + u64::from(output_compression) / 10,
+        _ => 30,
+    };
+    let quality_percent = match quality {
+        "low" => 60,
+        "medium" => 80,
+        _ => 100,
+    };
+    pixels * per_pixel / 10 * quality_percent / 100 * u64::from(n.max(1))
+        + MARGIN_BYTES
+}
+
+/// Fail if the filesystem holding `dir` doesn't have room for `needed`
+/// bytes, and warn if it's getting close.
+pub fn check_space(dir: &Path, needed: u64) -> anyhow::Result<()> {
+    let free = match free_space(dir) {
+        Ok(free) => free,
+        Err(err) => {
+            debug!("Skipping disk space check: {err:#}");
+            return Ok(());
+        }
+    };
+    debug!(
+        "Disk space: {} free in {}, ~{} needed",
+        format_bytes(free),
+        dir.display(),
+        format_bytes(needed)
+    );
+    if free < needed {
+        bail!(
+            "Not enough disk space to save the image(s): {} free in {}, \
+             but they need ~{}",
+            format_bytes(free),
+            dir.display(),
+            format_bytes(needed)
+        );
+    }
+    if free < needed * 2 {
+        warn!(
+            "Low disk space: {} free in {}, and the image(s) need ~{}",
+            format_bytes(free),
+            dir.display(),
+            format_bytes(needed)
+        );
+    }
+    Ok(())
+}
+
+/// The free space available to us on the filesystem holding `dir`.
+fn free_space(dir: &Path) -> anyhow::Result<u64> {
+    let output = Command::new("df")
+        .arg("-Pk")
+        .arg(dir)
+        .output()
+        .context("Failed to run df")?;
+    if !output.status.success() {
+        bail!("df failed: {}", output.status);
+    }
+    parse_df(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse the available space from `df -Pk` output.
+fn parse_df(output: &str) -> anyhow::Result<u64> {
+    // Filesystem 1024-blocks Used Available Capacity Mounted on
+    let available = output
+        .lines()
+        .nth(1)
+        .and_then(|line| line.split_whitespace().nth(3))
+        .context("Unexpected df output")?;
+    let kib: u64 = available.parse().context("Unexpected df output")?;
+    Ok(kib * 1024)
+}
+
+fn format_bytes(bytes: u64) -> String {
+    let mib = bytes as f64 / (1024.0 * 1024.0);
+    if mib >= 1024.0 {
+        format!("{:.1} GiB", mib / 1024.0)
+    } else {
+        format!("{mib:.1} MiB")
+    }
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_output_bytes() {
+        let png =
+            estimate_output_bytes(Some("1024x1024"), "high", "png", 100, 1);
+        assert_eq!(png, 1024 * 1024 * 3 + MARGIN_BYTES);
+        let auto = estimate_output_bytes(None, "auto", "png", 100, 2);
+        assert_eq!(auto, 1536 * 1024 * 3 * 2 + MARGIN_BYTES);
+        let jpeg =
+            estimate_output_bytes(Some("1024x1024"), "low", "jpeg", 80, 1);
+        assert!(jpeg < png / 2);
+    }
+
+    #[test]
+    fn test_parse_df() {
+        let output = "\
+Filesystem     1024-blocks      Used Available Capacity Mounted on
+/dev/sda1         41152736  20971520  18067632      54% /
+";
+        assert_eq!(parse_df(output).unwrap(), 18_067_632 * 1024);
+        assert!(parse_df("").is_err());
+    }
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(3 * 1024 * 1024 / 2), "1.5 MiB");
+        assert_eq!(format_bytes(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+}
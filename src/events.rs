@@ -0,0 +1,69 @@
+//! Newline-delimited JSON lifecycle events on stdout (`--events`) and the
+//! control socket, for GUIs and orchestrators wrapping imgen.
+
+use serde::Serialize;
+use std::{io::Write, path::Path};
+
+use crate::control::Subscribers;
+
+/// A lifecycle event. Serialized as `{"event": "<name>", ...}`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// The inputs were read and validated
+    Validated { operation: &'a str, n: u8 },
+    /// Uploading the input images (edit only)
+    Uploading { images: usize, bytes: usize },
+    /// The request was sent; waiting for the model
+    Generating { model: &'a str },
+    /// The response images were decoded
+    Decoded { images: usize },
+    /// An image was saved (`path` is `null` when written to stdout)
+    Saved {
+        index: usize,
+        path: Option<&'a Path>,
+    },
+    /// The run finished successfully
+    Done { cost: f64 },
+    /// The run failed
+    Error { message: String },
+}
+
+/// Emits [`Event`]s to stdout and/or control socket clients, if enabled.
+pub struct Events {
+    stdout: bool,
+    subscribers: Option<Subscribers>,
+}
+
+impl Events {
+    pub fn new(stdout: bool, subscribers: Option<Subscribers>) -> Self {
+        Self {
+            stdout,
+            subscribers,
+        }
+    }
+
+    pub fn emit(&self, event: Event<'_>) {
+        if !self.stdout && self.subscribers.is_none() {
+            return;
+        }
+        let mut line =
+            serde_json::to_string(&event).expect("Failed to serialize event");
+        line.push('\n');
+
+        // Flush each line so consumers see progress in real time. Ignore
+        // errors; a closed pipe shouldn't fail the run.
+        if self.stdout {
+            let mut stdout = std::io::stdout().lock();
+            let _ = stdout.write_all(line.as_bytes());
+            let _ = stdout.flush();
+        }
+        if let Some(subscribers) = &self.subscribers {
+            // Drop clients that have disconnected
+            subscribers.lock().unwrap().retain_mut(|writer| {
+                writer.write_all(line.as_bytes()).is_ok()
+                    && writer.flush().is_ok()
+            });
+        }
+    }
+}
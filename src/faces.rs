@@ -0,0 +1,176 @@
+//! Face detection and blurring for `--blur-faces`.
+//!
+//! Detection runs locally with the `facedetect` command (OpenCV-based, and
+//! packaged by most distros), so images never leave the machine unblurred.
+
+use anyhow::{bail, Context};
+use image::{DynamicImage, GenericImageView};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use crate::imageops;
+
+/// A detected face, in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Make sure the `facedetect` command is available, before we do anything.
+pub fn check_available() -> anyhow::Result<()> {
+    let status = Command::new("facedetect")
+        .arg("--help")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    if !status.is_ok_and(|status| status.success()) {
+        bail!(
+            "--blur-faces needs the `facedetect` command; install it with \
+             e.g. `apt install facedetect`"
+        );
+    }
+    Ok(())
+}
+
+/// Detect the faces in an image.
+pub fn detect(image: &DynamicImage) -> anyhow::Result<Vec<Rect>> {
+    // facedetect only reads files, so hand it a lossless copy. The file is
+    // private to us, and removed when dropped.
+    let png = imageops::encode(image, image::ImageFormat::Png)?;
+    let mut file = tempfile::Builder::new()
+        .prefix("imgen-faces-")
+        .suffix(".png")
+        .tempfile()
+        .context("Failed to create temporary file")?;
+    file.write_all(&png).with_context(|| {
+        format!("Failed to write temporary file: {}", file.path().display())
+    })?;
+
+    let output = Command::new("facedetect")
+        .arg(file.path())
+        .stderr(Stdio::inherit())
+        .output()
+        .context("Failed to run facedetect")?;
+    // facedetect exits with 2 when there are no faces
+    match output.status.code() {
+        Some(0 | 2) => (),
+        _ => bail!("facedetect failed: {}", output.status),
+    }
+    parse_rects(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Parse facedetect's `x y width height` lines.
+fn parse_rects(output: &str) -> anyhow::Result<Vec<Rect>> {
+    output
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let values = line
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<Vec<u32>, _>>()
+                .ok()
+                .filter(|values| values.len() == 4)
+                .with_context(|| {
+                    format!("Unexpected facedetect output: {line:?}")
+                })?;
+            Ok(Rect {
+                x: values[0],
+                y: values[1],
+                width: values[2],
+                height: values[3],
+            })
+        })
+        .collect()
+}
+
+/// Heavily blur each face, with some margin for hair and ears.
+pub fn blur(image: &DynamicImage, faces: &[Rect]) -> DynamicImage {
+    let mut out = image.to_rgba8();
+    let (width, height) = image.dimensions();
+    for face in faces {
+        let margin_x = face.width / 4;
+        let margin_y = face.height / 4;
+        let x = face.x.saturating_sub(margin_x);
+        let y = face.y.saturating_sub(margin_y);
+        let w = (face.width + 2 * margin_x).min(width - x.min(width));
+        let h = (face.height + 2 * margin_y).min(height - y.min(height));
+        if w == 0 || h == 0 {
+            continue;
+        }
+
+        let region = image::imageops::crop_imm(&out, x, y, w, h).to_image();
+        let sigma = (w.max(h) as f32 / 6.0).max(2.0);
+        let blurred = image::imageops::blur(&region, sigma);
+        image::imageops::replace(&mut out, &blurred, x as i64, y as i64);
+    }
+    DynamicImage::ImageRgba8(out)
+}
+
+/// Detect and blur the faces in an encoded image, keeping its format.
+/// Returns the image unchanged if there are no faces.
+pub fn blur_encoded(bytes: &[u8]) -> anyhow::Result<(Vec<u8>, usize)> {
+    let format = image::guess_format(bytes)
+        .context("Failed to detect the image format")?;
+    let image =
+        image::load_from_memory(bytes).context("Failed to decode the image")?;
+    let faces = detect(&image)?;
+    if faces.is_empty() {
+        return Ok((bytes.to_vec(), 0));
+    }
+    let blurred = imageops::encode(&blur(&image, &faces), format)?;
+    Ok((blurred, faces.len()))
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_parse_rects() {
+        let rects = parse_rects("10 20 30 40\n\n1 2 3 4\n").unwrap();
+        assert_eq!(rects.len(), 2);
+        assert_eq!(
+            rects[0],
+            Rect {
+                x: 10,
+                y: 20,
+                width: 30,
+                height: 40
+            }
+        );
+        assert!(parse_rects("10 20 30").is_err());
+        assert!(parse_rects("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_blur() {
+        // A checkerboard, blurred only around the "face"
+        let image = RgbaImage::from_fn(64, 64, |x, y| {
+            let v = if (x + y) % 2 == 0 { 0 } else { 255 };
+            Rgba([v, v, v, 255])
+        });
+        let image = DynamicImage::ImageRgba8(image);
+        let face = Rect {
+            x: 20,
+            y: 20,
+            width: 16,
+            height: 16,
+        };
+        let out = blur(&image, &[face]).into_rgba8();
+        let gray = out.get_pixel(28, 28)[0];
+        assert!((64..192).contains(&gray), "not blurred: {gray}");
+        assert_eq!(
+            out.get_pixel(0, 0),
+            image.as_rgba8().unwrap().get_pixel(0, 0)
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Google's Imagen models through the Gemini API, for `--provider gemini`.
+//!
+//! `--size` becomes an aspect ratio, `--quality high` asks for 2K images
+//! (on the models that make them), and `-n` is the sample count. Imagen
+//! can't edit images.
+
+use anyhow::Context;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use ureq::SendBody;
+
+use crate::{
+    api::{
+        self, CreateRequest, EditRequest, ImageData, Response, VariationRequest,
+    },
+    client::{self, ClientError, Provider, Transfer},
+    redact,
+};
+
+const BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+/// The environment variable checked for a Gemini API key
+pub const API_KEY_ENV: &str = "GEMINI_API_KEY";
+
+/// The model used unless `--model` picks another
+pub const DEFAULT_MODEL: &str = "imagen-4.0-generate-001";
+
+/// The Imagen models on the Gemini API
+pub const MODELS: &[&str] = &[
+    "imagen-4.0-generate-001",
+    "imagen-4.0-ultra-generate-001",
+    "imagen-4.0-fast-generate-001",
+    "imagen-3.0-generate-002",
+];
+
+/// A connection to the Gemini API.
+pub struct Gemini {
+    agent: ureq::Agent,
+    api_key: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PredictRequest<'a> {
+    instances: [Instance<'a>; 1],
+    parameters: Parameters,
+}
+
+#[derive(Debug, Serialize)]
+struct Instance<'a> {
+    prompt: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Parameters {
+    sample_count: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    aspect_ratio: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_size: Option<&'static str>,
+    /// Say why images were filtered, rather than silently dropping them
+    include_rai_reason: bool,
+}
+
+#[derive(Deserialize)]
+struct PredictResponse {
+    #[serde(default)]
+    predictions: Vec<Prediction>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Prediction {
+    bytes_base64_encoded: Option<String>,
+    rai_filtered_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    message: String,
+}
+
+impl Gemini {
+    /// Find the API key, so a missing one fails before we generate anything.
+    pub fn new() -> anyhow::Result<Self> {
+        let api_key = std::env::var(API_KEY_ENV).with_context(|| {
+            format!(
+                "--provider gemini needs an API key; set the `{API_KEY_ENV}` \
+                 environment variable"
+            )
+        })?;
+        redact::add_secret(&api_key);
+        Ok(Self {
+            agent: client::agent(),
+            api_key,
+        })
+    }
+
+    /// A client for `--dry-run`, which never sends a request, so needs no
+    /// API key.
+    pub fn for_dry_run() -> Self {
+        Self {
+            agent: client::agent(),
+            api_key: String::new(),
+        }
+    }
+}
+
+impl Provider for Gemini {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn create_images(
+        &self,
+        request: &CreateRequest,
+    ) -> Result<Response, ClientError> {
+        let body = serde_json::to_vec(&PredictRequest {
+            instances: [Instance {
+                prompt: &request.prompt,
+            }],
+            parameters: Parameters {
+                sample_count: request.n.unwrap_or(1),
+                aspect_ratio: aspect_ratio(request.size.as_deref())?,
+                image_size: image_size(request.quality.as_deref()),
+                include_rai_reason: true,
+            },
+        })?;
+        let content_length = body.len();
+        let transfer = Transfer::new(content_length);
+        let reader = transfer.reader(Cursor::new(body));
+        let mut response = self
+            .agent
+            .post(format!("{BASE_URL}/models/{}:predict", request.model))
+            .header("x-goog-api-key", &self.api_key)
+            .header("Content-Type", "application/json")
+            .header("Content-Length", content_length)
+            .send(SendBody::from_owned_reader(reader))
+            .map_err(|err| transfer.error(err))?;
+        let status = response.status();
+        let retry_after = client::retry_after(response.headers());
+        let text = response
+            .body_mut()
+            .with_config()
+            .limit(client::RESPONSE_BODY_LIMIT)
+            .read_to_string()
+            .map_err(|err| transfer.error(err))?;
+        if !status.is_success() {
+            debug!("Gemini response: {text}");
+            let message = serde_json::from_str::<ErrorResponse>(&text)
+                .map(|error| error.error.message)
+                .unwrap_or(text);
+            return Err(ClientError::ApiError {
+                status,
+                message,
+                retry_after,
+                error: None,
+            });
+        }
+
+        let response: PredictResponse = serde_json::from_str(&text)?;
+        let mut data = Vec::new();
+        let mut filtered = Vec::new();
+        for prediction in response.predictions {
+            match prediction.bytes_base64_encoded {
+                Some(b64_json) => data.push(ImageData {
+                    b64_json,
+                    url: None,
+                    revised_prompt: None,
+                    downloaded: None,
+                }),
+                None => filtered.extend(prediction.rai_filtered_reason),
+            }
+        }
+        if data.is_empty() {
+            let reason = if filtered.is_empty() {
+                "no reason given".to_string()
+            } else {
+                filtered.join("; ")
+            };
+            return Err(ClientError::ApiError {
+                status,
+                message: format!(
+                    "The image was blocked by Gemini's safety filters: \
+                     {reason}"
+                ),
+                retry_after: None,
+                error: None,
+            });
+        }
+        if !filtered.is_empty() {
+            warn!(
+                "{} image(s) blocked by Gemini's safety filters: {}",
+                filtered.len(),
+                filtered.join("; ")
+            );
+        }
+        Ok(Response {
+            created: api::unix_now(),
+            data,
+            usage: Default::default(),
+        })
+    }
+
+    fn edit_images(
+        &self,
+        _request: &EditRequest,
+    ) -> Result<Response, ClientError> {
+        Err(ClientError::Unsupported {
+            provider: self.name(),
+            feature: "editing images",
+        })
+    }
+
+    fn create_variations(
+        &self,
+        _request: &VariationRequest,
+    ) -> Result<Response, ClientError> {
+        Err(ClientError::Unsupported {
+            provider: self.name(),
+            feature: "variations",
+        })
+    }
+}
+
+/// The Imagen aspect ratio for a `--size` (one of Imagen's 1K sizes).
+/// `None` for the default (square).
+fn aspect_ratio(
+    size: Option<&str>,
+) -> Result<Option<&'static str>, ClientError> {
+    match size {
+        None => Ok(None),
+        Some("1024x1024") => Ok(Some("1:1")),
+        Some("1408x768") => Ok(Some("16:9")),
+        Some("768x1408") => Ok(Some("9:16")),
+        Some("1280x896") => Ok(Some("4:3")),
+        Some("896x1280") => Ok(Some("3:4")),
+        Some(_) => Err(ClientError::Unsupported {
+            provider: "gemini",
+            feature: "this --size",
+        }),
+    }
+}
+
+/// The Imagen image size for a `--quality`: "high" doubles the resolution.
+fn image_size(quality: Option<&str>) -> Option<&'static str> {
+    match quality? {
+        "high" => Some("2K"),
+        _ => Some("1K"),
+    }
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parameter_mapping() {
+        assert_eq!(aspect_ratio(None).unwrap(), None);
+        assert_eq!(aspect_ratio(Some("1408x768")).unwrap(), Some("16:9"));
+        assert!(aspect_ratio(Some("1536x1024")).is_err());
+        assert_eq!(image_size(None), None);
+        assert_eq!(image_size(Some("high")), Some("2K"));
+        assert_eq!(image_size(Some("low")), Some("1K"));
+
+        let request = PredictRequest {
+            instances: [Instance { prompt: "A cat" }],
+            parameters: Parameters {
+                sample_count: 2,
+                aspect_ratio: Some("16:9"),
+                image_size: None,
+                include_rai_reason: true,
+            },
+        };
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "instances": [{ "prompt": "A cat" }],
+                "parameters": {
+                    "sampleCount": 2,
+                    "aspectRatio": "16:9",
+                    "includeRaiReason": true,
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_filtered_response() {
+        let response: PredictResponse = serde_json::from_str(
+            r#"{"predictions": [
+                {"bytesBase64Encoded": "aGk=", "mimeType": "image/png"},
+                {"raiFilteredReason": "Filtered for safety"}
+            ]}"#,
+        )
+        .unwrap();
+        let [image, filtered] = response.predictions.as_slice() else {
+            panic!("Expected two predictions");
+        };
+        assert_eq!(image.bytes_base64_encoded.as_deref(), Some("aGk="));
+        assert_eq!(
+            filtered.rai_filtered_reason.as_deref(),
+            Some("Filtered for safety")
+        );
+    }
+}
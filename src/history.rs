@@ -0,0 +1,516 @@
+//! An append-only history of generation runs, stored as JSON lines in the
+//! data directory (`~/.local/share/imgen/history.jsonl`).
+
+use anyhow::{bail, Context};
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{config, record::RunRecord};
+
+const HISTORY_FILE_NAME: &str = "history.jsonl";
+
+/// How many recent runs [`typical_duration`] considers, so it follows changes
+/// in the API's speed
+const TYPICAL_DURATION_RUNS: usize = 20;
+
+/// The fewest runs [`typical_duration`] will estimate from
+const MIN_TYPICAL_DURATION_RUNS: usize = 3;
+
+/// How many hex digits of an image's SHA-256 make its history ID
+pub const ID_LEN: usize = 8;
+
+/// The shortest SHA-256 prefix [`find_image`] accepts as an ID
+const MIN_ID_LEN: usize = 4;
+
+/// Gets the path to the history file.
+///
+/// Returns `None` if the data directory cannot be determined.
+pub fn history_path() -> Option<PathBuf> {
+    let mut path = config::data_dir()?;
+    path.push(HISTORY_FILE_NAME);
+    Some(path)
+}
+
+/// Appends a run to the history file.
+///
+/// Image paths are made absolute, so the record stays useful regardless of
+/// where imgen was run from.
+pub fn append(record: &RunRecord) -> anyhow::Result<()> {
+    let path =
+        history_path().context("Could not determine history location")?;
+    let mut record = record.clone();
+    for image in &mut record.images {
+        if let Some(image_path) = &image.path {
+            image.path = Some(std::path::absolute(image_path)?);
+        }
+    }
+    append_to_path(&path, &record)
+}
+
+/// Appends a run to a specific history file.
+fn append_to_path(path: &Path, record: &RunRecord) -> anyhow::Result<()> {
+    if let Some(parent_dir) = path.parent() {
+        fs::create_dir_all(parent_dir)?;
+    }
+
+    let mut line =
+        serde_json::to_string(record).expect("Failed to serialize run record");
+    line.push('\n');
+
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| format!("Failed to open: {}", path.display()))?;
+    file.write_all(line.as_bytes())
+        .with_context(|| format!("Failed to write to: {}", path.display()))?;
+
+    debug!("Recorded run in history: {}", path.display());
+    Ok(())
+}
+
+/// Loads all runs from the history file. A missing history is empty.
+pub fn load() -> anyhow::Result<Vec<RunRecord>> {
+    let path =
+        history_path().context("Could not determine history location")?;
+    load_from_path(&path)
+}
+
+/// Loads all runs from a specific history file, skipping malformed lines.
+fn load_from_path(path: &Path) -> anyhow::Result<Vec<RunRecord>> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Vec::new())
+        }
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to read: {}", path.display()))
+        }
+    };
+
+    let mut records = Vec::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(record) => records.push(record),
+            Err(err) => {
+                warn!(
+                    "Skipping malformed history entry (line {}): {err}",
+                    i + 1
+                )
+            }
+        }
+    }
+    Ok(records)
+}
+
+/// The hex SHA-256 of an image, as recorded in the history.
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex(&Sha256::digest(bytes))
+}
+
+/// Like [`sha256_hex`], but for a file too large to read into memory.
+pub fn sha256_hex_reader(mut reader: impl Read) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(hex(&hasher.finalize())),
+            len => hasher.update(&buf[..len]),
+        }
+    }
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// For each SHA-256 in `hashes`, a previously saved image with those exact
+/// bytes, if it's still on disk.
+pub fn find_saved(hashes: &[String]) -> anyhow::Result<Vec<Option<PathBuf>>> {
+    Ok(find_saved_in(&load()?, hashes))
+}
+
+fn find_saved_in(
+    records: &[RunRecord],
+    hashes: &[String],
+) -> Vec<Option<PathBuf>> {
+    hashes
+        .iter()
+        .map(|hash| {
+            records
+                .iter()
+                .rev()
+                .flat_map(|record| &record.images)
+                .filter(|image| image.sha256.as_ref() == Some(hash))
+                .filter_map(|image| image.path.clone())
+                .find(|path| path.exists())
+        })
+        .collect()
+}
+
+/// The saved file of an image in the history, by ID: "last" for the most
+/// recent image, or a prefix of its SHA-256 (as listed by `imgen history
+/// list`).
+pub fn find_image(id: &str) -> anyhow::Result<PathBuf> {
+    find_image_in(&load()?, id)
+}
+
+fn find_image_in(records: &[RunRecord], id: &str) -> anyhow::Result<PathBuf> {
+    let mut images = records
+        .iter()
+        .rev()
+        .flat_map(|record| record.images.iter().rev());
+    let path = if id == "last" {
+        images
+            .find_map(|image| image.path.clone())
+            .context("No saved images in the history yet")?
+    } else {
+        if id.len() < MIN_ID_LEN || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!(
+                "Invalid history ID: {id} (use \"last\", or at least \
+                 {MIN_ID_LEN} hex digits from `imgen history list`)"
+            );
+        }
+        let id = id.to_ascii_lowercase();
+        let matches: Vec<_> = images
+            .filter(|image| {
+                image
+                    .sha256
+                    .as_ref()
+                    .is_some_and(|hash| hash.starts_with(&id))
+            })
+            .collect();
+        // The same image saved twice has the same ID, and either copy will do
+        if matches
+            .iter()
+            .any(|image| image.sha256 != matches[0].sha256)
+        {
+            bail!("History ID {id} matches several images; use more digits");
+        }
+        let saved: Vec<_> = matches
+            .iter()
+            .filter_map(|image| image.path.clone())
+            .collect();
+        if saved.is_empty() {
+            bail!("No saved image in the history with ID: {id}");
+        }
+        saved
+            .iter()
+            .find(|path| path.exists())
+            .unwrap_or(&saved[0])
+            .clone()
+    };
+    if !path.exists() {
+        bail!("{id} is no longer on disk at: {}", path.display());
+    }
+    Ok(path)
+}
+
+/// How long runs with this model, quality, and size usually take: the median
+/// of the most recent ones, if there are enough to go on.
+pub fn typical_duration(
+    model: &str,
+    quality: &str,
+    size: &str,
+) -> anyhow::Result<Option<Duration>> {
+    Ok(typical_duration_in(&load()?, model, quality, size))
+}
+
+fn typical_duration_in(
+    records: &[RunRecord],
+    model: &str,
+    quality: &str,
+    size: &str,
+) -> Option<Duration> {
+    let mut durations: Vec<f64> = records
+        .iter()
+        .rev()
+        .filter(|record| {
+            record.model == model
+                && record.quality.as_deref() == Some(quality)
+                && record.size.as_deref() == Some(size)
+        })
+        .filter_map(|record| record.duration)
+        .take(TYPICAL_DURATION_RUNS)
+        .collect();
+    if durations.len() < MIN_TYPICAL_DURATION_RUNS {
+        return None;
+    }
+    durations.sort_by(f64::total_cmp);
+    Some(Duration::from_secs_f64(durations[durations.len() / 2]))
+}
+
+/// The total estimated cost in USD of this month's runs (UTC).
+pub fn monthly_spend() -> anyhow::Result<f64> {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let start = month_start(now);
+    let spent = load()?
+        .iter()
+        .filter(|record| record.created >= start)
+        .map(|record| record.cost)
+        .sum();
+    Ok(spent)
+}
+
+/// The Unix timestamp of the start of the (UTC) month containing `timestamp`.
+fn month_start(timestamp: u64) -> u64 {
+    let days = timestamp / 86_400;
+    let (_, _, day) = civil_from_days(days);
+    (days - (day - 1)) * 86_400
+}
+
+/// The (UTC) month containing `timestamp`, formatted as "YYYY-MM".
+pub fn month_label(timestamp: u64) -> String {
+    let (year, month, _) = civil_from_days(timestamp / 86_400);
+    format!("{year:04}-{month:02}")
+}
+
+/// Convert days since the Unix epoch to a (year, month, day) date.
+///
+/// From Howard Hinnant's `civil_from_days`, restricted to dates after 1970.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::Usage,
+        record::{Environment, ImageRecord},
+    };
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_append_and_load() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("nested").join(HISTORY_FILE_NAME);
+        assert!(load_from_path(&path).unwrap().is_empty());
+
+        let usage: Usage = serde_json::from_str(
+            r#"{"total_tokens":2,"input_tokens":1,"output_tokens":1,
+                "input_tokens_details":{"text_tokens":1,"image_tokens":0}}"#,
+        )
+        .unwrap();
+        let record = RunRecord {
+            created: 1_700_000_000,
+            model: "gpt-image-1".to_string(),
+            prompt: "a cat".to_string(),
+            original_prompt: None,
+            images: vec![ImageRecord {
+                path: Some(PathBuf::from("/tmp/cat.png")),
+                revised_prompt: None,
+                alt_text: None,
+                sha256: None,
+                ipfs_cid: None,
+                signature: None,
+                cost: 0.25,
+            }],
+            usage,
+            cost: 0.25,
+            size: None,
+            quality: None,
+            duration: None,
+            environment: Some(Environment {
+                version: "0.1.0".to_string(),
+                provider: "openai".to_string(),
+                git_commit: None,
+                hostname: Some("studio".to_string()),
+            }),
+        };
+        append_to_path(&path, &record).unwrap();
+        append_to_path(&path, &record).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"not json\n")
+            .unwrap();
+
+        let records = load_from_path(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].prompt, "a cat");
+        assert_eq!(records[1].images[0].cost, 0.25);
+        let environment = records[1].environment.as_ref().unwrap();
+        assert_eq!(environment.provider, "openai");
+        assert_eq!(environment.hostname.as_deref(), Some("studio"));
+    }
+
+    #[test]
+    fn test_find_saved_in() {
+        let temp_dir = tempdir().unwrap();
+        let saved = temp_dir.path().join("cat.png");
+        fs::write(&saved, b"cat").unwrap();
+        let hash = sha256_hex(b"cat");
+        assert_eq!(hash.len(), 64);
+
+        let usage: Usage = serde_json::from_str(
+            r#"{"total_tokens":2,"input_tokens":1,"output_tokens":1,
+                "input_tokens_details":{"text_tokens":1,"image_tokens":0}}"#,
+        )
+        .unwrap();
+        let image = |path: &Path| ImageRecord {
+            path: Some(path.to_path_buf()),
+            revised_prompt: None,
+            alt_text: None,
+            sha256: Some(hash.clone()),
+            ipfs_cid: None,
+            signature: None,
+            cost: 0.25,
+        };
+        let record = RunRecord {
+            created: 1_700_000_000,
+            model: "gpt-image-1".to_string(),
+            prompt: "a cat".to_string(),
+            original_prompt: None,
+            // The deleted copy is skipped
+            images: vec![image(&saved), image(&temp_dir.path().join("gone"))],
+            usage,
+            cost: 0.5,
+            size: None,
+            quality: None,
+            duration: None,
+            environment: None,
+        };
+
+        let found = find_saved_in(&[record], &[hash, sha256_hex(b"dog")]);
+        assert_eq!(found, [Some(saved), None]);
+    }
+
+    #[test]
+    fn test_find_image_in() {
+        let temp_dir = tempdir().unwrap();
+        let cat = temp_dir.path().join("cat.png");
+        let dog = temp_dir.path().join("dog.png");
+        fs::write(&cat, b"cat").unwrap();
+        fs::write(&dog, b"dog").unwrap();
+
+        let usage: Usage = serde_json::from_str(
+            r#"{"total_tokens":2,"input_tokens":1,"output_tokens":1,
+                "input_tokens_details":{"text_tokens":1,"image_tokens":0}}"#,
+        )
+        .unwrap();
+        let image = |path: &Path, bytes: &[u8]| ImageRecord {
+            path: Some(path.to_path_buf()),
+            revised_prompt: None,
+            alt_text: None,
+            sha256: Some(sha256_hex(bytes)),
+            ipfs_cid: None,
+            signature: None,
+            cost: 0.25,
+        };
+        let record = |images| RunRecord {
+            created: 1_700_000_000,
+            model: "gpt-image-1".to_string(),
+            prompt: "a pet".to_string(),
+            original_prompt: None,
+            images,
+            usage: usage.clone(),
+            cost: 0.25,
+            size: None,
+            quality: None,
+            duration: None,
+            environment: None,
+        };
+        let records = [
+            record(vec![image(&cat, b"cat")]),
+            record(vec![image(&temp_dir.path().join("gone"), b"gone")]),
+            record(vec![image(&dog, b"dog")]),
+        ];
+
+        assert!(find_image_in(&[], "last").is_err());
+        assert_eq!(find_image_in(&records, "last").unwrap(), dog);
+        let cat_id = &sha256_hex(b"cat")[..ID_LEN];
+        assert_eq!(find_image_in(&records, cat_id).unwrap(), cat);
+        assert_eq!(
+            find_image_in(&records, &cat_id.to_uppercase()).unwrap(),
+            cat
+        );
+        let gone_id = &sha256_hex(b"gone")[..ID_LEN];
+        let err = find_image_in(&records, gone_id).unwrap_err();
+        assert!(err.to_string().contains("no longer on disk"), "{err}");
+        assert!(find_image_in(&records, "0000000000").is_err());
+        assert!(find_image_in(&records, "cat.png").is_err());
+        assert!(find_image_in(&records, "abc").is_err());
+    }
+
+    #[test]
+    fn test_typical_duration_in() {
+        let usage: Usage = serde_json::from_str(
+            r#"{"total_tokens":2,"input_tokens":1,"output_tokens":1,
+                "input_tokens_details":{"text_tokens":1,"image_tokens":0}}"#,
+        )
+        .unwrap();
+        let record = |quality: &str, duration: Option<f64>| RunRecord {
+            created: 1_700_000_000,
+            model: "gpt-image-1".to_string(),
+            prompt: "a cat".to_string(),
+            original_prompt: None,
+            images: Vec::new(),
+            usage: usage.clone(),
+            cost: 0.25,
+            size: Some("1536x1024".to_string()),
+            quality: Some(quality.to_string()),
+            duration,
+            environment: None,
+        };
+        let typical = |records: &[RunRecord]| {
+            typical_duration_in(records, "gpt-image-1", "high", "1536x1024")
+        };
+
+        // Too few runs to go on
+        let mut records =
+            vec![record("high", Some(90.0)), record("high", None)];
+        records.push(record("low", Some(20.0)));
+        records.push(record("high", Some(100.0)));
+        assert_eq!(typical(&records), None);
+
+        records.push(record("high", Some(95.0)));
+        assert_eq!(typical(&records), Some(Duration::from_secs(95)));
+    }
+
+    #[test]
+    fn test_month_start() {
+        // 2023-11-14 -> 2023-11-01
+        assert_eq!(month_start(1_700_000_000), 1_698_796_800);
+        // 2024-03-01T00:00:00Z is its own month start
+        assert_eq!(month_start(1_709_251_205), 1_709_251_200);
+        // A second earlier is still February (leap year)
+        assert_eq!(month_start(1_709_251_199), 1_706_745_600);
+        assert_eq!(month_start(0), 0);
+    }
+
+    #[test]
+    fn test_month_label() {
+        assert_eq!(month_label(0), "1970-01");
+        assert_eq!(month_label(1_700_000_000), "2023-11");
+        assert_eq!(month_label(1_709_251_199), "2024-02");
+        assert_eq!(month_label(1_709_251_200), "2024-03");
+        // 2025-01-01T00:00:00Z
+        assert_eq!(month_label(1_735_689_600), "2025-01");
+    }
+}
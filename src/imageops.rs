@@ -0,0 +1,711 @@
+//! Local image post-processing applied to generated outputs.
+
+use anyhow::Context;
+use image::{
+    codecs::jpeg::JpegEncoder, imageops::FilterType, DynamicImage, ImageFormat,
+    Rgb, RgbImage, Rgba, RgbaImage,
+};
+use log::{debug, info};
+use std::{collections::HashMap, io::Cursor, str::FromStr};
+
+use crate::metadata;
+
+/// Local post-processing applied to each decoded image before saving.
+#[derive(Default)]
+pub struct PostProcess {
+    /// Scale edits back up to the original input resolution.
+    pub composite_back: Option<CompositeBack>,
+    /// Blur any faces, for privacy.
+    pub blur_faces: bool,
+    /// Blend away the wrap-around seams of images that don't tile.
+    pub fix_seams: bool,
+    /// Remap every pixel to the nearest of these colors.
+    pub palette: Option<Palette>,
+    /// Images (e.g. a logo watermark or QR code) to composite on top, in
+    /// order.
+    pub overlays: Vec<Overlay>,
+    /// Remove C2PA content credentials instead of carrying them over to
+    /// re-encoded images.
+    pub strip_c2pa: bool,
+    /// The `--output-compression` to re-encode at, or `None` for the
+    /// encoder's default.
+    pub compression: Option<u8>,
+}
+
+impl PostProcess {
+    /// Whether there are no steps, so images needn't be touched at all.
+    pub fn is_empty(&self) -> bool {
+        !self.reencodes() && !self.strip_c2pa
+    }
+
+    /// Whether any step decodes and re-encodes the image.
+    fn reencodes(&self) -> bool {
+        self.composite_back.is_some()
+            || self.blur_faces
+            || self.fix_seams
+            || self.palette.is_some()
+            || !self.overlays.is_empty()
+    }
+
+    /// Apply the configured post-processing steps to an encoded image. It's
+    /// decoded once, and re-encoded once at the end, in the same format.
+    pub fn apply(&self, bytes: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let edited = if self.reencodes() {
+            self.apply_steps(&bytes)?
+        } else {
+            None
+        };
+        let Some((image, format)) = edited else {
+            if self.strip_c2pa {
+                return metadata::write_c2pa(&bytes, None)
+                    .context("Failed to remove C2PA content credentials");
+            }
+            return Ok(bytes);
+        };
+
+        let out = match self.compression {
+            Some(compression) => {
+                encode_with_compression(&image, format, compression)?
+            }
+            None => encode(&image, format)?,
+        };
+        // Re-encoding drops the C2PA manifest, so carry it over
+        if self.strip_c2pa {
+            return Ok(out);
+        }
+        match metadata::read_c2pa(&bytes)
+            .context("Failed to read C2PA content credentials")?
+        {
+            Some(c2pa) => metadata::write_c2pa(&out, Some(&c2pa))
+                .context("Failed to write C2PA content credentials"),
+            None => Ok(out),
+        }
+    }
+
+    /// Decode the image and apply the steps that change its pixels. Returns
+    /// `None` if none of them did.
+    fn apply_steps(
+        &self,
+        bytes: &[u8],
+    ) -> anyhow::Result<Option<(DynamicImage, ImageFormat)>> {
+        let format = image::guess_format(bytes)
+            .context("Failed to detect the image format")?;
+        let mut image = image::load_from_memory(bytes)
+            .context("Failed to decode the image")?;
+        let mut changed = false;
+
+        if let Some(composite_back) = &self.composite_back {
+            image = composite_back
+                .apply(&image)
+                .context("Failed to composite the edited image back")?;
+            changed = true;
+        }
+
+        if self.blur_faces {
+            let faces =
+                crate::faces::detect(&image).context("Failed to blur faces")?;
+            if !faces.is_empty() {
+                info!(
+                    "--blur-faces: blurred {} face(s) in the output",
+                    faces.len()
+                );
+                image = crate::faces::blur(&image, &faces);
+                changed = true;
+            }
+        }
+
+        if self.fix_seams {
+            let score = seam_score(&image);
+            if score <= SEAM_THRESHOLD {
+                debug!("--fix-seams: already tiles (seam score {score:.2})");
+            } else {
+                info!("--fix-seams: blending seams (seam score {score:.2})");
+                image = fix_seams(&image);
+                changed = true;
+            }
+        }
+
+        // After any other changes, so nothing reintroduces off-palette colors
+        if let Some(palette) = &self.palette {
+            image = palette.remap(&image);
+            changed = true;
+        }
+
+        // Overlays keep their own colors
+        for overlay in &self.overlays {
+            image = overlay.apply(&image);
+            changed = true;
+        }
+
+        Ok(changed.then_some((image, format)))
+    }
+}
+
+/// The original full-resolution edit input, used to composite the edited
+/// result back at the original dimensions.
+pub struct CompositeBack {
+    /// The encoded bytes of the first `--image` input.
+    pub original: Vec<u8>,
+    /// The encoded bytes of the `--mask` input, if any.
+    pub mask: Option<Vec<u8>>,
+}
+
+impl CompositeBack {
+    /// Scale the edited image back up to the original image's dimensions.
+    ///
+    /// An edit with a different aspect ratio is scaled to cover the original
+    /// and centered, cropping the difference, rather than stretched. With a
+    /// mask, only the masked (transparent) areas are taken from the edited
+    /// image; everything else keeps the original full-resolution pixels.
+    pub fn apply(&self, edited: &DynamicImage) -> anyhow::Result<DynamicImage> {
+        let original = image::load_from_memory(&self.original)
+            .context("Failed to decode the original input image")?;
+
+        let (width, height) = (original.width(), original.height());
+        let original_aspect = width as f64 / height as f64;
+        let edited_aspect = edited.width() as f64 / edited.height() as f64;
+        if (original_aspect / edited_aspect - 1.0).abs() > 0.01 {
+            info!(
+                "--composite-back: edited image aspect ratio ({}x{}) differs \
+                 from the original ({width}x{height}); cropping the edges",
+                edited.width(),
+                edited.height(),
+            );
+        }
+
+        let edited = edited
+            .resize_to_fill(width, height, FilterType::Lanczos3)
+            .into_rgba8();
+
+        let composite = match &self.mask {
+            None => edited,
+            Some(mask) => {
+                let mask = image::load_from_memory(mask)
+                    .context("Failed to decode the mask image")?
+                    .resize_exact(width, height, FilterType::Triangle)
+                    .into_rgba8();
+                let mut out = original.into_rgba8();
+                for ((out_px, edit_px), mask_px) in
+                    out.pixels_mut().zip(edited.pixels()).zip(mask.pixels())
+                {
+                    // Transparent mask pixels mark the area to take from the
+                    // edited image.
+                    let weight = 1.0 - mask_px[3] as f32 / 255.0;
+                    for c in 0..4 {
+                        let orig = out_px[c] as f32;
+                        let edit = edit_px[c] as f32;
+                        out_px[c] =
+                            (orig + (edit - orig) * weight).round() as u8;
+                    }
+                }
+                out
+            }
+        };
+
+        Ok(DynamicImage::ImageRgba8(composite))
+    }
+}
+
+/// The width and height of an encoded image, without decoding it.
+pub fn dimensions(bytes: &[u8]) -> anyhow::Result<(u32, u32)> {
+    image::ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()?
+        .into_dimensions()
+        .context("Failed to read the image dimensions")
+}
+
+/// Encode an image into the given format, with a compression level (0-100).
+///
+/// Only jpeg supports lossy compression; webp is always encoded losslessly.
+pub fn encode_with_compression(
+    image: &DynamicImage,
+    format: ImageFormat,
+    compression: u8,
+) -> anyhow::Result<Vec<u8>> {
+    if format != ImageFormat::Jpeg {
+        return encode(image, format);
+    }
+    let mut out = Vec::new();
+    let quality = compression.clamp(1, 100);
+    JpegEncoder::new_with_quality(&mut out, quality)
+        .encode_image(&image.to_rgb8())
+        .context("Failed to encode Jpeg image")?;
+    Ok(out)
+}
+
+/// Flatten any transparency onto a white background.
+pub fn flatten(image: &DynamicImage) -> RgbImage {
+    let mut out = RgbImage::new(image.width(), image.height());
+    for (src, dst) in image.to_rgba8().pixels().zip(out.pixels_mut()) {
+        let [r, g, b, a] = src.0;
+        let blend = |c: u8| {
+            let (c, a) = (c as u16, a as u16);
+            ((c * a + 255 * (255 - a) + 127) / 255) as u8
+        };
+        *dst = Rgb([blend(r), blend(g), blend(b)]);
+    }
+    out
+}
+
+/// Lay out rows of images side by side, each scaled to a common `height`,
+/// with a white `gap` between them.
+pub fn montage(rows: &[&[DynamicImage]], height: u32, gap: u32) -> RgbImage {
+    let scaled: Vec<Vec<RgbImage>> = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|image| {
+                    let width = (image.width() as u64 * height as u64
+                        / image.height().max(1) as u64)
+                        as u32;
+                    let resized = image.resize_exact(
+                        width.max(1),
+                        height,
+                        FilterType::Lanczos3,
+                    );
+                    flatten(&resized)
+                })
+                .collect()
+        })
+        .collect();
+
+    let row_width = |row: &Vec<RgbImage>| {
+        let gaps = gap * row.len().saturating_sub(1) as u32;
+        row.iter().map(|image| image.width()).sum::<u32>() + gaps
+    };
+    let width = scaled.iter().map(row_width).max().unwrap_or(0);
+    let num_rows = scaled.len() as u32;
+    let total_height = height * num_rows + gap * num_rows.saturating_sub(1);
+    let white = Rgb([255, 255, 255]);
+    let mut out = RgbImage::from_pixel(width, total_height, white);
+    for (row_idx, row) in scaled.iter().enumerate() {
+        let y = row_idx as u32 * (height + gap);
+        let mut x = 0;
+        for image in row {
+            image::imageops::replace(&mut out, image, x as i64, y as i64);
+            x += image.width() + gap;
+        }
+    }
+    out
+}
+
+/// Above this [`seam_score`], an image visibly doesn't tile.
+pub const SEAM_THRESHOLD: f64 = 2.0;
+
+/// How much more the pixels differ across the wrap-around edges (left vs
+/// right, top vs bottom) than between neighboring pixels just inside them.
+/// Seamless textures score around 1.
+pub fn seam_score(image: &DynamicImage) -> f64 {
+    let image = image.to_rgb8();
+    let (width, height) = image.dimensions();
+    if width < 3 || height < 3 {
+        return 1.0;
+    }
+    let diff = |(x1, y1): (u32, u32), (x2, y2): (u32, u32)| {
+        let (a, b) = (image.get_pixel(x1, y1), image.get_pixel(x2, y2));
+        (0..3).map(|c| a[c].abs_diff(b[c]) as u64).sum::<u64>()
+    };
+
+    let (mut seam, mut inside) = (0, 0);
+    for y in 0..height {
+        seam += diff((width - 1, y), (0, y));
+        inside += diff((0, y), (1, y)) + diff((width - 2, y), (width - 1, y));
+    }
+    for x in 0..width {
+        seam += diff((x, height - 1), (x, 0));
+        inside += diff((x, 0), (x, 1)) + diff((x, height - 2), (x, height - 1));
+    }
+    // `inside` covers twice as many pixel pairs
+    2.0 * seam as f64 / inside.max(1) as f64
+}
+
+/// Make an image tile seamlessly by blending it with a copy offset by half
+/// its size, weighted so each edge comes from the offset copy (whose edges
+/// wrap continuously) and the middle from the original.
+pub fn fix_seams(image: &DynamicImage) -> DynamicImage {
+    let image = image.to_rgba8();
+    let horizontal = offset_blend(&image, true);
+    DynamicImage::ImageRgba8(offset_blend(&horizontal, false))
+}
+
+/// One axis of [`fix_seams`]. Weights only vary along the blended axis, so
+/// the other axis keeps wrapping continuously.
+fn offset_blend(image: &RgbaImage, horizontal: bool) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let len = if horizontal { width } else { height };
+    RgbaImage::from_fn(width, height, |x, y| {
+        let pos = if horizontal { x } else { y };
+        // 0 at the edges, 1 in the middle
+        let weight = 1.0 - (2.0 * (pos as f32 + 0.5) / len as f32 - 1.0).abs();
+        let offset = if horizontal {
+            image.get_pixel((x + width / 2) % width, y)
+        } else {
+            image.get_pixel(x, (y + height / 2) % height)
+        };
+        let orig = image.get_pixel(x, y);
+        Rgba(std::array::from_fn(|c| {
+            let (orig, offset) = (orig[c] as f32, offset[c] as f32);
+            (offset + (orig - offset) * weight).round() as u8
+        }))
+    })
+}
+
+/// A fixed set of colors to remap images to, e.g. for pixel art or brand
+/// colors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette(pub Vec<Rgb<u8>>);
+
+impl FromStr for Palette {
+    type Err = anyhow::Error;
+
+    /// Parse comma-separated hex colors, like `#112233,#aabbcc`.
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let colors = s
+            .split(',')
+            .map(str::trim)
+            .filter(|color| !color.is_empty())
+            .map(parse_hex_color)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if colors.is_empty() {
+            anyhow::bail!("The palette has no colors");
+        }
+        Ok(Self(colors))
+    }
+}
+
+fn parse_hex_color(color: &str) -> anyhow::Result<Rgb<u8>> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    let value = (hex.len() == 6)
+        .then(|| u32::from_str_radix(hex, 16).ok())
+        .flatten()
+        .with_context(|| {
+            format!("Invalid color {color:?}, expected #rrggbb")
+        })?;
+    let [_, r, g, b] = value.to_be_bytes();
+    Ok(Rgb([r, g, b]))
+}
+
+impl Palette {
+    /// Pick up to `max_colors` representative colors from an image, by median
+    /// cut over its opaque pixels.
+    pub fn extract(image: &DynamicImage, max_colors: usize) -> Self {
+        let pixels: Vec<[u8; 3]> = image
+            .to_rgba8()
+            .pixels()
+            .filter(|px| px[3] >= 128)
+            .map(|px| [px[0], px[1], px[2]])
+            .collect();
+        if pixels.is_empty() {
+            return Self(vec![Rgb([255, 255, 255])]);
+        }
+
+        let mut boxes = vec![pixels];
+        while boxes.len() < max_colors {
+            // Split the box with the widest channel range at its median
+            let (idx, channel, range) = boxes
+                .iter()
+                .enumerate()
+                .flat_map(|(idx, pixels)| {
+                    (0..3).map(move |c| {
+                        let values = pixels.iter().map(|px| px[c]);
+                        let range = values.clone().max().unwrap()
+                            - values.min().unwrap();
+                        (idx, c, range)
+                    })
+                })
+                .max_by_key(|&(_, _, range)| range)
+                .unwrap();
+            if range == 0 {
+                break;
+            }
+            let mut pixels = boxes.swap_remove(idx);
+            pixels.sort_unstable_by_key(|px| px[channel]);
+            let upper = pixels.split_off(pixels.len() / 2);
+            boxes.push(pixels);
+            boxes.push(upper);
+        }
+
+        let colors = boxes
+            .iter()
+            .map(|pixels| {
+                let n = pixels.len() as u64;
+                Rgb(std::array::from_fn(|c| {
+                    let sum: u64 = pixels.iter().map(|px| px[c] as u64).sum();
+                    ((sum + n / 2) / n) as u8
+                }))
+            })
+            .collect();
+        Self(colors)
+    }
+
+    /// The palette color closest to `color`.
+    fn nearest(&self, color: [u8; 3]) -> Rgb<u8> {
+        let distance = |Rgb(c): &Rgb<u8>| {
+            (0..3)
+                .map(|i| (c[i] as i32 - color[i] as i32).pow(2))
+                .sum::<i32>()
+        };
+        *self.0.iter().min_by_key(|c| distance(c)).unwrap()
+    }
+
+    /// Remap every pixel to its nearest palette color, keeping alpha.
+    pub fn remap(&self, image: &DynamicImage) -> DynamicImage {
+        let mut out = image.to_rgba8();
+        let mut cache = HashMap::new();
+        for px in out.pixels_mut() {
+            let [r, g, b, a] = px.0;
+            let Rgb([r, g, b]) = *cache
+                .entry([r, g, b])
+                .or_insert_with(|| self.nearest([r, g, b]));
+            *px = Rgba([r, g, b, a]);
+        }
+        DynamicImage::ImageRgba8(out)
+    }
+}
+
+/// An image to composite onto each output.
+pub struct Overlay {
+    pub image: DynamicImage,
+    /// The top-left corner, in output pixels. `None` for the bottom-right
+    /// corner, with a small margin.
+    pub position: Option<(u32, u32)>,
+    /// The overlay width, as a fraction of the output width. `None` to keep
+    /// its own size.
+    pub scale: Option<f32>,
+}
+
+impl Overlay {
+    /// A logo stamped in the bottom-right corner, a sixth of the width.
+    pub fn watermark(logo: DynamicImage) -> Self {
+        Self {
+            image: logo,
+            position: None,
+            scale: Some(1.0 / 6.0),
+        }
+    }
+
+    /// Composite the overlay onto `image`.
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let over = match self.scale {
+            Some(scale) => {
+                let width =
+                    ((image.width() as f32 * scale).round() as u32).max(1);
+                let height = (self.image.height() as u64 * width as u64
+                    / self.image.width().max(1) as u64)
+                    .max(1) as u32;
+                self.image.resize_exact(width, height, FilterType::Lanczos3)
+            }
+            None => self.image.clone(),
+        };
+        let (x, y) = match self.position {
+            Some((x, y)) => (x as i64, y as i64),
+            None => {
+                let margin = (image.width() / 40) as i64;
+                (
+                    image.width() as i64 - over.width() as i64 - margin,
+                    image.height() as i64 - over.height() as i64 - margin,
+                )
+            }
+        };
+
+        let mut out = image.to_rgba8();
+        image::imageops::overlay(&mut out, &over.to_rgba8(), x, y);
+        DynamicImage::ImageRgba8(out)
+    }
+}
+
+/// Encode an image into the given format.
+pub fn encode(
+    image: &DynamicImage,
+    format: ImageFormat,
+) -> anyhow::Result<Vec<u8>> {
+    let mut out = Cursor::new(Vec::new());
+    let result = match format {
+        // JPEG doesn't support an alpha channel
+        ImageFormat::Jpeg => {
+            DynamicImage::ImageRgb8(image.to_rgb8()).write_to(&mut out, format)
+        }
+        _ => image.write_to(&mut out, format),
+    };
+    result.with_context(|| format!("Failed to encode {format:?} image"))?;
+    Ok(out.into_inner())
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(image: RgbaImage) -> Vec<u8> {
+        encode(&DynamicImage::ImageRgba8(image), ImageFormat::Png).unwrap()
+    }
+
+    #[test]
+    fn test_composite_back_with_mask() {
+        let red = Rgba([255, 0, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        let original = png(RgbaImage::from_pixel(8, 8, red));
+        let edited = png(RgbaImage::from_pixel(4, 4, blue));
+        // Left half transparent (edit), right half opaque (keep)
+        let mask = png(RgbaImage::from_fn(4, 4, |x, _| {
+            Rgba([0, 0, 0, if x < 2 { 0 } else { 255 }])
+        }));
+
+        let composite = CompositeBack {
+            original,
+            mask: Some(mask),
+        };
+        let edited = image::load_from_memory(&edited).unwrap();
+        let out = composite.apply(&edited).unwrap().into_rgba8();
+        assert_eq!(out.dimensions(), (8, 8));
+        assert_eq!(*out.get_pixel(0, 4), blue);
+        assert_eq!(*out.get_pixel(7, 4), red);
+    }
+
+    #[test]
+    fn test_composite_back_crops() {
+        let (green, blue) = (Rgba([0, 255, 0, 255]), Rgba([0, 0, 255, 255]));
+        let original = png(RgbaImage::new(4, 4));
+        // A wider edit, blue in the middle: the green sides are cropped
+        // rather than squeezed in
+        let edited = RgbaImage::from_fn(8, 4, |x, _| {
+            if (2..6).contains(&x) {
+                blue
+            } else {
+                green
+            }
+        });
+        let composite = CompositeBack {
+            original,
+            mask: None,
+        };
+        let out = composite
+            .apply(&DynamicImage::ImageRgba8(edited))
+            .unwrap()
+            .into_rgba8();
+        assert_eq!(out.dimensions(), (4, 4));
+        assert!(out.pixels().all(|pixel| *pixel == blue));
+    }
+
+    #[test]
+    fn test_seams() {
+        // A horizontal gradient jumps from white back to black at the wrap
+        let gradient = RgbaImage::from_fn(32, 32, |x, _| {
+            let v = (x * 8) as u8;
+            Rgba([v, v, v, 255])
+        });
+        let gradient = DynamicImage::ImageRgba8(gradient);
+        assert!(seam_score(&gradient) > SEAM_THRESHOLD);
+
+        let fixed = fix_seams(&gradient);
+        assert_eq!((fixed.width(), fixed.height()), (32, 32));
+        assert!(seam_score(&fixed) <= SEAM_THRESHOLD);
+    }
+
+    #[test]
+    fn test_palette() {
+        let palette: Palette = "#000000, #FF0000,#0000ff".parse().unwrap();
+        assert_eq!(palette.0[1], Rgb([255, 0, 0]));
+        assert!("#12345".parse::<Palette>().is_err());
+        assert!("red".parse::<Palette>().is_err());
+        assert!("".parse::<Palette>().is_err());
+
+        let image =
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 1, |x, _| {
+                [Rgba([200, 30, 20, 255]), Rgba([10, 10, 10, 128])]
+                    [x as usize % 2]
+            }));
+        let out = palette.remap(&image).into_rgba8();
+        assert_eq!(*out.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+        assert_eq!(*out.get_pixel(1, 0), Rgba([0, 0, 0, 128]));
+
+        // Two flat colors extract back exactly
+        let extracted = Palette::extract(&image, 8);
+        assert_eq!(extracted.0.len(), 2);
+        assert!(extracted.0.contains(&Rgb([200, 30, 20])));
+    }
+
+    #[test]
+    fn test_watermark() {
+        let white = Rgba([255, 255, 255, 255]);
+        let image =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(120, 60, white));
+        let logo = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            10,
+            5,
+            Rgba([255, 0, 0, 255]),
+        ));
+
+        // 20x10 logo, 3px from the bottom-right corner
+        let out = Overlay::watermark(logo.clone()).apply(&image).into_rgba8();
+        assert_eq!(out.dimensions(), (120, 60));
+        assert_eq!(*out.get_pixel(97, 47), Rgba([255, 0, 0, 255]));
+        assert_eq!(*out.get_pixel(96, 47), white);
+        assert_eq!(*out.get_pixel(97, 57), white);
+
+        // Positioned at its own size, clipped at the edge
+        let overlay = Overlay {
+            image: logo,
+            position: Some((115, 0)),
+            scale: None,
+        };
+        let out = overlay.apply(&image).into_rgba8();
+        assert_eq!(*out.get_pixel(115, 4), Rgba([255, 0, 0, 255]));
+        assert_eq!(*out.get_pixel(115, 5), white);
+        assert_eq!(*out.get_pixel(114, 0), white);
+    }
+
+    #[test]
+    fn test_post_process_compression() {
+        let noise = RgbaImage::from_fn(64, 64, |x, y| {
+            let v = (x * 31 + y * 17) as u8 ^ (x * y) as u8;
+            Rgba([v, v.wrapping_mul(3), 255 - v, 255])
+        });
+        let jpeg = encode(&DynamicImage::ImageRgba8(noise), ImageFormat::Jpeg)
+            .unwrap();
+        let palette: Palette = "#000000,#ffffff,#ff0000".parse().unwrap();
+        let process = |compression| {
+            let post_process = PostProcess {
+                palette: Some(palette.clone()),
+                overlays: vec![Overlay::watermark(DynamicImage::new_rgba8(
+                    4, 4,
+                ))],
+                compression,
+                ..PostProcess::default()
+            };
+            post_process.apply(jpeg.clone()).unwrap()
+        };
+
+        // Every step is applied before the one encode, at the compression
+        let (low, high) = (process(Some(10)), process(Some(95)));
+        assert_eq!(image::guess_format(&low).unwrap(), ImageFormat::Jpeg);
+        assert!(low.len() < high.len(), "{} < {}", low.len(), high.len());
+
+        // Nothing to do leaves the image untouched
+        assert_eq!(PostProcess::default().apply(jpeg.clone()).unwrap(), jpeg);
+    }
+
+    #[test]
+    fn test_montage() {
+        let red = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            20,
+            10,
+            Rgba([255, 0, 0, 255]),
+        ));
+        let clear = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            10,
+            10,
+            Rgba([0; 4]),
+        ));
+        let (top, bottom) = ([red.clone(), red], [clear]);
+
+        // Scaled to 5px high: 10 + 2 + 10 wide on top, 5 wide below
+        let out = montage(&[&top, &bottom], 5, 2);
+        assert_eq!(out.dimensions(), (22, 12));
+        assert_eq!(*out.get_pixel(11, 2), Rgb([255, 255, 255])); // gap
+        assert_eq!(*out.get_pixel(14, 2), Rgb([255, 0, 0]));
+        // Transparency is flattened onto white
+        assert_eq!(*out.get_pixel(2, 9), Rgb([255, 255, 255]));
+    }
+}
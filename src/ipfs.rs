@@ -0,0 +1,89 @@
+//! Pinning generated images to IPFS, for `--output ipfs://`.
+//!
+//! Images are uploaded to the Pinata pinning service, which keeps them
+//! available on the IPFS network. The images are still saved locally too.
+
+use anyhow::{bail, Context};
+use log::debug;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::Path;
+
+use crate::{client, config::Config, multipart, redact};
+
+const PIN_FILE_URL: &str = "https://api.pinata.cloud/pinning/pinFileToIPFS";
+
+/// The environment variable checked for a Pinata API JWT before the config
+/// file
+pub const TOKEN_ENV: &str = "PINATA_JWT";
+
+/// A connection to the pinning service.
+pub struct Pinata {
+    agent: ureq::Agent,
+    jwt: String,
+}
+
+#[derive(Deserialize)]
+struct PinResponse {
+    #[serde(rename = "IpfsHash")]
+    cid: String,
+}
+
+impl Pinata {
+    /// Find the API token, so a missing one fails before we generate
+    /// anything.
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let jwt = std::env::var(TOKEN_ENV)
+            .ok()
+            .or_else(|| config.pinata_jwt.clone())
+            .with_context(|| {
+                format!(
+                    "--output ipfs:// needs a Pinata API key (JWT); set the \
+                     `{TOKEN_ENV}` environment variable or \"pinata_jwt\" in \
+                     the config file"
+                )
+            })?;
+        redact::add_secret(&jwt);
+        Ok(Self {
+            agent: client::agent(),
+            jwt,
+        })
+    }
+
+    /// Upload and pin an image, returning its CID (v1).
+    pub fn pin(&self, filename: &Path, bytes: &[u8]) -> anyhow::Result<String> {
+        let name = filename.to_string_lossy();
+        let metadata = json!({ "name": name }).to_string();
+        let options = json!({ "cidVersion": 1 }).to_string();
+        let mut builder = multipart::Builder::new();
+        builder.add_file_bytes(
+            "file",
+            filename,
+            multipart::mime_from_bytes(bytes),
+            bytes,
+        );
+        builder.add_text("pinataMetadata", &metadata);
+        builder.add_text("pinataOptions", &options);
+        let body = builder.build();
+
+        let mut response = self
+            .agent
+            .post(PIN_FILE_URL)
+            .header("Authorization", format!("Bearer {}", self.jwt))
+            .header("Content-Type", &body.content_type)
+            .send(&body.body)
+            .context("Pinata request failed")?;
+        let status = response.status();
+        let text = response
+            .body_mut()
+            .read_to_string()
+            .context("Failed to read Pinata response")?;
+        if !status.is_success() {
+            debug!("Pinata response: {text}");
+            bail!("Failed to pin to IPFS: {status} {text}");
+        }
+        let pinned: PinResponse =
+            serde_json::from_str(&text).context("Invalid Pinata response")?;
+        Ok(pinned.cid)
+    }
+}
@@ -1,8 +1,33 @@
 mod api;
+mod audit;
+mod capabilities;
 mod cli;
 mod client;
+mod compress;
 mod config;
+mod control;
+mod discord;
+mod disk;
+mod events;
+mod faces;
+mod gemini;
+mod history;
+mod imageops;
+mod ipfs;
+mod metadata;
 mod multipart;
+mod pdf;
+mod pricing;
+mod record;
+mod redact;
+mod rescue;
+mod sdwebui;
+mod sftp;
+mod slack;
+mod stability;
+mod tokens;
+#[cfg(feature = "vectorize")]
+mod vectorize;
 
 use clap::Parser;
 use cli::Cli;
@@ -26,7 +51,9 @@ fn main() {
     // Wrap the logger so log messages and progress bars don't interfere with
     // each other.
     let progress = indicatif::MultiProgress::new();
-    indicatif_log_bridge::LogWrapper::new(progress.clone(), env_logger)
+    // Scrub any API keys or tokens from the output, too.
+    let logger = redact::Logger(env_logger);
+    indicatif_log_bridge::LogWrapper::new(progress.clone(), logger)
         .try_init()
         .unwrap();
 
@@ -0,0 +1,576 @@
+//! Reading and writing metadata embedded in png, jpeg, and webp containers.
+//!
+//! Generated images carry C2PA content credentials, which naive local
+//! re-encoding silently drops. This module lets us carry them (and any Exif or
+//! XMP metadata) over to a re-encoded image, or strip them, without a
+//! full-blown image metadata library.
+
+use anyhow::{bail, Context};
+
+use crate::multipart;
+
+pub mod c2pa;
+
+/// PNG chunk type holding a C2PA manifest store.
+const PNG_C2PA_CHUNK: &[u8; 4] = b"caBX";
+/// PNG chunk type holding raw Exif (TIFF) data.
+const PNG_EXIF_CHUNK: &[u8; 4] = b"eXIf";
+/// PNG textual chunk types.
+const PNG_TEXT_CHUNKS: [&[u8; 4]; 3] = [b"tEXt", b"zTXt", b"iTXt"];
+/// PNG `iTXt` keyword for an XMP packet.
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// JPEG APP1 marker, which holds Exif and XMP.
+const JPEG_APP1: u8 = 0xe1;
+/// JPEG APP11 marker, which holds JUMBF boxes (and so C2PA manifests).
+const JPEG_APP11: u8 = 0xeb;
+const JPEG_EXIF_HEADER: &[u8] = b"Exif\0\0";
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// WebP RIFF chunk holding a C2PA manifest store.
+const WEBP_C2PA_CHUNK: &[u8; 4] = b"C2PA";
+const WEBP_EXIF_CHUNK: &[u8; 4] = b"EXIF";
+const WEBP_XMP_CHUNK: &[u8; 4] = b"XMP ";
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Metadata that survives conversion between image containers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// Raw Exif data (a TIFF structure, without the JPEG "Exif\0\0" header).
+    pub exif: Option<Vec<u8>>,
+    /// An XMP packet (XML).
+    pub xmp: Option<Vec<u8>>,
+    /// The raw C2PA manifest store (a JUMBF superbox).
+    pub c2pa: Option<Vec<u8>>,
+    /// Other PNG textual chunks (`tEXt`, `zTXt`, `iTXt`) as `(type, data)`.
+    /// These only survive conversion to png.
+    pub png_text: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl Metadata {
+    /// Read all supported metadata from an encoded image.
+    pub fn read(bytes: &[u8]) -> anyhow::Result<Self> {
+        match multipart::mime_from_bytes(bytes) {
+            "image/png" => png_read(bytes),
+            "image/jpeg" => jpeg_read(bytes),
+            "image/webp" => webp_read(bytes),
+            mime => bail!("Unsupported image type: {mime}"),
+        }
+    }
+
+    /// Replace all supported metadata in an encoded image with `self`.
+    pub fn write(&self, bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match multipart::mime_from_bytes(bytes) {
+            "image/png" => png_write(bytes, self),
+            "image/jpeg" => jpeg_write(bytes, self),
+            "image/webp" => webp_write(bytes, self),
+            mime => bail!("Unsupported image type: {mime}"),
+        }
+    }
+}
+
+/// Extract the raw C2PA manifest store (a JUMBF superbox), if present.
+pub fn read_c2pa(bytes: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+    Ok(Metadata::read(bytes)?.c2pa)
+}
+
+/// Replace the C2PA manifest store in `bytes`. `None` strips any existing
+/// manifest.
+pub fn write_c2pa(
+    bytes: &[u8],
+    manifest: Option<&[u8]>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut metadata = Metadata::read(bytes)?;
+    metadata.c2pa = manifest.map(<[u8]>::to_vec);
+    metadata.write(bytes)
+}
+
+// --- PNG ---
+
+/// Split a PNG file into its `(type, data)` chunks.
+fn png_chunks(bytes: &[u8]) -> anyhow::Result<Vec<([u8; 4], &[u8])>> {
+    let mut rest = bytes
+        .strip_prefix(PNG_SIGNATURE)
+        .context("Not a PNG file")?;
+    let mut chunks = Vec::new();
+    while !rest.is_empty() {
+        let (len, ty) = match rest {
+            [a, b, c, d, t0, t1, t2, t3, ..] => (
+                u32::from_be_bytes([*a, *b, *c, *d]) as usize,
+                [*t0, *t1, *t2, *t3],
+            ),
+            _ => bail!("Truncated PNG chunk header"),
+        };
+        let data = rest.get(8..8 + len).context("Truncated PNG chunk")?;
+        chunks.push((ty, data));
+        rest = rest.get(12 + len..).context("Truncated PNG chunk CRC")?;
+    }
+    Ok(chunks)
+}
+
+/// Whether a PNG `iTXt` chunk holds an XMP packet.
+fn is_png_xmp(ty: &[u8; 4], data: &[u8]) -> bool {
+    ty == b"iTXt"
+        && data.starts_with(PNG_XMP_KEYWORD)
+        && data.get(PNG_XMP_KEYWORD.len()) == Some(&0)
+}
+
+/// Whether `png_write` manages this chunk type.
+fn is_png_metadata_chunk(ty: &[u8; 4]) -> bool {
+    ty == PNG_C2PA_CHUNK
+        || ty == PNG_EXIF_CHUNK
+        || PNG_TEXT_CHUNKS.contains(&ty)
+}
+
+fn png_read(bytes: &[u8]) -> anyhow::Result<Metadata> {
+    let mut metadata = Metadata::default();
+    for (ty, data) in png_chunks(bytes)? {
+        if &ty == PNG_C2PA_CHUNK {
+            metadata.c2pa = Some(data.to_vec());
+        } else if &ty == PNG_EXIF_CHUNK {
+            metadata.exif = Some(data.to_vec());
+        } else if is_png_xmp(&ty, data) {
+            match png_itxt_uncompressed_text(data) {
+                Some(text) => metadata.xmp = Some(text.to_vec()),
+                None => metadata.png_text.push((ty, data.to_vec())),
+            }
+        } else if PNG_TEXT_CHUNKS.contains(&&ty) {
+            metadata.png_text.push((ty, data.to_vec()));
+        }
+    }
+    Ok(metadata)
+}
+
+/// The text of an uncompressed `iTXt` chunk:
+/// `keyword\0 compression_flag(1) compression_method(1) lang\0 trans\0 text`
+fn png_itxt_uncompressed_text(data: &[u8]) -> Option<&[u8]> {
+    let keyword_end = data.iter().position(|b| *b == 0)?;
+    let (flag, rest) = data.get(keyword_end + 1..)?.split_first()?;
+    if *flag != 0 {
+        return None;
+    }
+    let mut fields = rest.get(1..)?.splitn(3, |b| *b == 0);
+    let (_lang, _translated) = (fields.next()?, fields.next()?);
+    fields.next()
+}
+
+fn png_write_chunk(out: &mut Vec<u8>, ty: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(ty);
+    out.extend_from_slice(data);
+    let crc = crc32(&[ty.as_slice(), data]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn png_write(bytes: &[u8], metadata: &Metadata) -> anyhow::Result<Vec<u8>> {
+    let chunks = png_chunks(bytes)?;
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(PNG_SIGNATURE);
+    for (ty, data) in chunks {
+        if is_png_metadata_chunk(&ty) {
+            continue;
+        }
+        png_write_chunk(&mut out, &ty, data);
+
+        // Place the metadata right after the header, before any image data
+        if &ty != b"IHDR" {
+            continue;
+        }
+        if let Some(c2pa) = &metadata.c2pa {
+            png_write_chunk(&mut out, PNG_C2PA_CHUNK, c2pa);
+        }
+        if let Some(exif) = &metadata.exif {
+            png_write_chunk(&mut out, PNG_EXIF_CHUNK, exif);
+        }
+        if let Some(xmp) = &metadata.xmp {
+            // Uncompressed, no language tag or translated keyword
+            let mut data = PNG_XMP_KEYWORD.to_vec();
+            data.extend_from_slice(&[0, 0, 0, 0, 0]);
+            data.extend_from_slice(xmp);
+            png_write_chunk(&mut out, b"iTXt", &data);
+        }
+        for (ty, data) in &metadata.png_text {
+            png_write_chunk(&mut out, ty, data);
+        }
+    }
+    Ok(out)
+}
+
+/// CRC-32 (ISO 3309) over the concatenation of `parts`, as used by PNG.
+fn crc32(parts: &[&[u8]]) -> u32 {
+    let mut crc = 0xffff_ffff_u32;
+    for byte in parts.iter().flat_map(|part| part.iter()) {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+// --- JPEG ---
+
+/// A JPEG marker segment before the start of scan.
+struct JpegSegment<'a> {
+    marker: u8,
+    /// The segment payload, excluding the marker and length.
+    data: &'a [u8],
+}
+
+impl JpegSegment<'_> {
+    /// Whether this is a JUMBF packet (APP11 with Common Identifier "JP").
+    fn is_jumbf(&self) -> bool {
+        self.marker == JPEG_APP11 && self.data.starts_with(b"JP")
+    }
+
+    fn is_exif(&self) -> bool {
+        self.marker == JPEG_APP1 && self.data.starts_with(JPEG_EXIF_HEADER)
+    }
+
+    fn is_xmp(&self) -> bool {
+        self.marker == JPEG_APP1 && self.data.starts_with(JPEG_XMP_HEADER)
+    }
+}
+
+/// Split a JPEG file into its header segments and the remaining entropy
+/// coded data (starting at the SOS marker).
+fn jpeg_segments(
+    bytes: &[u8],
+) -> anyhow::Result<(Vec<JpegSegment<'_>>, &[u8])> {
+    let mut rest =
+        bytes.strip_prefix(b"\xff\xd8").context("Not a JPEG file")?;
+    let mut segments = Vec::new();
+    loop {
+        let marker = match rest {
+            [0xff, 0xda, ..] => return Ok((segments, rest)),
+            [0xff, marker, ..] => *marker,
+            _ => bail!("Malformed JPEG segment marker"),
+        };
+        let len = match rest.get(2..4) {
+            Some(&[hi, lo]) => u16::from_be_bytes([hi, lo]) as usize,
+            _ => bail!("Truncated JPEG segment"),
+        };
+        let data = rest
+            .get(4..2 + len)
+            .context("Truncated JPEG segment data")?;
+        segments.push(JpegSegment { marker, data });
+        rest = &rest[2 + len..];
+    }
+}
+
+fn jpeg_read(bytes: &[u8]) -> anyhow::Result<Metadata> {
+    let (segments, _) = jpeg_segments(bytes)?;
+    let mut metadata = Metadata::default();
+    for segment in &segments {
+        if segment.is_exif() {
+            metadata.exif = Some(segment.data[JPEG_EXIF_HEADER.len()..].into());
+        } else if segment.is_xmp() {
+            metadata.xmp = Some(segment.data[JPEG_XMP_HEADER.len()..].into());
+        }
+    }
+    metadata.c2pa = jpeg_read_jumbf(&segments)?;
+    Ok(metadata)
+}
+
+/// Reassemble the JUMBF box split across APP11 segments.
+///
+/// Each segment is `CI(2) En(2) Z(4) LBox(4) TBox(4) payload...`, with the
+/// box header repeated in every segment.
+fn jpeg_read_jumbf(
+    segments: &[JpegSegment<'_>],
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut packets = segments
+        .iter()
+        .filter(|segment| segment.is_jumbf())
+        .map(|segment| {
+            let seq =
+                segment.data.get(4..8).context("Truncated JUMBF segment")?;
+            let seq = u32::from_be_bytes(seq.try_into().unwrap());
+            let body =
+                segment.data.get(8..).context("Truncated JUMBF segment")?;
+            Ok((seq, body))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if packets.is_empty() {
+        return Ok(None);
+    }
+    packets.sort_by_key(|(seq, _)| *seq);
+
+    let mut out = packets[0].1.to_vec();
+    for (_, body) in &packets[1..] {
+        // Skip the repeated box header
+        out.extend_from_slice(body.get(8..).context("Truncated JUMBF box")?);
+    }
+    Ok(Some(out))
+}
+
+fn jpeg_write(bytes: &[u8], metadata: &Metadata) -> anyhow::Result<Vec<u8>> {
+    // Max segment length is 65535, including the length field itself.
+    const MAX_SEGMENT_DATA: usize = 65535 - 2;
+    // Our JUMBF packets have a CI(2) En(2) Z(4) LBox(4) TBox(4) header.
+    const MAX_JUMBF_PAYLOAD: usize = MAX_SEGMENT_DATA - 16;
+
+    let (segments, scan) = jpeg_segments(bytes)?;
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(b"\xff\xd8");
+
+    fn write_segment(
+        out: &mut Vec<u8>,
+        marker: u8,
+        parts: &[&[u8]],
+    ) -> anyhow::Result<()> {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+        if len > MAX_SEGMENT_DATA {
+            bail!("JPEG metadata segment is too large ({len} bytes)");
+        }
+        out.extend_from_slice(&[0xff, marker]);
+        out.extend_from_slice(&(len as u16 + 2).to_be_bytes());
+        for part in parts {
+            out.extend_from_slice(part);
+        }
+        Ok(())
+    }
+
+    let mut pending = Some(metadata);
+    for segment in &segments {
+        if segment.is_jumbf() || segment.is_exif() || segment.is_xmp() {
+            continue;
+        }
+        // Place the metadata after the leading JFIF segment(s)
+        if segment.marker != 0xe0 {
+            if let Some(metadata) = pending.take() {
+                if let Some(exif) = &metadata.exif {
+                    write_segment(
+                        &mut out,
+                        JPEG_APP1,
+                        &[JPEG_EXIF_HEADER, exif],
+                    )?;
+                }
+                if let Some(xmp) = &metadata.xmp {
+                    write_segment(
+                        &mut out,
+                        JPEG_APP1,
+                        &[JPEG_XMP_HEADER, xmp],
+                    )?;
+                }
+                if let Some(c2pa) = &metadata.c2pa {
+                    let header = c2pa.get(..8).context("Invalid JUMBF box")?;
+                    let chunks = c2pa[8..].chunks(MAX_JUMBF_PAYLOAD);
+                    for (i, chunk) in chunks.enumerate() {
+                        let seq = (i as u32 + 1).to_be_bytes();
+                        write_segment(
+                            &mut out,
+                            JPEG_APP11,
+                            &[b"JP", b"\x00\x01", &seq, header, chunk],
+                        )?;
+                    }
+                }
+            }
+        }
+        write_segment(&mut out, segment.marker, &[segment.data])?;
+    }
+    if pending.is_some() {
+        bail!("JPEG has no frame header");
+    }
+    out.extend_from_slice(scan);
+    Ok(out)
+}
+
+// --- WebP ---
+
+/// Split a WebP RIFF file into its `(fourcc, data)` chunks.
+fn webp_chunks(bytes: &[u8]) -> anyhow::Result<Vec<([u8; 4], &[u8])>> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WEBP" {
+        bail!("Not a WebP file");
+    }
+    let mut rest = &bytes[12..];
+    let mut chunks = Vec::new();
+    while !rest.is_empty() {
+        let header = rest.get(..8).context("Truncated WebP chunk header")?;
+        let ty: [u8; 4] = header[..4].try_into().unwrap();
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        let data = rest.get(8..8 + len).context("Truncated WebP chunk")?;
+        chunks.push((ty, data));
+        // Chunks are padded to an even length
+        let padded = 8 + len + (len & 1);
+        rest = rest.get(padded..).unwrap_or_default();
+    }
+    Ok(chunks)
+}
+
+fn webp_read(bytes: &[u8]) -> anyhow::Result<Metadata> {
+    let mut metadata = Metadata::default();
+    for (ty, data) in webp_chunks(bytes)? {
+        match &ty {
+            WEBP_C2PA_CHUNK => metadata.c2pa = Some(data.to_vec()),
+            WEBP_EXIF_CHUNK => metadata.exif = Some(data.to_vec()),
+            WEBP_XMP_CHUNK => metadata.xmp = Some(data.to_vec()),
+            _ => (),
+        }
+    }
+    Ok(metadata)
+}
+
+/// Build a VP8X header chunk for a simple (lossy or lossless) WebP image, so
+/// we can attach extra chunks to it.
+fn webp_vp8x(ty: &[u8; 4], data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let (width, height, alpha) = match ty {
+        b"VP8L" => {
+            let bits = data.get(1..5).context("Truncated VP8L header")?;
+            let bits = u32::from_le_bytes(bits.try_into().unwrap());
+            let width = (bits & 0x3fff) + 1;
+            let height = ((bits >> 14) & 0x3fff) + 1;
+            let alpha = (bits >> 28) & 1 == 1;
+            (width, height, alpha)
+        }
+        b"VP8 " => {
+            let dims = data.get(6..10).context("Truncated VP8 header")?;
+            let width = u16::from_le_bytes([dims[0], dims[1]]) & 0x3fff;
+            let height = u16::from_le_bytes([dims[2], dims[3]]) & 0x3fff;
+            (width as u32, height as u32, false)
+        }
+        _ => bail!("Unsupported WebP image chunk"),
+    };
+
+    let mut vp8x = vec![if alpha { 0x10 } else { 0 }, 0, 0, 0];
+    vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+    vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+    Ok(vp8x)
+}
+
+fn webp_write(bytes: &[u8], metadata: &Metadata) -> anyhow::Result<Vec<u8>> {
+    // VP8X feature flags
+    const EXIF_FLAG: u8 = 0x08;
+    const XMP_FLAG: u8 = 0x04;
+
+    let chunks = webp_chunks(bytes)?;
+    let has_metadata = metadata.c2pa.is_some()
+        || metadata.exif.is_some()
+        || metadata.xmp.is_some();
+
+    let mut body = b"WEBP".to_vec();
+    let mut write_chunk = |ty: &[u8; 4], data: &[u8]| {
+        body.extend_from_slice(ty);
+        body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        body.extend_from_slice(data);
+        if data.len() % 2 == 1 {
+            body.push(0);
+        }
+    };
+
+    let set_flags = |mut vp8x: Vec<u8>| {
+        vp8x[0] &= !(EXIF_FLAG | XMP_FLAG);
+        if metadata.exif.is_some() {
+            vp8x[0] |= EXIF_FLAG;
+        }
+        if metadata.xmp.is_some() {
+            vp8x[0] |= XMP_FLAG;
+        }
+        vp8x
+    };
+
+    // Extra chunks are only allowed in the extended (VP8X) file format
+    if has_metadata {
+        if let [(ty, data)] = chunks.as_slice() {
+            write_chunk(b"VP8X", &set_flags(webp_vp8x(ty, data)?));
+        }
+    }
+    for (ty, data) in &chunks {
+        match ty {
+            WEBP_C2PA_CHUNK | WEBP_EXIF_CHUNK | WEBP_XMP_CHUNK => (),
+            b"VP8X" => {
+                let vp8x = data.get(..10).context("Truncated VP8X chunk")?;
+                let mut vp8x = set_flags(vp8x.to_vec());
+                vp8x.extend_from_slice(&data[10..]);
+                write_chunk(ty, &vp8x);
+            }
+            _ => write_chunk(ty, data),
+        }
+    }
+    if let Some(exif) = &metadata.exif {
+        write_chunk(WEBP_EXIF_CHUNK, exif);
+    }
+    if let Some(xmp) = &metadata.xmp {
+        write_chunk(WEBP_XMP_CHUNK, xmp);
+    }
+    if let Some(c2pa) = &metadata.c2pa {
+        write_chunk(WEBP_C2PA_CHUNK, c2pa);
+    }
+
+    let mut out = Vec::with_capacity(8 + body.len());
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::imageops;
+    use image::{DynamicImage, ImageFormat, RgbaImage};
+
+    const FORMATS: [ImageFormat; 3] =
+        [ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::WebP];
+
+    /// A minimal JUMBF superbox with a labeled description box.
+    fn fake_manifest() -> Vec<u8> {
+        let mut jumd = Vec::new();
+        jumd.extend_from_slice(&[0; 16]);
+        jumd.push(0x03);
+        jumd.extend_from_slice(b"c2pa\0");
+        let mut out = Vec::new();
+        out.extend_from_slice(&(8 + 8 + jumd.len() as u32).to_be_bytes());
+        out.extend_from_slice(b"jumb");
+        out.extend_from_slice(&(8 + jumd.len() as u32).to_be_bytes());
+        out.extend_from_slice(b"jumd");
+        out.extend_from_slice(&jumd);
+        out
+    }
+
+    #[test]
+    fn test_c2pa_roundtrip() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(3, 2));
+        let manifest = fake_manifest();
+
+        for format in FORMATS {
+            let bytes = imageops::encode(&image, format).unwrap();
+            assert_eq!(read_c2pa(&bytes).unwrap(), None, "{format:?}");
+
+            let with = write_c2pa(&bytes, Some(&manifest)).unwrap();
+            assert_eq!(read_c2pa(&with).unwrap(), Some(manifest.clone()));
+            let decoded = image::load_from_memory(&with).unwrap();
+            assert_eq!((decoded.width(), decoded.height()), (3, 2));
+
+            let without = write_c2pa(&with, None).unwrap();
+            assert_eq!(read_c2pa(&without).unwrap(), None, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn test_metadata_across_formats() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::new(3, 2));
+        let metadata = Metadata {
+            exif: Some(b"MM\0\x2a\0\0\0\x08\0\0".to_vec()),
+            xmp: Some(b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>".to_vec()),
+            c2pa: Some(fake_manifest()),
+            png_text: Vec::new(),
+        };
+
+        for format in FORMATS {
+            let bytes = imageops::encode(&image, format).unwrap();
+            let with = metadata.write(&bytes).unwrap();
+            assert_eq!(Metadata::read(&with).unwrap(), metadata, "{format:?}");
+            image::load_from_memory(&with).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(&[b"IEND"]), 0xae42_6082);
+    }
+}
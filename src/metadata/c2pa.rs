@@ -0,0 +1,309 @@
+//! Minimal C2PA manifest store reader, for displaying content credentials.
+//!
+//! A manifest store is a tree of JUMBF boxes. Each superbox (`jumb`) starts
+//! with a description box (`jumd`) carrying its label (e.g. "c2pa.claim"),
+//! followed by content boxes. Assertions and claims are CBOR-encoded, which we
+//! convert to JSON for display.
+
+use anyhow::{bail, Context};
+use serde_json::{Map, Number, Value};
+use std::fmt::Write;
+
+/// The deepest nesting of JUMBF boxes or CBOR values we'll follow, so a
+/// malicious file can't overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+/// A parsed JUMBF box.
+pub struct JumbfBox {
+    /// The four character box type, e.g. "jumb", "cbor", "json".
+    pub box_type: [u8; 4],
+    /// The label from the description box, for superboxes.
+    pub label: Option<String>,
+    /// The box content, for content boxes.
+    pub content: Vec<u8>,
+    /// Child boxes, for superboxes.
+    pub children: Vec<JumbfBox>,
+}
+
+impl JumbfBox {
+    /// Parse a single JUMBF superbox from `bytes`.
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut boxes = parse_boxes(bytes, 0)?;
+        match boxes.len() {
+            1 => Ok(boxes.remove(0)),
+            n => bail!("Expected a single JUMBF superbox, found {n} boxes"),
+        }
+    }
+
+    /// Render the box tree as an indented, human-readable listing.
+    pub fn describe(&self) -> String {
+        let mut out = String::new();
+        self.describe_inner(&mut out, 0);
+        out
+    }
+
+    fn describe_inner(&self, out: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth);
+        if &self.box_type == b"jumb" {
+            let label = self.label.as_deref().unwrap_or("<unlabeled>");
+            let _ = writeln!(out, "{indent}{label}");
+            for child in &self.children {
+                child.describe_inner(out, depth + 1);
+            }
+            return;
+        }
+
+        let box_type = String::from_utf8_lossy(&self.box_type);
+        let value = match &self.box_type {
+            b"cbor" => cbor_to_json(&self.content).ok(),
+            b"json" => serde_json::from_slice::<Value>(&self.content).ok(),
+            _ => None,
+        };
+        match value {
+            Some(value) => {
+                let pretty = serde_json::to_string_pretty(&value)
+                    .expect("Failed to serialize JSON");
+                for line in pretty.lines() {
+                    let _ = writeln!(out, "{indent}{line}");
+                }
+            }
+            None => {
+                let len = self.content.len();
+                let _ = writeln!(out, "{indent}<{box_type}: {len} bytes>");
+            }
+        }
+    }
+}
+
+/// Parse a sequence of JUMBF boxes, `depth` superboxes down.
+fn parse_boxes(
+    mut bytes: &[u8],
+    depth: usize,
+) -> anyhow::Result<Vec<JumbfBox>> {
+    if depth > MAX_DEPTH {
+        bail!("JUMBF boxes nested more than {MAX_DEPTH} deep");
+    }
+    let mut boxes = Vec::new();
+    while !bytes.is_empty() {
+        let header = bytes.get(..8).context("Truncated JUMBF box header")?;
+        let lbox = u32::from_be_bytes(header[..4].try_into().unwrap()) as u64;
+        let box_type: [u8; 4] = header[4..].try_into().unwrap();
+        let (header_len, len) = match lbox {
+            // Box extends to the end of the input
+            0 => (8, bytes.len() as u64),
+            // 64-bit extended length follows
+            1 => {
+                let xl = bytes.get(8..16).context("Truncated JUMBF XLBox")?;
+                (16, u64::from_be_bytes(xl.try_into().unwrap()))
+            }
+            len => (8, len),
+        };
+        let body = usize::try_from(len)
+            .ok()
+            .and_then(|len| bytes.get(header_len..len))
+            .context("Truncated JUMBF box")?;
+        bytes = &bytes[header_len + body.len()..];
+
+        if &box_type == b"jumb" {
+            let mut children = parse_boxes(body, depth + 1)?;
+            let label = match children.first() {
+                Some(first) if &first.box_type == b"jumd" => {
+                    parse_description_label(&children.remove(0).content)
+                }
+                _ => None,
+            };
+            boxes.push(JumbfBox {
+                box_type,
+                label,
+                content: Vec::new(),
+                children,
+            });
+        } else {
+            boxes.push(JumbfBox {
+                box_type,
+                label: None,
+                content: body.to_vec(),
+                children: Vec::new(),
+            });
+        }
+    }
+    Ok(boxes)
+}
+
+/// Extract the label from a `jumd` description box:
+/// `type UUID(16) toggles(1) [label\0] ...`
+fn parse_description_label(jumd: &[u8]) -> Option<String> {
+    let toggles = *jumd.get(16)?;
+    if toggles & 0x02 == 0 {
+        return None;
+    }
+    let rest = jumd.get(17..)?;
+    let end = rest.iter().position(|b| *b == 0)?;
+    Some(String::from_utf8_lossy(&rest[..end]).into_owned())
+}
+
+/// Decode a CBOR value into JSON for display. Byte strings (hashes,
+/// signatures) are summarized rather than dumped.
+pub fn cbor_to_json(bytes: &[u8]) -> anyhow::Result<Value> {
+    let mut reader = CborReader {
+        bytes,
+        pos: 0,
+        depth: 0,
+    };
+    reader.value()
+}
+
+struct CborReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// How many arrays, maps, and tags we're inside
+    depth: usize,
+}
+
+impl CborReader<'_> {
+    fn take(&mut self, n: u64) -> anyhow::Result<&[u8]> {
+        let end = usize::try_from(n)
+            .ok()
+            .and_then(|n| self.pos.checked_add(n))
+            .filter(|end| *end <= self.bytes.len())
+            .context("Truncated CBOR value")?;
+        let out = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(out)
+    }
+
+    /// Read the initial byte's argument. Returns `None` for indefinite length.
+    fn argument(&mut self, info: u8) -> anyhow::Result<Option<u64>> {
+        Ok(Some(match info {
+            0..=23 => info as u64,
+            24 => self.take(1)?[0] as u64,
+            25 => u16::from_be_bytes(self.take(2)?.try_into().unwrap()) as u64,
+            26 => u32::from_be_bytes(self.take(4)?.try_into().unwrap()) as u64,
+            27 => u64::from_be_bytes(self.take(8)?.try_into().unwrap()),
+            31 => return Ok(None),
+            _ => bail!("Invalid CBOR additional info: {info}"),
+        }))
+    }
+
+    fn is_break(&self) -> bool {
+        self.bytes.get(self.pos) == Some(&0xff)
+    }
+
+    fn value(&mut self) -> anyhow::Result<Value> {
+        if self.depth > MAX_DEPTH {
+            bail!("CBOR values nested more than {MAX_DEPTH} deep");
+        }
+        self.depth += 1;
+        let value = self.value_inner();
+        self.depth -= 1;
+        value
+    }
+
+    fn value_inner(&mut self) -> anyhow::Result<Value> {
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+        let arg = self.argument(info)?;
+        let value = match (major, arg) {
+            (0, Some(n)) => Value::from(n),
+            (1, Some(n)) => match i64::try_from(n) {
+                Ok(n) => Value::from(-1 - n),
+                Err(_) => float_value(-1.0 - n as f64),
+            },
+            (2, Some(n)) => {
+                self.take(n)?;
+                Value::from(format!("<{n} bytes>"))
+            }
+            (3, Some(n)) => {
+                let text = self.take(n)?;
+                Value::from(String::from_utf8_lossy(text).into_owned())
+            }
+            (4, len) => {
+                let mut items = Vec::new();
+                while len.map_or(!self.is_break(), |n| (items.len() as u64) < n)
+                {
+                    items.push(self.value()?);
+                }
+                if len.is_none() {
+                    self.pos += 1;
+                }
+                Value::Array(items)
+            }
+            (5, len) => {
+                let mut map = Map::new();
+                let mut count = 0;
+                while len.map_or(!self.is_break(), |n| count < n) {
+                    let key = match self.value()? {
+                        Value::String(key) => key,
+                        key => key.to_string(),
+                    };
+                    map.insert(key, self.value()?);
+                    count += 1;
+                }
+                if len.is_none() {
+                    self.pos += 1;
+                }
+                Value::Object(map)
+            }
+            // Tags (e.g. dates) don't matter for display
+            (6, Some(_)) => self.value()?,
+            (7, _) => match info {
+                20 => Value::Bool(false),
+                21 => Value::Bool(true),
+                22 | 23 => Value::Null,
+                26 => {
+                    let bits = arg.unwrap() as u32;
+                    float_value(f32::from_bits(bits) as f64)
+                }
+                27 => float_value(f64::from_bits(arg.unwrap())),
+                _ => Value::Null,
+            },
+            _ => bail!("Unsupported CBOR value: {initial:#04x}"),
+        };
+        Ok(value)
+    }
+}
+
+fn float_value(f: f64) -> Value {
+    Number::from_f64(f)
+        .map(Value::Number)
+        .unwrap_or(Value::Null)
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cbor_to_json() {
+        // {"a": [1, -2, "x"], "b": h'0102', "c": true}
+        let cbor = b"\xa3\x61a\x83\x01\x21\x61x\x61b\x42\x01\x02\x61c\xf5";
+        let value = cbor_to_json(cbor).unwrap();
+        assert_eq!(
+            value,
+            json!({ "a": [1, -2, "x"], "b": "<2 bytes>", "c": true })
+        );
+    }
+
+    #[test]
+    fn test_malformed() {
+        // A byte string claiming u64::MAX bytes
+        let cbor = b"\x5b\xff\xff\xff\xff\xff\xff\xff\xff";
+        assert!(cbor_to_json(cbor).is_err());
+        // Arrays nested far too deep: [[[...[0]...]]]
+        let mut cbor = vec![0x81; 10_000];
+        cbor.push(0);
+        let err = cbor_to_json(&cbor).unwrap_err();
+        assert!(err.to_string().contains("deep"), "{err}");
+
+        // JUMBF superboxes nested far too deep, each just an 8 byte header
+        let mut jumbf = Vec::new();
+        for _ in 0..1000 {
+            jumbf.extend_from_slice(b"\0\0\0\0jumb");
+        }
+        let err = JumbfBox::parse(&jumbf).err().unwrap();
+        assert!(err.to_string().contains("deep"), "{err}");
+    }
+}
@@ -2,7 +2,13 @@
 
 use anyhow::anyhow;
 use rand::{distr::Alphanumeric, Rng};
-use std::{ffi::OsStr, path::Path};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    ffi::OsStr,
+    io::{self, Read},
+    path::Path,
+};
 
 /// Builds a multipart/form-data request body.
 #[derive(Debug)]
@@ -13,7 +19,6 @@ pub struct Builder<'a> {
 
 impl<'a> Builder<'a> {
     /// Creates a new MultipartBuilder with a random boundary.
-    #[allow(dead_code)]
     pub fn new() -> Self {
         let boundary = generate_boundary();
         Builder {
@@ -32,8 +37,11 @@ impl<'a> Builder<'a> {
     }
 
     /// Adds a text field to the multipart form.
-    pub fn add_text(&mut self, name: &'a str, value: &'a str) {
-        self.parts.push(Part::Text { name, value });
+    pub fn add_text(&mut self, name: &'a str, value: impl Into<Cow<'a, str>>) {
+        self.parts.push(Part::Text {
+            name,
+            value: value.into(),
+        });
     }
 
     /// Adds a file field from in-memory bytes.
@@ -60,23 +68,48 @@ impl<'a> Builder<'a> {
     /// A `MultipartBody` struct containing the raw body bytes and the
     /// `Content-Type` header value.
     pub fn build(self) -> Body {
-        let mut body_bytes = Vec::new();
+        let (segments, content_type) = self.segments();
+        Body {
+            body: segments.concat(),
+            content_type,
+        }
+    }
+
+    /// Like [`Builder::build`], but the body is read from each part in turn,
+    /// so file contents are streamed from where they are rather than copied
+    /// into one buffer.
+    pub fn build_stream(self) -> Stream<'a> {
+        let (segments, content_type) = self.segments();
+        let len = segments.iter().map(|segment| segment.len()).sum();
+        Stream {
+            segments: segments.into(),
+            pos: 0,
+            len,
+            content_type,
+        }
+    }
+
+    /// The body, in order: the encoded headers and text fields, with the
+    /// file contents borrowed in between. Also returns the `Content-Type`.
+    fn segments(self) -> (Vec<Cow<'a, [u8]>>, String) {
+        let mut segments = Vec::new();
+        let mut head = Vec::new();
         let boundary_marker = format!("--{}\r\n", self.boundary);
         let boundary_end = format!("--{}--\r\n", self.boundary);
 
         for part in self.parts {
-            body_bytes.extend_from_slice(boundary_marker.as_bytes());
+            head.extend_from_slice(boundary_marker.as_bytes());
 
             match part {
                 Part::Text { name, value } => {
                     // Build Content-Disposition header directly
-                    body_bytes.extend_from_slice(
+                    head.extend_from_slice(
                         b"Content-Disposition: form-data; name=\"",
                     );
-                    body_bytes.extend_from_slice(name.as_bytes());
-                    body_bytes.extend_from_slice(b"\"\r\n\r\n");
-                    body_bytes.extend_from_slice(value.as_bytes());
-                    body_bytes.extend_from_slice(b"\r\n");
+                    head.extend_from_slice(name.as_bytes());
+                    head.extend_from_slice(b"\"\r\n\r\n");
+                    head.extend_from_slice(value.as_bytes());
+                    head.extend_from_slice(b"\r\n");
                 }
                 Part::FileBytes {
                     name,
@@ -85,36 +118,36 @@ impl<'a> Builder<'a> {
                     content,
                 } => {
                     // Build Content-Disposition header directly
-                    body_bytes.extend_from_slice(
+                    head.extend_from_slice(
                         b"Content-Disposition: form-data; name=\"",
                     );
-                    body_bytes.extend_from_slice(name.as_bytes());
-                    body_bytes.extend_from_slice(b"\"; filename=\"");
-                    body_bytes.extend_from_slice(
-                        filename.as_os_str().as_encoded_bytes(),
-                    );
-                    body_bytes.extend_from_slice(b"\"\r\n");
+                    head.extend_from_slice(name.as_bytes());
+                    // Only the file name, not where it is locally (e.g. a
+                    // Windows `\\?\C:\...` long path)
+                    let filename =
+                        filename.file_name().unwrap_or(filename.as_os_str());
+                    head.extend_from_slice(b"\"; filename=\"");
+                    head.extend_from_slice(filename.as_encoded_bytes());
+                    head.extend_from_slice(b"\"\r\n");
 
                     // Build Content-Type header directly
-                    body_bytes.extend_from_slice(b"Content-Type: ");
-                    body_bytes.extend_from_slice(content_type.as_bytes());
-                    body_bytes.extend_from_slice(b"\r\n\r\n");
-
-                    // Append file content
-                    body_bytes.extend_from_slice(content);
-                    body_bytes.extend_from_slice(b"\r\n");
+                    head.extend_from_slice(b"Content-Type: ");
+                    head.extend_from_slice(content_type.as_bytes());
+                    head.extend_from_slice(b"\r\n\r\n");
+
+                    // The file content, as is
+                    segments.push(Cow::Owned(std::mem::take(&mut head)));
+                    segments.push(Cow::Borrowed(content));
+                    head.extend_from_slice(b"\r\n");
                 }
             }
         }
 
-        body_bytes.extend_from_slice(boundary_end.as_bytes());
+        head.extend_from_slice(boundary_end.as_bytes());
+        segments.push(Cow::Owned(head));
         let content_type_header =
             format!("multipart/form-data; boundary={}", self.boundary);
-
-        Body {
-            body: body_bytes,
-            content_type: content_type_header,
-        }
+        (segments, content_type_header)
     }
 }
 
@@ -127,11 +160,41 @@ pub struct Body {
     pub content_type: String,
 }
 
+/// A multipart/form-data body, read a segment at a time.
+#[derive(Debug)]
+pub struct Stream<'a> {
+    segments: VecDeque<Cow<'a, [u8]>>,
+    /// How far into the first segment we've read
+    pos: usize,
+    /// The total length of the body, for the `Content-Length` header.
+    pub len: usize,
+    /// The value for the `Content-Type` header.
+    pub content_type: String,
+}
+
+impl Read for Stream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(segment) = self.segments.front() {
+            let rest = &segment[self.pos..];
+            if rest.is_empty() {
+                self.segments.pop_front();
+                self.pos = 0;
+                continue;
+            }
+            let n = rest.len().min(buf.len());
+            buf[..n].copy_from_slice(&rest[..n]);
+            self.pos += n;
+            return Ok(n);
+        }
+        Ok(0)
+    }
+}
+
 /// Represents a part in a multipart/form-data request.
 #[derive(Debug)]
 enum Part<'a> {
     /// A simple text field.
-    Text { name: &'a str, value: &'a str },
+    Text { name: &'a str, value: Cow<'a, str> },
     /// A file field provided as raw bytes.
     FileBytes {
         name: &'a str,
@@ -250,6 +313,41 @@ mod tests {
         assert_eq!(body_str, expected_body);
     }
 
+    #[test]
+    fn test_build_stream() {
+        let mut builder = Builder::with_boundary("b".to_string());
+        builder.add_text("prompt", "A cat");
+        builder.add_file_bytes(
+            "image[]",
+            Path::new("/tmp/cat.png"),
+            "image/png",
+            b"\x89PNG",
+        );
+        let mut stream = builder.build_stream();
+
+        let expected = "--b\r\n\
+             Content-Disposition: form-data; name=\"prompt\"\r\n\r\n\
+             A cat\r\n\
+             --b\r\n\
+             Content-Disposition: form-data; name=\"image[]\"; \
+             filename=\"cat.png\"\r\n\
+             Content-Type: image/png\r\n\r\n\
+             \u{fffd}PNG\r\n\
+             --b--\r\n";
+        let mut body = Vec::new();
+        // A small buffer, to read across the segments
+        let mut buf = [0; 7];
+        loop {
+            let n = stream.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(stream.len, body.len());
+        assert_eq!(String::from_utf8_lossy(&body), expected);
+    }
+
     #[test]
     fn test_mime_inference() {
         assert_eq!(
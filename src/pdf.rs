@@ -0,0 +1,353 @@
+//! Minimal PDF writer for image contact sheets.
+//!
+//! Images are embedded as JPEG (`DCTDecode`) and text uses the built-in
+//! Helvetica font, so we need neither font files nor a compression library.
+
+use std::io::Write;
+
+/// A4 page size, in points
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 40.0;
+const GUTTER: f32 = 16.0;
+
+/// Images per page
+const COLUMNS: usize = 2;
+const ROWS: usize = 2;
+
+const TITLE_SIZE: f32 = 11.0;
+const MAX_TITLE_LINES: usize = 6;
+const SUBTITLE_SIZE: f32 = 9.0;
+const CAPTION_SIZE: f32 = 8.0;
+const CAPTION_LINES: usize = 3;
+
+/// An image to lay out on a contact sheet.
+pub struct SheetImage {
+    /// JPEG-encoded RGB image data
+    pub jpeg: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    /// Text shown under the image
+    pub caption: String,
+}
+
+/// Lay out `images` in a grid on A4 pages, each headed by `title` (e.g. the
+/// prompt) and `subtitle` (e.g. the generation parameters).
+pub fn contact_sheet(
+    title: &str,
+    subtitle: &str,
+    images: &[SheetImage],
+) -> Vec<u8> {
+    let mut pdf = Pdf::default();
+    let catalog = pdf.reserve();
+    let pages = pdf.reserve();
+    let font = pdf.reserve();
+
+    let chunks: Vec<&[SheetImage]> = images.chunks(COLUMNS * ROWS).collect();
+    let num_pages = chunks.len().max(1);
+    let mut page_ids = Vec::with_capacity(num_pages);
+    for page_idx in 0..num_pages {
+        let chunk = chunks.get(page_idx).copied().unwrap_or_default();
+        let mut content = Vec::new();
+        let mut xobjects = String::new();
+
+        // Header
+        let text_width = PAGE_WIDTH - 2.0 * MARGIN;
+        let mut y = PAGE_HEIGHT - MARGIN;
+        for line in wrap(title, TITLE_SIZE, text_width, MAX_TITLE_LINES) {
+            y -= TITLE_SIZE * 1.3;
+            write_text(&mut content, MARGIN, y, TITLE_SIZE, &line);
+        }
+        let mut subtitle = subtitle.to_string();
+        if num_pages > 1 {
+            subtitle.push_str(&format!(" (page {}/{num_pages})", page_idx + 1));
+        }
+        y -= SUBTITLE_SIZE * 1.6;
+        write_text(&mut content, MARGIN, y, SUBTITLE_SIZE, &subtitle);
+        y -= GUTTER;
+
+        // Image grid
+        let grid_top = y;
+        let cell_width =
+            (text_width - GUTTER * (COLUMNS - 1) as f32) / COLUMNS as f32;
+        let cell_height =
+            (grid_top - MARGIN - GUTTER * (ROWS - 1) as f32) / ROWS as f32;
+        let caption_height = CAPTION_SIZE * 1.3 * CAPTION_LINES as f32 + 4.0;
+        for (i, image) in chunk.iter().enumerate() {
+            let (col, row) = (i % COLUMNS, i / COLUMNS);
+            let cell_x = MARGIN + col as f32 * (cell_width + GUTTER);
+            let cell_top = grid_top - row as f32 * (cell_height + GUTTER);
+
+            // Scale to fit, centered horizontally
+            let max_height = cell_height - caption_height;
+            let scale = (cell_width / image.width as f32)
+                .min(max_height / image.height as f32);
+            let (width, height) =
+                (image.width as f32 * scale, image.height as f32 * scale);
+            let image_x = cell_x + (cell_width - width) / 2.0;
+            let image_y = cell_top - height;
+
+            let image_id = pdf.add(stream(
+                &format!(
+                    "/Type /XObject /Subtype /Image /Width {} /Height {} \
+                     /ColorSpace /DeviceRGB /BitsPerComponent 8 \
+                     /Filter /DCTDecode",
+                    image.width, image.height
+                ),
+                &image.jpeg,
+            ));
+            xobjects.push_str(&format!("/Im{i} {image_id} 0 R "));
+            let _ = writeln!(
+                content,
+                "q {width:.2} 0 0 {height:.2} {image_x:.2} {image_y:.2} cm \
+                 /Im{i} Do Q"
+            );
+
+            let mut caption_y = image_y;
+            for line in
+                wrap(&image.caption, CAPTION_SIZE, cell_width, CAPTION_LINES)
+            {
+                caption_y -= CAPTION_SIZE * 1.3;
+                write_text(
+                    &mut content,
+                    cell_x,
+                    caption_y,
+                    CAPTION_SIZE,
+                    &line,
+                );
+            }
+        }
+
+        let content_id = pdf.add(stream("", &content));
+        let page_id = pdf.add(
+            format!(
+                "<< /Type /Page /Parent {pages} 0 R \
+                 /MediaBox [0 0 {PAGE_WIDTH} {PAGE_HEIGHT}] \
+                 /Resources << /Font << /F1 {font} 0 R >> \
+                 /XObject << {xobjects}>> >> \
+                 /Contents {content_id} 0 R >>"
+            )
+            .into_bytes(),
+        );
+        page_ids.push(page_id);
+    }
+
+    let kids: Vec<String> =
+        page_ids.iter().map(|id| format!("{id} 0 R")).collect();
+    pdf.set(catalog, format!("<< /Type /Catalog /Pages {pages} 0 R >>"));
+    pdf.set(
+        pages,
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            page_ids.len()
+        ),
+    );
+    pdf.set(
+        font,
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica \
+         /Encoding /WinAnsiEncoding >>"
+            .to_string(),
+    );
+    pdf.finish(catalog)
+}
+
+/// PDF objects, numbered from 1.
+#[derive(Default)]
+struct Pdf {
+    objects: Vec<Vec<u8>>,
+}
+
+impl Pdf {
+    /// Reserve an object number, to be filled in later with [`Pdf::set`].
+    fn reserve(&mut self) -> usize {
+        self.add(Vec::new())
+    }
+
+    fn set(&mut self, id: usize, object: String) {
+        self.objects[id - 1] = object.into_bytes();
+    }
+
+    fn add(&mut self, object: Vec<u8>) -> usize {
+        self.objects.push(object);
+        self.objects.len()
+    }
+
+    /// Serialize the document, with `root` as the catalog.
+    fn finish(self, root: usize) -> Vec<u8> {
+        let mut out = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = Vec::with_capacity(self.objects.len());
+        for (i, object) in self.objects.iter().enumerate() {
+            offsets.push(out.len());
+            let _ = writeln!(out, "{} 0 obj", i + 1);
+            out.extend_from_slice(object);
+            out.extend_from_slice(b"\nendobj\n");
+        }
+
+        let xref_offset = out.len();
+        let size = self.objects.len() + 1;
+        let _ = write!(out, "xref\n0 {size}\n0000000000 65535 f \n");
+        for offset in offsets {
+            let _ = writeln!(out, "{offset:010} 00000 n ");
+        }
+        let _ = write!(
+            out,
+            "trailer\n<< /Size {size} /Root {root} 0 R >>\n\
+             startxref\n{xref_offset}\n%%EOF\n"
+        );
+        out
+    }
+}
+
+/// A stream object with extra dictionary entries.
+fn stream(dict: &str, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + 64);
+    let _ = write!(out, "<< /Length {} {dict} >>\nstream\n", data.len());
+    out.extend_from_slice(data);
+    out.extend_from_slice(b"\nendstream");
+    out
+}
+
+/// Append a line of text at (`x`, `y`) to a content stream.
+fn write_text(content: &mut Vec<u8>, x: f32, y: f32, size: f32, text: &str) {
+    let _ = write!(content, "BT /F1 {size} Tf {x:.2} {y:.2} Td (");
+    content.extend(encode_text(text));
+    content.extend_from_slice(b") Tj ET\n");
+}
+
+/// Encode text as an escaped WinAnsi string literal body.
+fn encode_text(text: &str) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len());
+    for c in text.chars() {
+        let byte = match c {
+            '(' | ')' | '\\' => {
+                out.push(b'\\');
+                c as u8
+            }
+            ' '..='~' => c as u8,
+            // WinAnsi matches Latin-1 here
+            '\u{a0}'..='\u{ff}' => c as u32 as u8,
+            '\u{2026}' => 0x85,
+            '\u{2018}' => 0x91,
+            '\u{2019}' => 0x92,
+            '\u{201c}' => 0x93,
+            '\u{201d}' => 0x94,
+            '\u{2013}' => 0x96,
+            '\u{2014}' => 0x97,
+            _ => b'?',
+        };
+        if byte.is_ascii() {
+            out.push(byte);
+        } else {
+            let _ = write!(out, "\\{byte:03o}");
+        }
+    }
+    out
+}
+
+/// Greedily word-wrap `text` to lines that fit in `width` points, keeping at
+/// most `max_lines` (the last one ellipsized if truncated).
+fn wrap(text: &str, size: f32, width: f32, max_lines: usize) -> Vec<String> {
+    // Helvetica averages a bit over half an em per character
+    let max_chars = ((width / (size * 0.52)) as usize).max(1);
+
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word;
+        loop {
+            let line_len = line.chars().count();
+            let sep = usize::from(line_len > 0);
+            let word_len = word.chars().count();
+            if line_len + sep + word_len <= max_chars {
+                if sep == 1 {
+                    line.push(' ');
+                }
+                line.push_str(word);
+                break;
+            }
+            if line_len > 0 {
+                lines.push(std::mem::take(&mut line));
+                continue;
+            }
+            // Split words that don't fit on a line by themselves
+            let split = word
+                .char_indices()
+                .nth(max_chars)
+                .map_or(word.len(), |(idx, _)| idx);
+            lines.push(word[..split].to_string());
+            word = &word[split..];
+            if word.is_empty() {
+                break;
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        let last = lines.last_mut().unwrap();
+        let keep = max_chars.saturating_sub(1).min(last.chars().count());
+        *last = last.chars().take(keep).collect::<String>() + "\u{2026}";
+    }
+    lines
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contact_sheet_structure() {
+        let image = || SheetImage {
+            jpeg: b"\xff\xd8fake\xff\xd9".to_vec(),
+            width: 1024,
+            height: 1536,
+            caption: "cat.png (revised: a (very) cute cat)".to_string(),
+        };
+        let images: Vec<SheetImage> = (0..5).map(|_| image()).collect();
+        let pdf = contact_sheet("A cute cat", "gpt-image-1, auto", &images);
+
+        assert!(pdf.starts_with(b"%PDF-1.4\n"));
+        assert!(pdf.ends_with(b"%%EOF\n"));
+        let text = String::from_utf8_lossy(&pdf);
+        assert!(text.contains("/Count 2"));
+        assert!(text.contains(r"\(page 2/2\)"));
+        assert!(text.contains(r"(cat.png \(revised: a \(very\) cute cat\))"));
+
+        // Each xref entry points at its object
+        let xref_start = text.find("xref\n").unwrap();
+        let entries = text[xref_start..].lines().skip(3);
+        for (i, entry) in entries.take_while(|l| l.ends_with(" n ")).enumerate()
+        {
+            let offset: usize = entry[..10].parse().unwrap();
+            let header = format!("{} 0 obj\n", i + 1);
+            assert!(pdf[offset..].starts_with(header.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn test_encode_text() {
+        assert_eq!(encode_text(r"a (b) \c"), br"a \(b\) \\c");
+        assert_eq!(encode_text("caf\u{e9} \u{201c}x\u{201d} \u{1f600}"), {
+            br"caf\351 \223x\224 ?".to_vec()
+        });
+    }
+
+    #[test]
+    fn test_wrap() {
+        // 10pt at 52pt wide fits 10 characters
+        let lines = wrap("the quick brown fox", 10.0, 52.0, 5);
+        assert_eq!(lines, ["the quick", "brown fox"]);
+
+        let lines = wrap("abcdefghijklmnop", 10.0, 52.0, 5);
+        assert_eq!(lines, ["abcdefghij", "klmnop"]);
+
+        let lines = wrap("one two three four five six", 10.0, 52.0, 2);
+        assert_eq!(lines, ["one two", "three fou\u{2026}"]);
+    }
+}
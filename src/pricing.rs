@@ -0,0 +1,161 @@
+//! Per-model pricing, for estimating costs before generating anything.
+
+use crate::api::Usage;
+
+/// Pricing for a single image model.
+pub struct ModelPricing {
+    /// The model name, as sent to the API
+    pub model: &'static str,
+    /// USD per 1M text input tokens
+    pub text_input: f64,
+    /// USD per 1M image input tokens
+    pub image_input: f64,
+    /// USD per 1M output (image) tokens
+    pub output: f64,
+    /// Output tokens per image, indexed by `[quality][size]`, if known
+    output_tokens: Option<[[u32; 3]; 3]>,
+}
+
+/// Known model prices.
+pub const MODELS: &[ModelPricing] = &[
+    ModelPricing {
+        model: "gpt-image-1",
+        text_input: 5.0,
+        image_input: 10.0,
+        output: 40.0,
+        output_tokens: Some([
+            // 1024x1024, 1024x1536, 1536x1024
+            [272, 408, 400],    // low
+            [1056, 1584, 1568], // medium
+            [4160, 6240, 6208], // high
+        ]),
+    },
+    ModelPricing {
+        model: "gpt-image-1-mini",
+        text_input: 2.0,
+        image_input: 2.5,
+        output: 8.0,
+        output_tokens: None,
+    },
+];
+
+/// Look up the pricing for a model.
+pub fn for_model(model: &str) -> Option<&'static ModelPricing> {
+    MODELS.iter().find(|pricing| pricing.model == model)
+}
+
+/// Image quality levels with distinct pricing.
+#[derive(Clone, Copy, Debug)]
+pub enum Quality {
+    Low,
+    Medium,
+    High,
+}
+
+impl Quality {
+    pub const ALL: [Quality; 3] =
+        [Quality::Low, Quality::Medium, Quality::High];
+
+    /// Parse a `--quality` value. `None` for "auto".
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|quality| quality.as_str() == name)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Medium => "medium",
+            Self::High => "high",
+        }
+    }
+}
+
+/// Roughly estimate the number of tokens in a text prompt (~4 chars/token).
+pub fn estimate_text_tokens(text: &str) -> u32 {
+    text.chars().count().div_ceil(4) as u32
+}
+
+impl ModelPricing {
+    /// Output tokens for a single image, or `None` for an unknown size.
+    pub fn output_tokens(&self, quality: Quality, size: &str) -> Option<u32> {
+        let size_idx = match size {
+            "1024x1024" => 0,
+            "1024x1536" => 1,
+            "1536x1024" => 2,
+            _ => return None,
+        };
+        Some(self.output_tokens?[quality as usize][size_idx])
+    }
+
+    /// Estimate the cost in USD of generating `n` images from a text prompt.
+    pub fn estimate_cost(
+        &self,
+        quality: Quality,
+        size: &str,
+        n: u8,
+        prompt_tokens: u32,
+    ) -> Option<f64> {
+        let output_tokens = self.output_tokens(quality, size)? * n as u32;
+        Some(self.cost(prompt_tokens, 0, output_tokens))
+    }
+
+    /// The cost in USD of a completed request.
+    pub fn usage_cost(&self, usage: &Usage) -> f64 {
+        let details = &usage.input_tokens_details;
+        self.cost(
+            details.text_tokens,
+            details.image_tokens,
+            usage.output_tokens,
+        )
+    }
+
+    /// The cost in USD for the given token counts.
+    pub fn cost(
+        &self,
+        text_input_tokens: u32,
+        image_input_tokens: u32,
+        output_tokens: u32,
+    ) -> f64 {
+        let per_token = |tokens: u32, per_million: f64| {
+            tokens as f64 / 1_000_000.0 * per_million
+        };
+        per_token(text_input_tokens, self.text_input)
+            + per_token(image_input_tokens, self.image_input)
+            + per_token(output_tokens, self.output)
+    }
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_cost() {
+        let pricing = &MODELS[0];
+        assert_eq!(pricing.model, "gpt-image-1");
+
+        // Matches OpenAI's published per-image prices
+        let low = pricing.estimate_cost(Quality::Low, "1024x1024", 1, 0);
+        assert_eq!(format!("{:.3}", low.unwrap()), "0.011");
+        let high = pricing.estimate_cost(Quality::High, "1024x1536", 1, 0);
+        assert_eq!(format!("{:.3}", high.unwrap()), "0.250");
+
+        let n2 = pricing.estimate_cost(Quality::Medium, "1536x1024", 2, 100);
+        assert_eq!(format!("{:.4}", n2.unwrap()), "0.1259");
+
+        assert!(pricing
+            .estimate_cost(Quality::High, "512x512", 1, 0)
+            .is_none());
+
+        // Known rates, but unknown per-image token counts
+        let mini = for_model("gpt-image-1-mini").unwrap();
+        assert!(mini
+            .estimate_cost(Quality::Low, "1024x1024", 1, 0)
+            .is_none());
+        assert!(for_model("flux-pro").is_none());
+    }
+}
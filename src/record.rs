@@ -0,0 +1,174 @@
+//! Machine-readable records of completed generation runs.
+
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::OnceLock;
+
+use crate::api::Usage;
+
+/// A summary of a completed generation run. Printed with `--json` and
+/// appended to the history.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunRecord {
+    /// The Unix timestamp (in seconds) of when the image(s) were created
+    pub created: u64,
+
+    /// The model used to generate the image(s)
+    pub model: String,
+
+    /// The prompt we sent
+    pub prompt: String,
+
+    /// The prompt as written, if we translated it before sending
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub original_prompt: Option<String>,
+
+    /// The generated images
+    pub images: Vec<ImageRecord>,
+
+    /// Token usage information for the run
+    pub usage: Usage,
+
+    /// The estimated cost of the run in USD
+    pub cost: f64,
+
+    /// The size and quality the images were generated at, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+
+    /// How long the model took to respond, in seconds, for estimating how
+    /// long the next run will take
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<f64>,
+
+    /// What made the image(s), to tell which imgen version (and setup)
+    /// produced an asset later on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub environment: Option<Environment>,
+}
+
+/// The tool and machine a run was made with.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Environment {
+    /// The imgen version
+    pub version: String,
+
+    /// The image provider, like "openai" or "stability"
+    pub provider: String,
+
+    /// The commit checked out in the git repo imgen ran from, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_commit: Option<String>,
+
+    /// The machine's hostname
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+}
+
+impl Environment {
+    /// The current environment, for a run with `provider`.
+    pub fn capture(provider: &str) -> Self {
+        // Neither changes during a run, so only ask once
+        static GIT_COMMIT: OnceLock<Option<String>> = OnceLock::new();
+        static HOSTNAME: OnceLock<Option<String>> = OnceLock::new();
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            provider: provider.to_string(),
+            git_commit: GIT_COMMIT
+                .get_or_init(|| {
+                    command_output(
+                        Command::new("git").args(["rev-parse", "HEAD"]),
+                    )
+                })
+                .clone(),
+            hostname: HOSTNAME
+                .get_or_init(|| command_output(&mut Command::new("hostname")))
+                .clone(),
+        }
+    }
+}
+
+/// The trimmed stdout of a command that succeeded, if it printed anything.
+fn command_output(command: &mut Command) -> Option<String> {
+    let output = command.stderr(std::process::Stdio::null()).output().ok()?;
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let stdout = stdout.trim();
+    (output.status.success() && !stdout.is_empty()).then(|| stdout.to_string())
+}
+
+/// A single generated image in a [`RunRecord`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ImageRecord {
+    /// Where the image was saved. `None` if written to stdout.
+    pub path: Option<PathBuf>,
+
+    /// The prompt the model actually used, if it rewrote ours
+    #[serde(default)]
+    pub revised_prompt: Option<String>,
+
+    /// A short accessibility description of the image, with `--alt-text`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt_text: Option<String>,
+
+    /// The SHA-256 of the saved image, to spot duplicates of it later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
+    /// The IPFS CID the image was pinned to, with `--output ipfs://`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipfs_cid: Option<String>,
+
+    /// The detached signature of the saved image, with `--sign`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<PathBuf>,
+
+    /// The estimated cost of this image in USD. The API only reports usage
+    /// for the whole run, so this is the run cost split evenly.
+    pub cost: f64,
+}
+
+/// The metadata sidecar written next to a saved image with `--sidecar`.
+#[derive(Debug, Serialize)]
+pub struct Sidecar<'a> {
+    pub created: u64,
+    pub model: &'a str,
+    pub prompt: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_prompt: Option<&'a str>,
+    pub revised_prompt: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alt_text: Option<&'a str>,
+    /// The estimated cost of this image in USD
+    pub cost: f64,
+    /// The estimated cost of the whole run in USD
+    pub run_cost: f64,
+    /// This image's position in the run (1-based)
+    pub index: usize,
+    /// The number of images in the run
+    pub n: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<&'a Environment>,
+}
+
+impl RunRecord {
+    /// The sidecar metadata for the `i`-th image (0-based).
+    pub fn sidecar(&self, i: usize) -> Sidecar<'_> {
+        let image = &self.images[i];
+        Sidecar {
+            created: self.created,
+            model: &self.model,
+            prompt: &self.prompt,
+            original_prompt: self.original_prompt.as_deref(),
+            revised_prompt: image.revised_prompt.as_deref(),
+            alt_text: image.alt_text.as_deref(),
+            cost: image.cost,
+            run_cost: self.cost,
+            index: i + 1,
+            n: self.images.len(),
+            environment: self.environment.as_ref(),
+        }
+    }
+}
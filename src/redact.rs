@@ -0,0 +1,93 @@
+//! A safety net that scrubs secrets (API keys and tokens) from log and error
+//! output, in case a server or proxy echoes one back in an error message.
+
+use std::{borrow::Cow, sync::RwLock};
+
+/// Shorter values are too likely to match ordinary text.
+const MIN_SECRET_LEN: usize = 8;
+
+const REDACTED: &str = "[REDACTED]";
+
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Scrub this secret from all log output from now on.
+pub fn add_secret(secret: &str) {
+    let secret = secret.trim();
+    if secret.len() < MIN_SECRET_LEN {
+        return;
+    }
+    let mut secrets = SECRETS.write().unwrap_or_else(|err| err.into_inner());
+    if !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
+/// Replace any known secrets in `text`.
+pub fn scrub(text: &str) -> Cow<'_, str> {
+    let secrets = SECRETS.read().unwrap_or_else(|err| err.into_inner());
+    scrub_with(text, &secrets)
+}
+
+fn scrub_with<'a>(text: &'a str, secrets: &[String]) -> Cow<'a, str> {
+    let mut text = Cow::Borrowed(text);
+    for secret in secrets {
+        if text.contains(secret.as_str()) {
+            text = Cow::Owned(text.replace(secret.as_str(), REDACTED));
+        }
+    }
+    text
+}
+
+/// A logger that scrubs known secrets from messages before passing them on.
+pub struct Logger<L>(pub L);
+
+impl<L: log::Log> log::Log for Logger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.0.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        let message = scrub(&message);
+        self.0.log(
+            &log::Record::builder()
+                .args(format_args!("{message}"))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrub() {
+        let secrets =
+            vec!["sk-abc123456".to_string(), "xoxb-98765".to_string()];
+        assert_eq!(
+            scrub_with(
+                "bad key sk-abc123456 (sk-abc123456), xoxb-98765",
+                &secrets
+            ),
+            "bad key [REDACTED] ([REDACTED]), [REDACTED]"
+        );
+        assert!(matches!(
+            scrub_with("nothing to see", &secrets),
+            Cow::Borrowed(_)
+        ));
+    }
+}
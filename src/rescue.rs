@@ -0,0 +1,59 @@
+//! Keeping raw API data we failed to parse or decode, since it may hold
+//! images that were already paid for. Saved in the data directory
+//! (`~/.local/share/imgen/responses`).
+
+use anyhow::Context;
+use std::{path::PathBuf, time::SystemTime};
+
+use crate::config;
+
+const DIR_NAME: &str = "responses";
+
+/// Save raw response data to a new file with this extension, returning its
+/// path.
+pub fn save(extension: &str, bytes: &[u8]) -> anyhow::Result<PathBuf> {
+    let dir = config::data_dir()
+        .context("Could not determine the data directory")?
+        .join(DIR_NAME);
+    std::fs::create_dir_all(&dir)?;
+    let millis = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis())
+        .unwrap_or(0);
+    // Never overwrite an earlier one
+    let mut k = 0;
+    loop {
+        let path = match k {
+            0 => dir.join(format!("{millis}.{extension}")),
+            k => dir.join(format!("{millis}-{k}.{extension}")),
+        };
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path);
+        match file {
+            Ok(mut file) => {
+                std::io::Write::write_all(&mut file, bytes).with_context(
+                    || format!("Failed to write to: {}", path.display()),
+                )?;
+                return Ok(path);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                k += 1
+            }
+            Err(err) => {
+                return Err(anyhow::Error::new(err)
+                    .context(format!("Failed to create: {}", path.display())))
+            }
+        }
+    }
+}
+
+/// Save raw response data, logging where it went (or why it couldn't be
+/// saved).
+pub fn save_and_report(what: &str, extension: &str, bytes: &[u8]) {
+    match save(extension, bytes) {
+        Ok(path) => log::error!("Saved the raw {what} to: {}", path.display()),
+        Err(err) => log::warn!("Failed to save the raw {what}: {err:#}"),
+    }
+}
@@ -0,0 +1,310 @@
+//! A local Stable Diffusion server as an image provider, for
+//! `--provider sdwebui`: the Automatic1111 SD WebUI API (and compatible
+//! forks, like Forge), started with `--api`.
+//!
+//! Creates go to txt2img and edits to img2img. The server renders with its
+//! loaded checkpoint, unless `--model` names another.
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use image::ImageFormat;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+use ureq::SendBody;
+
+use crate::{
+    api::{
+        self, CreateRequest, EditRequest, ImageData, Response, VariationRequest,
+    },
+    client::{self, ClientError, Provider, Transfer},
+};
+
+/// Where the SD WebUI listens by default
+pub const DEFAULT_API_BASE: &str = "http://127.0.0.1:7860";
+
+/// The `--model` that means "the server's loaded checkpoint"
+pub const DEFAULT_MODEL: &str = "sdwebui";
+
+/// A connection to an SD WebUI server.
+pub struct SdWebUi {
+    agent: ureq::Agent,
+    api_base: String,
+}
+
+/// A txt2img or img2img request. Only the parameters we set; the server
+/// fills in the rest (steps, sampler, ...) from its own defaults.
+#[derive(Debug, Default, Serialize)]
+struct GenerateRequest<'a> {
+    prompt: &'a str,
+    batch_size: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    height: Option<u32>,
+    /// The base64 image to edit (img2img only)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    init_images: Vec<String>,
+    /// The base64 inpainting mask, white where to repaint (img2img only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mask: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    override_settings: Option<OverrideSettings<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct OverrideSettings<'a> {
+    sd_model_checkpoint: &'a str,
+}
+
+#[derive(Deserialize)]
+struct GenerateResponse {
+    /// The base64 png images
+    images: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(default)]
+    error: String,
+    #[serde(default)]
+    errors: String,
+}
+
+impl SdWebUi {
+    pub fn new(api_base: Option<String>) -> Self {
+        let api_base = api_base.unwrap_or_else(|| DEFAULT_API_BASE.to_string());
+        Self {
+            agent: client::local_agent(),
+            api_base: api_base.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// POST a request and read the images from the JSON response.
+    fn post(
+        &self,
+        endpoint: &str,
+        request: &GenerateRequest<'_>,
+    ) -> Result<Response, ClientError> {
+        let body = serde_json::to_vec(request)?;
+        let content_length = body.len();
+        let transfer = Transfer::new(content_length);
+        let reader = transfer.reader(Cursor::new(body));
+        let mut response = self
+            .agent
+            .post(format!("{}/sdapi/v1/{endpoint}", self.api_base))
+            .header("Content-Type", "application/json")
+            .header("Content-Length", content_length)
+            .send(SendBody::from_owned_reader(reader))
+            .map_err(|err| transfer.error(err))?;
+        let status = response.status();
+        let retry_after = client::retry_after(response.headers());
+        let text = response
+            .body_mut()
+            .with_config()
+            .limit(client::RESPONSE_BODY_LIMIT)
+            .read_to_string()
+            .map_err(|err| transfer.error(err))?;
+        if !status.is_success() {
+            debug!("SD WebUI response: {text}");
+            let message = serde_json::from_str::<ErrorResponse>(&text)
+                .ok()
+                .and_then(|error| {
+                    [error.errors, error.error]
+                        .into_iter()
+                        .find(|message| !message.is_empty())
+                })
+                .unwrap_or(text);
+            return Err(ClientError::ApiError {
+                status,
+                message,
+                retry_after,
+                error: None,
+            });
+        }
+
+        let response: GenerateResponse = serde_json::from_str(&text)?;
+        // Extensions (like ControlNet) append their own images after ours
+        let data = response
+            .images
+            .into_iter()
+            .take(usize::from(request.batch_size))
+            .map(|b64_json| ImageData {
+                b64_json,
+                url: None,
+                revised_prompt: None,
+                downloaded: None,
+            })
+            .collect();
+        Ok(Response {
+            created: api::unix_now(),
+            data,
+            usage: Default::default(),
+        })
+    }
+}
+
+impl Provider for SdWebUi {
+    fn name(&self) -> &'static str {
+        "sdwebui"
+    }
+
+    fn create_images(
+        &self,
+        request: &CreateRequest,
+    ) -> Result<Response, ClientError> {
+        let (width, height) = dimensions(request.size.as_deref())?.unzip();
+        let request = GenerateRequest {
+            prompt: &request.prompt,
+            batch_size: request.n.unwrap_or(1),
+            width,
+            height,
+            override_settings: checkpoint(&request.model),
+            ..GenerateRequest::default()
+        };
+        self.post("txt2img", &request)
+    }
+
+    fn edit_images(
+        &self,
+        request: &EditRequest,
+    ) -> Result<Response, ClientError> {
+        if !request.references.is_empty() {
+            return Err(ClientError::Unsupported {
+                provider: self.name(),
+                feature: "--image-ref",
+            });
+        }
+        if request.style_ref.is_some() {
+            return Err(ClientError::Unsupported {
+                provider: self.name(),
+                feature: "--style-ref",
+            });
+        }
+        let [image] = request.images.as_slice() else {
+            return Err(ClientError::Unsupported {
+                provider: self.name(),
+                feature: "editing more than one --image at a time",
+            });
+        };
+        let mask = request
+            .mask
+            .as_ref()
+            .map(|mask| inpainting_mask(&mask.bytes))
+            .transpose()?;
+        let (width, height) = dimensions(request.size.as_deref())?.unzip();
+        let request = GenerateRequest {
+            prompt: &request.prompt,
+            batch_size: request.n.unwrap_or(1),
+            width,
+            height,
+            init_images: vec![BASE64_STANDARD.encode(&image.bytes)],
+            mask: mask.map(|mask| BASE64_STANDARD.encode(mask)),
+            override_settings: checkpoint(&request.model),
+        };
+        self.post("img2img", &request)
+    }
+
+    fn create_variations(
+        &self,
+        _request: &VariationRequest,
+    ) -> Result<Response, ClientError> {
+        Err(ClientError::Unsupported {
+            provider: self.name(),
+            feature: "variations",
+        })
+    }
+}
+
+/// Switch to the `--model` checkpoint for this request, unless it's the
+/// default (the loaded checkpoint).
+fn checkpoint(model: &str) -> Option<OverrideSettings<'_>> {
+    (model != DEFAULT_MODEL).then_some(OverrideSettings {
+        sd_model_checkpoint: model,
+    })
+}
+
+/// The width and height of a `--size` like "768x512". `None` for the
+/// server's default.
+fn dimensions(size: Option<&str>) -> Result<Option<(u32, u32)>, ClientError> {
+    let Some(size) = size else {
+        return Ok(None);
+    };
+    size.split_once('x')
+        .and_then(|(width, height)| {
+            Some((width.parse().ok()?, height.parse().ok()?))
+        })
+        .map(Some)
+        .ok_or(ClientError::Unsupported {
+            provider: "sdwebui",
+            feature: "this --size; use WIDTHxHEIGHT",
+        })
+}
+
+/// Our masks mark the areas to edit as transparent, while the SD WebUI
+/// repaints the white areas of a black and white mask.
+fn inpainting_mask(mask: &[u8]) -> Result<Vec<u8>, ClientError> {
+    let invalid =
+        |err: image::ImageError| ClientError::Io(std::io::Error::other(err));
+    let mask = image::load_from_memory(mask).map_err(invalid)?.to_rgba8();
+    let mask =
+        image::GrayImage::from_fn(mask.width(), mask.height(), |x, y| {
+            let repaint = mask.get_pixel(x, y)[3] < 128;
+            image::Luma([if repaint { 255 } else { 0 }])
+        });
+    let mut png = Vec::new();
+    mask.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .map_err(invalid)?;
+    Ok(png)
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parameter_mapping() {
+        assert_eq!(dimensions(None).unwrap(), None);
+        assert_eq!(dimensions(Some("768x512")).unwrap(), Some((768, 512)));
+        assert!(dimensions(Some("big")).is_err());
+        assert!(checkpoint(DEFAULT_MODEL).is_none());
+
+        let request = GenerateRequest {
+            prompt: "A cat",
+            batch_size: 2,
+            width: Some(768),
+            height: Some(512),
+            override_settings: checkpoint("sd_xl_base_1.0"),
+            ..GenerateRequest::default()
+        };
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "prompt": "A cat",
+                "batch_size": 2,
+                "width": 768,
+                "height": 512,
+                "override_settings": {
+                    "sd_model_checkpoint": "sd_xl_base_1.0",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_inpainting_mask() {
+        // Transparent (edit) on the left, opaque (keep) on the right
+        let mask = image::RgbaImage::from_fn(2, 1, |x, _| {
+            image::Rgba([0, 0, 0, if x == 0 { 0 } else { 255 }])
+        });
+        let mut png = Vec::new();
+        mask.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let converted = inpainting_mask(&png).unwrap();
+        let converted = image::load_from_memory(&converted).unwrap().to_luma8();
+        assert_eq!(converted.get_pixel(0, 0).0, [255]);
+        assert_eq!(converted.get_pixel(1, 0).0, [0]);
+    }
+}
@@ -0,0 +1,288 @@
+//! Uploading generated images over SSH, for `--output sftp://...` (or
+//! `scp://...`).
+//!
+//! Runs the OpenSSH `sftp` or `scp` command, so uploads use your SSH config,
+//! agent, and keys, with no passphrase or password prompts. The server's host
+//! key must already be in `~/.ssh/known_hosts` (e.g. from connecting once with
+//! `ssh`).
+
+use anyhow::{bail, Context};
+use log::debug;
+use std::{
+    io::Write,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+const DEFAULT_PORT: u16 = 22;
+
+/// A parsed `sftp://[user@]host[:port]/path` URL.
+///
+/// Paths are absolute, except under `/~/`, which is relative to the user's
+/// home directory. A path ending in `/` is a directory to upload
+/// automatically named images into.
+#[derive(Debug, PartialEq)]
+pub struct Remote {
+    scp: bool,
+    user: String,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl Remote {
+    pub fn parse(url: &str) -> anyhow::Result<Self> {
+        let (scp, rest) =
+            match (url.strip_prefix("sftp://"), url.strip_prefix("scp://")) {
+                (Some(rest), _) => (false, rest),
+                (_, Some(rest)) => (true, rest),
+                _ => bail!("Expected an sftp:// or scp:// URL: {url}"),
+            };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        if path.len() <= 1 {
+            bail!("Missing the remote path in: {url}");
+        }
+        let path = match path.strip_prefix("/~/") {
+            Some("") => "./".to_string(),
+            Some(relative) => relative.to_string(),
+            None => path.to_string(),
+        };
+
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (user.to_string(), host_port),
+            None => (default_user()?, authority),
+        };
+        let (host, port) = if let Some(rest) = host_port.strip_prefix('[') {
+            // An IPv6 address, like `[::1]:2222`
+            let (host, port) = rest
+                .split_once(']')
+                .with_context(|| format!("Invalid host in: {url}"))?;
+            (host, port.strip_prefix(':'))
+        } else {
+            match host_port.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            }
+        };
+        if host.is_empty() {
+            bail!("Missing the host in: {url}");
+        }
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .with_context(|| format!("Invalid port in: {url}"))?,
+            None => DEFAULT_PORT,
+        };
+        Ok(Self {
+            scp,
+            user,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    /// Whether the path is a directory to upload into, rather than a file.
+    pub fn is_dir(&self) -> bool {
+        self.path.ends_with('/')
+    }
+
+    /// The remote path for an image, given its local filename.
+    fn dest(&self, filename: &str) -> String {
+        if self.is_dir() {
+            format!("{}{filename}", self.path)
+        } else {
+            self.path.clone()
+        }
+    }
+
+    /// A display form of the remote path, for logs.
+    pub fn display(&self, path: &str) -> String {
+        let scheme = if self.scp { "scp" } else { "sftp" };
+        let slash = if path.starts_with('/') { "" } else { "/~/" };
+        format!("{scheme}://{}@{}{slash}{path}", self.user, self.host)
+    }
+
+    /// `user@host`, as the `ssh` tools take it.
+    fn destination(&self) -> String {
+        if self.host.contains(':') {
+            format!("{}@[{}]", self.user, self.host)
+        } else {
+            format!("{}@{}", self.user, self.host)
+        }
+    }
+
+    /// The `sftp`/`scp` command, with the options both take: no prompts,
+    /// only known hosts, and the port.
+    fn command(&self, program: &str) -> Command {
+        let mut command = Command::new(program);
+        command
+            .args(["-q", "-o", "BatchMode=yes"])
+            .args(["-o", "StrictHostKeyChecking=yes"])
+            .arg("-P")
+            .arg(self.port.to_string());
+        command
+    }
+
+    /// Check that we can log in (and that the host key is known), so a bad
+    /// URL fails before we pay for any images.
+    pub fn connect(self) -> anyhow::Result<Connection> {
+        let mut command = self.command("sftp");
+        command.args(["-b", "-"]).arg(self.destination());
+        run(&mut command, b"")
+            .with_context(|| format!("Failed to log in to {}", self.host))?;
+        debug!("Logged in to {}", self.destination());
+        Ok(Connection { remote: self })
+    }
+}
+
+/// A remote we've checked we can log in to, ready to upload.
+pub struct Connection {
+    remote: Remote,
+}
+
+impl Connection {
+    /// Upload an image, returning the remote path it was written to.
+    pub fn upload(
+        &self,
+        filename: &str,
+        bytes: &[u8],
+        mode: Option<u32>,
+    ) -> anyhow::Result<String> {
+        let path = self.remote.dest(filename);
+        let mode = mode.unwrap_or(0o644);
+        let context = || format!("Failed to upload to: {path}");
+        let mut local = tempfile::Builder::new()
+            .prefix("imgen-upload-")
+            .tempfile()?;
+        local.write_all(bytes).with_context(context)?;
+        local.flush()?;
+        if self.remote.scp {
+            // `scp -p` keeps the local file's mode
+            set_mode(local.path(), mode)?;
+            let target = format!("{}:{path}", self.remote.destination());
+            let mut command = self.remote.command("scp");
+            command.arg("-p").arg(local.path()).arg(target);
+            run(&mut command, b"").with_context(context)?;
+        } else {
+            let batch = upload_batch(local.path(), &path, mode);
+            let mut command = self.remote.command("sftp");
+            command.args(["-b", "-"]).arg(self.remote.destination());
+            run(&mut command, batch.as_bytes()).with_context(context)?;
+        }
+        Ok(path)
+    }
+
+    pub fn remote(&self) -> &Remote {
+        &self.remote
+    }
+}
+
+/// The `sftp` batch commands to upload a file and set its mode.
+fn upload_batch(local: &Path, remote: &str, mode: u32) -> String {
+    let local = quote(&local.to_string_lossy());
+    let remote = quote(remote);
+    format!("put {local} {remote}\nchmod {mode:o} {remote}\n")
+}
+
+/// Quote a path for an `sftp` batch file.
+fn quote(path: &str) -> String {
+    let escaped = path.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{escaped}\"")
+}
+
+/// Run a command with the given stdin, failing with its stderr if it doesn't
+/// succeed.
+fn run(command: &mut Command, stdin: &[u8]) -> anyhow::Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {program}"))?;
+    if let Some(mut input) = child.stdin.take() {
+        input.write_all(stdin)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{program} failed: {}", stderr.trim());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let permissions = std::fs::Permissions::from_mode(mode);
+    std::fs::set_permissions(path, permissions)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> anyhow::Result<()> {
+    Ok(())
+}
+
+fn default_user() -> anyhow::Result<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .context("No user in the URL, and $USER isn't set")
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote() {
+        let remote = Remote::parse("sftp://me@example.com/var/www/a.png");
+        assert_eq!(
+            remote.unwrap(),
+            Remote {
+                scp: false,
+                user: "me".to_string(),
+                host: "example.com".to_string(),
+                port: 22,
+                path: "/var/www/a.png".to_string(),
+            }
+        );
+
+        let remote = Remote::parse("scp://me@[::1]:2222/~/renders/").unwrap();
+        assert!(remote.scp);
+        assert_eq!((remote.host.as_str(), remote.port), ("::1", 2222));
+        assert_eq!(remote.path, "renders/");
+        assert!(remote.is_dir());
+        assert_eq!(remote.dest("cat.1.png"), "renders/cat.1.png");
+        assert_eq!(
+            remote.display(&remote.dest("cat.1.png")),
+            "scp://me@::1/~/renders/cat.1.png"
+        );
+        assert_eq!(remote.destination(), "me@[::1]");
+
+        assert!(Remote::parse("sftp://me@example.com").is_err());
+        assert!(Remote::parse("sftp://me@example.com/").is_err());
+        assert!(Remote::parse("sftp://me@:22/a.png").is_err());
+        assert!(Remote::parse("sftp://me@host:ssh/a.png").is_err());
+        assert!(Remote::parse("ftp://me@host/a.png").is_err());
+    }
+
+    #[test]
+    fn test_upload_batch() {
+        assert_eq!(
+            upload_batch(
+                Path::new("/tmp/imgen-upload-1"),
+                "a \"b\".png",
+                0o640
+            ),
+            "put \"/tmp/imgen-upload-1\" \"a \\\"b\\\".png\"\n\
+             chmod 640 \"a \\\"b\\\".png\"\n"
+        );
+    }
+}
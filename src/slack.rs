@@ -0,0 +1,188 @@
+//! Posting generated images to a Slack channel, for `--slack-channel`.
+//!
+//! Uses Slack's external upload flow: reserve an upload URL for each file,
+//! upload the bytes, then share all the files to the channel in a single
+//! message. The bot token needs the `files:write` scope, plus
+//! `channels:read` (and `groups:read` for private channels) to look up
+//! channels by name.
+
+use anyhow::{bail, Context};
+use log::{debug, info};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::path::Path;
+
+use crate::{client, config::Config, redact};
+
+const API_URL: &str = "https://slack.com/api";
+
+/// The environment variable checked for a bot token before the config file
+pub const TOKEN_ENV: &str = "SLACK_TOKEN";
+
+/// A Slack bot, ready to post to a channel.
+pub struct Slack {
+    agent: ureq::Agent,
+    token: String,
+    channel_id: String,
+}
+
+#[derive(Deserialize)]
+struct ChannelList {
+    channels: Vec<Channel>,
+    response_metadata: Option<ResponseMetadata>,
+}
+
+#[derive(Deserialize)]
+struct Channel {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ResponseMetadata {
+    next_cursor: String,
+}
+
+#[derive(Deserialize)]
+struct UploadUrl {
+    upload_url: String,
+    file_id: String,
+}
+
+impl Slack {
+    /// Find the bot token and look up the channel (`#name`, or an ID like
+    /// `C0123456789`), so a typo fails before we generate anything.
+    pub fn connect(config: &Config, channel: &str) -> anyhow::Result<Self> {
+        let token = std::env::var(TOKEN_ENV)
+            .ok()
+            .or_else(|| config.slack_token.clone())
+            .with_context(|| {
+                format!(
+                    "--slack-channel needs a Slack bot token; set the \
+                     `{TOKEN_ENV}` environment variable or \"slack_token\" \
+                     in the config file"
+                )
+            })?;
+        redact::add_secret(&token);
+        let mut slack = Self {
+            agent: client::agent(),
+            token,
+            channel_id: String::new(),
+        };
+        slack.channel_id = match channel.strip_prefix('#') {
+            Some(name) => slack.find_channel(name)?,
+            None => channel.to_string(),
+        };
+        debug!("Slack channel {channel}: {}", slack.channel_id);
+        Ok(slack)
+    }
+
+    /// Upload the files to the channel, in one message with `comment`.
+    pub fn post(&self, paths: &[&Path], comment: &str) -> anyhow::Result<()> {
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            let bytes = std::fs::read(path).with_context(|| {
+                format!("Failed to read image: {}", path.display())
+            })?;
+            let filename = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| "image.png".to_string());
+
+            let length = bytes.len().to_string();
+            let upload: UploadUrl = self.call(
+                "files.getUploadURLExternal",
+                self.request("files.getUploadURLExternal").send_form([
+                    ("filename", filename.as_str()),
+                    ("length", length.as_str()),
+                ]),
+            )?;
+            let response = self
+                .agent
+                .post(&upload.upload_url)
+                .send(&bytes[..])
+                .context("Failed to upload image to Slack")?;
+            if !response.status().is_success() {
+                bail!("Failed to upload image to Slack: {}", response.status());
+            }
+            files.push(serde_json::json!({
+                "id": upload.file_id,
+                "title": filename,
+            }));
+        }
+
+        let _: serde_json::Value = self.call(
+            "files.completeUploadExternal",
+            self.request("files.completeUploadExternal").send_json(
+                serde_json::json!({
+                    "files": files,
+                    "channel_id": self.channel_id,
+                    "initial_comment": comment,
+                }),
+            ),
+        )?;
+        info!("Posted {} image(s) to Slack", paths.len());
+        Ok(())
+    }
+
+    /// Look up a channel's ID by name.
+    fn find_channel(&self, name: &str) -> anyhow::Result<String> {
+        let mut cursor = String::new();
+        loop {
+            let list: ChannelList = self.call(
+                "conversations.list",
+                self.request("conversations.list").send_form([
+                    ("types", "public_channel,private_channel"),
+                    ("exclude_archived", "true"),
+                    ("limit", "1000"),
+                    ("cursor", cursor.as_str()),
+                ]),
+            )?;
+            if let Some(channel) = list
+                .channels
+                .into_iter()
+                .find(|channel| channel.name == name)
+            {
+                return Ok(channel.id);
+            }
+            cursor = match list.response_metadata {
+                Some(metadata) if !metadata.next_cursor.is_empty() => {
+                    metadata.next_cursor
+                }
+                _ => bail!(
+                    "Slack channel #{name} not found; check the name, and \
+                     that the bot can see it"
+                ),
+            };
+        }
+    }
+
+    fn request(
+        &self,
+        method: &str,
+    ) -> ureq::RequestBuilder<ureq::typestate::WithBody> {
+        self.agent
+            .post(format!("{API_URL}/{method}"))
+            .header("Authorization", format!("Bearer {}", self.token))
+    }
+
+    /// Read a Slack Web API response, which reports errors in the body with
+    /// `"ok": false`.
+    fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        response: Result<ureq::http::Response<ureq::Body>, ureq::Error>,
+    ) -> anyhow::Result<T> {
+        let mut response = response
+            .with_context(|| format!("Slack {method} request failed"))?;
+        let body: serde_json::Value = response
+            .body_mut()
+            .read_json()
+            .with_context(|| format!("Invalid Slack {method} response"))?;
+        if body["ok"] != true {
+            let error = body["error"].as_str().unwrap_or("unknown error");
+            bail!("Slack {method} failed: {error}");
+        }
+        serde_json::from_value(body)
+            .with_context(|| format!("Invalid Slack {method} response"))
+    }
+}
@@ -0,0 +1,271 @@
+//! Stability AI as an image provider, for `--provider stability`.
+//!
+//! Requests keep imgen's (OpenAI-shaped) options, which are mapped to
+//! Stability's parameters here: `--size` becomes an aspect ratio, and edits
+//! use inpainting. Stability makes one image per request, so `-n` is sent
+//! as parallel requests (see the model capabilities).
+
+use anyhow::Context;
+use log::debug;
+use serde::Deserialize;
+use std::io::Cursor;
+use ureq::SendBody;
+
+use crate::{
+    api::{
+        self, CreateRequest, EditRequest, ImageData, Response, VariationRequest,
+    },
+    client::{self, ClientError, Provider, Transfer},
+    multipart, redact,
+};
+
+const BASE_URL: &str = "https://api.stability.ai/v2beta/stable-image";
+
+/// The environment variable checked for a Stability API key
+pub const API_KEY_ENV: &str = "STABILITY_API_KEY";
+
+/// The model used unless `--model` picks another
+pub const DEFAULT_MODEL: &str = "stable-image-core";
+
+/// Stability's image models, and the generate endpoint each is served from.
+pub const MODELS: &[(&str, &str)] = &[
+    ("stable-image-core", "core"),
+    ("stable-image-ultra", "ultra"),
+    ("sd3.5-large", "sd3"),
+    ("sd3.5-large-turbo", "sd3"),
+    ("sd3.5-medium", "sd3"),
+];
+
+/// A connection to the Stability AI API.
+pub struct Stability {
+    agent: ureq::Agent,
+    api_key: String,
+}
+
+#[derive(Deserialize)]
+struct ImageResponse {
+    /// The base64 image
+    image: String,
+    /// "SUCCESS", or "CONTENT_FILTERED" if moderation blurred the image
+    finish_reason: String,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    #[serde(default)]
+    errors: Vec<String>,
+}
+
+impl Stability {
+    /// Find the API key, so a missing one fails before we generate anything.
+    pub fn new() -> anyhow::Result<Self> {
+        let api_key = std::env::var(API_KEY_ENV).with_context(|| {
+            format!(
+                "--provider stability needs an API key; set the \
+                 `{API_KEY_ENV}` environment variable"
+            )
+        })?;
+        redact::add_secret(&api_key);
+        Ok(Self {
+            agent: client::agent(),
+            api_key,
+        })
+    }
+
+    /// A client for `--dry-run`, which never sends a request, so needs no
+    /// API key.
+    pub fn for_dry_run() -> Self {
+        Self {
+            agent: client::agent(),
+            api_key: String::new(),
+        }
+    }
+
+    /// POST a multipart form and read the image from the JSON response.
+    fn post(
+        &self,
+        endpoint: &str,
+        builder: multipart::Builder<'_>,
+    ) -> Result<Response, ClientError> {
+        let body = builder.build();
+        let content_length = body.body.len();
+        let transfer = Transfer::new(content_length);
+        let reader = transfer.reader(Cursor::new(body.body));
+        let mut response = self
+            .agent
+            .post(format!("{BASE_URL}/{endpoint}"))
+            .header("Authorization", format!("Bearer {}", self.api_key))
+            .header("Accept", "application/json")
+            .header("Content-Type", &body.content_type)
+            .header("Content-Length", content_length)
+            .send(SendBody::from_owned_reader(reader))
+            .map_err(|err| transfer.error(err))?;
+        let status = response.status();
+        let retry_after = client::retry_after(response.headers());
+        let text = response
+            .body_mut()
+            .read_to_string()
+            .map_err(|err| transfer.error(err))?;
+        if !status.is_success() {
+            debug!("Stability response: {text}");
+            let message = serde_json::from_str::<ErrorResponse>(&text)
+                .ok()
+                .filter(|error| !error.errors.is_empty())
+                .map(|error| error.errors.join("; "))
+                .unwrap_or(text);
+            return Err(ClientError::ApiError {
+                status,
+                message,
+                retry_after,
+                error: None,
+            });
+        }
+
+        let image: ImageResponse = serde_json::from_str(&text)?;
+        if image.finish_reason == "CONTENT_FILTERED" {
+            return Err(ClientError::ApiError {
+                status,
+                message: "The image was blocked by Stability's content \
+                          moderation"
+                    .to_string(),
+                retry_after: None,
+                error: None,
+            });
+        }
+        Ok(Response {
+            created: api::unix_now(),
+            data: vec![ImageData {
+                b64_json: image.image,
+                url: None,
+                revised_prompt: None,
+                downloaded: None,
+            }],
+            usage: Default::default(),
+        })
+    }
+}
+
+impl Provider for Stability {
+    fn name(&self) -> &'static str {
+        "stability"
+    }
+
+    fn create_images(
+        &self,
+        request: &CreateRequest,
+    ) -> Result<Response, ClientError> {
+        let endpoint = endpoint_for(&request.model)?;
+        let aspect_ratio = aspect_ratio(request.size.as_deref())?;
+        let mut builder = multipart::Builder::new();
+        builder.add_text("prompt", &request.prompt);
+        if endpoint == "sd3" {
+            builder.add_text("model", &request.model);
+        }
+        if let Some(aspect_ratio) = aspect_ratio {
+            builder.add_text("aspect_ratio", aspect_ratio);
+        }
+        builder.add_text("output_format", "png");
+        self.post(&format!("generate/{endpoint}"), builder)
+    }
+
+    fn edit_images(
+        &self,
+        request: &EditRequest,
+    ) -> Result<Response, ClientError> {
+        if !request.references.is_empty() {
+            return Err(ClientError::Unsupported {
+                provider: self.name(),
+                feature: "--image-ref",
+            });
+        }
+        if request.style_ref.is_some() {
+            return Err(ClientError::Unsupported {
+                provider: self.name(),
+                feature: "--style-ref",
+            });
+        }
+        let [image] = request.images.as_slice() else {
+            return Err(ClientError::Unsupported {
+                provider: self.name(),
+                feature: "editing more than one --image at a time",
+            });
+        };
+        // Inpainting repaints the mask, or else the image's transparent areas
+        let mut builder = multipart::Builder::new();
+        builder.add_text("prompt", &request.prompt);
+        builder.add_file_bytes(
+            "image",
+            &image.filename,
+            image.content_type,
+            &image.bytes,
+        );
+        if let Some(mask) = &request.mask {
+            builder.add_file_bytes(
+                "mask",
+                &mask.filename,
+                mask.content_type,
+                &mask.bytes,
+            );
+        }
+        builder.add_text("output_format", "png");
+        self.post("edit/inpaint", builder)
+    }
+
+    fn create_variations(
+        &self,
+        _request: &VariationRequest,
+    ) -> Result<Response, ClientError> {
+        Err(ClientError::Unsupported {
+            provider: self.name(),
+            feature: "variations",
+        })
+    }
+}
+
+/// The generate endpoint for a Stability model.
+fn endpoint_for(model: &str) -> Result<&'static str, ClientError> {
+    MODELS
+        .iter()
+        .find(|(name, _)| *name == model)
+        .map(|(_, endpoint)| *endpoint)
+        .ok_or(ClientError::Unsupported {
+            provider: "stability",
+            feature: "this --model",
+        })
+}
+
+/// The Stability aspect ratio for an (OpenAI) `--size`. `None` for the
+/// default (square).
+fn aspect_ratio(
+    size: Option<&str>,
+) -> Result<Option<&'static str>, ClientError> {
+    match size {
+        None => Ok(None),
+        Some("1024x1024") => Ok(Some("1:1")),
+        Some("1536x1024") => Ok(Some("3:2")),
+        Some("1024x1536") => Ok(Some("2:3")),
+        Some(_) => Err(ClientError::Unsupported {
+            provider: "stability",
+            feature: "this --size",
+        }),
+    }
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parameter_mapping() {
+        assert_eq!(endpoint_for("sd3.5-large").unwrap(), "sd3");
+        assert_eq!(endpoint_for(DEFAULT_MODEL).unwrap(), "core");
+        let err = endpoint_for("gpt-image-1").unwrap_err();
+        assert_eq!(err.to_string(), "stability doesn't support this --model");
+
+        assert_eq!(aspect_ratio(None).unwrap(), None);
+        assert_eq!(aspect_ratio(Some("1536x1024")).unwrap(), Some("3:2"));
+        assert!(aspect_ratio(Some("1792x1024")).is_err());
+    }
+}
@@ -0,0 +1,227 @@
+//! Local prompt length checks.
+//!
+//! We don't ship the model's tokenizer vocabulary, so token counts are
+//! estimated: the text is split the way tiktoken's pre-tokenizer splits it
+//! (words with their leading space, short digit runs, punctuation, and
+//! whitespace), and each piece is costed like a typical BPE would.
+
+use log::{debug, warn};
+
+use crate::capabilities::ModelCapabilities;
+
+/// The longest prompt a model accepts. Most limits are in characters, which
+/// we count exactly; token limits can only be checked against our estimate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PromptLimit {
+    Chars(usize),
+    Tokens(usize),
+}
+
+impl PromptLimit {
+    /// The length of `text` in this limit's unit.
+    fn len(self, text: &str) -> usize {
+        match self {
+            Self::Chars(_) => text.chars().count(),
+            Self::Tokens(_) => estimate_tokens(text),
+        }
+    }
+
+    fn max(self) -> usize {
+        match self {
+            Self::Chars(max) | Self::Tokens(max) => max,
+        }
+    }
+
+    /// `len` with its unit, e.g. "120 characters" or "~30 tokens".
+    fn describe(self, len: usize) -> String {
+        match self {
+            Self::Chars(_) => format!("{len} characters"),
+            Self::Tokens(_) => format!("~{len} tokens"),
+        }
+    }
+
+    /// Where a prompt over the limit is cut off, as a byte index, or `None`
+    /// if it fits.
+    fn cut_at(self, prompt: &str) -> Option<usize> {
+        match self {
+            Self::Chars(max) => prompt.char_indices().nth(max).map(|(i, _)| i),
+            Self::Tokens(max) => {
+                let mut tokens = 0;
+                let mut idx = 0;
+                for piece in pieces(prompt) {
+                    tokens += piece_tokens(piece);
+                    if tokens > max {
+                        return Some(idx);
+                    }
+                    idx += piece.len();
+                }
+                None
+            }
+        }
+    }
+}
+
+/// Warn once a prompt uses this fraction of the limit.
+const WARN_FRACTION: f64 = 0.9;
+
+/// Estimate how many tokens `text` encodes to.
+pub fn estimate_tokens(text: &str) -> usize {
+    pieces(text).map(piece_tokens).sum()
+}
+
+/// Split text like tiktoken's pre-tokenizer.
+fn pieces(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let mut chars = rest.char_indices().peekable();
+        let (_, first) = chars.next()?;
+        let class = |c: char| {
+            if c.is_alphabetic() {
+                0
+            } else if c.is_numeric() {
+                1
+            } else if c.is_whitespace() {
+                2
+            } else {
+                3
+            }
+        };
+        // A single leading space joins the word or punctuation after it
+        let joins = first == ' '
+            && chars
+                .peek()
+                .is_some_and(|&(_, c)| class(c) == 0 || class(c) == 3);
+        let kind = if joins {
+            class(chars.next().unwrap().1)
+        } else {
+            class(first)
+        };
+
+        // Digits group in threes
+        let max_len = if kind == 1 { 2 } else { usize::MAX };
+        let end = chars
+            .enumerate()
+            .find(|&(len, (_, c))| class(c) != kind || len == max_len)
+            .map_or(rest.len(), |(_, (idx, _))| idx);
+        let (piece, tail) = rest.split_at(end);
+        rest = tail;
+        Some(piece)
+    })
+}
+
+/// The estimated tokens in one pre-tokenized piece.
+fn piece_tokens(piece: &str) -> usize {
+    let chars = piece.chars().count();
+    if piece.is_ascii() {
+        let letters = piece.trim_start().len();
+        if piece.trim().is_empty()
+            || piece.trim_start().starts_with(|c: char| c.is_ascii_digit())
+        {
+            return 1;
+        }
+        // Common words are a single token; longer ones split into roughly
+        // four-character chunks
+        if letters <= 6 {
+            1
+        } else {
+            letters.div_ceil(4)
+        }
+    } else {
+        // Non-Latin scripts are closer to a token per character
+        chars.max(1)
+    }
+}
+
+/// Warn if `prompt` is near or over the model's prompt length limit,
+/// showing where it would be cut off. Unknown models aren't checked.
+pub fn check_prompt(prompt: &str, capabilities: Option<&ModelCapabilities>) {
+    debug!(
+        "Prompt length: {} characters, ~{} tokens",
+        prompt.chars().count(),
+        estimate_tokens(prompt)
+    );
+    let Some(capabilities) = capabilities else {
+        return;
+    };
+    let model = capabilities.model;
+    let limit = capabilities.max_prompt;
+    let len = limit.describe(limit.len(prompt));
+    let max = limit.describe(limit.max());
+    let estimated = match limit {
+        PromptLimit::Chars(_) => "",
+        PromptLimit::Tokens(_) => " (estimated; we don't have its tokenizer)",
+    };
+
+    if let Some(cut) = limit.cut_at(prompt) {
+        let before = tail_chars(&prompt[..cut], 40);
+        let dropped = &prompt[cut..];
+        warn!(
+            "The prompt is {len}, over {model}'s limit of {max}{estimated}. \
+             Everything after \"…{before}\" ({}) would be cut off.",
+            limit.describe(limit.len(dropped)),
+        );
+    } else if limit.len(prompt) as f64 >= limit.max() as f64 * WARN_FRACTION {
+        warn!(
+            "The prompt is {len}, close to {model}'s limit of \
+             {max}{estimated}"
+        );
+    }
+}
+
+/// The last `n` characters of `text`, on one line.
+fn tail_chars(text: &str, n: usize) -> String {
+    let start = text
+        .char_indices()
+        .rev()
+        .nth(n.saturating_sub(1))
+        .map_or(0, |(idx, _)| idx);
+    text[start..].replace(['\n', '\r'], " ")
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pieces() {
+        let split: Vec<&str> = pieces("A cat, 12345 cats!\n\nDone").collect();
+        assert_eq!(
+            split,
+            [
+                "A", " cat", ",", " ", "123", "45", " cats", "!", "\n\n",
+                "Done"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert_eq!(estimate_tokens(""), 0);
+        // tiktoken: "A cute cat on the Moon" is 6 tokens
+        assert_eq!(estimate_tokens("A cute cat on the Moon"), 6);
+        assert_eq!(estimate_tokens(" photorealistic"), 4);
+        assert_eq!(estimate_tokens("日本語"), 3);
+    }
+
+    #[test]
+    fn test_prompt_limit() {
+        let chars = PromptLimit::Chars(5);
+        assert_eq!(chars.cut_at("A cat"), None);
+        assert_eq!(chars.cut_at("A cute cat"), Some(5));
+        assert_eq!(chars.cut_at("日本語の猫です"), Some("日本語の猫".len()));
+
+        // Cut before the piece that goes over
+        let tokens = PromptLimit::Tokens(3);
+        assert_eq!(tokens.cut_at("A cute cat"), None);
+        assert_eq!(tokens.cut_at("A cute cat on the Moon"), Some(10));
+        assert_eq!(tokens.describe(3), "~3 tokens");
+    }
+
+    #[test]
+    fn test_tail_chars() {
+        assert_eq!(tail_chars("hello\nworld", 7), "o world");
+        assert_eq!(tail_chars("hi", 40), "hi");
+    }
+}
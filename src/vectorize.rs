@@ -0,0 +1,260 @@
+//! Trace raster images into SVG, for simple logos and icons.
+//!
+//! Like potrace, but in color: the image is reduced to a small palette, and
+//! the outline of each color's regions is traced into straight-line paths,
+//! simplified to drop the staircase of the pixel grid.
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+use std::{collections::HashMap, fmt::Write};
+
+use crate::imageops::Palette;
+
+/// The most colors to trace. Logos and icons rarely need more.
+const MAX_COLORS: usize = 8;
+
+/// Trace at most this many pixels across; finer detail is mostly noise.
+const MAX_TRACE_SIZE: u32 = 512;
+
+/// How far (in traced pixels) a simplified outline may stray from the pixel
+/// grid.
+const TOLERANCE: f64 = 0.75;
+
+/// A corner of the pixel grid.
+type Point = (u32, u32);
+
+/// Trace `image` into an SVG document of the same size.
+pub fn to_svg(image: &DynamicImage) -> String {
+    let (width, height) = image.dimensions();
+    let scale = (MAX_TRACE_SIZE as f64 / width.max(height) as f64).min(1.0);
+    let (trace_width, trace_height) = (
+        ((width as f64 * scale).round() as u32).max(1),
+        ((height as f64 * scale).round() as u32).max(1),
+    );
+    let small = if scale < 1.0 {
+        image.resize_exact(trace_width, trace_height, FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+
+    let palette = Palette::extract(&small, MAX_COLORS);
+    let remapped = palette.remap(&small).into_rgba8();
+
+    // Count each color so the most common (usually the background) is
+    // painted first and the rest layered on top
+    let mut layers: Vec<([u8; 3], usize)> = palette
+        .0
+        .iter()
+        .map(|color| {
+            let count = remapped
+                .pixels()
+                .filter(|px| px[3] >= 128 && px.0[..3] == color.0)
+                .count();
+            (color.0, count)
+        })
+        .filter(|&(_, count)| count > 0)
+        .collect();
+    layers.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" \
+         height=\"{height}\" viewBox=\"0 0 {trace_width} {trace_height}\">"
+    );
+    for (color, _) in layers {
+        let inside = |x: u32, y: u32| {
+            let px = remapped.get_pixel(x, y);
+            px[3] >= 128 && px.0[..3] == color
+        };
+        let outlines = trace(trace_width, trace_height, inside);
+        let [r, g, b] = color;
+        let _ = write!(
+            svg,
+            "<path fill=\"#{r:02x}{g:02x}{b:02x}\" fill-rule=\"evenodd\" d=\""
+        );
+        for outline in outlines {
+            write_outline(&mut svg, &simplify(&outline, TOLERANCE));
+        }
+        svg.push_str("\"/>\n");
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Trace the closed outlines around the pixels where `inside` is true, as
+/// grid corners.
+fn trace(
+    width: u32,
+    height: u32,
+    inside: impl Fn(u32, u32) -> bool,
+) -> Vec<Vec<Point>> {
+    let is_inside = |x: i64, y: i64| {
+        x >= 0
+            && y >= 0
+            && x < width as i64
+            && y < height as i64
+            && inside(x as u32, y as u32)
+    };
+
+    // Collect the edges between inside and outside pixels, directed so the
+    // inside is always on the same side
+    let mut edges: HashMap<Point, Vec<Point>> = HashMap::new();
+    for y in 0..height {
+        for x in 0..width {
+            if !inside(x, y) {
+                continue;
+            }
+            let (xi, yi) = (x as i64, y as i64);
+            let mut add = |from: Point, to: Point| {
+                edges.entry(from).or_default().push(to)
+            };
+            if !is_inside(xi, yi - 1) {
+                add((x, y), (x + 1, y));
+            }
+            if !is_inside(xi + 1, yi) {
+                add((x + 1, y), (x + 1, y + 1));
+            }
+            if !is_inside(xi, yi + 1) {
+                add((x + 1, y + 1), (x, y + 1));
+            }
+            if !is_inside(xi - 1, yi) {
+                add((x, y + 1), (x, y));
+            }
+        }
+    }
+
+    // Every corner has as many edges in as out, so following edges from any
+    // start always leads back to it
+    let mut outlines = Vec::new();
+    let mut starts: Vec<Point> = edges.keys().copied().collect();
+    starts.sort_unstable();
+    for start in starts {
+        while let Some(mut next) = pop_edge(&mut edges, start) {
+            let mut outline = vec![start];
+            while next != start {
+                outline.push(next);
+                next = pop_edge(&mut edges, next)
+                    .expect("Pixel outlines are always closed");
+            }
+            outlines.push(outline);
+        }
+    }
+    outlines
+}
+
+fn pop_edge(
+    edges: &mut HashMap<Point, Vec<Point>>,
+    from: Point,
+) -> Option<Point> {
+    edges.get_mut(&from)?.pop()
+}
+
+/// Simplify a closed outline with Ramer-Douglas-Peucker, keeping points
+/// within `tolerance` of the original.
+fn simplify(outline: &[Point], tolerance: f64) -> Vec<Point> {
+    if outline.len() <= 4 {
+        return outline.to_vec();
+    }
+    // Split the loop at its start and its farthest point, and simplify each
+    // half as an open line
+    let start = outline[0];
+    let dist2 = |(x, y): Point| {
+        let (dx, dy) = (x as f64 - start.0 as f64, y as f64 - start.1 as f64);
+        dx * dx + dy * dy
+    };
+    let far = (1..outline.len())
+        .max_by(|&a, &b| dist2(outline[a]).total_cmp(&dist2(outline[b])))
+        .unwrap();
+
+    let mut closed = outline.to_vec();
+    closed.push(start);
+    let mut out = Vec::new();
+    rdp(&closed[..=far], tolerance, &mut out);
+    out.pop();
+    rdp(&closed[far..], tolerance, &mut out);
+    out.pop();
+    out
+}
+
+/// Append the simplified points of the open line `points` to `out`,
+/// including both ends.
+fn rdp(points: &[Point], tolerance: f64, out: &mut Vec<Point>) {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let (fx, fy) = (first.0 as f64, first.1 as f64);
+    let (dx, dy) = (last.0 as f64 - fx, last.1 as f64 - fy);
+    let len = (dx * dx + dy * dy).sqrt();
+    let distance = |&(x, y): &Point| {
+        let (px, py) = (x as f64 - fx, y as f64 - fy);
+        if len == 0.0 {
+            (px * px + py * py).sqrt()
+        } else {
+            (px * dy - py * dx).abs() / len
+        }
+    };
+
+    let farthest = points[1..points.len().saturating_sub(1)]
+        .iter()
+        .enumerate()
+        .map(|(i, point)| (i + 1, distance(point)))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+    match farthest {
+        Some((i, dist)) if dist > tolerance => {
+            rdp(&points[..=i], tolerance, out);
+            out.pop();
+            rdp(&points[i..], tolerance, out);
+        }
+        _ => {
+            out.push(first);
+            out.push(last);
+        }
+    }
+}
+
+/// Append a closed outline as SVG path commands.
+fn write_outline(svg: &mut String, outline: &[Point]) {
+    for (i, (x, y)) in outline.iter().enumerate() {
+        let command = if i == 0 { 'M' } else { 'L' };
+        let _ = write!(svg, "{command}{x} {y}");
+    }
+    svg.push('Z');
+}
+
+// --- Tests ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    #[test]
+    fn test_trace_square_with_hole() {
+        // A 4x4 square with a 2x2 hole in the middle
+        let inside = |x, y| !((1..3).contains(&x) && (1..3).contains(&y));
+        let outlines = trace(4, 4, inside);
+        assert_eq!(outlines.len(), 2);
+
+        let simplified: Vec<_> =
+            outlines.iter().map(|o| simplify(o, TOLERANCE)).collect();
+        let mut corners: Vec<_> =
+            simplified.iter().map(|outline| outline.len()).collect();
+        corners.sort();
+        assert_eq!(corners, [4, 4]);
+        assert!(simplified.iter().any(|o| o.contains(&(4, 4))));
+        assert!(simplified.iter().any(|o| o.contains(&(3, 3))));
+    }
+
+    #[test]
+    fn test_to_svg() {
+        let red = Rgba([255, 0, 0, 255]);
+        let white = Rgba([255, 255, 255, 255]);
+        let image =
+            RgbaImage::from_fn(16, 16, |x, _| if x < 8 { red } else { white });
+        let svg = to_svg(&DynamicImage::ImageRgba8(image));
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.contains("viewBox=\"0 0 16 16\""));
+        assert!(svg.contains("fill=\"#ff0000\""));
+        assert!(svg.contains("fill=\"#ffffff\""));
+        assert!(svg.contains("d=\"M0 0L8 0L8 16L0 16Z\""));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+}